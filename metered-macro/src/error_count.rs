@@ -1,6 +1,7 @@
 use crate::error_count_opts::ErrorCountKeyValAttribute;
 use heck::ToSnakeCase;
 use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenTree};
 use syn::{Attribute, Field, Fields, Ident, ItemEnum};
 
 pub fn error_count(attrs: TokenStream, item: TokenStream) -> syn::Result<TokenStream> {
@@ -31,10 +32,74 @@ pub fn error_count(attrs: TokenStream, item: TokenStream) -> syn::Result<TokenSt
         })
         .collect::<Vec<_>>();
 
+    // nested error counts must be clearable for the struct to be, and mergeable
+    // for it to be mergeable
+    let nested_metric_types = nested_attrs
+        .iter()
+        .zip(metric_type.iter())
+        .filter(|((_, nested_attr), _)| nested_attr.is_some())
+        .map(|(_, ty)| ty)
+        .collect::<Vec<_>>();
+
+    // with latencies, each count is paired with the response times of the
+    // calls failing with its variant
+    let with_latency = attrs.with_latency;
+    let field_type = metric_type.iter().map(|ty| {
+        if with_latency {
+            quote!(metered::common::WithLatency<#ty>)
+        } else {
+            quote!(#ty)
+        }
+    });
+    let count = if with_latency {
+        quote!(.count)
+    } else {
+        quote!()
+    };
+
+    // with a total, every error is also counted in a `total` counter, which
+    // is not a variant and is thus serialized without labels
+    let with_total = attrs.with_total;
+    if with_total {
+        if let Some(v) = input
+            .variants
+            .iter()
+            .find(|v| v.ident.to_string().to_snake_case() == "total")
+        {
+            return Err(syn::Error::new_spanned(
+                &v.ident,
+                "`with_total` can't be used with a variant named `Total`",
+            ));
+        }
+    }
+    let (total_field, total_incr, total_clear, total_cleared, total_merge) = if with_total {
+        (
+            quote!(pub total: C,),
+            quote!(metered::metric::Counter::incr(&self.total);),
+            quote!(self.total.clear();),
+            quote!(cleared &= metered::clear::Clearable::is_cleared(&self.total);),
+            quote!(metered::merge::Merge::merge_from(&self.total, &other.total);),
+        )
+    } else {
+        (quote!(), quote!(), quote!(), quote!(), quote!())
+    };
+    let total = if with_total {
+        quote!(metered::metric::Counter::value(&self.total))
+    } else {
+        quote!(self
+            .iter()
+            .map(|(_, count)| count)
+            .fold(0, u64::saturating_add))
+    };
+
     let ident = &input.ident;
 
-    let variants = input.variants.iter().map(|v| &v.ident);
-    let stringified_variants = input.variants.iter().map(|v| v.ident.to_string());
+    let variants = input.variants.iter().map(|v| &v.ident).collect::<Vec<_>>();
+    let stringified_variants = input
+        .variants
+        .iter()
+        .map(|v| v.ident.to_string())
+        .collect::<Vec<_>>();
     let snake_variants: Vec<Ident> = input
         .variants
         .iter()
@@ -77,6 +142,16 @@ pub fn error_count(attrs: TokenStream, item: TokenStream) -> syn::Result<TokenSt
             syn::Fields::Unit => quote!(),
         });
 
+    // generate patterns matching each enum variant, whatever its fields
+    let variants_wildcards = nested_attrs
+        .iter()
+        .map(|(fields, _)| match fields {
+            syn::Fields::Named(_) => quote!({ .. }),
+            syn::Fields::Unnamed(_) => quote!((..)),
+            syn::Fields::Unit => quote!(),
+        })
+        .collect::<Vec<_>>();
+
     // generate incr calls for each variant, if a field is marked with `#[nested]`,
     // the incr is instead delegated there
     let variant_incr_call =
@@ -90,19 +165,184 @@ pub fn error_count(attrs: TokenStream, item: TokenStream) -> syn::Result<TokenSt
                         .clone()
                         .unwrap_or_else(|| Ident::new("nested", attr.bracket_token.span));
                     quote! {{
-                        self.#ident.incr(#inner_val_ident);
+                        metered::ErrorBreakdownIncr::incr(&self.#ident #count, #inner_val_ident);
                     }}
                 } else {
-                    quote!(self.#ident.incr())
+                    quote!(self.#ident #count.incr())
                 }
             });
 
-    let skip_cleared = attrs.skip_cleared;
-    let serializer = nested_attrs.iter().map(|(_, nested_attr)| {
-        if skip_cleared && nested_attr.is_none() {
-            quote!("metered::error_variant_serializer_skip_cleared")
+    // generate the collection of the counts of each variant, nested counts
+    // being named by their path
+    let variant_counts = nested_attrs
+        .iter()
+        .zip(snake_variants.iter().zip(stringified_variants.iter()))
+        .map(|((_, nested_attr), (ident, name))| {
+            if nested_attr.is_some() {
+                quote! {
+                    counts.extend(
+                        metered::ErrorBreakdownCounts::counts(&self.#ident #count)
+                            .into_iter()
+                            .map(|(variant, count)| (format!("{}::{}", #name, variant), count)),
+                    )
+                }
+            } else {
+                quote!(counts.push((#name.to_string(), metered::metric::Counter::value(&self.#ident #count))))
+            }
+        });
+
+    // with samples, the last errors of each variant are kept as displayed, in
+    // a field that is only serialized on demand, and that counts the errors of
+    // each variant to sample one in every `sample_every`
+    let (samples_field, samples_record, samples_clear, samples_merge, samples_impl) = match attrs
+        .samples
+    {
+        Some(samples) => {
+            let variant_count = variants.len();
+            let variant_indexes = 0..variant_count;
+            let sample_every = match attrs.sample_every {
+                Some(every) => quote!(#every),
+                None => quote!(1),
+            };
+            let serde_attr = if attrs.serialize_samples {
+                quote!(#[serde(rename = "samples")])
+            } else {
+                quote!(#[serde(skip)])
+            };
+            (
+                quote! {
+                    #serde_attr
+                    __samples: metered::common::ErrorSamples<#samples, #variant_count, #sample_every>,
+                },
+                quote! {
+                    let (index, variant) = match err {
+                        #( #(#cfg_attrs)* #ident::#variants #variants_wildcards => (#variant_indexes, #stringified_variants), )*
+                    };
+                    self.__samples.record(index, variant, err);
+                },
+                quote!(metered::clear::Clear::clear(&self.__samples);),
+                quote!(metered::merge::Merge::merge_from(&self.__samples, &other.__samples);),
+                quote! {
+                    impl<C: metered::metric::Counter> #metrics_ident<C> {
+                        /// Get the last sampled errors of a variant, as
+                        /// displayed, from oldest to newest
+                        pub fn samples(&self, variant: &str) -> Vec<String> {
+                            self.__samples.get(variant)
+                        }
+                    }
+                },
+            )
+        }
+        None => (quote!(), quote!(), quote!(), quote!(), quote!()),
+    };
+
+    // nested error counts are skipped as a whole when all their counters are
+    // cleared, however deep they are nested
+    let (serializer_fn, serialized_bound) = if attrs.skip_cleared {
+        (
+            quote!(error_variant_serializer_skip_cleared),
+            quote!(serde::Serialize + metered::clear::Clearable),
+        )
+    } else {
+        (quote!(error_variant_serializer), quote!(serde::Serialize))
+    };
+
+    // a custom label needs its own serializer, as `serialize_with` cannot pass
+    // it to the one of metered
+    let (serializer, label_impl) = match &attrs.label_alias {
+        Some(alias) => {
+            let serializer_as = Ident::new(&format!("{}_as", serializer_fn), Span::call_site());
+            let label_impl = quote! {
+                impl<C: metered::metric::Counter> #metrics_ident<C> {
+                    fn serialize_error_variant<S, T>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+                    where
+                        S: serde::Serializer,
+                        T: #serialized_bound,
+                    {
+                        metered::#serializer_as(#alias, value, serializer)
+                    }
+                }
+            };
+            (
+                format!("{}::<C>::serialize_error_variant", metrics_ident),
+                label_impl,
+            )
+        }
+        None => (format!("metered::{}", serializer_fn), quote!()),
+    };
+    let serializer = std::iter::repeat(serializer).take(nested_attrs.len());
+
+    // with latencies, calls are timed from their start, and the time of failed
+    // ones is recorded to their variant
+    let (start, start_type, record_latency) = if with_latency {
+        (
+            quote!(start),
+            quote!(metered::time_source::StdInstant),
+            quote!(self.record_latency(e, start);),
+        )
+    } else {
+        (quote!(()), quote!(()), quote!())
+    };
+    let enter = if with_latency {
+        quote! {
+            type E = metered::time_source::StdInstant;
+            fn enter(&self) -> metered::time_source::StdInstant {
+                metered::time_source::Instant::now()
+            }
+        }
+    } else {
+        quote! {
+            type E = ();
+            fn enter(&self) {}
+        }
+    };
+    let latency_impl = if with_latency {
+        quote! {
+            impl<C: metered::metric::Counter> #metrics_ident<C> {
+                fn record_latency(&self, err: &#ident, start: metered::time_source::StdInstant) {
+                    match err {
+                        #( #(#cfg_attrs)* #ident::#variants #variants_wildcards => self.#snake_variants.record_latency(start), )*
+                    }
+                }
+            }
+        }
+    } else {
+        quote!()
+    };
+
+    // project results of wrapping errors into the enum: `_` placeholders are
+    // replaced by the enum and projected with `AsRef`, other wrappers with
+    // `metered::ProjectError`
+    let wrapped_impls = attrs.wrapped_by.iter().map(|wrapper| {
+        let (wrapper, generic) = replace_placeholders(quote!(#wrapper), ident);
+        let project = if generic {
+            quote!(Some(<#wrapper as std::convert::AsRef<#ident>>::as_ref(e)))
         } else {
-            quote!("metered::error_variant_serializer")
+            quote!(<#wrapper as metered::ProjectError<#ident>>::project_error(e))
+        };
+        quote! {
+            impl<T, C: metered::metric::Counter> metered::metric::Metric<Result<T, #wrapper>> for #metrics_ident<C> {}
+
+            impl<T, C: metered::metric::Counter> metered::metric::OnResult<Result<T, #wrapper>> for #metrics_ident<C> {
+                fn on_result(&self, #start: #start_type, r: &Result<T, #wrapper>) -> metered::metric::Advice {
+                    if let Some(e) = r.as_ref().err().and_then(|e| #project) {
+                        metered::ErrorBreakdownIncr::incr(self, e);
+                        #record_latency
+                    }
+                    metered::metric::Advice::Return
+                }
+            }
+
+            impl<T, C: metered::metric::Counter> metered::metric::Metric<Option<Result<T, #wrapper>>> for #metrics_ident<C> {}
+
+            impl<T, C: metered::metric::Counter> metered::metric::OnResult<Option<Result<T, #wrapper>>> for #metrics_ident<C> {
+                fn on_result(&self, #start: #start_type, r: &Option<Result<T, #wrapper>>) -> metered::metric::Advice {
+                    if let Some(r) = r {
+                        metered::metric::OnResult::on_result(self, #start, r);
+                    }
+                    metered::metric::Advice::Return
+                }
+            }
         }
     });
 
@@ -117,12 +357,16 @@ pub fn error_count(attrs: TokenStream, item: TokenStream) -> syn::Result<TokenSt
             #(
                 #(#cfg_attrs)*
                 #[serde(rename = #stringified_variants, serialize_with = #serializer)]
-                pub #snake_variants: #metric_type,
+                pub #snake_variants: #field_type,
             )*
+            #total_field
+            #samples_field
         }
 
         impl<C: metered::metric::Counter> metered::ErrorBreakdownIncr<#ident> for #metrics_ident<C> {
             fn incr(&self, err: &#ident) {
+                #total_incr
+                #samples_record
                 match err {
                     #( #(#cfg_attrs)* #ident::#variants #variants_args => #variant_incr_call, )*
                 }
@@ -132,20 +376,86 @@ pub fn error_count(attrs: TokenStream, item: TokenStream) -> syn::Result<TokenSt
         impl<C: metered::metric::Counter> metered::clear::Clear for #metrics_ident<C> {
             fn clear(&self) {
                 #( #(#cfg_attrs)* self.#snake_variants.clear(); )*
+                #total_clear
+                #samples_clear
+            }
+        }
+
+        impl<C: metered::metric::Counter> metered::clear::Clearable for #metrics_ident<C>
+        where
+            #( #nested_metric_types: metered::clear::Clearable, )*
+        {
+            fn is_cleared(&self) -> bool {
+                let mut cleared = true;
+                #( #(#cfg_attrs)* { cleared &= metered::clear::Clearable::is_cleared(&self.#snake_variants); } )*
+                #total_cleared
+                cleared
+            }
+        }
+
+        impl<C: metered::metric::Counter + metered::merge::Merge> metered::merge::Merge for #metrics_ident<C>
+        where
+            #( #nested_metric_types: metered::merge::Merge, )*
+        {
+            fn merge_from(&self, other: &Self) {
+                #( #(#cfg_attrs)* metered::merge::Merge::merge_from(&self.#snake_variants, &other.#snake_variants); )*
+                #total_merge
+                #samples_merge
+            }
+        }
+
+        impl<C: metered::metric::Counter> metered::ErrorBreakdownCounts for #metrics_ident<C>
+        where
+            #( #nested_metric_types: metered::ErrorBreakdownCounts, )*
+        {
+            fn counts(&self) -> Vec<(String, u64)> {
+                let mut counts = Vec::new();
+                #( #(#cfg_attrs)* #variant_counts; )*
+                counts
+            }
+        }
+
+        impl<C: metered::metric::Counter> #metrics_ident<C>
+        where
+            #( #nested_metric_types: metered::ErrorBreakdownCounts, )*
+        {
+            /// Iterates over the name and count of each variant, nested
+            /// variants being named by their path joined with `::`
+            pub fn iter(&self) -> impl Iterator<Item = (String, u64)> {
+                metered::ErrorBreakdownCounts::counts(self).into_iter()
+            }
+
+            /// Get the total count of errors, of all variants
+            pub fn total(&self) -> u64 {
+                #total
             }
         }
 
         impl<T, C: metered::metric::Counter> metered::metric::Metric<Result<T, #ident>> for #metrics_ident<C> {}
 
         impl<C: metered::metric::Counter> metered::metric::Enter for #metrics_ident<C> {
-            type E = ();
-            fn enter(&self) {}
+            #enter
         }
 
         impl<T, C: metered::metric::Counter> metered::metric::OnResult<Result<T, #ident>> for #metrics_ident<C> {
-            fn on_result(&self, (): (), r: &Result<T, #ident>) -> metered::metric::Advice {
+            fn on_result(&self, #start: #start_type, r: &Result<T, #ident>) -> metered::metric::Advice {
                 if let Err(e) = r {
                     metered::ErrorBreakdownIncr::incr(self, e);
+                    #record_latency
+                }
+                metered::metric::Advice::Return
+            }
+        }
+
+        // optional results, e.g. the items of streams, count their errors
+        // like results do, and results of options are results
+        impl<T, C: metered::metric::Counter> metered::metric::Metric<Option<Result<T, #ident>>> for #metrics_ident<C> {}
+
+        impl<T, C: metered::metric::Counter> metered::metric::OnResult<Option<Result<T, #ident>>> for #metrics_ident<C> {
+            fn on_result(&self, #start: #start_type, r: &Option<Result<T, #ident>>) -> metered::metric::Advice {
+                if let Some(Err(e)) = r {
+                    metered::ErrorBreakdownIncr::incr(self, e);
+                    #record_latency
                 }
                 metered::metric::Advice::Return
             }
@@ -154,9 +464,44 @@ pub fn error_count(attrs: TokenStream, item: TokenStream) -> syn::Result<TokenSt
         impl<C: metered::metric::Counter> metered::ErrorBreakdown<C> for #ident {
             type ErrorCount = #metrics_ident<C>;
         }
+
+        #latency_impl
+
+        #label_impl
+
+        #samples_impl
+
+        #( #wrapped_impls )*
     }.into())
 }
 
+/// Replaces the `_` placeholders of a wrapper type by the error type, and
+/// returns whether there were any.
+fn replace_placeholders(
+    tokens: proc_macro2::TokenStream,
+    error: &Ident,
+) -> (proc_macro2::TokenStream, bool) {
+    let mut replaced = false;
+    let tokens = tokens
+        .into_iter()
+        .map(|token| match token {
+            TokenTree::Ident(ident) if ident == "_" => {
+                replaced = true;
+                TokenTree::Ident(error.clone())
+            }
+            TokenTree::Group(group) => {
+                let (stream, inner) = replace_placeholders(group.stream(), error);
+                replaced |= inner;
+                let mut replacement = proc_macro2::Group::new(group.delimiter(), stream);
+                replacement.set_span(group.span());
+                TokenTree::Group(replacement)
+            }
+            token => token,
+        })
+        .collect();
+    (tokens, replaced)
+}
+
 type FieldWithNestedAttribute = Option<(Field, Attribute)>;
 
 /// Gets all variants from the given `ItemEnum`, and returns `Some(Field,
@@ -5,7 +5,10 @@ use syn::{
     Result,
 };
 
-use synattra::{types::KVOption, *};
+use synattra::{
+    types::{KVOption, MultipleValArray},
+    *,
+};
 
 use std::borrow::Cow;
 
@@ -13,6 +16,13 @@ pub struct ErrorCountOpts<'a> {
     pub name_ident: &'a syn::Ident,
     pub visibility: Cow<'a, syn::Visibility>,
     pub skip_cleared: bool,
+    pub with_latency: bool,
+    pub with_total: bool,
+    pub samples: Option<&'a syn::LitInt>,
+    pub sample_every: Option<&'a syn::LitInt>,
+    pub serialize_samples: bool,
+    pub label_alias: Option<String>,
+    pub wrapped_by: Vec<&'a syn::Type>,
 }
 
 pub struct ErrorCountKeyValAttribute {
@@ -39,6 +49,45 @@ impl ErrorCountKeyValAttribute {
             .map(|opt| (std::mem::discriminant(opt), opt.as_str()))
             .collect();
 
+        for opt in self.values.iter() {
+            if let ErrorCountOption::Label(label) = opt {
+                let name = label.value.value();
+                let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                if !valid {
+                    let error = format!("`{}` is not a valid label name.", name);
+                    return Err(syn::Error::new_spanned(&label.value, error));
+                }
+            }
+        }
+
+        let labels = self
+            .values
+            .iter()
+            .filter(|opt| {
+                matches!(
+                    opt,
+                    ErrorCountOption::Label(_) | ErrorCountOption::LabelAlias(_)
+                )
+            })
+            .count();
+        if labels > 1 {
+            return Err(input.error("`label` and `label_alias` attributes are exclusive."));
+        }
+
+        let has_samples = self
+            .values
+            .iter()
+            .any(|opt| matches!(opt, ErrorCountOption::Samples(_)));
+        for opt in self.values.iter() {
+            if let ErrorCountOption::SampleEvery(_) | ErrorCountOption::SerializeSamples(_) = opt {
+                if !has_samples {
+                    let error = format!("`{}` attribute requires `samples`.", opt.as_str());
+                    return Err(input.error(error));
+                }
+            }
+        }
+
         for (opt_type, opt_name) in opt_types.iter() {
             let count = self
                 .values
@@ -98,10 +147,103 @@ impl ErrorCountKeyValAttribute {
             .map(|value| value.value)
             .unwrap_or(cfg!(feature = "error-count-skip-cleared-by-default"));
 
+        let with_latency = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let ErrorCountOption::WithLatency(tpe) = opt {
+                    Some(tpe.value.value)
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or(false);
+
+        let with_total = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let ErrorCountOption::WithTotal(tpe) = opt {
+                    Some(tpe.value.value)
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or(false);
+
+        let samples = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let ErrorCountOption::Samples(tpe) = opt {
+                    Some(&tpe.value)
+                } else {
+                    None
+                }
+            })
+            .next();
+
+        let sample_every = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let ErrorCountOption::SampleEvery(tpe) = opt {
+                    Some(&tpe.value)
+                } else {
+                    None
+                }
+            })
+            .next();
+
+        let serialize_samples = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let ErrorCountOption::SerializeSamples(tpe) = opt {
+                    Some(tpe.value.value)
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or(false);
+
+        let label_alias = self
+            .values
+            .iter()
+            .filter_map(|opt| match opt {
+                ErrorCountOption::Label(tpe) => Some(format!("!|{}[::]==<", tpe.value.value())),
+                ErrorCountOption::LabelAlias(tpe) => Some(tpe.value.value()),
+                _ => None,
+            })
+            .next();
+
+        let wrapped_by = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let ErrorCountOption::WrappedBy(tpe) = opt {
+                    Some(tpe.value.types())
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or_default();
+
         ErrorCountOpts {
             name_ident,
             visibility,
             skip_cleared,
+            with_latency,
+            with_total,
+            samples,
+            sample_every,
+            serialize_samples,
+            label_alias,
+            wrapped_by,
         }
     }
 }
@@ -122,6 +264,14 @@ mod kw {
     syn::custom_keyword!(name);
     syn::custom_keyword!(visibility);
     syn::custom_keyword!(skip_cleared);
+    syn::custom_keyword!(with_latency);
+    syn::custom_keyword!(with_total);
+    syn::custom_keyword!(samples);
+    syn::custom_keyword!(sample_every);
+    syn::custom_keyword!(serialize_samples);
+    syn::custom_keyword!(label);
+    syn::custom_keyword!(label_alias);
+    syn::custom_keyword!(wrapped_by);
 }
 
 pub type ErrorCountNameOption = KVOption<kw::name, syn::Ident>;
@@ -130,11 +280,62 @@ pub type ErrorCountVisibilityOption = KVOption<kw::visibility, syn::Visibility>;
 
 pub type ErrorCountSkipClearedOption = KVOption<kw::skip_cleared, syn::LitBool>;
 
+pub type ErrorCountWithLatencyOption = KVOption<kw::with_latency, syn::LitBool>;
+
+pub type ErrorCountWithTotalOption = KVOption<kw::with_total, syn::LitBool>;
+
+pub type ErrorCountSamplesOption = KVOption<kw::samples, syn::LitInt>;
+
+pub type ErrorCountSampleEveryOption = KVOption<kw::sample_every, syn::LitInt>;
+
+pub type ErrorCountSerializeSamplesOption = KVOption<kw::serialize_samples, syn::LitBool>;
+
+pub type ErrorCountLabelOption = KVOption<kw::label, syn::LitStr>;
+
+pub type ErrorCountLabelAliasOption = KVOption<kw::label_alias, syn::LitStr>;
+
+pub type ErrorCountWrappedByOption = KVOption<kw::wrapped_by, WrappedBy>;
+
+/// A wrapper type, or an array of them. Unlike `MultipleVal`, an array is not
+/// mistaken for a slice type.
+#[allow(clippy::large_enum_variant)]
+pub enum WrappedBy {
+    Single(syn::Type),
+    Multiple(MultipleValArray<syn::Type>),
+}
+
+impl WrappedBy {
+    pub fn types(&self) -> Vec<&syn::Type> {
+        match self {
+            WrappedBy::Single(ty) => vec![ty],
+            WrappedBy::Multiple(array) => array.values.iter().collect(),
+        }
+    }
+}
+
+impl Parse for WrappedBy {
+    fn parse(input: ParseStream<'_>) -> Result<Self> {
+        if input.peek(syn::token::Bracket) {
+            Ok(WrappedBy::Multiple(input.parse()?))
+        } else {
+            Ok(WrappedBy::Single(input.parse()?))
+        }
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
 pub enum ErrorCountOption {
     Name(ErrorCountNameOption),
     Visibility(ErrorCountVisibilityOption),
     SkipCleared(ErrorCountSkipClearedOption),
+    WithLatency(ErrorCountWithLatencyOption),
+    WithTotal(ErrorCountWithTotalOption),
+    Samples(ErrorCountSamplesOption),
+    SampleEvery(ErrorCountSampleEveryOption),
+    SerializeSamples(ErrorCountSerializeSamplesOption),
+    Label(ErrorCountLabelOption),
+    LabelAlias(ErrorCountLabelAliasOption),
+    WrappedBy(ErrorCountWrappedByOption),
 }
 
 impl ErrorCountOption {
@@ -144,6 +345,14 @@ impl ErrorCountOption {
             ErrorCountOption::Name(_) => <kw::name>::display(),
             ErrorCountOption::Visibility(_) => <kw::visibility>::display(),
             ErrorCountOption::SkipCleared(_) => <kw::skip_cleared>::display(),
+            ErrorCountOption::WithLatency(_) => <kw::with_latency>::display(),
+            ErrorCountOption::WithTotal(_) => <kw::with_total>::display(),
+            ErrorCountOption::Samples(_) => <kw::samples>::display(),
+            ErrorCountOption::SampleEvery(_) => <kw::sample_every>::display(),
+            ErrorCountOption::SerializeSamples(_) => <kw::serialize_samples>::display(),
+            ErrorCountOption::Label(_) => <kw::label>::display(),
+            ErrorCountOption::LabelAlias(_) => <kw::label_alias>::display(),
+            ErrorCountOption::WrappedBy(_) => <kw::wrapped_by>::display(),
         }
     }
 }
@@ -156,6 +365,22 @@ impl Parse for ErrorCountOption {
             Ok(input.parse_as(ErrorCountOption::Visibility)?)
         } else if ErrorCountSkipClearedOption::peek(input) {
             Ok(input.parse_as(ErrorCountOption::SkipCleared)?)
+        } else if ErrorCountWithLatencyOption::peek(input) {
+            Ok(input.parse_as(ErrorCountOption::WithLatency)?)
+        } else if ErrorCountWithTotalOption::peek(input) {
+            Ok(input.parse_as(ErrorCountOption::WithTotal)?)
+        } else if ErrorCountSamplesOption::peek(input) {
+            Ok(input.parse_as(ErrorCountOption::Samples)?)
+        } else if ErrorCountSampleEveryOption::peek(input) {
+            Ok(input.parse_as(ErrorCountOption::SampleEvery)?)
+        } else if ErrorCountSerializeSamplesOption::peek(input) {
+            Ok(input.parse_as(ErrorCountOption::SerializeSamples)?)
+        } else if ErrorCountLabelAliasOption::peek(input) {
+            Ok(input.parse_as(ErrorCountOption::LabelAlias)?)
+        } else if ErrorCountLabelOption::peek(input) {
+            Ok(input.parse_as(ErrorCountOption::Label)?)
+        } else if ErrorCountWrappedByOption::peek(input) {
+            Ok(input.parse_as(ErrorCountOption::WrappedBy)?)
         } else {
             let err = format!("invalid error_count option: {}", input);
             Err(input.error(err))
@@ -16,12 +16,14 @@ mod error_count_opts;
 mod measure_opts;
 mod metered;
 mod metered_opts;
+mod variant;
 
 use proc_macro::TokenStream;
 
 /// A procedural macro that generates a metric registry for an `impl` block.
 ///
-/// ```
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
 /// use metered::{metered, Throughput, HitCount};
 ///
 /// #[derive(Default, Debug)]
@@ -40,7 +42,7 @@ use proc_macro::TokenStream;
 /// #
 /// # let biz = Biz::default();
 /// # biz.biz();
-/// # assert_eq!(biz.metrics.biz.hit_count.0.get(), 1);
+/// # assert_eq!(biz.metrics.biz.hit_count.get(), 1);
 /// ```
 ///
 /// ### The `metered` attribute
@@ -53,6 +55,193 @@ use proc_macro::TokenStream;
 /// `registry_expr` defaults to `self.metrics`, alternate values must be a valid
 /// Rust expression.
 ///
+/// `visibility = pub` sets the visibility of the registry and its method
+/// sub-registries, `pub(crate)` by default. `field_visibility = pub(crate)`
+/// sets the visibility of their fields, `pub` by default, so that other crates
+/// can serialize a `pub` registry without reaching into its metrics.
+///
+/// `last_updated = true` tracks the wall-clock time of the last call to each
+/// measured method, reported by the `metered::staleness::LastUpdated` trait
+/// implemented on the registry and its method sub-registries. It is disabled by
+/// default, as it reads the system clock on every call.
+///
+/// `labels(component = "cache", region = env("REGION"))` attaches constant
+/// labels to every metric of the registry, for serializers supporting them such
+/// as `serde_prometheus`. Values are string literals, or read from an
+/// environment variable when the registry is first serialized. See
+/// `metered::labels::ConstLabels`.
+///
+/// `rename = "service"` changes the serialized name of the registry struct.
+///
+/// `skip_cleared = true` skips serializing metrics that have been cleared and
+/// not recorded since, e.g. counters at 0 or empty histograms, as well as method
+/// sub-registries and registries whose metrics are all cleared. Every measured
+/// metric must then implement `metered::clear::Clearable`, as stock metrics do.
+/// It is disabled by default.
+///
+/// `toggle = true` adds a `toggle` field to the registry and its method
+/// sub-registries, to disable and enable measuring methods at runtime, e.g. for
+/// heavy metrics. See `metered::toggle::Toggles`. It is disabled by default, as
+/// it checks toggles on every call.
+///
+/// `warn_unmeasured = true` emits a warning for each `pub` method of the `impl`
+/// block lacking a `measure` attribute, to enforce instrumentation coverage;
+/// `warn_unmeasured = deny` makes them errors. Stable proc macros cannot emit
+/// warnings, so they are reported as uses of a deprecated item. It is disabled
+/// by default.
+///
+/// `merge = true` implements `metered::merge::Merge` for the registry and its
+/// method sub-registries, to combine per-worker registries before export.
+/// Every measured metric must then implement `Merge`, as stock counters,
+/// gauges and hdr histograms do. It is disabled by default.
+///
+/// `init_fn = init_metrics` calls a function with the registry, as a
+/// `&mut YourRegistryName`, after its `Default` implementation constructed it,
+/// e.g. to wire polled gauges, set bounds or register it with a global
+/// collection. A bare name refers to an associated function of the `impl`
+/// block, other paths to any function:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{metered, ResponseTime};
+/// use std::time::Duration;
+///
+/// #[derive(Default, Debug)]
+/// pub struct Batch {
+///     metrics: BatchMetrics,
+/// }
+///
+/// #[metered(registry = BatchMetrics, init_fn = init_metrics)]
+/// impl Batch {
+///     #[measure(ResponseTime)]
+///     pub fn run(&self) {}
+///
+///     fn init_metrics(metrics: &mut BatchMetrics) {
+///         metrics.run.response_time = ResponseTime::with_bound(Duration::from_secs(10));
+///     }
+/// }
+///
+/// let batch = Batch::default();
+/// assert_eq!(batch.metrics.run.response_time.histogram().bound(), 10_000);
+/// ```
+///
+/// `inline_closure = true` forces the closure wrapping the body of measured
+/// methods, which captures early returns, to be inlined with
+/// `#[inline(always)]`. It is disabled by default, leaving inlining to the
+/// compiler.
+///
+/// `skip_nested = true` only measures the outermost call of the methods of
+/// the registry on each thread, so that measured methods calling each other
+/// are not counted twice, see `metered::nesting`. As calls are tracked per
+/// thread, it does not support async methods. It is disabled by default, and
+/// `metered::common::ExclusiveTime` instead measures nested calls separately.
+///
+/// `distributed_slice = true` registers a descriptor of the registry in
+/// `metered::discovery::REGISTRIES`, a `linkme` distributed slice letting a
+/// central exporter discover every registry of the binary. It requires the
+/// `discovery` feature of `metered`, and is disabled by default.
+///
+/// The registry of an `impl` block exposes its structure as associated
+/// constants, to enumerate series without instantiating or serializing it:
+/// `METHOD_NAMES` holds the serialized names of the measured methods, and
+/// `METRIC_PATHS` the paths of the exported metrics, as described by
+/// `metered::metadata::DescribeMetrics`:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{metered, HitCount, ResponseTime};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Store {
+///     metrics: StoreMetrics,
+/// }
+///
+/// #[metered(registry = StoreMetrics)]
+/// impl Store {
+///     #[measure([HitCount, ResponseTime])]
+///     pub fn get(&self) {}
+///
+///     #[measure(type = HitCount, rename_method = "set")]
+///     pub fn put(&self) {}
+/// }
+///
+/// assert_eq!(StoreMetrics::METHOD_NAMES, ["get", "set"]);
+/// assert_eq!(
+///     StoreMetrics::METRIC_PATHS,
+///     [["get", "hit_count"], ["get", "response_time"], ["set", "hit_count"]]
+/// );
+/// ```
+///
+/// These paths, and the paths of methods, can be cleared one at a time with
+/// `metered::clear::ClearPath`, implemented by every generated registry.
+///
+/// `#[metered(registry = YourRegistryName)]` on the struct itself declares the
+/// field holding the registry, named after `registry_expr`, so that it stays
+/// in sync with the `impl` block. The struct only takes `registry` and
+/// `registry_expr`, and the attribute must be placed above its `derive`s, for
+/// a derived `Default` to initialize the registry:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{metered, HitCount};
+///
+/// #[metered(registry = CacheMetrics)]
+/// #[derive(Default, Debug)]
+/// pub struct Cache {
+///     capacity: usize,
+/// }
+///
+/// #[metered(registry = CacheMetrics)]
+/// impl Cache {
+///     #[measure(HitCount)]
+///     pub fn get(&self) -> Option<usize> {
+///         Some(self.capacity)
+///     }
+/// }
+///
+/// let cache = Cache::default();
+/// cache.get();
+/// assert_eq!(cache.metrics.get.hit_count.get(), 1);
+/// ```
+///
+/// `#[metered(registry = YourRegistryName)]` on an inline `mod` gathers the
+/// registries of its impl blocks and free functions having `measure`
+/// attributes in one module-level registry. Each impl block contributes a
+/// sub-registry named after the snake case name of its type, and free
+/// functions a `functions` sub-registry. The registry is held by a `METRICS`
+/// static declared in the module, unless `registry_expr` points at another
+/// instance. `labels`, `toggle` and `init_fn` are not supported on modules,
+/// and other options apply to every sub-registry:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// #[metered::metered(registry = StorageMetrics)]
+/// mod storage {
+///     use metered::{HitCount, ResponseTime};
+///
+///     pub struct Index;
+///
+///     impl Index {
+///         #[measure(HitCount)]
+///         pub fn lookup(&self, key: &str) -> Option<usize> {
+///             key.find('/')
+///         }
+///     }
+///
+///     #[measure(ResponseTime)]
+///     pub fn compact() {}
+/// }
+///
+/// storage::Index.lookup("a/b");
+/// storage::compact();
+/// assert_eq!(storage::METRICS.index.lookup.hit_count.get(), 1);
+/// assert_eq!(storage::METRICS.functions.compact.response_time.histogram().len(), 1);
+/// ```
+///
+/// When the `disabled` feature of `metered` is enabled, `#[metered]` leaves
+/// methods untouched and generates an empty registry, implementing the same
+/// traits, to ship uninstrumented builds without source changes.
+///
 /// ### The `measure` attribute
 ///
 /// Single metric:
@@ -74,15 +263,232 @@ use proc_macro::TokenStream;
 /// The `type` keyword is allowed because other keywords are planned for future
 /// extra attributes (e.g, instantation options).
 ///
+/// Metrics implementing `metered::metric::Gate` can reject calls before the
+/// method body runs. The `abort` keyword provides the expression returned
+/// instead, which must have the method's return type or diverge:
+///
+/// `#[measure(type = path::to::MyGate, abort = Err(MyError::Rejected))]`
+///
+/// `#[measure(type = path::to::MyGate, abort = panic!("rejected"))]`
+///
+/// All metrics of a `measure` attribute with an `abort` option must implement
+/// `Gate`. Metrics declared after the gate wrap it, and thus still observe
+/// rejected calls.
+///
+/// The `help` keyword describes the metrics of a `measure` attribute, for
+/// exporters reading the registry's `metered::metadata::DescribeMetrics`:
+///
+/// `#[measure(type = ResponseTime, help = "Time to render a page")]`
+///
+/// Metrics are serialized under the snake case name of their type. The `rename`
+/// keyword changes the serialized name of a single metric, and `rename_method`
+/// changes the serialized name of a method's sub-registry, which defaults to
+/// the method name. `rename_method` can be used without `type`:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{metered, HitCount, ResponseTime};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Db {
+///     metrics: DbMetrics,
+/// }
+///
+/// #[metered(registry = DbMetrics)]
+/// impl Db {
+///     #[measure(rename_method = "db_query")]
+///     #[measure(HitCount)]
+///     #[measure(type = ResponseTime, rename = "db_latency")]
+///     pub fn query(&self) {}
+/// }
+///
+/// let descriptions = <DbMetrics as metered::metadata::DescribeMetrics>::describe_metrics();
+/// assert_eq!(descriptions[0].path, ["db_query", "hit_count"]);
+/// assert_eq!(descriptions[1].path, ["db_query", "db_latency"]);
+/// ```
+///
+/// The `serialize_with` keyword serializes the metrics of a `measure` attribute
+/// through a custom function, with the signature expected by serde's
+/// `serialize_with` field attribute, like `metered::error_variant_serializer`:
+///
+/// ```
+/// use metered::{metered, ResponseTime};
+///
+/// // Only export the mean response time
+/// fn serialize_mean<S: serde::Serializer>(
+///     response_time: &ResponseTime,
+///     serializer: S,
+/// ) -> Result<S::Ok, S::Error> {
+///     serializer.serialize_f64(response_time.histogram().mean())
+/// }
+///
+/// #[derive(Default, Debug)]
+/// pub struct Db {
+///     metrics: DbMetrics,
+/// }
+///
+/// #[metered(registry = DbMetrics)]
+/// impl Db {
+///     #[measure(type = ResponseTime, serialize_with = "serialize_mean")]
+///     pub fn query(&self) {}
+/// }
+/// ```
+///
+/// The `skip_serializing` flag keeps the metrics of a `measure` attribute out
+/// of the serialized registry and its descriptions, while they can still be
+/// read in code, e.g. for internal control metrics:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{metadata::DescribeMetrics, metered, HitCount, InFlight};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Breaker {
+///     metrics: BreakerMetrics,
+/// }
+///
+/// #[metered(registry = BreakerMetrics)]
+/// impl Breaker {
+///     #[measure(HitCount)]
+///     #[measure(type = InFlight, skip_serializing)]
+///     pub fn call(&self) {}
+/// }
+///
+/// let breaker = Breaker::default();
+/// breaker.call();
+/// assert_eq!(breaker.metrics.call.in_flight.get(), 0);
+/// assert_eq!(BreakerMetrics::describe_metrics().len(), 1);
+/// ```
+///
+/// The `init` keyword initializes the metric of a single-type `measure`
+/// attribute with an expression instead of `Default::default()`, either the
+/// metric itself or a builder implementing `metered::metric::MetricBuilder`:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{metered, ResponseTime};
+/// use std::time::Duration;
+///
+/// #[derive(Default, Debug)]
+/// pub struct Batch {
+///     metrics: BatchMetrics,
+/// }
+///
+/// #[metered(registry = BatchMetrics)]
+/// impl Batch {
+///     #[measure(type = ResponseTime, init = ResponseTime::with_bound(Duration::from_secs(10)))]
+///     pub fn run(&self) {}
+/// }
+///
+/// let batch = Batch::default();
+/// assert_eq!(batch.metrics.run.response_time.histogram().bound(), 10_000);
+/// ```
+///
+/// The `cfg` keyword compiles the metrics of a `measure` attribute in only if
+/// its predicate holds, e.g. to keep heavy metrics out of release builds while
+/// cheap counters remain. Their registry fields are gated the same way, and
+/// measured methods skip them otherwise:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{metered, HitCount, ResponseTime};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Render {
+///     metrics: RenderMetrics,
+/// }
+///
+/// #[metered(registry = RenderMetrics)]
+/// impl Render {
+///     #[measure(HitCount)]
+///     #[measure(cfg(debug_assertions), type = ResponseTime)]
+///     pub fn page(&self) {}
+/// }
+///
+/// let render = Render::default();
+/// render.page();
+/// assert_eq!(render.metrics.page.hit_count.get(), 1);
+/// #[cfg(debug_assertions)]
+/// assert_eq!(render.metrics.page.response_time.histogram().len(), 1);
+/// ```
+///
+/// The `record` keyword records a value derived from the result of the method
+/// instead of a duration or count, in metrics implementing
+/// `metered::metric::RecordValue` such as `metered::common::ValueHistogram`.
+/// The expression is evaluated after the method ran, with `result` bound to a
+/// reference to its result:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{common::ValueHistogram, metered};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Index {
+///     metrics: IndexMetrics,
+/// }
+///
+/// #[metered(registry = IndexMetrics)]
+/// impl Index {
+///     #[measure(type = ValueHistogram, record = result.len() as u64)]
+///     pub fn lookup(&self, key: &str) -> Vec<String> {
+///         vec![key.to_string(); 3]
+///     }
+/// }
+///
+/// let index = Index::default();
+/// index.lookup("k");
+/// assert_eq!(index.metrics.lookup.value_histogram.histogram().max(), 3);
+/// ```
+///
+/// The `by` keyword breaks the metrics of a `measure` attribute down by the
+/// variant of an argument of the method, typed with an enum deriving
+/// `metered::breakdown::Variant` or a reference to it. Each variant has its own
+/// metric, in a `metered::breakdown::Breakdown` indexed without hashing:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{breakdown::Variant, metered, ErrorCount};
+///
+/// #[derive(Variant)]
+/// pub enum Op {
+///     Read,
+///     Write,
+/// }
+///
+/// #[derive(Default, Debug)]
+/// pub struct Disk {
+///     metrics: DiskMetrics,
+/// }
+///
+/// #[metered(registry = DiskMetrics)]
+/// impl Disk {
+///     #[measure(type = ErrorCount, by = op)]
+///     pub fn io(&self, op: &Op) -> Result<(), ()> {
+///         match op {
+///             Op::Read => Ok(()),
+///             Op::Write => Err(()),
+///         }
+///     }
+/// }
+///
+/// let disk = Disk::default();
+/// let _ = disk.io(&Op::Write);
+/// assert_eq!(disk.metrics.io.error_count.by(&Op::Write).get(), 1);
+/// assert_eq!(disk.metrics.io.error_count.get("Read").unwrap().get(), 0);
+/// ```
+///
 /// When `measure` attribute is applied to an `impl` block, it applies for every
 /// method that has a `measure` attribute. If a method does not need extra
 /// measure infos, it is possible to annotate it with simply `#[measure]` and
-/// the `impl` block's `measure` configuration will be applied.
+/// the `impl` block's `measure` configuration will be applied. Metrics of the
+/// `impl` block must thus measure the results of every measured method, e.g.
+/// an error count generated by `error_count` only fits blocks whose measured
+/// methods all return its error: the compiler otherwise reports the methods it
+/// cannot measure.
 ///
 /// The `measure` keyword can be added several times on an `impl` block or
 /// method, which will add to the list of metrics applied. Adding the same
 /// metric several time will lead in a name clash.
-
 #[proc_macro_attribute]
 pub fn metered(attrs: TokenStream, item: TokenStream) -> TokenStream {
     metered::metered(attrs, item).unwrap_or_else(|e| TokenStream::from(e.to_compile_error()))
@@ -92,7 +498,8 @@ pub fn metered(attrs: TokenStream, item: TokenStream) -> TokenStream {
 /// of times each variant of an error has been thrown, to be used as
 /// crate-specific replacement for `metered::ErrorCount`.
 ///
-/// ```
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
 /// # use metered_macro::{metered, error_count};
 /// # use thiserror::Error;
 /// #
@@ -129,8 +536,19 @@ pub fn metered(attrs: TokenStream, item: TokenStream) -> TokenStream {
 /// baz.biz();
 /// assert_eq!(baz.metrics.biz.error_count.my_library.read_error.get(), 0);
 /// assert_eq!(baz.metrics.biz.error_count.my_library.init_error.get(), 1);
+///
+/// // Counts can also be listed by variant, without naming fields
+/// for (variant, count) in baz.metrics.biz.error_count.iter() {
+///     println!("{}: {}", variant, count); // e.g. "MyLibrary::InitError: 1"
+/// }
+/// assert_eq!(baz.metrics.biz.error_count.total(), 1);
 /// ```
 ///
+/// A `#[nested]` error may itself nest errors, to any depth, and may be boxed
+/// in a `Box` or `Arc`. Its counts are labeled with the path of variants
+/// leading to them, e.g. `variant="MyLibrary::InitError"` with
+/// `serde_prometheus`.
+///
 /// - `name` is required and must be a valid Rust ident, this is the name of the
 ///   generated struct containing a counter for each enum variant.
 /// - `visibility` specifies to visibility of the generated struct, it defaults
@@ -139,16 +557,292 @@ pub fn metered(attrs: TokenStream, item: TokenStream) -> TokenStream {
 ///   is entries for which the `Clearable::is_cleared` function returns true
 ///   (for counters, by default, whether they are 0). It defaults to whether the
 ///   feature `error-count-skip-cleared-by-default` is enabled. By default, this
-///   feature is disabled, and no entry will be skipped.
+///   feature is disabled, and no entry will be skipped. Nested error counts
+///   are skipped when all their entries are cleared.
+/// - `with_latency` pairs the count of each variant with a `ResponseTime` of
+///   the calls failing with it, in a `metered::common::WithLatency`. It
+///   defaults to `false`, and requires the `histograms` feature of `metered`.
+/// - `with_total` adds a `total` counter to the generated struct, counting
+///   every error alongside the counter of its variant, so that dashboards get
+///   the aggregate without summing every variant. It is serialized without
+///   label, and defaults to `false`:
+///
+/// ```
+/// # use metered_macro::error_count;
+/// # use thiserror::Error;
+/// use metered::{flatten::to_samples, ErrorBreakdownIncr};
+///
+/// #[error_count(name = NetErrorCount, visibility = pub, with_total = true)]
+/// #[derive(Debug, Error)]
+/// pub enum NetError {
+///     #[error("refused")]
+///     Refused,
+///     #[error("reset")]
+///     Reset,
+/// }
+///
+/// let count: NetErrorCount = NetErrorCount::default();
+/// count.incr(&NetError::Refused);
+/// count.incr(&NetError::Reset);
+/// assert_eq!(count.total.get(), 2);
+///
+/// let samples = to_samples(&count).unwrap();
+/// let total = samples.iter().find(|sample| sample.name == "total").unwrap();
+/// assert_eq!((total.labels.len(), total.value), (0, 2.0));
+/// ```
+///
+/// - `label` sets the key of the label carrying the variant when serialized by
+///   `serde_prometheus`, which defaults to `variant`. Nested variants are
+///   joined with `::` in the label of the outermost error count with the same
+///   key. `label_alias` instead sets the whole name of the newtype struct
+///   wrapping counts, `"!|variant[::]==<"` by default, for serializers with
+///   other conventions. They are exclusive:
+///
+/// ```
+/// # use metered_macro::error_count;
+/// # use thiserror::Error;
+/// use metered::{flatten::to_samples, ErrorBreakdownIncr};
+///
+/// #[error_count(name = LibErrorCount, visibility = pub, label = "reason")]
+/// #[derive(Debug, Error)]
+/// pub enum LibError {
+///     #[error("read error")]
+///     ReadError,
+/// }
+///
+/// let count: LibErrorCount = LibErrorCount::default();
+/// count.incr(&LibError::ReadError);
+/// let samples = to_samples(&count).unwrap();
+/// assert_eq!(samples[0].labels, [("reason".to_string(), "ReadError".to_string())]);
+/// ```
+///
+/// - `samples = N` keeps the last `N` errors of each variant, as displayed, to
+///   tell which inputs trigger it. They are read with the `samples` method of
+///   the generated struct. The enum must implement `Display`:
+///
+/// ```
+/// # use metered_macro::error_count;
+/// # use thiserror::Error;
+/// use metered::ErrorBreakdownIncr;
+///
+/// #[error_count(name = ParseErrorCount, visibility = pub, samples = 2)]
+/// #[derive(Debug, Error)]
+/// pub enum ParseError {
+///     #[error("invalid digit in {0:?}")]
+///     InvalidDigit(String),
+///     #[error("empty input")]
+///     Empty,
+/// }
+///
+/// let count: ParseErrorCount = ParseErrorCount::default();
+/// for input in ["1a", "2b", "3c"] {
+///     count.incr(&ParseError::InvalidDigit(input.to_string()));
+/// }
+/// assert_eq!(count.invalid_digit.get(), 3);
+/// assert_eq!(
+///     count.samples("InvalidDigit"),
+///     ["invalid digit in \"2b\"", "invalid digit in \"3c\""]
+/// );
+/// assert!(count.samples("Empty").is_empty());
+/// ```
+///
+/// - `sample_every = K` only samples one in every `K` errors of each variant,
+///   starting with the first one, as displaying errors and locking the samples
+///   may cost more than the errors themselves on hot paths. It defaults to 1.
+/// - `serialize_samples = true` serializes the samples of each variant under a
+///   `samples` key, e.g. for JSON snapshots. They are strings, which some
+///   formats like Prometheus' can't report, and it defaults to `false`:
+///
+/// ```
+/// # use metered_macro::error_count;
+/// # use thiserror::Error;
+/// use metered::ErrorBreakdownIncr;
+///
+/// #[error_count(
+///     name = HotErrorCount,
+///     visibility = pub,
+///     samples = 2,
+///     sample_every = 10,
+///     serialize_samples = true
+/// )]
+/// #[derive(Debug, Error)]
+/// pub enum HotError {
+///     #[error("timeout after {0}ms")]
+///     Timeout(u64),
+/// }
+///
+/// let count: HotErrorCount = HotErrorCount::default();
+/// for ms in 0..25 {
+///     count.incr(&HotError::Timeout(ms));
+/// }
+/// assert_eq!(count.samples("Timeout"), ["timeout after 10ms", "timeout after 20ms"]);
+/// assert_eq!(
+///     serde_json::to_string(&count).unwrap(),
+///     r#"{"Timeout":25,"samples":{"Timeout":["timeout after 10ms","timeout after 20ms"]}}"#
+/// );
+/// ```
+///
+/// - `wrapped_by` lists error types wrapping the enum, to also count errors of
+///   methods returning them. A `_` placeholder stands for the enum, for
+///   wrappers implementing `AsRef` to it. Other types must implement
+///   `metered::ProjectError`, as `std::io::Error` does by downcasting the error
+///   it was built from, e.g. with a `From` implementation:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// # use metered_macro::{metered, error_count};
+/// # use thiserror::Error;
+/// use std::{io, sync::Arc};
+///
+/// #[error_count(name = StoreErrorCount, visibility = pub, wrapped_by = [Arc<_>, io::Error])]
+/// #[derive(Debug, Error)]
+/// pub enum StoreError {
+///     #[error("not found")]
+///     NotFound,
+/// }
+///
+/// impl From<StoreError> for io::Error {
+///     fn from(error: StoreError) -> Self {
+///         io::Error::other(error)
+///     }
+/// }
+///
+/// #[derive(Default, Debug)]
+/// pub struct Store {
+///     metrics: StoreMetrics,
+/// }
+///
+/// #[metered(registry = StoreMetrics)]
+/// impl Store {
+///     #[measure(StoreErrorCount)]
+///     pub fn shared(&self) -> Result<(), Arc<StoreError>> {
+///         Err(Arc::new(StoreError::NotFound))
+///     }
+///
+///     #[measure(StoreErrorCount)]
+///     pub fn read(&self) -> io::Result<()> {
+///         Err(StoreError::NotFound.into())
+///     }
+/// }
+///
+/// let store = Store::default();
+/// let _ = store.shared();
+/// let _ = store.read();
+/// assert_eq!(store.metrics.shared.store_error_count.not_found.get(), 1);
+/// assert_eq!(store.metrics.read.store_error_count.not_found.get(), 1);
+/// ```
+///
+/// Besides `Result<T, E>`, the generated struct measures methods returning
+/// `Option<Result<T, E>>`, such as the items of iterators or streams, and thus
+/// `Result<Option<T>, E>` as well:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// # use metered_macro::{metered, error_count};
+/// # use thiserror::Error;
+/// #
+/// #[error_count(name = ParseErrorCount, visibility = pub)]
+/// #[derive(Debug, Error)]
+/// pub enum ParseError {
+///     #[error("invalid line")]
+///     InvalidLine,
+/// }
+///
+/// #[derive(Default, Debug)]
+/// pub struct Parser {
+///     metrics: ParserMetrics,
+/// }
 ///
+/// #[metered(registry = ParserMetrics)]
+/// impl Parser {
+///     #[measure(ParseErrorCount)]
+///     pub fn next_line(&self) -> Option<Result<String, ParseError>> {
+///         Some(Err(ParseError::InvalidLine))
+///     }
+///
+///     #[measure(ParseErrorCount)]
+///     pub fn header(&self) -> Result<Option<String>, ParseError> {
+///         Ok(None)
+///     }
+/// }
+///
+/// let parser = Parser::default();
+/// let _ = parser.next_line();
+/// let _ = parser.header();
+/// assert_eq!(parser.metrics.next_line.parse_error_count.invalid_line.get(), 1);
+/// assert_eq!(parser.metrics.header.parse_error_count.invalid_line.get(), 0);
+/// ```
+///
+/// With latencies, the count of a variant is reached through the `count` field
+/// of its `WithLatency`, and still serialized under the variant's name:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// # use metered_macro::{metered, error_count};
+/// # use thiserror::Error;
+/// #
+/// #[error_count(name = QueryErrorCount, visibility = pub, with_latency = true)]
+/// #[derive(Debug, Error)]
+/// pub enum QueryError {
+///     #[error("timeout")]
+///     Timeout,
+///     #[error("syntax error")]
+///     Syntax,
+/// }
+///
+/// #[derive(Default, Debug)]
+/// pub struct Database {
+///     metrics: DatabaseMetrics,
+/// }
+///
+/// #[metered(registry = DatabaseMetrics)]
+/// impl Database {
+///     #[measure(QueryErrorCount)]
+///     pub fn query(&self) -> Result<(), QueryError> {
+///         std::thread::sleep(std::time::Duration::from_millis(10));
+///         Err(QueryError::Timeout)
+///     }
+/// }
+///
+/// let db = Database::default();
+/// let _ = db.query();
+/// let timeouts = &db.metrics.query.query_error_count.timeout;
+/// assert_eq!(timeouts.count.get(), 1);
+/// assert!(timeouts.response_time.histogram().min() >= 10);
+/// assert_eq!(db.metrics.query.query_error_count.syntax.response_time.histogram().len(), 0);
+/// ```
 ///
 /// The `error_count` macro may only be applied to any enums that have a
 /// `std::error::Error` impl. The generated struct may then be included
 /// in `measure` attributes to measure the amount of errors returned of
 /// each variant defined in your error enum.
-
 #[proc_macro_attribute]
 pub fn error_count(attrs: TokenStream, item: TokenStream) -> TokenStream {
     error_count::error_count(attrs, item)
         .unwrap_or_else(|e| TokenStream::from(e.to_compile_error()))
 }
+
+/// A procedural macro deriving `metered::breakdown::Variant` on enums, so that
+/// metrics can be broken down by their variants with the `by` option of
+/// `measure` attributes.
+///
+/// Variants are named after their identifier, and indexed in declaration
+/// order, whatever their fields:
+///
+/// ```
+/// use metered::breakdown::Variant;
+///
+/// #[derive(Variant)]
+/// pub enum Command {
+///     Ping,
+///     Get(String),
+///     Set { key: String, value: String },
+/// }
+///
+/// assert_eq!(Command::VARIANTS, ["Ping", "Get", "Set"]);
+/// assert_eq!(Command::Get("key".to_string()).variant_index(), 1);
+/// ```
+#[proc_macro_derive(Variant)]
+pub fn variant(item: TokenStream) -> TokenStream {
+    variant::variant(item).unwrap_or_else(|e| TokenStream::from(e.to_compile_error()))
+}
@@ -13,7 +13,17 @@ use synattra::{
 pub struct MeasureRequest<'a> {
     pub tpe: &'a syn::TypePath,
     pub field_name: String,
+    #[allow(dead_code)]
     pub debug: Option<&'a InvokePath>,
+    pub abort: Option<&'a syn::Expr>,
+    pub help: Option<&'a syn::LitStr>,
+    pub rename: Option<&'a syn::LitStr>,
+    pub serialize_with: Option<&'a syn::LitStr>,
+    pub skip_serializing: bool,
+    pub init: Option<&'a syn::Expr>,
+    pub cfg: Option<&'a syn::Meta>,
+    pub record: Option<&'a syn::Expr>,
+    pub by: Option<&'a syn::Ident>,
 }
 
 impl<'a> MeasureRequest<'a> {
@@ -24,6 +34,22 @@ impl<'a> MeasureRequest<'a> {
     pub fn type_path(&self) -> &syn::TypePath {
         self.tpe
     }
+
+    /// The serialized name of the metric
+    pub fn serialized_name(&self) -> String {
+        match self.rename {
+            Some(rename) => rename.value(),
+            None => self.field_name.clone(),
+        }
+    }
+
+    /// The `#[cfg]` attribute compiling the metric in, if any
+    pub fn cfg_attr(&self) -> proc_macro2::TokenStream {
+        match self.cfg {
+            Some(cfg) => quote! { #[cfg(#cfg)] },
+            None => quote! {},
+        }
+    }
 }
 
 pub enum MeasureRequestAttribute {
@@ -38,6 +64,16 @@ impl MeasureRequestAttribute {
             MeasureRequestAttribute::NonEmpty(req) => req.to_requests(),
         }
     }
+
+    pub fn rename_method(&self) -> Option<&syn::LitStr> {
+        match self {
+            MeasureRequestAttribute::NonEmpty(NonEmptyMeasureRequestAttribute {
+                inner: Some(MeasureRequestAttributeInner::KeyVal(key_val)),
+                ..
+            }) => key_val.rename_method(),
+            _ => None,
+        }
+    }
 }
 
 impl Parse for MeasureRequestAttribute {
@@ -53,6 +89,7 @@ impl Parse for MeasureRequestAttribute {
 }
 
 pub struct NonEmptyMeasureRequestAttribute {
+    #[allow(dead_code)]
     pub paren_token: syn::token::Paren,
     pub inner: Option<MeasureRequestAttributeInner>,
 }
@@ -100,12 +137,24 @@ impl MeasureRequestAttributeInner {
 
 impl Parse for MeasureRequestAttributeInner {
     fn parse(input: ParseStream<'_>) -> Result<Self> {
+        // Options other than `type` would otherwise parse as a type path
+        if input.peek2(Token![=]) || MeasureCfgOption::peek(input) {
+            return input.parse_as(MeasureRequestAttributeInner::KeyVal);
+        }
+
         input
             .try_parse_as(MeasureRequestAttributeInner::TypePath)
-            .or_else(|_| input.try_parse_as(MeasureRequestAttributeInner::KeyVal))
-            .map_err(|_| {
-                let err = format!("invalid format for measure attribute: {}", input);
-                input.error(err)
+            .or_else(|type_path_err| {
+                input
+                    .try_parse_as(MeasureRequestAttributeInner::KeyVal)
+                    .map_err(|key_val_err| {
+                        // Report the error of the format the user most likely meant
+                        if MeasureOptions::peek(input) {
+                            key_val_err
+                        } else {
+                            type_path_err
+                        }
+                    })
             })
     }
 }
@@ -123,6 +172,15 @@ impl MeasureRequestTypePathAttribute {
                 tpe: type_path,
                 field_name,
                 debug: None,
+                abort: None,
+                help: None,
+                rename: None,
+                serialize_with: None,
+                skip_serializing: false,
+                init: None,
+                cfg: None,
+                record: None,
+                by: None,
             })
         }
         v
@@ -143,7 +201,8 @@ pub struct MeasureRequestKeyValAttribute {
 
 impl MeasureRequestKeyValAttribute {
     fn validate(&self, input: ParseStream<'_>) -> Result<()> {
-        self.values
+        let type_paths = self
+            .values
             .iter()
             .filter_map(|opt| {
                 if let MeasureOptions::Type(tpe) = opt {
@@ -152,12 +211,25 @@ impl MeasureRequestKeyValAttribute {
                     None
                 }
             })
-            .next()
-            .ok_or_else(|| {
-                input.error(
+            .next();
+
+        match type_paths {
+            None if self.rename_method().is_none() => {
+                return Err(input.error(
                     "missing `type` attribute with a path to a valid metered::Metric struct.",
-                )
-            })?;
+                ));
+            }
+            Some(type_paths) if type_paths.iter().count() > 1 && self.rename().is_some() => {
+                return Err(input.error("`rename` requires a single metric `type`."));
+            }
+            Some(type_paths) if type_paths.iter().count() > 1 && self.init().is_some() => {
+                return Err(input.error("`init` requires a single metric `type`."));
+            }
+            Some(_) if self.init().is_some() && self.by().is_some() => {
+                return Err(input.error("`init` cannot be combined with `by`."));
+            }
+            _ => {}
+        }
 
         let opt_types: std::collections::HashMap<_, _> = self
             .values
@@ -166,24 +238,74 @@ impl MeasureRequestKeyValAttribute {
             .collect();
 
         for (opt_type, opt_name) in opt_types.iter() {
-            let count = self
+            let duplicate = self
                 .values
                 .iter()
                 .filter(|&opt| std::mem::discriminant(opt) == *opt_type)
-                .count();
-            if count > 1 {
-                let error = format!("`{}` attribute is defined more than once.", opt_name);
-                return Err(input.error(error));
+                .nth(1);
+            if let Some(duplicate) = duplicate {
+                let error = format!("{} attribute is defined more than once.", opt_name);
+                return Err(syn::Error::new(duplicate.span(), error));
             }
         }
 
-        // self.values.iter().
-
         Ok(())
     }
 
+    fn rename(&self) -> Option<&syn::LitStr> {
+        self.values
+            .iter()
+            .filter_map(|opt| {
+                if let MeasureOptions::Rename(rename) = opt {
+                    Some(&rename.value)
+                } else {
+                    None
+                }
+            })
+            .next()
+    }
+
+    fn init(&self) -> Option<&syn::Expr> {
+        self.values
+            .iter()
+            .filter_map(|opt| {
+                if let MeasureOptions::Init(init) = opt {
+                    Some(&init.value)
+                } else {
+                    None
+                }
+            })
+            .next()
+    }
+
+    fn by(&self) -> Option<&syn::Ident> {
+        self.values
+            .iter()
+            .filter_map(|opt| {
+                if let MeasureOptions::By(by) = opt {
+                    Some(&by.value)
+                } else {
+                    None
+                }
+            })
+            .next()
+    }
+
+    fn rename_method(&self) -> Option<&syn::LitStr> {
+        self.values
+            .iter()
+            .filter_map(|opt| {
+                if let MeasureOptions::RenameMethod(rename) = opt {
+                    Some(&rename.value)
+                } else {
+                    None
+                }
+            })
+            .next()
+    }
+
     pub fn to_requests(&self) -> Vec<MeasureRequest<'_>> {
-        let type_paths = self
+        let type_paths = match self
             .values
             .iter()
             .filter_map(|opt| {
@@ -194,7 +316,11 @@ impl MeasureRequestKeyValAttribute {
                 }
             })
             .next()
-            .expect("There should be a type! This error cannot happen if the structure has been validated first!");
+        {
+            Some(type_paths) => type_paths,
+            // Only allowed for attributes renaming the method sub-registry
+            None => return Vec::new(),
+        };
         let debug = self
             .values
             .iter()
@@ -206,6 +332,67 @@ impl MeasureRequestKeyValAttribute {
                 }
             })
             .next();
+        let abort = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeasureOptions::Abort(abort) = opt {
+                    Some(&abort.value)
+                } else {
+                    None
+                }
+            })
+            .next();
+        let help = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeasureOptions::Help(help) = opt {
+                    Some(&help.value)
+                } else {
+                    None
+                }
+            })
+            .next();
+        let rename = self.rename();
+        let serialize_with = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeasureOptions::SerializeWith(serialize_with) = opt {
+                    Some(&serialize_with.value)
+                } else {
+                    None
+                }
+            })
+            .next();
+        let skip_serializing = self
+            .values
+            .iter()
+            .any(|opt| matches!(opt, MeasureOptions::SkipSerializing(_)));
+        let init = self.init();
+        let cfg = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeasureOptions::Cfg(cfg) = opt {
+                    Some(&cfg.predicate)
+                } else {
+                    None
+                }
+            })
+            .next();
+        let record = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeasureOptions::Record(record) = opt {
+                    Some(&record.value)
+                } else {
+                    None
+                }
+            })
+            .next();
 
         let mut v = Vec::new();
         for type_path in type_paths.iter() {
@@ -214,6 +401,15 @@ impl MeasureRequestKeyValAttribute {
                 tpe: type_path,
                 field_name,
                 debug,
+                abort,
+                help,
+                rename,
+                serialize_with,
+                skip_serializing,
+                init,
+                cfg,
+                record,
+                by: self.by(),
             })
         }
         v
@@ -246,22 +442,138 @@ impl Parse for MeasureRequestKeyValAttribute {
 
 mod kw {
     syn::custom_keyword!(debug);
+    syn::custom_keyword!(abort);
+    syn::custom_keyword!(help);
+    syn::custom_keyword!(rename);
+    syn::custom_keyword!(rename_method);
+    syn::custom_keyword!(serialize_with);
+    syn::custom_keyword!(skip_serializing);
+    syn::custom_keyword!(init);
+    syn::custom_keyword!(cfg);
+    syn::custom_keyword!(record);
+    syn::custom_keyword!(by);
 }
 
 pub type MeasureTypeOption = KVOption<syn::Token![type], MultipleVal<syn::TypePath>>;
 pub type MeasureDebugOption = KVOption<kw::debug, InvokePath>;
+pub type MeasureAbortOption = KVOption<kw::abort, syn::Expr>;
+pub type MeasureHelpOption = KVOption<kw::help, syn::LitStr>;
+pub type MeasureRenameOption = KVOption<kw::rename, syn::LitStr>;
+pub type MeasureRenameMethodOption = KVOption<kw::rename_method, syn::LitStr>;
+pub type MeasureSerializeWithOption = KVOption<kw::serialize_with, syn::LitStr>;
+pub type MeasureInitOption = KVOption<kw::init, syn::Expr>;
+pub type MeasureRecordOption = KVOption<kw::record, syn::Expr>;
+pub type MeasureByOption = KVOption<kw::by, syn::Ident>;
+
+/// `skip_serializing`, a flag without value
+pub struct MeasureSkipSerializingOption {
+    pub skip_serializing_token: kw::skip_serializing,
+}
+
+impl MeasureSkipSerializingOption {
+    pub fn peek(input: ParseStream<'_>) -> bool {
+        input.peek(kw::skip_serializing)
+    }
+}
+
+impl Parse for MeasureSkipSerializingOption {
+    fn parse(input: ParseStream<'_>) -> Result<Self> {
+        Ok(MeasureSkipSerializingOption {
+            skip_serializing_token: input.parse()?,
+        })
+    }
+}
+
+/// `cfg(feature = "detailed-metrics")`, compiling metrics in only if the
+/// predicate holds
+pub struct MeasureCfgOption {
+    pub cfg_token: kw::cfg,
+    #[allow(dead_code)]
+    pub paren_token: syn::token::Paren,
+    pub predicate: syn::Meta,
+}
+
+impl MeasureCfgOption {
+    pub fn peek(input: ParseStream<'_>) -> bool {
+        input.peek(kw::cfg)
+    }
+}
+
+impl Parse for MeasureCfgOption {
+    fn parse(input: ParseStream<'_>) -> Result<Self> {
+        let content;
+        Ok(MeasureCfgOption {
+            cfg_token: input.parse()?,
+            paren_token: parenthesized!(content in input),
+            predicate: content.parse()?,
+        })
+    }
+}
 
 pub enum MeasureOptions {
     Type(MeasureTypeOption),
     Debug(MeasureDebugOption),
+    Abort(MeasureAbortOption),
+    Help(MeasureHelpOption),
+    Rename(MeasureRenameOption),
+    RenameMethod(MeasureRenameMethodOption),
+    SerializeWith(MeasureSerializeWithOption),
+    SkipSerializing(MeasureSkipSerializingOption),
+    Init(MeasureInitOption),
+    Cfg(MeasureCfgOption),
+    Record(MeasureRecordOption),
+    By(MeasureByOption),
 }
 
 impl MeasureOptions {
+    pub fn peek(input: ParseStream<'_>) -> bool {
+        MeasureTypeOption::peek(input)
+            || MeasureDebugOption::peek(input)
+            || MeasureAbortOption::peek(input)
+            || MeasureHelpOption::peek(input)
+            || MeasureRenameMethodOption::peek(input)
+            || MeasureRenameOption::peek(input)
+            || MeasureSerializeWithOption::peek(input)
+            || MeasureSkipSerializingOption::peek(input)
+            || MeasureInitOption::peek(input)
+            || MeasureCfgOption::peek(input)
+            || MeasureRecordOption::peek(input)
+            || MeasureByOption::peek(input)
+    }
+
+    /// The span of the option's key
+    pub fn span(&self) -> proc_macro2::Span {
+        match self {
+            MeasureOptions::Type(opt) => opt.key.span,
+            MeasureOptions::Debug(opt) => opt.key.span,
+            MeasureOptions::Abort(opt) => opt.key.span,
+            MeasureOptions::Help(opt) => opt.key.span,
+            MeasureOptions::Rename(opt) => opt.key.span,
+            MeasureOptions::RenameMethod(opt) => opt.key.span,
+            MeasureOptions::SerializeWith(opt) => opt.key.span,
+            MeasureOptions::SkipSerializing(opt) => opt.skip_serializing_token.span,
+            MeasureOptions::Init(opt) => opt.key.span,
+            MeasureOptions::Cfg(opt) => opt.cfg_token.span,
+            MeasureOptions::Record(opt) => opt.key.span,
+            MeasureOptions::By(opt) => opt.key.span,
+        }
+    }
+
     pub fn as_str(&self) -> &str {
         use syn::token::Token;
         match self {
             MeasureOptions::Type(_) => <syn::Token![type]>::display(),
             MeasureOptions::Debug(_) => <kw::debug>::display(),
+            MeasureOptions::Abort(_) => <kw::abort>::display(),
+            MeasureOptions::Help(_) => <kw::help>::display(),
+            MeasureOptions::Rename(_) => <kw::rename>::display(),
+            MeasureOptions::RenameMethod(_) => <kw::rename_method>::display(),
+            MeasureOptions::SerializeWith(_) => <kw::serialize_with>::display(),
+            MeasureOptions::SkipSerializing(_) => <kw::skip_serializing>::display(),
+            MeasureOptions::Init(_) => <kw::init>::display(),
+            MeasureOptions::Cfg(_) => <kw::cfg>::display(),
+            MeasureOptions::Record(_) => <kw::record>::display(),
+            MeasureOptions::By(_) => <kw::by>::display(),
         }
     }
 }
@@ -272,9 +584,35 @@ impl Parse for MeasureOptions {
             Ok(input.parse_as(MeasureOptions::Type)?)
         } else if MeasureDebugOption::peek(input) {
             Ok(input.parse_as(MeasureOptions::Debug)?)
+        } else if MeasureAbortOption::peek(input) {
+            Ok(input.parse_as(MeasureOptions::Abort)?)
+        } else if MeasureHelpOption::peek(input) {
+            Ok(input.parse_as(MeasureOptions::Help)?)
+        } else if MeasureRenameMethodOption::peek(input) {
+            Ok(input.parse_as(MeasureOptions::RenameMethod)?)
+        } else if MeasureRenameOption::peek(input) {
+            Ok(input.parse_as(MeasureOptions::Rename)?)
+        } else if MeasureSerializeWithOption::peek(input) {
+            Ok(input.parse_as(MeasureOptions::SerializeWith)?)
+        } else if MeasureSkipSerializingOption::peek(input) {
+            Ok(input.parse_as(MeasureOptions::SkipSerializing)?)
+        } else if MeasureInitOption::peek(input) {
+            Ok(input.parse_as(MeasureOptions::Init)?)
+        } else if MeasureCfgOption::peek(input) {
+            Ok(input.parse_as(MeasureOptions::Cfg)?)
+        } else if MeasureRecordOption::peek(input) {
+            Ok(input.parse_as(MeasureOptions::Record)?)
+        } else if MeasureByOption::peek(input) {
+            Ok(input.parse_as(MeasureOptions::By)?)
         } else {
-            let err = format!("invalid measure option: {}", input);
-            Err(input.error(err))
+            let token: proc_macro2::TokenTree = input.parse()?;
+            let err = format!(
+                "unknown measure option `{}`, expected one of `type`, `debug`, `abort`, \
+                 `help`, `rename`, `rename_method`, `serialize_with`, `skip_serializing`, `init`, \
+                 `cfg`, `record` or `by`",
+                token
+            );
+            Err(syn::Error::new(token.span(), err))
         }
     }
 }
@@ -2,13 +2,30 @@
 
 use proc_macro::TokenStream;
 
-use crate::{measure_opts::MeasureRequestAttribute, metered_opts::MeteredKeyValAttribute};
+use crate::{
+    measure_opts::{MeasureRequest, MeasureRequestAttribute},
+    metered_opts::{
+        ConstLabelValue, Metered, MeteredKeyValAttribute, MeteredLabelsOption, MeteredOption,
+        UnmeasuredLint,
+    },
+};
 
 use aspect_weave::*;
 use std::rc::Rc;
 use synattra::ParseAttributes;
 
 pub fn metered(attrs: TokenStream, item: TokenStream) -> syn::Result<TokenStream> {
+    if let Ok(item_struct) = syn::parse::<syn::ItemStruct>(item.clone()) {
+        return metered_struct(attrs, item_struct);
+    }
+    if let Ok(item_mod) = syn::parse::<syn::ItemMod>(item.clone()) {
+        return metered_mod(attrs, item_mod);
+    }
+
+    if cfg!(feature = "disabled") {
+        return disabled_metered(attrs, item);
+    }
+
     let woven_impl_block = weave_impl_block::<MeteredWeave>(attrs, item)?;
 
     let impl_block = &woven_impl_block.woven_block;
@@ -17,13 +34,31 @@ pub fn metered(attrs: TokenStream, item: TokenStream) -> syn::Result<TokenStream
     let registry_name = &metered.registry_name;
     let registry_ident = &metered.registry_ident;
     let visibility = &metered.visibility;
+    let field_visibility = &metered.field_visibility;
 
-    let mut code = quote! {};
+    let mut code = unmeasured_lint(impl_block, metered.warn_unmeasured, |ident| {
+        measured.contains_key(ident)
+    })?;
 
     let mut reg_fields = quote! {};
+    let mut reg_inits = quote! {};
     let mut reg_clears = quote! {};
+    let mut reg_last_updated = quote! {};
+    let mut reg_descriptions = quote! {};
+    let mut reg_cleared = quote! { true };
+    let mut reg_merges = quote! {};
+    let mut reg_method_toggles = quote! {};
+    let mut reg_clear_paths = quote! {};
+    let mut reg_method_names = quote! {};
+    let mut reg_metric_paths = quote! {};
+
+    let skip_cleared = if metered.skip_cleared {
+        quote! { #[serde(skip_serializing_if = "metered::clear::Clearable::is_cleared")] }
+    } else {
+        quote! {}
+    };
 
-    for (fun_name, _) in measured.iter() {
+    for (fun_name, measure_request_attrs) in measured.iter() {
         use heck::ToUpperCamelCase;
         let fun_reg_name = format!(
             "{}{}",
@@ -32,34 +67,255 @@ pub fn metered(attrs: TokenStream, item: TokenStream) -> syn::Result<TokenStream
         );
         let fun_registry_ident = syn::Ident::new(&fun_reg_name, impl_block.impl_token.span);
 
+        let serialize_with = if metered.labels.is_some() {
+            let path = format!(
+                "metered::labels::serialize_labeled::<{}, _, _>",
+                registry_ident
+            );
+            quote! { #[serde(serialize_with = #path)] }
+        } else {
+            quote! {}
+        };
+
+        let fun_serialized_name = measure_request_attrs
+            .iter()
+            .find_map(|attr| attr.rename_method())
+            .map(syn::LitStr::value)
+            .unwrap_or_else(|| fun_name.to_string());
+
         reg_fields = quote! {
             #reg_fields
-            pub #fun_name : #fun_registry_ident,
+            #serialize_with
+            #skip_cleared
+            #[serde(rename = #fun_serialized_name)]
+            #field_visibility #fun_name : #fun_registry_ident,
+        };
+
+        reg_inits = quote! {
+            #reg_inits
+            #fun_name: std::default::Default::default(),
         };
 
         reg_clears = quote! {
             #reg_clears
             self.#fun_name.clear();
         };
+
+        reg_cleared = quote! {
+            #reg_cleared && metered::clear::Clearable::is_cleared(&self.#fun_name)
+        };
+
+        reg_merges = quote! {
+            #reg_merges
+            metered::merge::Merge::merge_from(&self.#fun_name, &other.#fun_name);
+        };
+
+        reg_descriptions = quote! {
+            #reg_descriptions
+            for mut description in <#fun_registry_ident as metered::metadata::DescribeMetrics>::describe_metrics() {
+                description.path.insert(0, #fun_serialized_name);
+                descriptions.push(description);
+            }
+        };
+
+        reg_last_updated = quote! {
+            #reg_last_updated
+            let last_updated = last_updated
+                .max(metered::staleness::LastUpdated::last_updated(&self.#fun_name));
+        };
+
+        reg_method_toggles = quote! {
+            #reg_method_toggles
+            #fun_serialized_name => Some(&self.#fun_name.toggle),
+        };
+
+        reg_clear_paths = quote! {
+            #reg_clear_paths
+            #fun_serialized_name => metered::clear::ClearPath::clear_path(&self.#fun_name, rest),
+        };
+
+        reg_method_names = quote! {
+            #reg_method_names
+            #fun_serialized_name,
+        };
+
+        for metric in measure_request_attrs
+            .iter()
+            .flat_map(|attr| attr.to_requests())
+            .filter(|metric| !metric.skip_serializing)
+        {
+            let metric_serialized_name = metric.serialized_name();
+            let metric_cfg = metric.cfg_attr();
+            reg_metric_paths = quote! {
+                #reg_metric_paths
+                #metric_cfg
+                &[#fun_serialized_name, #metric_serialized_name],
+            };
+        }
+    }
+
+    if metered.toggle {
+        reg_fields = quote! {
+            #reg_fields
+            #[serde(skip)]
+            #field_visibility toggle: metered::toggle::Toggle,
+        };
+
+        reg_inits = quote! {
+            #reg_inits
+            toggle: std::default::Default::default(),
+        };
     }
 
+    let registry_rename = metered
+        .rename
+        .map(|rename| quote! { #[serde(rename = #rename)] });
+
+    let (registry_derive, registry_default) = match metered.init_fn {
+        Some(init_fn) => (
+            quote! { #[derive(Debug, serde::Serialize)] },
+            registry_default_impl(impl_block, registry_ident, reg_inits, init_fn),
+        ),
+        None => (
+            quote! { #[derive(Debug, Default, serde::Serialize)] },
+            quote! {},
+        ),
+    };
+
     code = quote! {
         #code
 
-        #[derive(Debug, Default, serde::Serialize)]
+        #registry_derive
         #[allow(missing_docs)]
+        #registry_rename
         #visibility struct #registry_ident {
             #reg_fields
         }
 
+        #registry_default
+
+        impl #registry_ident {
+            /// The serialized names of the measured methods
+            pub const METHOD_NAMES: &'static [&'static str] = &[#reg_method_names];
+
+            /// The paths of the exported metrics, as described by
+            /// `metered::metadata::DescribeMetrics`
+            pub const METRIC_PATHS: &'static [&'static [&'static str]] = &[#reg_metric_paths];
+        }
 
         impl metered::clear::Clear for #registry_ident {
             fn clear(&self) {
                 #reg_clears
             }
         }
+
+        impl metered::clear::ClearPath for #registry_ident {
+            #[allow(unused_variables)]
+            fn clear_path(&self, path: &[&str]) -> bool {
+                match path {
+                    [] => {
+                        metered::clear::Clear::clear(self);
+                        true
+                    }
+                    [method, rest @ ..] => match *method {
+                        #reg_clear_paths
+                        _ => false,
+                    },
+                }
+            }
+        }
+
+        impl metered::metadata::DescribeMetrics for #registry_ident {
+            fn describe_metrics() -> Vec<metered::metadata::MetricDescription> {
+                let mut descriptions = Vec::new();
+                #reg_descriptions
+                descriptions
+            }
+        }
+
+        impl std::fmt::Display for #registry_ident {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                std::fmt::Display::fmt(&metered::pretty::Pretty::new(self), f)
+            }
+        }
     };
 
+    if let Some(labels) = metered.labels {
+        let const_labels = const_labels_impl(registry_ident, labels);
+        code = quote! {
+            #code
+
+            #const_labels
+        };
+    }
+
+    if metered.skip_cleared {
+        code = quote! {
+            #code
+
+            impl metered::clear::Clearable for #registry_ident {
+                fn is_cleared(&self) -> bool {
+                    #reg_cleared
+                }
+            }
+        };
+    }
+
+    if metered.toggle {
+        code = quote! {
+            #code
+
+            impl metered::toggle::Toggles for #registry_ident {
+                fn toggle(&self) -> &metered::toggle::Toggle {
+                    &self.toggle
+                }
+
+                fn method_toggle(&self, method: &str) -> Option<&metered::toggle::Toggle> {
+                    match method {
+                        #reg_method_toggles
+                        _ => None,
+                    }
+                }
+            }
+        };
+    }
+
+    if metered.merge {
+        code = quote! {
+            #code
+
+            impl metered::merge::Merge for #registry_ident {
+                fn merge_from(&self, other: &Self) {
+                    #reg_merges
+                }
+            }
+        };
+    }
+
+    if metered.last_updated {
+        code = quote! {
+            #code
+
+            impl metered::staleness::LastUpdated for #registry_ident {
+                fn last_updated(&self) -> Option<std::time::SystemTime> {
+                    // `None` is lower than any `Some`, so `max` skips it
+                    let last_updated = None;
+                    #reg_last_updated
+                    last_updated
+                }
+            }
+        };
+    }
+
+    if metered.distributed_slice {
+        let descriptor = registry_descriptor(metered, None);
+        code = quote! {
+            #code
+
+            #descriptor
+        };
+    }
+
     drop(reg_fields);
 
     for (fun_name, measure_request_attrs) in measured.iter() {
@@ -71,43 +327,238 @@ pub fn metered(attrs: TokenStream, item: TokenStream) -> syn::Result<TokenStream
         );
         let fun_registry_ident = syn::Ident::new(&fun_reg_name, impl_block.impl_token.span);
 
+        let fun_sig = impl_block.items.iter().find_map(|item| match item {
+            syn::ImplItem::Method(method) if method.sig.ident == *fun_name => Some(&method.sig),
+            _ => None,
+        });
+
         let mut fun_reg_fields = quote! {};
         let mut fun_reg_clears = quote! {};
+        let mut fun_reg_clear_paths = quote! {};
+        let mut fun_reg_descriptions = quote! {};
+        let mut fun_reg_cleared = quote! { true };
+        let mut fun_reg_merges = quote! {};
+        let mut fun_reg_inits = quote! {};
+
+        if metered.last_updated {
+            fun_reg_fields = quote! {
+                #[serde(skip)]
+                #field_visibility last_updated: metered::common::LastCalled,
+            };
+
+            fun_reg_inits = quote! {
+                last_updated: std::default::Default::default(),
+            };
+
+            fun_reg_merges = quote! {
+                metered::merge::Merge::merge_from(&self.last_updated, &other.last_updated);
+            };
+        }
+
+        if metered.toggle {
+            fun_reg_fields = quote! {
+                #fun_reg_fields
+                #[serde(skip)]
+                #field_visibility toggle: metered::toggle::Toggle,
+            };
+
+            fun_reg_inits = quote! {
+                #fun_reg_inits
+                toggle: std::default::Default::default(),
+            };
+        }
 
         for measure_req_attr in measure_request_attrs.iter() {
             let metric_requests = measure_req_attr.to_requests();
 
             for metric in metric_requests.iter() {
                 let metric_field = metric.ident();
-                let metric_type = metric.type_path();
+                let metric_serialized_name = metric.serialized_name();
+                let metric_type = metric_field_type(metric, fun_sig);
+                let metric_cfg = metric.cfg_attr();
+
+                fun_reg_merges = quote! {
+                    #fun_reg_merges
+                    #metric_cfg
+                    metered::merge::Merge::merge_from(&self.#metric_field, &other.#metric_field);
+                };
+
+                fun_reg_inits = match metric.init {
+                    Some(init) => quote! {
+                        #fun_reg_inits
+                        #metric_cfg
+                        #metric_field: metered::metric::MetricBuilder::<#metric_type>::build_metric(#init),
+                    },
+                    None => quote! {
+                        #fun_reg_inits
+                        #metric_cfg
+                        #metric_field: std::default::Default::default(),
+                    },
+                };
+
+                let metric_metadata = match metric.help {
+                    Some(help) => quote! {
+                        (&MetadataOf::<#metric_type>::new()).metric_metadata().with_help(#help)
+                    },
+                    None => quote! {
+                        (&MetadataOf::<#metric_type>::new()).metric_metadata()
+                    },
+                };
+
+                if metric.skip_serializing {
+                    fun_reg_fields = quote! {
+                        #fun_reg_fields
+                        #metric_cfg
+                        #[serde(skip)]
+                        #field_visibility #metric_field : #metric_type,
+                    };
+
+                    fun_reg_clears = quote! {
+                        #fun_reg_clears
+                        #metric_cfg
+                        self.#metric_field.clear();
+                    };
+
+                    // Not exported, so neither cleared nor described
+                    continue;
+                }
+
+                let metric_serialize_with = metric
+                    .serialize_with
+                    .map(|path| quote! { #[serde(serialize_with = #path)] });
 
                 fun_reg_fields = quote! {
                     #fun_reg_fields
-                    pub #metric_field : #metric_type,
+                    #metric_cfg
+                    #skip_cleared
+                    #metric_serialize_with
+                    #[serde(rename = #metric_serialized_name)]
+                    #field_visibility #metric_field : #metric_type,
                 };
 
                 fun_reg_clears = quote! {
                     #fun_reg_clears
+                    #metric_cfg
                     self.#metric_field.clear();
                 };
+
+                fun_reg_clear_paths = quote! {
+                    #fun_reg_clear_paths
+                    #metric_cfg
+                    [#metric_serialized_name] => metered::clear::Clear::clear(&self.#metric_field),
+                };
+
+                fun_reg_cleared = match metric.cfg {
+                    Some(cfg) => quote! {
+                        #fun_reg_cleared && {
+                            #[cfg(#cfg)]
+                            let cleared = metered::clear::Clearable::is_cleared(&self.#metric_field);
+                            #[cfg(not(#cfg))]
+                            let cleared = true;
+                            cleared
+                        }
+                    },
+                    None => quote! {
+                        #fun_reg_cleared && metered::clear::Clearable::is_cleared(&self.#metric_field)
+                    },
+                };
+
+                fun_reg_descriptions = quote! {
+                    #fun_reg_descriptions
+                    #metric_cfg
+                    metered::metadata::MetricDescription {
+                        path: vec![#metric_serialized_name],
+                        metadata: #metric_metadata,
+                    },
+                };
             }
         }
 
         code = quote! {
             #code
 
-            #[derive(Debug, Default, serde::Serialize)]
+            #[derive(Debug, serde::Serialize)]
             #[allow(missing_docs)]
             #visibility struct #fun_registry_ident {
                 #fun_reg_fields
             }
 
+            impl Default for #fun_registry_ident {
+                fn default() -> Self {
+                    #fun_registry_ident {
+                        #fun_reg_inits
+                    }
+                }
+            }
+
             impl metered::clear::Clear for #fun_registry_ident {
                 fn clear(&self) {
                     #fun_reg_clears
                 }
             }
+
+            impl metered::clear::ClearPath for #fun_registry_ident {
+                fn clear_path(&self, path: &[&str]) -> bool {
+                    match path {
+                        [] => metered::clear::Clear::clear(self),
+                        #fun_reg_clear_paths
+                        _ => return false,
+                    }
+                    true
+                }
+            }
+
+            impl metered::metadata::DescribeMetrics for #fun_registry_ident {
+                fn describe_metrics() -> Vec<metered::metadata::MetricDescription> {
+                    #[allow(unused_imports)]
+                    use metered::metadata::__private::{MetadataOf, ViaDefault, ViaDescribe};
+
+                    vec![#fun_reg_descriptions]
+                }
+            }
+
+            impl std::fmt::Display for #fun_registry_ident {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    std::fmt::Display::fmt(&metered::pretty::Pretty::new(self), f)
+                }
+            }
         };
+
+        if metered.skip_cleared {
+            code = quote! {
+                #code
+
+                impl metered::clear::Clearable for #fun_registry_ident {
+                    fn is_cleared(&self) -> bool {
+                        #fun_reg_cleared
+                    }
+                }
+            };
+        }
+
+        if metered.merge {
+            code = quote! {
+                #code
+
+                impl metered::merge::Merge for #fun_registry_ident {
+                    fn merge_from(&self, other: &Self) {
+                        #fun_reg_merges
+                    }
+                }
+            };
+        }
+
+        if metered.last_updated {
+            code = quote! {
+                #code
+
+                impl metered::staleness::LastUpdated for #fun_registry_ident {
+                    fn last_updated(&self) -> Option<std::time::SystemTime> {
+                        metered::staleness::LastUpdated::last_updated(&self.last_updated)
+                    }
+                }
+            };
+        }
     }
 
     code = quote! {
@@ -121,6 +572,809 @@ pub fn metered(attrs: TokenStream, item: TokenStream) -> syn::Result<TokenStream
     Ok(result)
 }
 
+/// On a struct, declares the field holding the registry, named after the
+/// `registry_expr` of the `impl` block.
+fn metered_struct(
+    attrs: TokenStream,
+    mut item_struct: syn::ItemStruct,
+) -> syn::Result<TokenStream> {
+    let main_attributes = syn::parse::<MeteredKeyValAttribute>(attrs)?;
+    if let Some(opt) = main_attributes.values.iter().find(|opt| {
+        !matches!(
+            opt,
+            MeteredOption::Registry(_) | MeteredOption::RegistryExpr(_)
+        )
+    }) {
+        let err = format!(
+            "{} only applies to `#[metered]` on an impl block, a struct only takes \
+             `registry` and `registry_expr`",
+            opt.as_str()
+        );
+        return Err(syn::Error::new(opt.span(), err));
+    }
+
+    let metered = main_attributes.to_metered();
+    let registry_ident = metered.registry_ident;
+    let registry_expr = &metered.registry_expr;
+    let field_ident = match &**registry_expr {
+        syn::Expr::Field(syn::ExprField {
+            base,
+            member: syn::Member::Named(field_ident),
+            ..
+        }) if matches!(&**base, syn::Expr::Path(path) if path.path.is_ident("self")) => field_ident,
+        _ => {
+            let err = "`registry_expr` must be a field of `self`, e.g. `self.metrics`, to \
+                       declare it on a struct";
+            return Err(syn::Error::new_spanned(registry_expr, err));
+        }
+    };
+
+    let fields = match item_struct.fields {
+        syn::Fields::Named(ref mut fields) => fields,
+        _ => {
+            let err = "`#[metered]` only declares registry fields on structs with named fields";
+            return Err(syn::Error::new_spanned(&item_struct.ident, err));
+        }
+    };
+    if let Some(field) = fields
+        .named
+        .iter()
+        .find(|field| field.ident.as_ref() == Some(field_ident))
+    {
+        let err = format!(
+            "field `{}` is already declared, remove it to let `#[metered]` declare it",
+            field_ident
+        );
+        return Err(syn::Error::new_spanned(field, err));
+    }
+
+    let field = syn::parse::Parser::parse2(
+        syn::Field::parse_named,
+        quote! { #field_ident: #registry_ident },
+    )?;
+    fields.named.push(field);
+
+    Ok(quote! { #item_struct }.into())
+}
+
+/// On an inline module, gathers the registries of its measured impl blocks
+/// and free functions in a module-level registry, held by a `METRICS` static
+/// unless `registry_expr` points elsewhere.
+fn metered_mod(attrs: TokenStream, item_mod: syn::ItemMod) -> syn::Result<TokenStream> {
+    let main_attributes = syn::parse::<MeteredKeyValAttribute>(attrs)?;
+    if let Some(opt) = main_attributes.values.iter().find(|opt| {
+        matches!(
+            opt,
+            MeteredOption::Labels(_) | MeteredOption::Toggle(_) | MeteredOption::InitFn(_)
+        )
+    }) {
+        let err = format!(
+            "{} is not supported by `#[metered]` on a module",
+            opt.as_str()
+        );
+        return Err(syn::Error::new(opt.span(), err));
+    }
+
+    let metered = main_attributes.to_metered();
+    let registry_ident = metered.registry_ident;
+    let visibility = &metered.visibility;
+    let field_visibility = &metered.field_visibility;
+    let items = match item_mod.content {
+        Some((_, ref items)) => items,
+        None => {
+            let err = "`#[metered]` only applies to inline modules";
+            return Err(syn::Error::new_spanned(&item_mod.ident, err));
+        }
+    };
+
+    let static_ident = syn::Ident::new("METRICS", registry_ident.span());
+    let has_registry_expr = main_attributes
+        .values
+        .iter()
+        .any(|opt| matches!(opt, MeteredOption::RegistryExpr(_)));
+    let registry_expr: syn::Expr = if has_registry_expr {
+        metered.registry_expr.clone().into_owned()
+    } else {
+        parse_quote!(#static_ident)
+    };
+
+    // Options applying to the registry of each impl block and of functions
+    let skip_cleared = metered.skip_cleared;
+    let merge = metered.merge;
+    let last_updated = metered.last_updated;
+    let inline_closure = metered.inline_closure;
+    let skip_nested = metered.skip_nested;
+    let warn_unmeasured = match metered.warn_unmeasured {
+        UnmeasuredLint::Allow => quote! { false },
+        UnmeasuredLint::Warn => quote! { true },
+        UnmeasuredLint::Deny => quote! { deny },
+    };
+    let options = quote! {
+        visibility = #visibility,
+        field_visibility = #field_visibility,
+        skip_cleared = #skip_cleared,
+        merge = #merge,
+        last_updated = #last_updated,
+        inline_closure = #inline_closure,
+        skip_nested = #skip_nested,
+        warn_unmeasured = #warn_unmeasured,
+    };
+
+    let is_measure = |attr: &syn::Attribute| attr.path.is_ident("measure");
+    let mut groups: Vec<(syn::Ident, syn::Ident)> = Vec::new();
+    let mut add_group = |field: syn::Ident, sub_registry: syn::Ident, span| {
+        if groups.iter().any(|(f, _)| *f == field) {
+            let err = format!(
+                "`{}` is measured more than once in the module, merge its measured impl blocks",
+                field
+            );
+            return Err(syn::Error::new(span, err));
+        }
+        let registry_expr = field_expr(&registry_expr, &field);
+        groups.push((field, sub_registry.clone()));
+        Ok(quote! { registry = #sub_registry, registry_expr = #registry_expr, #options })
+    };
+
+    let mut content = Vec::new();
+    let mut functions = Vec::new();
+    for item in items.iter() {
+        match item {
+            syn::Item::Impl(item_impl)
+                if !item_impl.attrs.iter().any(|attr| attr.path.is_ident("metered"))
+                    && (item_impl.attrs.iter().any(is_measure)
+                        || item_impl.items.iter().any(|item| {
+                            matches!(item, syn::ImplItem::Method(m) if m.attrs.iter().any(is_measure))
+                        })) =>
+            {
+                let type_ident = match *item_impl.self_ty {
+                    syn::Type::Path(ref path) => &path.path.segments.last().unwrap().ident,
+                    ref self_ty => {
+                        let err = "measured impl blocks of a module must be of a named type";
+                        return Err(syn::Error::new_spanned(self_ty, err));
+                    }
+                };
+                use heck::ToSnakeCase;
+                let field = syn::Ident::new(&type_ident.to_string().to_snake_case(), type_ident.span());
+                let sub_registry = format_ident!("{}{}", registry_ident, type_ident);
+                let attrs = add_group(field, sub_registry, type_ident.span())?;
+                content.push(Some(self::metered(attrs.into(), quote! { #item_impl }.into())?.into()));
+            }
+            syn::Item::Fn(item_fn) => {
+                functions.push((content.len(), item_fn));
+                content.push(None);
+            }
+            item => content.push(Some(quote! { #item })),
+        }
+    }
+
+    // Free functions are woven as associated functions of a placeholder type
+    let placeholder = syn::Ident::new("__MeteredFunctions", proc_macro2::Span::call_site());
+    let methods = functions.iter().map(|(_, item_fn)| syn::ImplItemMethod {
+        attrs: item_fn.attrs.clone(),
+        vis: item_fn.vis.clone(),
+        defaultness: None,
+        sig: item_fn.sig.clone(),
+        block: (*item_fn.block).clone(),
+    });
+    let placeholder_impl: syn::ItemImpl = parse_quote! { impl #placeholder { #(#methods)* } };
+    if functions
+        .iter()
+        .any(|(_, item_fn)| item_fn.attrs.iter().any(is_measure))
+    {
+        let sub_registry = format_ident!("{}Functions", registry_ident);
+        let field = syn::Ident::new("functions", registry_ident.span());
+        let attrs = add_group(field, sub_registry, registry_ident.span())?;
+        let woven: syn::File = syn::parse(self::metered(
+            attrs.into(),
+            quote! { #placeholder_impl }.into(),
+        )?)?;
+        for item in woven.items {
+            let woven_impl = match item {
+                syn::Item::Impl(ref item_impl) if matches!(*item_impl.self_ty, syn::Type::Path(ref path) if path.path.is_ident(&placeholder)) => {
+                    item_impl
+                }
+                item => {
+                    content.push(Some(quote! { #item }));
+                    continue;
+                }
+            };
+            for (woven_method, (index, item_fn)) in woven_impl.items.iter().zip(functions.iter()) {
+                if let syn::ImplItem::Method(woven_method) = woven_method {
+                    let item_fn = syn::ItemFn {
+                        attrs: woven_method.attrs.clone(),
+                        block: Box::new(woven_method.block.clone()),
+                        ..(*item_fn).clone()
+                    };
+                    content[*index] = Some(quote! { #item_fn });
+                }
+            }
+        }
+    } else {
+        content.push(Some(unmeasured_lint(
+            &placeholder_impl,
+            metered.warn_unmeasured,
+            |_| false,
+        )?));
+        for (index, item_fn) in functions.iter() {
+            content[*index] = Some(quote! { #item_fn });
+        }
+    }
+
+    let registry_static = if has_registry_expr {
+        quote! {}
+    } else {
+        quote! {
+            #[allow(missing_docs)]
+            #visibility static #static_ident: std::sync::LazyLock<#registry_ident> =
+                std::sync::LazyLock::new(std::default::Default::default);
+        }
+    };
+    let mut registry = mod_registry(&metered, &groups);
+    if metered.distributed_slice {
+        let instance = if has_registry_expr {
+            None
+        } else {
+            Some(parse_quote!(#static_ident))
+        };
+        let descriptor = registry_descriptor(&metered, instance.as_ref());
+        registry = quote! {
+            #registry
+
+            #descriptor
+        };
+    }
+
+    let outer_attrs = item_mod
+        .attrs
+        .iter()
+        .filter(|attr| matches!(attr.style, syn::AttrStyle::Outer));
+    let inner_attrs = item_mod
+        .attrs
+        .iter()
+        .filter(|attr| matches!(attr.style, syn::AttrStyle::Inner(_)));
+    let mod_vis = &item_mod.vis;
+    let mod_token = &item_mod.mod_token;
+    let mod_ident = &item_mod.ident;
+    let content = content.into_iter().flatten();
+
+    Ok(quote! {
+        #(#outer_attrs)*
+        #mod_vis #mod_token #mod_ident {
+            #(#inner_attrs)*
+            #(#content)*
+
+            #registry_static
+
+            #registry
+        }
+    }
+    .into())
+}
+
+/// Accesses a field of a registry expression, in parentheses unless binding
+/// tighter than field access
+fn field_expr(base: &syn::Expr, field: &syn::Ident) -> syn::Expr {
+    match base {
+        syn::Expr::Path(_)
+        | syn::Expr::Field(_)
+        | syn::Expr::Call(_)
+        | syn::Expr::MethodCall(_)
+        | syn::Expr::Index(_)
+        | syn::Expr::Paren(_) => parse_quote!(#base.#field),
+        _ => parse_quote!((#base).#field),
+    }
+}
+
+/// The registry of a module, holding the registries of its impl blocks and of
+/// its free functions
+fn mod_registry(
+    metered: &Metered<'_>,
+    groups: &[(syn::Ident, syn::Ident)],
+) -> proc_macro2::TokenStream {
+    let registry_ident = metered.registry_ident;
+    let visibility = &metered.visibility;
+    let field_visibility = &metered.field_visibility;
+    let registry_rename = metered
+        .rename
+        .map(|rename| quote! { #[serde(rename = #rename)] });
+    let skip_cleared = if metered.skip_cleared {
+        quote! { #[serde(skip_serializing_if = "metered::clear::Clearable::is_cleared")] }
+    } else {
+        quote! {}
+    };
+    let fields: Vec<_> = groups.iter().map(|(field, _)| field).collect();
+    let sub_registries: Vec<_> = groups
+        .iter()
+        .map(|(_, sub_registry)| sub_registry)
+        .collect();
+    let sub_registries_described = &sub_registries;
+    let serialized_names: Vec<_> = fields.iter().map(|field| field.to_string()).collect();
+
+    let mut code = quote! {
+        #[derive(Debug, Default, serde::Serialize)]
+        #[allow(missing_docs)]
+        #registry_rename
+        #visibility struct #registry_ident {
+            #(
+                #skip_cleared
+                #field_visibility #fields: #sub_registries,
+            )*
+        }
+
+        impl metered::clear::Clear for #registry_ident {
+            fn clear(&self) {
+                #( metered::clear::Clear::clear(&self.#fields); )*
+            }
+        }
+
+        impl metered::clear::ClearPath for #registry_ident {
+            #[allow(unused_variables)]
+            fn clear_path(&self, path: &[&str]) -> bool {
+                match path {
+                    [] => {
+                        metered::clear::Clear::clear(self);
+                        true
+                    }
+                    [group, rest @ ..] => match *group {
+                        #( #serialized_names => metered::clear::ClearPath::clear_path(&self.#fields, rest), )*
+                        _ => false,
+                    },
+                }
+            }
+        }
+
+        impl metered::metadata::DescribeMetrics for #registry_ident {
+            fn describe_metrics() -> Vec<metered::metadata::MetricDescription> {
+                let mut descriptions = Vec::new();
+                #(
+                    for mut description in <#sub_registries_described as metered::metadata::DescribeMetrics>::describe_metrics() {
+                        description.path.insert(0, #serialized_names);
+                        descriptions.push(description);
+                    }
+                )*
+                descriptions
+            }
+        }
+
+        impl std::fmt::Display for #registry_ident {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                std::fmt::Display::fmt(&metered::pretty::Pretty::new(self), f)
+            }
+        }
+    };
+
+    if metered.skip_cleared {
+        code = quote! {
+            #code
+
+            impl metered::clear::Clearable for #registry_ident {
+                fn is_cleared(&self) -> bool {
+                    true #( && metered::clear::Clearable::is_cleared(&self.#fields) )*
+                }
+            }
+        };
+    }
+
+    if metered.merge {
+        code = quote! {
+            #code
+
+            impl metered::merge::Merge for #registry_ident {
+                fn merge_from(&self, other: &Self) {
+                    #( metered::merge::Merge::merge_from(&self.#fields, &other.#fields); )*
+                }
+            }
+        };
+    }
+
+    if metered.last_updated {
+        code = quote! {
+            #code
+
+            impl metered::staleness::LastUpdated for #registry_ident {
+                fn last_updated(&self) -> Option<std::time::SystemTime> {
+                    // `None` is lower than any `Some`, so `max` skips it
+                    None #( .max(metered::staleness::LastUpdated::last_updated(&self.#fields)) )*
+                }
+            }
+        };
+    }
+
+    code
+}
+
+/// Implements `Default` for a registry, running its `init_fn` hook on the
+/// constructed registry.
+fn registry_default_impl(
+    impl_block: &syn::ItemImpl,
+    registry_ident: &syn::Ident,
+    reg_inits: proc_macro2::TokenStream,
+    init_fn: &syn::Path,
+) -> proc_macro2::TokenStream {
+    // A bare name refers to an associated function of the impl block
+    let names_method = init_fn.get_ident().is_some_and(|ident| {
+        impl_block.items.iter().any(|item| match item {
+            syn::ImplItem::Method(method) => method.sig.ident == *ident,
+            _ => false,
+        })
+    });
+    let init_fn = if names_method {
+        let self_ty = &impl_block.self_ty;
+        quote! { <#self_ty>::#init_fn }
+    } else {
+        quote! { #init_fn }
+    };
+
+    quote! {
+        impl Default for #registry_ident {
+            fn default() -> Self {
+                let mut registry = #registry_ident {
+                    #reg_inits
+                };
+                #init_fn(&mut registry);
+                registry
+            }
+        }
+    }
+}
+
+/// Registers a descriptor of the registry in `metered::discovery::REGISTRIES`,
+/// sampling it through `instance` when it is held in a static.
+fn registry_descriptor(
+    metered: &Metered<'_>,
+    instance: Option<&syn::Expr>,
+) -> proc_macro2::TokenStream {
+    use heck::ToShoutySnakeCase;
+    let registry_ident = metered.registry_ident;
+    let name = metered
+        .rename
+        .map(syn::LitStr::value)
+        .unwrap_or_else(|| metered.registry_name.clone());
+    let descriptor_ident = format_ident!(
+        "__METERED_DESCRIPTOR_{}",
+        metered.registry_name.to_shouty_snake_case()
+    );
+    let samples = match instance {
+        Some(instance) => quote! {
+            Some(|| metered::flatten::to_samples::<#registry_ident>(&#instance))
+        },
+        None => quote! { None },
+    };
+
+    quote! {
+        #[metered::discovery::__private::linkme::distributed_slice(metered::discovery::REGISTRIES)]
+        #[linkme(crate = metered::discovery::__private::linkme)]
+        #[doc(hidden)]
+        static #descriptor_ident: metered::discovery::RegistryDescriptor =
+            metered::discovery::RegistryDescriptor {
+                name: #name,
+                describe_metrics:
+                    <#registry_ident as metered::metadata::DescribeMetrics>::describe_metrics,
+                samples: #samples,
+            };
+    }
+}
+
+fn const_labels_impl(
+    registry_ident: &syn::Ident,
+    labels: &MeteredLabelsOption,
+) -> proc_macro2::TokenStream {
+    let mut label_values = quote! {};
+    for label in labels.values.iter() {
+        let key = label.key.to_string();
+        let value = match label.value {
+            ConstLabelValue::Literal(ref lit) => quote! { String::from(#lit) },
+            ConstLabelValue::Env(ref var) => quote! { metered::labels::env_label(#var) },
+        };
+        label_values = quote! {
+            #label_values
+            (#key, #value),
+        };
+    }
+
+    quote! {
+        impl metered::labels::ConstLabels for #registry_ident {
+            fn const_labels() -> &'static metered::labels::LabelSet {
+                static LABELS: std::sync::OnceLock<metered::labels::LabelSet> =
+                    std::sync::OnceLock::new();
+                LABELS.get_or_init(|| metered::labels::LabelSet::new(vec![#label_values]))
+            }
+        }
+    }
+}
+
+/// With the `disabled` feature, emits the impl block without its `measure`
+/// attributes, and a zero-sized registry implementing the same traits as
+/// usual.
+fn disabled_metered(attrs: TokenStream, item: TokenStream) -> syn::Result<TokenStream> {
+    // Weave anyway, to report the same errors as instrumented builds
+    let woven_impl_block = weave_impl_block::<MeteredWeave>(attrs.clone(), item.clone())?;
+
+    let main_attributes = syn::parse::<MeteredKeyValAttribute>(attrs)?;
+    let metered = main_attributes.to_metered();
+    let registry_ident = metered.registry_ident;
+    let visibility = &metered.visibility;
+    let registry_rename = metered
+        .rename
+        .map(|rename| quote! { #[serde(rename = #rename)] });
+
+    let mut impl_block = syn::parse::<syn::ItemImpl>(item)?;
+    impl_block
+        .attrs
+        .retain(|attr| !attr.path.is_ident("measure"));
+    for impl_item in impl_block.items.iter_mut() {
+        if let syn::ImplItem::Method(method) = impl_item {
+            method.attrs.retain(|attr| !attr.path.is_ident("measure"));
+
+            // Keep using metric types, so that their imports are not unused
+            if let Some(measure_request_attrs) = woven_impl_block.woven_fns.get(&method.sig.ident) {
+                let metric_types = measure_request_attrs.iter().flat_map(|attr| {
+                    attr.to_requests()
+                        .iter()
+                        .map(|metric| -> syn::Stmt {
+                            let metric_type = metric.type_path();
+                            let metric_cfg = metric.cfg_attr();
+                            syn::parse_quote! {
+                                #metric_cfg
+                                let _: std::marker::PhantomData<#metric_type>;
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                });
+
+                // And expressions initializing them
+                let inits = measure_request_attrs.iter().flat_map(|attr| {
+                    attr.to_requests()
+                        .iter()
+                        .filter_map(|metric| {
+                            let metric_type = metric.type_path();
+                            let metric_cfg = metric.cfg_attr();
+                            metric.init.map(|init| -> syn::Stmt {
+                                syn::parse_quote! {
+                                    #metric_cfg
+                                    let _ = || metered::metric::MetricBuilder::<#metric_type>::build_metric(#init);
+                                }
+                            })
+                        })
+                        .collect::<Vec<_>>()
+                });
+                let stmts = std::mem::take(&mut method.block.stmts);
+                method.block.stmts = inits.chain(metric_types).chain(stmts).collect();
+            }
+        }
+    }
+
+    let lint = unmeasured_lint(&impl_block, metered.warn_unmeasured, |ident| {
+        woven_impl_block.woven_fns.contains_key(ident)
+    })?;
+
+    let (registry_derive, registry_default) = match metered.init_fn {
+        Some(init_fn) => (
+            quote! { #[derive(Debug, Clone, Copy, serde::Serialize)] },
+            registry_default_impl(&impl_block, registry_ident, quote! {}, init_fn),
+        ),
+        None => (
+            quote! { #[derive(Debug, Default, Clone, Copy, serde::Serialize)] },
+            quote! {},
+        ),
+    };
+
+    let mut code = quote! {
+        #impl_block
+
+        #lint
+
+        #registry_derive
+        #[allow(missing_docs)]
+        #registry_rename
+        #visibility struct #registry_ident {}
+
+        #registry_default
+
+        impl #registry_ident {
+            /// The serialized names of the measured methods
+            pub const METHOD_NAMES: &'static [&'static str] = &[];
+
+            /// The paths of the exported metrics, as described by
+            /// `metered::metadata::DescribeMetrics`
+            pub const METRIC_PATHS: &'static [&'static [&'static str]] = &[];
+        }
+
+        impl metered::clear::Clear for #registry_ident {
+            fn clear(&self) {}
+        }
+
+        // Only the empty path, clearing the whole registry, is accepted
+        impl metered::clear::ClearPath for #registry_ident {}
+
+        impl metered::metadata::DescribeMetrics for #registry_ident {
+            fn describe_metrics() -> Vec<metered::metadata::MetricDescription> {
+                Vec::new()
+            }
+        }
+
+        impl std::fmt::Display for #registry_ident {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                std::fmt::Display::fmt(&metered::pretty::Pretty::new(self), f)
+            }
+        }
+    };
+
+    if let Some(labels) = metered.labels {
+        let const_labels = const_labels_impl(registry_ident, labels);
+        code = quote! {
+            #code
+
+            #const_labels
+        };
+    }
+
+    if metered.skip_cleared {
+        code = quote! {
+            #code
+
+            impl metered::clear::Clearable for #registry_ident {
+                fn is_cleared(&self) -> bool {
+                    true
+                }
+            }
+        };
+    }
+
+    if metered.toggle {
+        code = quote! {
+            #code
+
+            impl metered::toggle::Toggles for #registry_ident {
+                fn toggle(&self) -> &metered::toggle::Toggle {
+                    static TOGGLE: metered::toggle::Toggle = metered::toggle::Toggle::new(false);
+                    &TOGGLE
+                }
+
+                fn method_toggle(&self, _method: &str) -> Option<&metered::toggle::Toggle> {
+                    None
+                }
+            }
+        };
+    }
+
+    if metered.merge {
+        code = quote! {
+            #code
+
+            impl metered::merge::Merge for #registry_ident {
+                fn merge_from(&self, _other: &Self) {}
+            }
+        };
+    }
+
+    if metered.last_updated {
+        code = quote! {
+            #code
+
+            impl metered::staleness::LastUpdated for #registry_ident {
+                fn last_updated(&self) -> Option<std::time::SystemTime> {
+                    None
+                }
+            }
+        };
+    }
+
+    if metered.distributed_slice {
+        let descriptor = registry_descriptor(&metered, None);
+        code = quote! {
+            #code
+
+            #descriptor
+        };
+    }
+
+    Ok(code.into())
+}
+
+/// Reports the public methods of an impl block lacking a `measure` attribute,
+/// as errors or as warnings emitted by the returned code.
+fn unmeasured_lint<F>(
+    impl_block: &syn::ItemImpl,
+    lint: UnmeasuredLint,
+    is_measured: F,
+) -> syn::Result<proc_macro2::TokenStream>
+where
+    F: Fn(&syn::Ident) -> bool,
+{
+    let mut warnings = quote! {};
+    let mut error: Option<syn::Error> = None;
+    if lint == UnmeasuredLint::Allow {
+        return Ok(warnings);
+    }
+
+    for impl_item in impl_block.items.iter() {
+        let method = match impl_item {
+            syn::ImplItem::Method(method) => method,
+            _ => continue,
+        };
+        let ident = &method.sig.ident;
+        if !matches!(method.vis, syn::Visibility::Public(_)) || is_measured(ident) {
+            continue;
+        }
+
+        let message = format!(
+            "public method `{}` is not measured: add a `#[measure]` attribute, or \
+             set `warn_unmeasured = false` on `#[metered]`",
+            ident
+        );
+        if lint == UnmeasuredLint::Deny {
+            let method_error = syn::Error::new(ident.span(), message);
+            match error {
+                Some(ref mut error) => error.combine(method_error),
+                None => error = Some(method_error),
+            }
+        } else {
+            // Stable proc macros cannot emit warnings: use a deprecated item
+            // instead, at the method's span
+            let usage = quote_spanned! {ident.span()=>
+                let _ = unmeasured_method;
+            };
+            warnings = quote! {
+                #warnings
+
+                const _: () = {
+                    #[deprecated(note = #message)]
+                    #[allow(non_camel_case_types)]
+                    struct unmeasured_method;
+                    #usage
+                };
+            };
+        }
+    }
+
+    match error {
+        Some(error) => Err(error),
+        None => Ok(warnings),
+    }
+}
+
+/// The type of the argument named `by` of a method, without references
+fn by_arg_type<'a>(sig: &'a syn::Signature, by: &syn::Ident) -> Option<&'a syn::Type> {
+    let mut ty = sig.inputs.iter().find_map(|arg| match arg {
+        syn::FnArg::Typed(arg) => match *arg.pat {
+            syn::Pat::Ident(ref pat) if pat.ident == *by => Some(&*arg.ty),
+            _ => None,
+        },
+        syn::FnArg::Receiver(_) => None,
+    })?;
+    while let syn::Type::Reference(reference) = ty {
+        ty = &reference.elem;
+    }
+    Some(ty)
+}
+
+/// The type of the registry field of a metric, kept per variant of the
+/// argument of the method with `by`
+fn metric_field_type(
+    metric: &MeasureRequest<'_>,
+    sig: Option<&syn::Signature>,
+) -> proc_macro2::TokenStream {
+    let metric_type = metric.type_path();
+    match (metric.by, sig) {
+        (Some(by), Some(sig)) => match by_arg_type(sig, by) {
+            Some(by_type) => quote! { metered::breakdown::Breakdown<#by_type, #metric_type> },
+            // Reported when weaving the method
+            None => quote! { #metric_type },
+        },
+        _ => quote! { #metric_type },
+    }
+}
+
+/// Returns true if the tokens contain `self`, at any depth
+fn mentions_self(tokens: proc_macro2::TokenStream) -> bool {
+    tokens.into_iter().any(|token| match token {
+        proc_macro2::TokenTree::Ident(ident) => ident == "self",
+        proc_macro2::TokenTree::Group(group) => mentions_self(group.stream()),
+        _ => false,
+    })
+}
+
 struct MeteredWeave;
 impl Weave for MeteredWeave {
     type MacroAttributes = MeteredKeyValAttribute;
@@ -133,9 +1387,43 @@ impl Weave for MeteredWeave {
         let metered = main_attr.to_metered();
         let ident = &item_fn.sig.ident;
         let block = &item_fn.block;
+
+        let registry_expr = &metered.registry_expr;
+        if item_fn.sig.receiver().is_none() && mentions_self(quote! { #registry_expr }) {
+            let err = format!(
+                "measured methods must take `self` to access their registry at `{}`; \
+                 set `registry_expr` on `#[metered]` to measure associated functions",
+                quote! { #registry_expr }
+            );
+            return Err(syn::Error::new_spanned(&item_fn.sig.ident, err));
+        }
+
+        let mut field_names = std::collections::HashSet::new();
+        for measure_req_attr in fn_attr.iter() {
+            for metric in measure_req_attr.to_requests() {
+                if !field_names.insert(metric.field_name.clone()) {
+                    let err = format!(
+                        "metric `{}` is measured more than once on `{}`",
+                        metric.field_name, ident
+                    );
+                    return Err(syn::Error::new_spanned(metric.type_path(), err));
+                }
+                if let Some(by) = metric.by {
+                    if by_arg_type(&item_fn.sig, by).is_none() {
+                        let err = format!("`{}` is not an argument of `{}`", by, ident);
+                        return Err(syn::Error::new_spanned(by, err));
+                    }
+                }
+            }
+        }
         // We must alter the block to capture early returns
         // using a closure, and handle the async case.
 
+        if metered.skip_nested && item_fn.sig.asyncness.is_some() {
+            let err = "`skip_nested` tracks calls per thread and does not support async methods";
+            return Err(syn::Error::new_spanned(item_fn.sig.asyncness, err));
+        }
+
         let outer_block = if item_fn.sig.asyncness.is_some() {
             // For versions before `.await` stabilization,
             // We cannot use the `await` keyword in the `quote!` macro
@@ -144,19 +1432,28 @@ impl Weave for MeteredWeave {
             // (move || async move #block)().await`
 
             let await_fut = syn::parse_str::<syn::Expr>("fut.await")?;
+            let fut = if metered.inline_closure {
+                quote! { metered::__call_inline(#[inline(always)] move || async move #block) }
+            } else {
+                quote! { (move || async move #block)() }
+            };
             quote! {
                 {
-                    let fut = (move || async move #block)();
+                    let fut = #fut;
                     #await_fut
                 }
             }
+        } else if metered.inline_closure {
+            quote! {
+                metered::__call_inline(#[inline(always)] move || #block)
+            }
         } else {
             quote! {
                 (move || #block)()
             }
         };
 
-        let r = measure_list(&metered.registry_expr, ident, fn_attr, outer_block);
+        let r = measure_list(&metered, ident, fn_attr, outer_block);
 
         let new_block = syn::parse2::<syn::Block>(r)?;
         Ok(new_block)
@@ -172,23 +1469,69 @@ impl ParseAttributes for MeteredWeave {
 }
 
 fn measure_list(
-    registry_expr: &syn::Expr,
+    metered: &Metered<'_>,
     fun_ident: &syn::Ident,
     measure_request_attrs: &[Rc<MeasureRequestAttribute>],
     mut inner: proc_macro2::TokenStream,
 ) -> proc_macro2::TokenStream {
     // Recursive macro invocations
+    // Metrics that cannot measure the result of the method, e.g. defaults of
+    // the impl block, are reported on the method's name
+    let span = fun_ident.span();
+    let conditional = metered.toggle || metered.skip_nested;
     for measure_req_attr in measure_request_attrs.iter() {
         let metric_requests = measure_req_attr.to_requests();
 
         for metric in metric_requests.iter() {
             let metric_var = metric.ident();
-            inner = quote! {
-                metered::measure! { #metric_var, #inner }
+            inner = match (metric.abort, conditional) {
+                (Some(abort), false) => quote_spanned! {span=>
+                    metered::measure! { #metric_var, #inner, abort => #abort }
+                },
+                (None, false) => quote_spanned! {span=>
+                    metered::measure! { #metric_var, #inner }
+                },
+                (Some(abort), true) => quote_spanned! {span=>
+                    metered::__measure_if! { __metered_enabled, #metric_var, #inner, abort => #abort }
+                },
+                (None, true) => quote_spanned! {span=>
+                    metered::__measure_if! { __metered_enabled, #metric_var, #inner }
+                },
             };
+
+            // Values derived from the result are recorded once it is known
+            if let Some(record) = metric.record {
+                let record_value = quote_spanned! {span=>
+                    metered::metric::RecordValue::record_value(#metric_var, {
+                        let result = &result;
+                        #record
+                    });
+                };
+                let record_value = if conditional {
+                    quote! { if __metered_enabled { #record_value } }
+                } else {
+                    record_value
+                };
+                inner = quote! {
+                    {
+                        let result = #inner;
+                        #record_value
+                        result
+                    }
+                };
+            }
         }
     }
 
+    let registry_expr = &metered.registry_expr;
+
+    if metered.last_updated {
+        inner = quote! {
+            metered::Enter::enter(&#registry_expr.#fun_ident.last_updated);
+            #inner
+        };
+    }
+
     // Let-bindings to avoid moving issues
     for measure_req_attr in measure_request_attrs.iter() {
         let metric_requests = measure_req_attr.to_requests();
@@ -196,9 +1539,27 @@ fn measure_list(
         for metric in metric_requests.iter() {
             let metric_var = syn::Ident::new(&metric.field_name, proc_macro2::Span::call_site());
 
-            inner = quote! {
-                let #metric_var = &#registry_expr.#fun_ident.#metric_var;
-                #inner
+            // Metrics broken down by an argument measure its variant
+            let metric_expr = match metric.by {
+                Some(by) => quote! {
+                    metered::breakdown::Breakdown::by(&#registry_expr.#fun_ident.#metric_var, &#by)
+                },
+                None => quote! { &#registry_expr.#fun_ident.#metric_var },
+            };
+
+            inner = match metric.cfg {
+                // Compiled out metrics measure nothing
+                Some(cfg) => quote! {
+                    #[cfg(#cfg)]
+                    let #metric_var = #metric_expr;
+                    #[cfg(not(#cfg))]
+                    let #metric_var = &metered::null::NullMetric;
+                    #inner
+                },
+                None => quote! {
+                    let #metric_var = #metric_expr;
+                    #inner
+                },
             };
         }
 
@@ -207,6 +1568,31 @@ fn measure_list(
         // }
     }
 
+    // Nested calls are tracked until the end of the block
+    let enabled = match (metered.toggle, metered.skip_nested) {
+        (true, false) => Some(quote! {
+            let __metered_enabled = #registry_expr.toggle.is_enabled()
+                && #registry_expr.#fun_ident.toggle.is_enabled();
+        }),
+        (false, true) => Some(quote! {
+            let __metered_call = metered::nesting::NestedCall::enter(&#registry_expr);
+            let __metered_enabled = !__metered_call.is_nested();
+        }),
+        (true, true) => Some(quote! {
+            let __metered_call = metered::nesting::NestedCall::enter(&#registry_expr);
+            let __metered_enabled = !__metered_call.is_nested()
+                && #registry_expr.toggle.is_enabled()
+                && #registry_expr.#fun_ident.toggle.is_enabled();
+        }),
+        (false, false) => None,
+    };
+    if let Some(enabled) = enabled {
+        inner = quote! {
+            #enabled
+            #inner
+        };
+    }
+
     // Add final braces
     quote! {
         {
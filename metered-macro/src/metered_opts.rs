@@ -14,6 +14,26 @@ pub struct Metered<'a> {
     pub registry_name: String,
     pub registry_expr: Cow<'a, syn::Expr>,
     pub visibility: Cow<'a, syn::Visibility>,
+    pub field_visibility: Cow<'a, syn::Visibility>,
+    pub last_updated: bool,
+    pub labels: Option<&'a MeteredLabelsOption>,
+    pub rename: Option<&'a syn::LitStr>,
+    pub skip_cleared: bool,
+    pub toggle: bool,
+    pub warn_unmeasured: UnmeasuredLint,
+    pub merge: bool,
+    pub init_fn: Option<&'a syn::Path>,
+    pub distributed_slice: bool,
+    pub inline_closure: bool,
+    pub skip_nested: bool,
+}
+
+/// How to report public methods lacking a `#[measure]` attribute
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum UnmeasuredLint {
+    Allow,
+    Warn,
+    Deny,
 }
 
 pub struct MeteredKeyValAttribute {
@@ -41,14 +61,14 @@ impl MeteredKeyValAttribute {
             .collect();
 
         for (opt_type, opt_name) in opt_types.iter() {
-            let count = self
+            let duplicate = self
                 .values
                 .iter()
                 .filter(|&opt| std::mem::discriminant(opt) == *opt_type)
-                .count();
-            if count > 1 {
-                let error = format!("`{}` attribute is defined more than once.", opt_name);
-                return Err(input.error(error));
+                .nth(1);
+            if let Some(duplicate) = duplicate {
+                let error = format!("{} attribute is defined more than once.", opt_name);
+                return Err(syn::Error::new(duplicate.span(), error));
             }
         }
 
@@ -83,7 +103,10 @@ impl MeteredKeyValAttribute {
             })
             .next()
             .map(Cow::Borrowed)
-            .unwrap_or_else(|| Cow::Owned(syn::parse_str::<syn::Expr>("self.metrics").unwrap()));
+            // Spanned at the registry so a missing `metrics` field points at it
+            .unwrap_or_else(|| {
+                Cow::Owned(syn::parse_quote_spanned!(registry_ident.span()=> self.metrics))
+            });
 
         let visibility = self
             .values
@@ -100,11 +123,182 @@ impl MeteredKeyValAttribute {
             .unwrap_or_else(|| {
                 Cow::Owned(syn::parse_str::<syn::Visibility>("pub(crate)").unwrap())
             });
+
+        let field_visibility = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeteredOption::FieldVisibility(tpe) = opt {
+                    Some(&tpe.value)
+                } else {
+                    None
+                }
+            })
+            .next()
+            .map(Cow::Borrowed)
+            .unwrap_or_else(|| Cow::Owned(syn::parse_str::<syn::Visibility>("pub").unwrap()));
+
+        let last_updated = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeteredOption::LastUpdated(tpe) = opt {
+                    Some(tpe.value.value)
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or(false);
+
+        let labels = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeteredOption::Labels(labels) = opt {
+                    Some(labels)
+                } else {
+                    None
+                }
+            })
+            .next();
+
+        let rename = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeteredOption::Rename(rename) = opt {
+                    Some(&rename.value)
+                } else {
+                    None
+                }
+            })
+            .next();
+
+        let skip_cleared = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeteredOption::SkipCleared(tpe) = opt {
+                    Some(tpe.value.value)
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or(false);
+
+        let toggle = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeteredOption::Toggle(tpe) = opt {
+                    Some(tpe.value.value)
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or(false);
+
+        let warn_unmeasured = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeteredOption::WarnUnmeasured(tpe) = opt {
+                    Some(match tpe.value {
+                        WarnUnmeasuredValue::Bool(ref b) if b.value => UnmeasuredLint::Warn,
+                        WarnUnmeasuredValue::Bool(_) => UnmeasuredLint::Allow,
+                        WarnUnmeasuredValue::Deny(_) => UnmeasuredLint::Deny,
+                    })
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or(UnmeasuredLint::Allow);
+
+        let merge = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeteredOption::Merge(tpe) = opt {
+                    Some(tpe.value.value)
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or(false);
+
+        let init_fn = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeteredOption::InitFn(init_fn) = opt {
+                    Some(&init_fn.value)
+                } else {
+                    None
+                }
+            })
+            .next();
+
+        let distributed_slice = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeteredOption::DistributedSlice(tpe) = opt {
+                    Some(tpe.value.value)
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or(false);
+
+        let inline_closure = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeteredOption::InlineClosure(tpe) = opt {
+                    Some(tpe.value.value)
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or(false);
+
+        let skip_nested = self
+            .values
+            .iter()
+            .filter_map(|opt| {
+                if let MeteredOption::SkipNested(tpe) = opt {
+                    Some(tpe.value.value)
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or(false);
+
         Metered {
             registry_ident,
             registry_name,
             registry_expr,
             visibility,
+            field_visibility,
+            last_updated,
+            labels,
+            rename,
+            skip_cleared,
+            toggle,
+            warn_unmeasured,
+            merge,
+            init_fn,
+            distributed_slice,
+            inline_closure,
+            skip_nested,
         }
     }
 }
@@ -125,6 +319,20 @@ mod kw {
     syn::custom_keyword!(registry);
     syn::custom_keyword!(registry_expr);
     syn::custom_keyword!(visibility);
+    syn::custom_keyword!(field_visibility);
+    syn::custom_keyword!(last_updated);
+    syn::custom_keyword!(labels);
+    syn::custom_keyword!(env);
+    syn::custom_keyword!(rename);
+    syn::custom_keyword!(skip_cleared);
+    syn::custom_keyword!(toggle);
+    syn::custom_keyword!(warn_unmeasured);
+    syn::custom_keyword!(deny);
+    syn::custom_keyword!(merge);
+    syn::custom_keyword!(init_fn);
+    syn::custom_keyword!(distributed_slice);
+    syn::custom_keyword!(inline_closure);
+    syn::custom_keyword!(skip_nested);
 }
 
 pub type MeteredRegistryOption = KVOption<kw::registry, syn::Ident>;
@@ -133,20 +341,167 @@ pub type MeteredRegistryExprOption = KVOption<kw::registry_expr, syn::Expr>;
 
 pub type MeteredVisibilityOption = KVOption<kw::visibility, syn::Visibility>;
 
+pub type MeteredFieldVisibilityOption = KVOption<kw::field_visibility, syn::Visibility>;
+
+pub type MeteredLastUpdatedOption = KVOption<kw::last_updated, syn::LitBool>;
+
+pub type MeteredRenameOption = KVOption<kw::rename, syn::LitStr>;
+
+pub type MeteredSkipClearedOption = KVOption<kw::skip_cleared, syn::LitBool>;
+
+pub type MeteredToggleOption = KVOption<kw::toggle, syn::LitBool>;
+
+pub type MeteredMergeOption = KVOption<kw::merge, syn::LitBool>;
+
+pub type MeteredInitFnOption = KVOption<kw::init_fn, syn::Path>;
+
+pub type MeteredDistributedSliceOption = KVOption<kw::distributed_slice, syn::LitBool>;
+
+pub type MeteredInlineClosureOption = KVOption<kw::inline_closure, syn::LitBool>;
+pub type MeteredSkipNestedOption = KVOption<kw::skip_nested, syn::LitBool>;
+
+pub type MeteredWarnUnmeasuredOption = KVOption<kw::warn_unmeasured, WarnUnmeasuredValue>;
+
+/// `warn_unmeasured = true`, `warn_unmeasured = false` or
+/// `warn_unmeasured = deny`
+pub enum WarnUnmeasuredValue {
+    Bool(syn::LitBool),
+    Deny(#[allow(dead_code)] kw::deny),
+}
+
+impl Parse for WarnUnmeasuredValue {
+    fn parse(input: ParseStream<'_>) -> Result<Self> {
+        if input.peek(kw::deny) {
+            Ok(WarnUnmeasuredValue::Deny(input.parse()?))
+        } else if input.peek(syn::LitBool) {
+            Ok(WarnUnmeasuredValue::Bool(input.parse()?))
+        } else {
+            Err(input.error("expected `true`, `false` or `deny`"))
+        }
+    }
+}
+
+/// `labels(key = "value", other_key = env("VAR"))`
+pub struct MeteredLabelsOption {
+    pub labels_token: kw::labels,
+    #[allow(dead_code)]
+    pub paren_token: syn::token::Paren,
+    pub values: syn::punctuated::Punctuated<ConstLabel, Token![,]>,
+}
+
+impl MeteredLabelsOption {
+    pub fn peek(input: ParseStream<'_>) -> bool {
+        input.peek(kw::labels)
+    }
+}
+
+impl Parse for MeteredLabelsOption {
+    fn parse(input: ParseStream<'_>) -> Result<Self> {
+        let content;
+        Ok(MeteredLabelsOption {
+            labels_token: input.parse()?,
+            paren_token: parenthesized!(content in input),
+            values: content.parse_terminated(ConstLabel::parse)?,
+        })
+    }
+}
+
+pub struct ConstLabel {
+    pub key: syn::Ident,
+    #[allow(dead_code)]
+    pub eq_token: Token![=],
+    pub value: ConstLabelValue,
+}
+
+impl Parse for ConstLabel {
+    fn parse(input: ParseStream<'_>) -> Result<Self> {
+        Ok(ConstLabel {
+            key: input.parse()?,
+            eq_token: input.parse()?,
+            value: input.parse()?,
+        })
+    }
+}
+
+pub enum ConstLabelValue {
+    Literal(syn::LitStr),
+    Env(syn::LitStr),
+}
+
+impl Parse for ConstLabelValue {
+    fn parse(input: ParseStream<'_>) -> Result<Self> {
+        if input.peek(kw::env) {
+            let _: kw::env = input.parse()?;
+            let content;
+            parenthesized!(content in input);
+            Ok(ConstLabelValue::Env(content.parse()?))
+        } else if input.peek(syn::LitStr) {
+            Ok(ConstLabelValue::Literal(input.parse()?))
+        } else {
+            Err(input.error("expected a string literal or `env(\"VAR\")` label value"))
+        }
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
 pub enum MeteredOption {
     Registry(MeteredRegistryOption),
     RegistryExpr(MeteredRegistryExprOption),
     Visibility(MeteredVisibilityOption),
+    FieldVisibility(MeteredFieldVisibilityOption),
+    LastUpdated(MeteredLastUpdatedOption),
+    Labels(MeteredLabelsOption),
+    Rename(MeteredRenameOption),
+    SkipCleared(MeteredSkipClearedOption),
+    Toggle(MeteredToggleOption),
+    WarnUnmeasured(MeteredWarnUnmeasuredOption),
+    Merge(MeteredMergeOption),
+    InitFn(MeteredInitFnOption),
+    DistributedSlice(MeteredDistributedSliceOption),
+    InlineClosure(MeteredInlineClosureOption),
+    SkipNested(MeteredSkipNestedOption),
 }
 
 impl MeteredOption {
+    /// The span of the option's key
+    pub fn span(&self) -> proc_macro2::Span {
+        match self {
+            MeteredOption::Registry(opt) => opt.key.span,
+            MeteredOption::RegistryExpr(opt) => opt.key.span,
+            MeteredOption::Visibility(opt) => opt.key.span,
+            MeteredOption::FieldVisibility(opt) => opt.key.span,
+            MeteredOption::LastUpdated(opt) => opt.key.span,
+            MeteredOption::Labels(opt) => opt.labels_token.span,
+            MeteredOption::Rename(opt) => opt.key.span,
+            MeteredOption::SkipCleared(opt) => opt.key.span,
+            MeteredOption::Toggle(opt) => opt.key.span,
+            MeteredOption::WarnUnmeasured(opt) => opt.key.span,
+            MeteredOption::Merge(opt) => opt.key.span,
+            MeteredOption::InitFn(opt) => opt.key.span,
+            MeteredOption::DistributedSlice(opt) => opt.key.span,
+            MeteredOption::InlineClosure(opt) => opt.key.span,
+            MeteredOption::SkipNested(opt) => opt.key.span,
+        }
+    }
+
     pub fn as_str(&self) -> &str {
         use syn::token::Token;
         match self {
             MeteredOption::Registry(_) => <kw::registry>::display(),
             MeteredOption::RegistryExpr(_) => <kw::registry_expr>::display(),
             MeteredOption::Visibility(_) => <kw::visibility>::display(),
+            MeteredOption::FieldVisibility(_) => <kw::field_visibility>::display(),
+            MeteredOption::LastUpdated(_) => <kw::last_updated>::display(),
+            MeteredOption::Labels(_) => <kw::labels>::display(),
+            MeteredOption::Rename(_) => <kw::rename>::display(),
+            MeteredOption::SkipCleared(_) => <kw::skip_cleared>::display(),
+            MeteredOption::Toggle(_) => <kw::toggle>::display(),
+            MeteredOption::WarnUnmeasured(_) => <kw::warn_unmeasured>::display(),
+            MeteredOption::Merge(_) => <kw::merge>::display(),
+            MeteredOption::InitFn(_) => <kw::init_fn>::display(),
+            MeteredOption::DistributedSlice(_) => <kw::distributed_slice>::display(),
+            MeteredOption::InlineClosure(_) => <kw::inline_closure>::display(),
+            MeteredOption::SkipNested(_) => <kw::skip_nested>::display(),
         }
     }
 }
@@ -159,9 +514,40 @@ impl Parse for MeteredOption {
             Ok(input.parse_as(MeteredOption::RegistryExpr)?)
         } else if MeteredVisibilityOption::peek(input) {
             Ok(input.parse_as(MeteredOption::Visibility)?)
+        } else if MeteredFieldVisibilityOption::peek(input) {
+            Ok(input.parse_as(MeteredOption::FieldVisibility)?)
+        } else if MeteredLastUpdatedOption::peek(input) {
+            Ok(input.parse_as(MeteredOption::LastUpdated)?)
+        } else if MeteredLabelsOption::peek(input) {
+            Ok(input.parse_as(MeteredOption::Labels)?)
+        } else if MeteredRenameOption::peek(input) {
+            Ok(input.parse_as(MeteredOption::Rename)?)
+        } else if MeteredSkipClearedOption::peek(input) {
+            Ok(input.parse_as(MeteredOption::SkipCleared)?)
+        } else if MeteredToggleOption::peek(input) {
+            Ok(input.parse_as(MeteredOption::Toggle)?)
+        } else if MeteredWarnUnmeasuredOption::peek(input) {
+            Ok(input.parse_as(MeteredOption::WarnUnmeasured)?)
+        } else if MeteredMergeOption::peek(input) {
+            Ok(input.parse_as(MeteredOption::Merge)?)
+        } else if MeteredInitFnOption::peek(input) {
+            Ok(input.parse_as(MeteredOption::InitFn)?)
+        } else if MeteredDistributedSliceOption::peek(input) {
+            Ok(input.parse_as(MeteredOption::DistributedSlice)?)
+        } else if MeteredInlineClosureOption::peek(input) {
+            Ok(input.parse_as(MeteredOption::InlineClosure)?)
+        } else if MeteredSkipNestedOption::peek(input) {
+            Ok(input.parse_as(MeteredOption::SkipNested)?)
         } else {
-            let err = format!("invalid metered option: {}", input);
-            Err(input.error(err))
+            let token: proc_macro2::TokenTree = input.parse()?;
+            let err = format!(
+                "unknown metered option `{}`, expected one of `registry`, `registry_expr`, \
+                 `visibility`, `field_visibility`, `last_updated`, `labels`, `rename`, \
+                 `skip_cleared`, `toggle`, `warn_unmeasured`, `merge`, `init_fn`, \
+                 `distributed_slice`, `inline_closure` or `skip_nested`",
+                token
+            );
+            Err(syn::Error::new(token.span(), err))
         }
     }
 }
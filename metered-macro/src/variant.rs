@@ -0,0 +1,41 @@
+use proc_macro::TokenStream;
+
+pub fn variant(item: TokenStream) -> syn::Result<TokenStream> {
+    let input: syn::DeriveInput = syn::parse(item)?;
+    let data = match input.data {
+        syn::Data::Enum(ref data) => data,
+        _ => {
+            let err = "`Variant` can only be derived on enums";
+            return Err(syn::Error::new_spanned(&input.ident, err));
+        }
+    };
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let names = data.variants.iter().map(|v| v.ident.to_string());
+
+    // match each variant, whatever its fields, to its index
+    let arms = data.variants.iter().enumerate().map(|(i, v)| {
+        let variant = &v.ident;
+        quote! { #ident::#variant { .. } => #i, }
+    });
+
+    // empty enums have no values to match on
+    let index = if data.variants.is_empty() {
+        quote! { match *self {} }
+    } else {
+        quote! { match self { #(#arms)* } }
+    };
+
+    Ok(quote! {
+        impl #impl_generics metered::breakdown::Variant for #ident #ty_generics #where_clause {
+            const VARIANTS: &'static [&'static str] = &[#(#names),*];
+
+            #[inline]
+            fn variant_index(&self) -> usize {
+                #index
+            }
+        }
+    }
+    .into())
+}
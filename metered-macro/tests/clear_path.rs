@@ -0,0 +1,83 @@
+use metered::{
+    clear::{clear_path, ClearPath},
+    metered, HitCount, ResponseTime,
+};
+
+#[derive(Default, Debug)]
+pub struct Biz {
+    metrics: BizMetrics,
+}
+
+#[metered(registry = BizMetrics)]
+impl Biz {
+    #[measure([HitCount, ResponseTime])]
+    #[measure(cfg(any()), type = metered::InFlight)]
+    pub fn bar(&self) {}
+
+    #[measure(type = HitCount, rename = "hits", rename_method = "baz_renamed")]
+    #[measure(type = metered::InFlight, skip_serializing)]
+    pub fn baz(&self) {}
+}
+
+#[metered(registry = ComponentMetrics)]
+mod component {
+    use metered::HitCount;
+
+    #[measure(HitCount)]
+    pub fn parse(input: &str) -> Option<u8> {
+        input.parse().ok()
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disabled"))]
+fn clears_by_path() {
+    let biz = Biz::default();
+    biz.bar();
+    biz.baz();
+
+    assert!(clear_path(&biz.metrics, "bar.response_time"));
+    assert_eq!(biz.metrics.bar.response_time.histogram().len(), 0);
+    assert_eq!(biz.metrics.bar.hit_count.get(), 1);
+
+    // Renamed metrics and methods are addressed by their serialized names
+    assert!(!clear_path(&biz.metrics, "baz.hits"));
+    assert!(clear_path(&biz.metrics, "baz_renamed.hits"));
+    assert_eq!(biz.metrics.baz.hit_count.get(), 0);
+
+    // Compiled out and unexported metrics have no path
+    assert!(!clear_path(&biz.metrics, "bar.in_flight"));
+    assert!(!biz.metrics.clear_path(&["baz_renamed", "in_flight"]));
+    assert!(!clear_path(&biz.metrics, "bar.hit_count.count"));
+
+    assert!(clear_path(&biz.metrics, "bar"));
+    assert_eq!(biz.metrics.bar.hit_count.get(), 0);
+}
+
+#[test]
+#[cfg(not(feature = "disabled"))]
+fn clears_modules_by_path() {
+    let _ = component::parse("1");
+    let metrics: &component::ComponentMetrics = &component::METRICS;
+    assert_eq!(metrics.functions.parse.hit_count.get(), 1);
+
+    assert!(!clear_path(metrics, "parse.hit_count"));
+    assert!(clear_path(metrics, "functions.parse.hit_count"));
+    assert_eq!(metrics.functions.parse.hit_count.get(), 0);
+    assert!(clear_path(metrics, ""));
+}
+
+// Registries are empty with the `disabled` feature
+#[test]
+#[cfg(feature = "disabled")]
+fn only_clears_whole_registries_when_disabled() {
+    let biz = Biz::default();
+    biz.bar();
+    let _ = component::parse("1");
+
+    assert!(clear_path(&biz.metrics, ""));
+    assert!(!clear_path(&biz.metrics, "bar.response_time"));
+    assert!(!biz.metrics.clear_path(&["bar"]));
+    assert!(clear_path(&*component::METRICS, ""));
+    assert!(!clear_path(&*component::METRICS, "functions.parse"));
+}
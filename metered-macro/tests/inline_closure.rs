@@ -0,0 +1,51 @@
+// Registries are empty with the `disabled` feature
+#![cfg(not(feature = "disabled"))]
+
+use metered::{metered, ErrorCount, HitCount};
+use std::{
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+
+#[derive(Default, Debug)]
+pub struct Parser {
+    metrics: ParserMetrics,
+}
+
+#[metered(registry = ParserMetrics, inline_closure = true)]
+impl Parser {
+    #[measure([HitCount, ErrorCount])]
+    pub fn parse(&self, input: &str) -> Result<u8, std::num::ParseIntError> {
+        if input.is_empty() {
+            return Ok(0);
+        }
+        input.parse()
+    }
+
+    #[measure(HitCount)]
+    pub async fn parse_async(&self, input: &str) -> u8 {
+        input.parse().unwrap_or_default()
+    }
+}
+
+#[test]
+fn measures_early_returns() {
+    let parser = Parser::default();
+    assert_eq!(parser.parse(""), Ok(0));
+    assert_eq!(parser.parse("1"), Ok(1));
+    assert!(parser.parse("a").is_err());
+
+    assert_eq!(parser.metrics.parse.hit_count.get(), 3);
+    assert_eq!(parser.metrics.parse.error_count.get(), 1);
+}
+
+#[test]
+fn measures_async_methods() {
+    let parser = Parser::default();
+    let fut = pin!(parser.parse_async("2"));
+    let mut cx = Context::from_waker(Waker::noop());
+    assert_eq!(fut.poll(&mut cx), Poll::Ready(2));
+
+    assert_eq!(parser.metrics.parse_async.hit_count.get(), 1);
+}
@@ -0,0 +1,81 @@
+// Registries are empty with the `disabled` feature
+#![cfg(not(feature = "disabled"))]
+
+use metered::{breakdown::Variant, metered, HitCount, InFlight};
+use std::future::Future;
+
+#[derive(Variant)]
+pub enum Route<T> {
+    Index,
+    Page(T),
+    Search { query: String },
+}
+
+#[derive(Default, Debug)]
+pub struct Server {
+    metrics: ServerMetrics,
+}
+
+#[metered(registry = ServerMetrics, toggle = true)]
+impl Server {
+    #[measure(type = [HitCount, InFlight], by = route)]
+    pub fn serve(&self, route: Route<u32>) -> usize {
+        match route {
+            Route::Index => 0,
+            Route::Page(page) => page as usize,
+            Route::Search { query } => query.len(),
+        }
+    }
+
+    #[measure(type = HitCount, by = route)]
+    pub async fn prefetch(&self, route: &Route<u32>) {
+        let _ = route;
+    }
+}
+
+#[test]
+fn measures_by_variant() {
+    let server = Server::default();
+    assert_eq!(server.serve(Route::Page(3)), 3);
+    assert_eq!(server.serve(Route::Page(4)), 4);
+    server.serve(Route::Search {
+        query: "q".to_string(),
+    });
+
+    let hits = &server.metrics.serve.hit_count;
+    assert_eq!(hits.get("Index").unwrap().get(), 0);
+    assert_eq!(hits.get("Page").unwrap().get(), 2);
+    assert_eq!(
+        hits.by(&Route::Search {
+            query: String::new()
+        })
+        .get(),
+        1
+    );
+    assert_eq!(server.metrics.serve.in_flight.get("Page").unwrap().get(), 0);
+
+    server.metrics.serve.toggle.disable();
+    server.serve(Route::Index);
+    assert_eq!(hits.get("Index").unwrap().get(), 0);
+}
+
+#[test]
+fn measures_by_referenced_variant() {
+    let server = Server::default();
+    let fut = server.prefetch(&Route::Index);
+    let mut fut = std::pin::pin!(fut);
+    let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+    assert!(fut.as_mut().poll(&mut cx).is_ready());
+
+    assert_eq!(
+        server
+            .metrics
+            .prefetch
+            .hit_count
+            .get("Index")
+            .unwrap()
+            .get(),
+        1
+    );
+    assert_eq!(Route::<u32>::VARIANTS, ["Index", "Page", "Search"]);
+}
@@ -0,0 +1,58 @@
+// Registries are empty with the `disabled` feature
+#![cfg(not(feature = "disabled"))]
+
+use metered::{
+    clear::{Clear, Clearable},
+    merge::Merge,
+    metadata::DescribeMetrics,
+    metered, ErrorCount, HitCount,
+};
+
+#[derive(Default, Debug)]
+pub struct Service {
+    metrics: ServiceMetrics,
+}
+
+#[metered(registry = ServiceMetrics, skip_cleared = true, merge = true)]
+impl Service {
+    #[measure(HitCount)]
+    #[measure(cfg(any()), type = [metered::InFlight, metered::ResponseTime])]
+    #[measure(cfg(not(any())), type = ErrorCount, rename = "failures")]
+    pub fn call(&self, fail: bool) -> Result<(), ()> {
+        if fail {
+            Err(())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn compiles_out_metrics() {
+    let service = Service::default();
+    service.call(false).unwrap();
+    service.call(true).unwrap_err();
+
+    assert_eq!(service.metrics.call.hit_count.get(), 2);
+    assert_eq!(service.metrics.call.error_count.get(), 1);
+    assert_eq!(
+        std::mem::size_of_val(&service.metrics.call),
+        2 * std::mem::size_of::<HitCount>()
+    );
+
+    let paths: Vec<_> = ServiceMetrics::describe_metrics()
+        .into_iter()
+        .map(|description| description.path.join("."))
+        .collect();
+    assert_eq!(paths, ["call.hit_count", "call.failures"]);
+    assert_eq!(
+        ServiceMetrics::METRIC_PATHS,
+        [["call", "hit_count"], ["call", "failures"]]
+    );
+
+    let merged = ServiceMetrics::default();
+    merged.merge_from(&service.metrics);
+    assert_eq!(merged.call.error_count.get(), 1);
+    merged.clear();
+    assert!(merged.is_cleared());
+}
@@ -0,0 +1,42 @@
+// Registries are empty with the `disabled` feature
+#![cfg(not(feature = "disabled"))]
+
+use metered::{common::ValueHistogram, metered, HitCount};
+
+#[derive(Default, Debug)]
+pub struct Cache {
+    metrics: CacheMetrics,
+}
+
+#[metered(registry = CacheMetrics, toggle = true)]
+impl Cache {
+    #[measure(HitCount)]
+    #[measure(type = ValueHistogram, record = result.as_ref().map_or(*limit as u64, |v| v.len() as u64))]
+    pub fn fetch(&self, limit: &usize) -> Option<Vec<u8>> {
+        if *limit == 0 {
+            return None;
+        }
+        Some(vec![0; *limit])
+    }
+}
+
+#[test]
+fn records_values_derived_from_results() {
+    let cache = Cache::default();
+    assert_eq!(cache.fetch(&4), Some(vec![0; 4]));
+    assert_eq!(cache.fetch(&0), None);
+
+    let histogram = cache.metrics.fetch.value_histogram.histogram();
+    assert_eq!(histogram.len(), 2);
+    assert_eq!(histogram.max(), 4);
+    assert_eq!(histogram.min(), 0);
+    assert_eq!(cache.metrics.fetch.hit_count.get(), 2);
+}
+
+#[test]
+fn skips_recording_when_disabled() {
+    let cache = Cache::default();
+    cache.metrics.fetch.toggle.disable();
+    cache.fetch(&8);
+    assert_eq!(cache.metrics.fetch.value_histogram.histogram().len(), 0);
+}
@@ -0,0 +1,122 @@
+#[metered::metered(registry = ComponentMetrics, skip_cleared = true, merge = true)]
+mod component {
+    use metered::{HitCount, InFlight};
+
+    #[derive(Default, Debug)]
+    pub struct Cache;
+
+    impl Cache {
+        #[measure(HitCount)]
+        pub fn get(&self) -> Option<u8> {
+            None
+        }
+
+        pub fn capacity(&self) -> usize {
+            0
+        }
+    }
+
+    #[derive(Default, Debug)]
+    pub struct StoreClient;
+
+    #[measure(InFlight)]
+    impl StoreClient {
+        #[measure(HitCount)]
+        pub fn put(&self, _value: u8) {}
+    }
+
+    #[measure(HitCount)]
+    pub fn parse(input: &str) -> Option<u8> {
+        input.parse().ok()
+    }
+
+    pub fn unmeasured() {}
+}
+
+#[metered::metered(registry = DetachedMetrics, registry_expr = registry())]
+mod detached {
+    use metered::HitCount;
+
+    pub fn registry() -> &'static DetachedMetrics {
+        static REGISTRY: std::sync::OnceLock<DetachedMetrics> = std::sync::OnceLock::new();
+        REGISTRY.get_or_init(Default::default)
+    }
+
+    #[measure(HitCount)]
+    pub fn fetch() -> u8 {
+        1
+    }
+}
+
+use metered::{
+    clear::{Clear, Clearable},
+    flatten::to_samples,
+    merge::Merge,
+    metadata::DescribeMetrics,
+};
+
+#[test]
+#[cfg(not(feature = "disabled"))]
+fn gathers_impl_blocks_and_functions() {
+    let metrics = &component::METRICS;
+    let _ = component::Cache.get();
+    let _ = component::Cache.capacity();
+    component::StoreClient.put(1);
+    component::StoreClient.put(2);
+    let _ = component::parse("3");
+    component::unmeasured();
+
+    assert_eq!(metrics.cache.get.hit_count.get(), 1);
+    assert_eq!(metrics.store_client.put.hit_count.get(), 2);
+    assert_eq!(metrics.store_client.put.in_flight.get(), 0);
+    assert_eq!(metrics.functions.parse.hit_count.get(), 1);
+
+    let paths: Vec<_> = component::ComponentMetrics::describe_metrics()
+        .into_iter()
+        .map(|description| description.path.join("."))
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            "cache.get.hit_count",
+            "store_client.put.in_flight",
+            "store_client.put.hit_count",
+            "functions.parse.hit_count",
+        ]
+    );
+
+    let merged = component::ComponentMetrics::default();
+    merged.merge_from(metrics);
+    assert_eq!(merged.store_client.put.hit_count.get(), 2);
+    merged.clear();
+    assert_eq!(merged.store_client.put.hit_count.get(), 0);
+    assert!(merged.is_cleared());
+    assert!(to_samples(&merged).unwrap().is_empty());
+}
+
+#[test]
+#[cfg(not(feature = "disabled"))]
+fn uses_registry_expr() {
+    assert_eq!(detached::fetch(), 1);
+    assert_eq!(detached::registry().functions.fetch.hit_count.get(), 1);
+}
+
+// Registries are empty with the `disabled` feature
+#[test]
+#[cfg(feature = "disabled")]
+fn generates_empty_registries_when_disabled() {
+    let _ = component::Cache.get();
+    let _ = component::Cache.capacity();
+    component::StoreClient.put(1);
+    let _ = component::parse("3");
+    component::unmeasured();
+    assert_eq!(detached::fetch(), 1);
+    detached::registry().clear();
+
+    let metrics = &*component::METRICS;
+    assert!(component::ComponentMetrics::describe_metrics().is_empty());
+    assert!(to_samples(metrics).unwrap().is_empty());
+    component::ComponentMetrics::default().merge_from(metrics);
+    metrics.clear();
+    assert!(metrics.is_cleared());
+}
@@ -0,0 +1,150 @@
+use metered::{
+    clear::{Clear, Clearable},
+    error_count,
+    flatten::to_samples,
+    merge::Merge,
+};
+use std::sync::Arc;
+use thiserror::Error;
+
+#[error_count(name = DiskErrorCount, visibility = pub, skip_cleared = false)]
+#[derive(Debug, Error)]
+pub enum DiskError {
+    #[error("disk full")]
+    Full,
+    #[error("bad sector")]
+    BadSector,
+}
+
+#[error_count(name = StorageErrorCount, visibility = pub, skip_cleared = false)]
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("disk error: {0}")]
+    Disk(#[nested] Box<DiskError>),
+    #[error("corrupted")]
+    Corrupted,
+}
+
+#[error_count(name = RepoErrorCount, visibility = pub, skip_cleared = false)]
+#[derive(Debug, Error)]
+pub enum RepoError {
+    #[error("storage error: {source}")]
+    Storage {
+        #[nested]
+        source: StorageError,
+    },
+    #[error("conflict")]
+    Conflict,
+}
+
+#[error_count(name = ServiceErrorCount, visibility = pub, skip_cleared = false)]
+#[derive(Debug, Error)]
+pub enum ServiceError {
+    #[error("repository error: {0}")]
+    Repo(
+        #[from]
+        #[nested]
+        Arc<RepoError>,
+    ),
+    #[error("unauthorized")]
+    Unauthorized,
+}
+
+#[error_count(name = SkippingErrorCount, visibility = pub, skip_cleared = true)]
+#[derive(Debug, Error)]
+pub enum SkippingError {
+    #[error("repository error: {0}")]
+    Repo(#[nested] RepoError),
+    #[error("unauthorized")]
+    Unauthorized,
+}
+
+fn bad_sector() -> RepoError {
+    RepoError::Storage {
+        source: StorageError::Disk(Box::new(DiskError::BadSector)),
+    }
+}
+
+fn variants<T: serde::Serialize>(count: &T) -> Vec<(String, f64)> {
+    to_samples(count)
+        .unwrap()
+        .into_iter()
+        .map(|sample| (sample.labels[0].1.clone(), sample.value))
+        .collect()
+}
+
+#[test]
+fn counts_through_four_levels() {
+    let count = ServiceErrorCount::<metered::atomic::AtomicInt<u64>>::default();
+    metered::ErrorBreakdownIncr::incr(&count, &ServiceError::from(Arc::new(bad_sector())));
+    metered::ErrorBreakdownIncr::incr(&count, &ServiceError::Unauthorized);
+
+    assert_eq!(count.repo.storage.disk.bad_sector.get(), 1);
+    assert_eq!(count.repo.storage.disk.full.get(), 0);
+    assert_eq!(count.unauthorized.get(), 1);
+    assert_eq!(
+        variants(&count),
+        [
+            ("Repo::Storage::Disk::Full".to_string(), 0.0),
+            ("Repo::Storage::Disk::BadSector".to_string(), 1.0),
+            ("Repo::Storage::Corrupted".to_string(), 0.0),
+            ("Repo::Conflict".to_string(), 0.0),
+            ("Unauthorized".to_string(), 1.0),
+        ]
+    );
+}
+
+#[test]
+fn iterates_over_nested_counts() {
+    let count = ServiceErrorCount::<metered::atomic::AtomicInt<u64>>::default();
+    metered::ErrorBreakdownIncr::incr(&count, &ServiceError::from(Arc::new(bad_sector())));
+    metered::ErrorBreakdownIncr::incr(&count, &ServiceError::Unauthorized);
+    metered::ErrorBreakdownIncr::incr(&count, &ServiceError::Unauthorized);
+
+    let counts: Vec<_> = count.iter().filter(|(_, count)| *count > 0).collect();
+    assert_eq!(
+        counts,
+        [
+            ("Repo::Storage::Disk::BadSector".to_string(), 1),
+            ("Unauthorized".to_string(), 2),
+        ]
+    );
+    assert_eq!(count.iter().count(), 5);
+    assert_eq!(count.total(), 3);
+    assert_eq!(count.repo.total(), 1);
+}
+
+#[test]
+fn clears_and_merges_through_three_levels() {
+    let count = RepoErrorCount::<metered::atomic::AtomicInt<u64>>::default();
+    let other = RepoErrorCount::default();
+    metered::ErrorBreakdownIncr::incr(&other, &bad_sector());
+    metered::ErrorBreakdownIncr::incr(&other, &bad_sector());
+
+    count.merge_from(&other);
+    assert_eq!(count.storage.disk.bad_sector.get(), 2);
+    assert!(!count.is_cleared());
+
+    count.clear();
+    assert!(count.is_cleared());
+    assert_eq!(count.storage.disk.bad_sector.get(), 0);
+}
+
+#[test]
+fn skips_cleared_nested_counts() {
+    let count = SkippingErrorCount::<metered::atomic::AtomicInt<u64>>::default();
+    metered::ErrorBreakdownIncr::incr(&count, &SkippingError::Unauthorized);
+    assert_eq!(variants(&count), [("Unauthorized".to_string(), 1.0)]);
+
+    metered::ErrorBreakdownIncr::incr(&count, &SkippingError::Repo(RepoError::Conflict));
+    assert_eq!(
+        variants(&count),
+        [
+            ("Repo::Storage::Disk::Full".to_string(), 0.0),
+            ("Repo::Storage::Disk::BadSector".to_string(), 0.0),
+            ("Repo::Storage::Corrupted".to_string(), 0.0),
+            ("Repo::Conflict".to_string(), 1.0),
+            ("Unauthorized".to_string(), 1.0),
+        ]
+    );
+}
@@ -0,0 +1,94 @@
+// Registries are empty with the `disabled` feature
+#![cfg(not(feature = "disabled"))]
+
+use metered::{
+    common::ExclusiveTime,
+    hdr_histogram::AtomicHdrHistogram,
+    metered,
+    simulation::{SimInstant, Simulation},
+    toggle::Toggles,
+    HitCount, ResponseTime,
+};
+use std::{thread, time::Duration};
+
+type Exclusive = ExclusiveTime<AtomicHdrHistogram, SimInstant>;
+type Total = ResponseTime<AtomicHdrHistogram, SimInstant>;
+
+#[derive(Default, Debug)]
+pub struct Tree {
+    metrics: TreeMetrics,
+}
+
+#[metered(registry = TreeMetrics, skip_nested = true, toggle = true)]
+impl Tree {
+    #[measure(HitCount)]
+    pub fn walk(&self, depth: u32) -> u32 {
+        if depth == 0 {
+            return 0;
+        }
+        1 + self.walk(depth - 1)
+    }
+
+    #[measure(HitCount)]
+    pub fn visit(&self) -> u32 {
+        self.walk(3)
+    }
+}
+
+#[test]
+fn skips_nested_calls() {
+    let tree = Tree::default();
+    assert_eq!(tree.walk(3), 3);
+    assert_eq!(tree.metrics.walk.hit_count.get(), 1);
+
+    tree.visit();
+    assert_eq!(tree.metrics.visit.hit_count.get(), 1);
+    assert_eq!(tree.metrics.walk.hit_count.get(), 1);
+
+    // Other registries are measured as usual
+    let other = Tree::default();
+    let tree = &tree;
+    thread::scope(|s| {
+        s.spawn(|| tree.walk(2));
+    });
+    other.walk(1);
+    assert_eq!(tree.metrics.walk.hit_count.get(), 2);
+    assert_eq!(other.metrics.walk.hit_count.get(), 1);
+
+    tree.metrics.toggle().disable();
+    tree.walk(1);
+    assert_eq!(tree.metrics.walk.hit_count.get(), 2);
+}
+
+#[derive(Default, Debug)]
+pub struct Pipeline {
+    metrics: PipelineMetrics,
+}
+
+#[metered(registry = PipelineMetrics)]
+impl Pipeline {
+    #[measure(Exclusive)]
+    pub fn stage(&self, simulation: &Simulation) {
+        simulation.advance(Duration::from_millis(20));
+    }
+
+    #[measure(type = [Exclusive, Total])]
+    pub fn run(&self, simulation: &Simulation) {
+        self.stage(simulation);
+        self.stage(simulation);
+    }
+}
+
+#[test]
+fn measures_exclusive_time() {
+    let simulation = Simulation::start(0);
+    let pipeline = Pipeline::default();
+    pipeline.run(&simulation);
+
+    let run = &pipeline.metrics.run;
+    let stage = pipeline.metrics.stage.exclusive.histogram();
+    assert_eq!(stage.len(), 2);
+    assert_eq!(stage.min(), 20);
+    assert_eq!(run.total.histogram().max(), 40);
+    assert_eq!(run.exclusive.histogram().max(), 0);
+}
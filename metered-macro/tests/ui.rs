@@ -0,0 +1,9 @@
+// Measured code compiles untouched, without diagnostics, with the `disabled`
+// feature
+#![cfg(not(feature = "disabled"))]
+
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,14 @@
+use metered::metered;
+
+#[derive(Default, Debug)]
+pub struct Biz {
+    metrics: BizMetrics,
+}
+
+#[metered(registry = BizMetrics)]
+impl Biz {
+    #[measure(type = metered::HitCount, rename = "hits", rename = "calls")]
+    pub fn biz(&self) {}
+}
+
+fn main() {}
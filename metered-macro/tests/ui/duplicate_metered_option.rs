@@ -0,0 +1,14 @@
+use metered::metered;
+
+#[derive(Default, Debug)]
+pub struct Biz {
+    metrics: BizMetrics,
+}
+
+#[metered(registry = BizMetrics, visibility = pub, visibility = pub(crate))]
+impl Biz {
+    #[measure(metered::HitCount)]
+    pub fn biz(&self) {}
+}
+
+fn main() {}
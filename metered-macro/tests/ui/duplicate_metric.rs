@@ -0,0 +1,15 @@
+use metered::metered;
+
+#[derive(Default, Debug)]
+pub struct Biz {
+    metrics: BizMetrics,
+}
+
+#[metered(registry = BizMetrics)]
+impl Biz {
+    #[measure([metered::HitCount, metered::ResponseTime])]
+    #[measure(metered::HitCount)]
+    pub fn biz(&self) {}
+}
+
+fn main() {}
@@ -0,0 +1,28 @@
+use metered::{error_count, metered};
+
+#[error_count(name = LibErrorCount)]
+#[derive(Debug)]
+pub enum LibError {
+    ReadError,
+}
+
+#[derive(Default, Debug)]
+pub struct Biz {
+    metrics: BizMetrics,
+}
+
+#[metered(registry = BizMetrics)]
+#[measure(LibErrorCount)]
+impl Biz {
+    #[measure]
+    pub fn read(&self) -> Result<(), LibError> {
+        Err(LibError::ReadError)
+    }
+
+    #[measure]
+    pub fn name(&self) -> String {
+        String::new()
+    }
+}
+
+fn main() {}
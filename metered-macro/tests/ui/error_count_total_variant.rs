@@ -0,0 +1,9 @@
+use metered::error_count;
+
+#[error_count(name = LibErrorCount, with_total = true)]
+#[derive(Debug)]
+pub enum LibError {
+    Total,
+}
+
+fn main() {}
@@ -0,0 +1,17 @@
+use metered::metered;
+
+#[derive(Default, Debug)]
+pub struct Biz {
+    metrics: BizMetrics,
+}
+
+#[metered(registry = BizMetrics)]
+impl Biz {
+    #[measure(
+        type = [metered::HitCount, metered::ResponseTime],
+        init = metered::ResponseTime::builder()
+    )]
+    pub fn biz(&self) {}
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use metered::error_count;
+
+#[error_count(name = LibErrorCount, label = "error kind")]
+#[derive(Debug)]
+pub enum LibError {
+    ReadError,
+}
+
+fn main() {}
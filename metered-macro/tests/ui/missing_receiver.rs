@@ -0,0 +1,14 @@
+use metered::metered;
+
+#[derive(Default, Debug)]
+pub struct Biz {
+    metrics: BizMetrics,
+}
+
+#[metered(registry = BizMetrics)]
+impl Biz {
+    #[measure(metered::HitCount)]
+    pub fn biz() {}
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use metered::metered;
+
+#[derive(Default, Debug)]
+pub struct Biz;
+
+#[metered]
+impl Biz {
+    #[measure(metered::HitCount)]
+    pub fn biz(&self) {}
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+#[metered::metered(registry = ComponentMetrics, toggle = true)]
+mod component {
+    #[measure(metered::HitCount)]
+    pub fn parse() {}
+}
+
+fn main() {}
@@ -0,0 +1,20 @@
+mod service {
+    use metered::metered;
+
+    #[derive(Default, Debug)]
+    pub struct Biz {
+        pub metrics: BizMetrics,
+    }
+
+    #[metered(registry = BizMetrics, visibility = pub, field_visibility = pub(self))]
+    impl Biz {
+        #[measure(metered::HitCount)]
+        pub fn biz(&self) {}
+    }
+}
+
+fn main() {
+    let biz = service::Biz::default();
+    biz.biz();
+    let _ = biz.metrics.biz.hit_count.get();
+}
@@ -0,0 +1,14 @@
+use metered::metered;
+
+#[derive(Default, Debug)]
+pub struct Biz {
+    metrics: BizMetrics,
+}
+
+#[metered(registry = BizMetrics, skip_nested = true)]
+impl Biz {
+    #[measure(metered::HitCount)]
+    pub async fn biz(&self) {}
+}
+
+fn main() {}
@@ -0,0 +1,25 @@
+use metered::metered;
+
+#[metered(registry = BizMetrics)]
+#[derive(Default, Debug)]
+pub struct Biz {
+    metrics: BizMetrics,
+}
+
+#[metered(registry = BizMetrics, registry_expr = self.inner.metrics)]
+#[derive(Default, Debug)]
+pub struct Baz {
+    inner: Biz,
+}
+
+#[metered(registry = BizMetrics, skip_cleared = true)]
+#[derive(Default, Debug)]
+pub struct Buz {}
+
+#[metered(registry = BizMetrics)]
+impl Biz {
+    #[measure(metered::HitCount)]
+    pub fn biz(&self) {}
+}
+
+fn main() {}
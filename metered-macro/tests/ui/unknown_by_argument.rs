@@ -0,0 +1,20 @@
+use metered::{breakdown::Variant, metered};
+
+#[derive(Variant)]
+pub enum Op {
+    Read,
+    Write,
+}
+
+#[derive(Default, Debug)]
+pub struct Disk {
+    metrics: DiskMetrics,
+}
+
+#[metered(registry = DiskMetrics)]
+impl Disk {
+    #[measure(type = metered::HitCount, by = kind)]
+    pub fn io(&self, op: Op) {}
+}
+
+fn main() {}
@@ -0,0 +1,18 @@
+use metered::metered;
+
+#[derive(Default, Debug)]
+pub struct Biz {
+    metrics: BizMetrics,
+}
+
+#[metered(registry = BizMetrics, warn_unmeasured = deny)]
+impl Biz {
+    #[measure(metered::HitCount)]
+    pub fn biz(&self) {}
+
+    pub fn baz(&self) {}
+
+    fn internal(&self) {}
+}
+
+fn main() {}
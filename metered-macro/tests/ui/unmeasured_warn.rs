@@ -0,0 +1,20 @@
+#![deny(deprecated)]
+
+use metered::metered;
+
+#[derive(Default, Debug)]
+pub struct Biz {
+    metrics: BizMetrics,
+}
+
+#[metered(registry = BizMetrics, warn_unmeasured = true)]
+impl Biz {
+    #[measure(metered::HitCount)]
+    pub fn biz(&self) {}
+
+    pub fn baz(&self) {}
+}
+
+fn main() {
+    Biz::default().baz();
+}
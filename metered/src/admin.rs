@@ -0,0 +1,788 @@
+//! A module providing a tiny admin HTTP server for registries, for small
+//! services without a metrics stack: it exposes their metrics, and lets
+//! operators clear them or toggle them at runtime.
+//!
+//! * `GET /metrics` lists the samples of the registries, see
+//!   [`flatten::to_samples`], in the Prometheus text format with a `registry`
+//!   label,
+//! * `POST /metrics/clear` clears the registries, or only one of them with
+//!   `?registry=<name>`, or only a method or metric of the registries with
+//!   `&path=<path>`, e.g. `path=call.response_time`, see [`ClearPath`],
+//! * `POST /metrics/toggle?enabled=<true|false>` enables or disables the
+//!   registries registered with [`Admin::register_toggled`], or one of them
+//!   with `&registry=<name>`, or one of its methods with `&method=<name>`.
+//!
+#![cfg_attr(feature = "disabled", doc = "```ignore")]
+#![cfg_attr(not(feature = "disabled"), doc = "```rust,no_run")]
+//! use metered::{admin::Admin, metered, HitCount};
+//! use std::sync::Arc;
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Service {
+//!     metrics: ServiceMetrics,
+//! }
+//!
+//! #[metered(registry = ServiceMetrics, toggle = true)]
+//! impl Service {
+//!     #[measure(HitCount)]
+//!     pub fn call(&self) {}
+//! }
+//!
+//! let service = Arc::new(Service::default());
+//! let admin = Admin::new()
+//!     .register_toggled("service", Arc::clone(&service), |service| &service.metrics)
+//!     .spawn("127.0.0.1:9100")
+//!     .unwrap();
+//!
+//! // curl -X POST 'http://127.0.0.1:9100/metrics/toggle?enabled=false&method=call'
+//! service.call();
+//! admin.stop();
+//! ```
+//!
+//! Each connection is served on a short-lived thread, with timeouts to send
+//! its request and read the response, so that stalled clients only delay
+//! themselves. To serve the endpoints from another HTTP server, see
+//! [`Admin::respond`].
+//!
+//! On Unix, [`Admin::spawn_unix`] instead accepts commands on a Unix domain
+//! socket, for operators to reach the metrics of a running process without
+//! exposing a port, see [`Admin::command`]:
+//!
+//! ```text
+//! $ echo 'disable service.call' | nc -U /run/service/metrics.sock
+//! disabled 1 registries
+//! ```
+//!
+//! This module is only available when the `admin` feature is enabled.
+
+use crate::{
+    clear::ClearPath,
+    flatten::{self, Sample},
+    toggle::Toggles,
+};
+use serde::Serialize;
+use std::{
+    fmt::{self, Write as _},
+    io::{self, BufRead, BufReader, Write as _},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+};
+
+/// How long a connection may take to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a connection may take to read its response
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The most connections served at once, further connections being closed
+const MAX_CONNECTIONS: usize = 16;
+
+/// The longest request head read, request lines and headers included
+const MAX_HEAD: u64 = 8 * 1024;
+
+type Samples = Box<dyn Fn() -> Result<Vec<Sample>, flatten::Error> + Send + Sync>;
+type ClearFn = Box<dyn Fn(&[&str]) -> bool + Send + Sync>;
+type Toggle = Box<dyn Fn(Option<&str>, bool) -> bool + Send + Sync>;
+
+struct Registered {
+    name: String,
+    samples: Samples,
+    clear: ClearFn,
+    toggle: Option<Toggle>,
+}
+
+/// The admin endpoints of a set of registries, served by [`Admin::spawn`].
+#[derive(Default)]
+pub struct Admin {
+    registries: Vec<Registered>,
+}
+
+/// The response of an admin endpoint, see [`Admin::respond`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdminResponse {
+    /// The HTTP status code
+    pub status: u16,
+    /// The plain text body
+    pub body: String,
+}
+
+impl AdminResponse {
+    fn new(status: u16, body: impl Into<String>) -> Self {
+        AdminResponse {
+            status,
+            body: body.into(),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+impl Admin {
+    /// Creates admin endpoints without registries
+    pub fn new() -> Self {
+        Admin::default()
+    }
+
+    /// Registers a registry owned by a shared value, usually the measured
+    /// service, under a name
+    pub fn register<T, R>(
+        mut self,
+        name: impl Into<String>,
+        owner: Arc<T>,
+        registry: fn(&T) -> &R,
+    ) -> Self
+    where
+        T: Send + Sync + 'static,
+        R: Serialize + ClearPath + 'static,
+    {
+        let clear_owner = Arc::clone(&owner);
+        self.registries.push(Registered {
+            name: name.into(),
+            samples: Box::new(move || flatten::to_samples(registry(&owner))),
+            clear: Box::new(move |path| registry(&clear_owner).clear_path(path)),
+            toggle: None,
+        });
+        self
+    }
+
+    /// Registers a registry generated with `toggle = true`, which can also be
+    /// toggled
+    pub fn register_toggled<T, R>(
+        self,
+        name: impl Into<String>,
+        owner: Arc<T>,
+        registry: fn(&T) -> &R,
+    ) -> Self
+    where
+        T: Send + Sync + 'static,
+        R: Serialize + ClearPath + Toggles + 'static,
+    {
+        let toggle_owner = Arc::clone(&owner);
+        let mut admin = self.register(name, owner, registry);
+        if let Some(registered) = admin.registries.last_mut() {
+            registered.toggle = Some(Box::new(move |method, enabled| {
+                let registry = registry(&toggle_owner);
+                let toggle = match method {
+                    Some(method) => registry.method_toggle(method),
+                    None => Some(registry.toggle()),
+                };
+                toggle.map(|toggle| toggle.set(enabled)).is_some()
+            }));
+        }
+        admin
+    }
+
+    /// Responds to a request, given its method and target, such as `GET` and
+    /// `/metrics`
+    pub fn respond(&self, method: &str, target: &str) -> AdminResponse {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let param = |key: &str| {
+            query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v)
+        };
+
+        match (method, path) {
+            ("GET", "/metrics") => self.metrics(),
+            ("POST", "/metrics/clear") => self.clear(param("registry"), param("path")),
+            ("POST", "/metrics/toggle") => match param("enabled").map(str::parse) {
+                Some(Ok(enabled)) => self.toggle(param("registry"), param("method"), enabled),
+                _ => AdminResponse::new(400, "expected `enabled=true` or `enabled=false`\n"),
+            },
+            (_, "/metrics") | (_, "/metrics/clear") | (_, "/metrics/toggle") => {
+                AdminResponse::new(405, "method not allowed\n")
+            }
+            _ => AdminResponse::new(404, "not found\n"),
+        }
+    }
+
+    /// Responds to a command, as accepted by [`Admin::spawn_unix`]:
+    ///
+    /// * `dump` lists the samples of the registries, like `GET /metrics`,
+    /// * `clear [<registry>[.<path>]]` clears all registries, one of them, or
+    ///   one of its methods or metrics, e.g. `service.call.response_time`,
+    /// * `enable [<registry>[.<method>]]` and `disable [<registry>[.<method>]]`
+    ///   toggle all registries, one of them, or one of its methods.
+    pub fn command(&self, command: &str) -> AdminResponse {
+        let mut words = command.split_whitespace();
+        let (command, arg) = (words.next(), words.next());
+        if words.next().is_some() {
+            return AdminResponse::new(400, "too many arguments\n");
+        }
+
+        match (command, arg) {
+            (Some("dump"), None) => self.metrics(),
+            (Some("clear"), path) => {
+                let (registry, path) = split_path(path);
+                self.clear(registry, path)
+            }
+            (Some(toggle @ "enable"), path) | (Some(toggle @ "disable"), path) => {
+                let (registry, method) = split_path(path);
+                self.toggle(registry, method, toggle == "enable")
+            }
+            _ => AdminResponse::new(
+                400,
+                "expected `dump`, `clear [<registry>]`, `enable [<path>]` or `disable [<path>]`\n",
+            ),
+        }
+    }
+
+    fn metrics(&self) -> AdminResponse {
+        let mut body = String::new();
+        for registered in self.registries.iter() {
+            let samples = match (registered.samples)() {
+                Ok(samples) => samples,
+                Err(e) => {
+                    let err = format!("could not serialize `{}`: {}\n", registered.name, e);
+                    return AdminResponse::new(500, err);
+                }
+            };
+            for sample in samples.iter() {
+                let _ = write_sample(&mut body, &registered.name, sample);
+            }
+        }
+        AdminResponse::new(200, body)
+    }
+
+    fn clear(&self, name: Option<&str>, path: Option<&str>) -> AdminResponse {
+        let names: Vec<&str> = path
+            .into_iter()
+            .flat_map(|path| path.split('.'))
+            .filter(|name| !name.is_empty())
+            .collect();
+        let (mut selected, mut cleared) = (0, 0);
+        for registered in self.selected(name) {
+            selected += 1;
+            if (registered.clear)(&names) {
+                cleared += 1;
+            }
+        }
+        match (selected, cleared, name, path) {
+            (0, _, Some(name), _) => {
+                AdminResponse::new(404, format!("unknown registry `{}`\n", name))
+            }
+            (_, 0, _, Some(path)) => AdminResponse::new(404, format!("unknown path `{}`\n", path)),
+            _ => AdminResponse::new(200, format!("cleared {} registries\n", cleared)),
+        }
+    }
+
+    fn toggle(&self, name: Option<&str>, method: Option<&str>, enabled: bool) -> AdminResponse {
+        let mut toggled = 0;
+        for registered in self.selected(name) {
+            let toggle = match registered.toggle {
+                Some(ref toggle) => toggle,
+                None if name.is_some() => {
+                    let err = format!("registry `{}` has no toggles\n", registered.name);
+                    return AdminResponse::new(404, err);
+                }
+                None => continue,
+            };
+            if toggle(method, enabled) {
+                toggled += 1;
+            }
+        }
+        match (toggled, name, method) {
+            (0, _, Some(method)) => {
+                AdminResponse::new(404, format!("unknown method `{}`\n", method))
+            }
+            (0, Some(name), None) => {
+                AdminResponse::new(404, format!("unknown registry `{}`\n", name))
+            }
+            _ => {
+                let state = if enabled { "enabled" } else { "disabled" };
+                AdminResponse::new(200, format!("{} {} registries\n", state, toggled))
+            }
+        }
+    }
+
+    fn selected<'a>(&'a self, name: Option<&'a str>) -> impl Iterator<Item = &'a Registered> {
+        self.registries
+            .iter()
+            .filter(move |registered| name.map_or(true, |name| registered.name == name))
+    }
+
+    /// Spawns a thread serving the endpoints on a listener bound to `addr`,
+    /// until the returned handle is stopped or dropped
+    pub fn spawn(self, addr: impl ToSocketAddrs) -> io::Result<AdminHandle> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let thread_stopped = Arc::clone(&stopped);
+        let admin = Arc::new(self);
+        let thread = thread::Builder::new()
+            .name("metered-admin".to_string())
+            .spawn(move || accept(admin, listener.incoming(), &thread_stopped, Admin::serve))?;
+
+        Ok(AdminHandle {
+            local_addr,
+            stopped,
+            thread: Some(thread),
+        })
+    }
+
+    fn serve(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let mut reader = BufReader::new(io::Read::take(&stream, MAX_HEAD));
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // Headers are ignored, but read so that clients see their request
+        // consumed
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let response = match (parts.next(), parts.next()) {
+            (Some(method), Some(target)) => self.respond(method, target),
+            _ => AdminResponse::new(400, "malformed request\n"),
+        };
+
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            response.status,
+            response.reason(),
+            response.body.len(),
+            response.body
+        )?;
+        stream.flush()
+    }
+}
+
+#[cfg(unix)]
+impl Admin {
+    /// Spawns a thread accepting one command per connection on a Unix domain
+    /// socket created at `path`, until the returned handle is stopped or
+    /// dropped, which removes the socket
+    ///
+    /// Failed commands are answered with their error prefixed with `error: `.
+    /// Binding fails if a file already exists at `path`.
+    pub fn spawn_unix(self, path: impl AsRef<Path>) -> io::Result<AdminSocketHandle> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path)?;
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let thread_stopped = Arc::clone(&stopped);
+        let admin = Arc::new(self);
+        let thread = thread::Builder::new()
+            .name("metered-admin".to_string())
+            .spawn(move || {
+                accept(
+                    admin,
+                    listener.incoming(),
+                    &thread_stopped,
+                    Admin::serve_command,
+                )
+            })?;
+
+        Ok(AdminSocketHandle {
+            path,
+            stopped,
+            thread: Some(thread),
+        })
+    }
+
+    fn serve_command(&self, stream: UnixStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let mut reader = BufReader::new(io::Read::take(&stream, MAX_HEAD));
+        let mut command = String::new();
+        reader.read_line(&mut command)?;
+
+        let response = self.command(&command);
+        let mut stream = stream;
+        if response.status != 200 {
+            stream.write_all(b"error: ")?;
+        }
+        stream.write_all(response.body.as_bytes())?;
+        stream.flush()
+    }
+}
+
+impl fmt::Debug for Admin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Admin")
+            .field(
+                "registries",
+                &self.registries.iter().map(|r| &r.name).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+/// Accepts connections until stopped, serving each of them on its own thread
+fn accept<S, I>(
+    admin: Arc<Admin>,
+    incoming: I,
+    stopped: &AtomicBool,
+    serve: fn(&Admin, S) -> io::Result<()>,
+) where
+    S: Send + 'static,
+    I: Iterator<Item = io::Result<S>>,
+{
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in incoming {
+        if stopped.load(Ordering::Acquire) {
+            break;
+        }
+        // Failing connections only affect their client
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        // Connections over the limit are closed right away
+        if connections.fetch_add(1, Ordering::AcqRel) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::AcqRel);
+            continue;
+        }
+
+        let admin = Arc::clone(&admin);
+        let served = Arc::clone(&connections);
+        let spawned = thread::Builder::new()
+            .name("metered-admin-connection".to_string())
+            .spawn(move || {
+                let _ = serve(&admin, stream);
+                served.fetch_sub(1, Ordering::AcqRel);
+            });
+        if spawned.is_err() {
+            connections.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+/// Splits the path of a command in a registry name and the path within it,
+/// at the first dot
+fn split_path(path: Option<&str>) -> (Option<&str>, Option<&str>) {
+    match path.map(|path| path.split_once('.')) {
+        Some(Some((registry, rest))) => (Some(registry), Some(rest)),
+        Some(None) => (path, None),
+        None => (None, None),
+    }
+}
+
+/// Writes a sample in the Prometheus text format, with a `registry` label
+fn write_sample(out: &mut String, registry: &str, sample: &Sample) -> fmt::Result {
+    write!(out, "{}{{registry=\"{}\"", sample.name, escape(registry))?;
+    for (key, value) in sample.labels.iter() {
+        write!(out, ",{}=\"{}\"", key, escape(value))?;
+    }
+    writeln!(out, "}} {}", sample.value)
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// A handle on a running [`Admin`] server, stopping it when dropped.
+pub struct AdminHandle {
+    local_addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AdminHandle {
+    /// Get the address the server listens on, e.g. to find the port picked
+    /// for port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops the server, waiting for its thread to exit
+    pub fn stop(self) {
+        drop(self)
+    }
+}
+
+impl Drop for AdminHandle {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Release);
+        // Wakes the listener up, so that it sees it is stopped
+        let _ = TcpStream::connect(self.local_addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A handle on an [`Admin`] listening on a Unix domain socket, stopping it and
+/// removing the socket when dropped.
+#[cfg(unix)]
+pub struct AdminSocketHandle {
+    path: PathBuf,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[cfg(unix)]
+impl AdminSocketHandle {
+    /// Get the path of the socket
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Stops listening, waiting for the thread to exit
+    pub fn stop(self) {
+        drop(self)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for AdminSocketHandle {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Release);
+        // Wakes the listener up, so that it sees it is stopped
+        let _ = UnixStream::connect(&self.path);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+impl fmt::Debug for AdminSocketHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminSocketHandle")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl fmt::Debug for AdminHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminHandle")
+            .field("local_addr", &self.local_addr)
+            .finish()
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{clear::Clear, measure, toggle::Toggle, HitCount};
+    use std::io::Read;
+
+    #[derive(Default, Serialize)]
+    struct Registry {
+        call: HitCount,
+        #[serde(skip)]
+        toggle: Toggle,
+        #[serde(skip)]
+        call_toggle: Toggle,
+    }
+
+    impl Clear for Registry {
+        fn clear(&self) {
+            self.call.clear();
+        }
+    }
+
+    impl ClearPath for Registry {
+        fn clear_path(&self, path: &[&str]) -> bool {
+            match path {
+                [] => self.clear(),
+                ["call"] => self.call.clear(),
+                _ => return false,
+            }
+            true
+        }
+    }
+
+    impl Toggles for Registry {
+        fn toggle(&self) -> &Toggle {
+            &self.toggle
+        }
+
+        fn method_toggle(&self, method: &str) -> Option<&Toggle> {
+            match method {
+                "call" => Some(&self.call_toggle),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn responds_to_endpoints() {
+        let registry = Arc::new(Registry::default());
+        measure!(&registry.call, {});
+        let admin = Admin::new()
+            .register_toggled("a\"b", Arc::clone(&registry), |r| r)
+            .register("plain", Arc::new(Registry::default()), |r| r);
+
+        let metrics = admin.respond("GET", "/metrics");
+        assert_eq!(metrics.status, 200);
+        assert_eq!(
+            metrics.body,
+            "call{registry=\"a\\\"b\"} 1\ncall{registry=\"plain\"} 0\n"
+        );
+
+        let toggled = admin.respond("POST", "/metrics/toggle?method=call&enabled=false");
+        assert_eq!(toggled, AdminResponse::new(200, "disabled 1 registries\n"));
+        assert!(!registry.call_toggle.is_enabled());
+        assert!(registry.toggle.is_enabled());
+        assert_eq!(
+            admin.respond("POST", "/metrics/toggle?enabled=no").status,
+            400
+        );
+        assert_eq!(
+            admin
+                .respond("POST", "/metrics/toggle?registry=plain&enabled=true")
+                .status,
+            404
+        );
+        assert_eq!(
+            admin
+                .respond("POST", "/metrics/toggle?method=get&enabled=true")
+                .status,
+            404
+        );
+
+        assert_eq!(
+            admin.respond("POST", "/metrics/clear?registry=x").status,
+            404
+        );
+        assert_eq!(
+            admin.respond("POST", "/metrics/clear?path=call.x"),
+            AdminResponse::new(404, "unknown path `call.x`\n")
+        );
+        let cleared = admin.respond("POST", "/metrics/clear");
+        assert_eq!(cleared, AdminResponse::new(200, "cleared 2 registries\n"));
+        assert_eq!(registry.call.get(), 0);
+
+        assert_eq!(admin.respond("GET", "/metrics/clear").status, 405);
+        assert_eq!(admin.respond("GET", "/").status, 404);
+    }
+
+    #[test]
+    fn responds_to_commands() {
+        let registry = Arc::new(Registry::default());
+        measure!(&registry.call, {});
+        let admin = Admin::new().register_toggled("service", Arc::clone(&registry), |r| r);
+
+        assert_eq!(
+            admin.command("dump\n").body,
+            "call{registry=\"service\"} 1\n"
+        );
+        assert_eq!(admin.command("disable service.call").status, 200);
+        assert!(!registry.call_toggle.is_enabled());
+        assert_eq!(admin.command("disable").status, 200);
+        assert!(!registry.toggle.is_enabled());
+        assert_eq!(admin.command("enable service").status, 200);
+        assert!(registry.toggle.is_enabled());
+        assert_eq!(admin.command("enable other").status, 404);
+        assert_eq!(admin.command("clear service.other").status, 404);
+        assert_eq!(registry.call.get(), 1);
+        assert_eq!(
+            admin.command("clear service.call"),
+            AdminResponse::new(200, "cleared 1 registries\n")
+        );
+        assert_eq!(registry.call.get(), 0);
+        assert_eq!(admin.command("clear service").status, 200);
+        assert_eq!(admin.command("clear a b").status, 400);
+        assert_eq!(admin.command("").status, 400);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serves_over_unix_socket() {
+        use std::os::unix::net::UnixStream;
+
+        let registry = Arc::new(Registry::default());
+        let path = std::env::temp_dir().join(format!("metered-admin-{}.sock", std::process::id()));
+        let handle = Admin::new()
+            .register("service", registry, |r| r)
+            .spawn_unix(&path)
+            .unwrap();
+
+        let command = |command: &[u8]| {
+            let mut stream = UnixStream::connect(handle.path()).unwrap();
+            stream.write_all(command).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        assert_eq!(command(b"dump\n"), "call{registry=\"service\"} 0\n");
+        assert_eq!(command(b"enable\n"), "enabled 0 registries\n");
+        assert_eq!(
+            command(b"clear other\n"),
+            "error: unknown registry `other`\n"
+        );
+
+        handle.stop();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn serves_over_http() {
+        let registry = Arc::new(Registry::default());
+        measure!(&registry.call, {});
+        let handle = Admin::new()
+            .register("hits", registry, |r| r)
+            .spawn("127.0.0.1:0")
+            .unwrap();
+
+        let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\ncall{registry=\"hits\"} 1\n"));
+        handle.stop();
+    }
+
+    #[test]
+    fn serves_around_stalled_clients() {
+        let handle = Admin::new()
+            .register("hits", Arc::new(Registry::default()), |r| r)
+            .spawn("127.0.0.1:0")
+            .unwrap();
+
+        // Never completes its request, nor reads a response
+        let mut stalled = TcpStream::connect(handle.local_addr()).unwrap();
+        stalled.write_all(b"GET /metrics HTTP/1.1\r\n").unwrap();
+
+        let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        handle.stop();
+    }
+}
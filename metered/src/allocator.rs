@@ -0,0 +1,111 @@
+//! A module providing an instrumented global allocator, used by the
+//! [`AllocationCount`](crate::common::AllocationCount) metric.
+//!
+//! This module is only available when the `allocation-count` feature is
+//! enabled.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    static ALLOCATED_BYTES: Cell<u64> = const { Cell::new(0) };
+}
+
+/// A global allocator wrapper counting, for each thread, how many allocations
+/// were performed and how many bytes were requested.
+///
+/// It must be installed as the program's global allocator for the
+/// [`AllocationCount`](crate::common::AllocationCount) metric to report
+/// anything:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{allocator::CountingAllocator, measure, AllocationCount};
+///
+/// #[global_allocator]
+/// static GLOBAL: CountingAllocator = CountingAllocator::system();
+///
+/// let allocation_count: AllocationCount = AllocationCount::default();
+///
+/// measure!(&allocation_count, {
+///     let v = vec![0u8; 64];
+///     drop(v);
+/// });
+///
+/// assert_eq!(allocation_count.allocations.get(), 1);
+/// assert_eq!(allocation_count.bytes.get(), 64);
+/// ```
+///
+/// Counters are thread-local, so that concurrent threads do not pollute each
+/// other's measurements.
+pub struct CountingAllocator<A: GlobalAlloc = System> {
+    inner: A,
+}
+
+impl CountingAllocator<System> {
+    /// Builds a `CountingAllocator` wrapping the system allocator.
+    pub const fn system() -> Self {
+        CountingAllocator { inner: System }
+    }
+}
+
+impl<A: GlobalAlloc> CountingAllocator<A> {
+    /// Builds a `CountingAllocator` wrapping another allocator.
+    pub const fn new(inner: A) -> Self {
+        CountingAllocator { inner }
+    }
+}
+
+#[inline]
+fn record(size: usize) {
+    // `try_with` fails while thread-local storage is being torn down, in which
+    // case the allocation simply goes unrecorded.
+    let _ = ALLOCATIONS.try_with(|c| c.set(c.get().wrapping_add(1)));
+    let _ = ALLOCATED_BYTES.try_with(|c| c.set(c.get().wrapping_add(size as u64)));
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        self.inner.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        self.inner.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(new_size);
+        self.inner.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout)
+    }
+}
+
+/// Allocation counts recorded by [`CountingAllocator`] on a thread.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocationStats {
+    /// Number of allocations (including reallocations)
+    pub allocations: u64,
+    /// Number of bytes requested
+    pub bytes: u64,
+}
+
+impl AllocationStats {
+    /// Returns the allocations recorded on the current thread since its start.
+    ///
+    /// The counts only grow (wrapping on overflow): measuring a piece of code
+    /// is done by subtracting two snapshots.
+    pub fn current_thread() -> Self {
+        AllocationStats {
+            allocations: ALLOCATIONS.try_with(Cell::get).unwrap_or(0),
+            bytes: ALLOCATED_BYTES.try_with(Cell::get).unwrap_or(0),
+        }
+    }
+}
@@ -1,75 +1,205 @@
 //! A module providing new-type Atomic wrapper that implements Debug &
 //! Serialize.
 
+use crate::sync::atomic::AtomicU64;
+#[cfg(not(loom))]
+use atomic::Atomic;
 use serde::{Serialize, Serializer};
 use std::{
     fmt,
     fmt::{Debug, Display},
+    marker::PhantomData,
     sync::atomic::Ordering,
 };
 
+#[cfg(loom)]
+#[doc(hidden)]
+pub use crate::sync::atomic::Atomic;
+// Lets tests name the modeled `atomic::Atomic` too
+#[cfg(all(test, loom))]
+use crate::sync::atomic;
+
+/// The memory ordering of the operations of an [`OrderedAtomicInt`], chosen at
+/// the type level so that it costs nothing at runtime.
+pub trait MemoryOrdering {
+    /// The ordering of loads
+    const LOAD: Ordering;
+    /// The ordering of stores
+    const STORE: Ordering;
+    /// The ordering of read-modify-write operations, such as increments
+    const RMW: Ordering;
+}
+
+/// Relaxed operations, enough for counters only read for reporting
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Relaxed;
+
+impl MemoryOrdering for Relaxed {
+    const LOAD: Ordering = Ordering::Relaxed;
+    const STORE: Ordering = Ordering::Relaxed;
+    const RMW: Ordering = Ordering::Relaxed;
+}
+
+/// Acquire loads and release stores, for counters read by other threads to
+/// make control decisions about the memory writes preceding their updates
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AcqRel;
+
+impl MemoryOrdering for AcqRel {
+    const LOAD: Ordering = Ordering::Acquire;
+    const STORE: Ordering = Ordering::Release;
+    const RMW: Ordering = Ordering::AcqRel;
+}
+
+/// Sequentially consistent operations
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SeqCst;
+
+impl MemoryOrdering for SeqCst {
+    const LOAD: Ordering = Ordering::SeqCst;
+    const STORE: Ordering = Ordering::SeqCst;
+    const RMW: Ordering = Ordering::SeqCst;
+}
+
 /// A new-type wrapper over `atomic::Atomic` that supports serde serialization
 /// and a cleaner debug output.
 ///
 /// All default operations on the wrapper type are using a relaxed memory
-/// ordering, which makes it suitable for counters and little else.
-#[derive(Default)]
+/// ordering, which makes it suitable for counters and little else. See
+/// [`OrderedAtomicInt`] for other orderings.
 pub struct AtomicInt<T: Copy> {
     /// The inner atomic instance
-    pub inner: atomic::Atomic<T>,
+    pub inner: Atomic<T>,
 }
 
 impl<T: Copy> AtomicInt<T> {
+    /// Creates a new atomic integer
+    #[cfg(not(loom))]
+    pub const fn new(v: T) -> Self {
+        AtomicInt {
+            inner: Atomic::new(v),
+        }
+    }
+
+    /// Creates a new atomic integer
+    #[cfg(loom)]
+    pub fn new(v: T) -> Self {
+        AtomicInt {
+            inner: Atomic::new(v),
+        }
+    }
+
     /// Returns the current value
     pub fn get(&self) -> T {
         self.inner.load(Ordering::Relaxed)
     }
 }
 
+impl<T: Copy + Default> Default for AtomicInt<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
 impl<T: Copy + Display> Debug for AtomicInt<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.get())
     }
 }
 
+/// An [`AtomicInt`] whose operations use the [`MemoryOrdering`] `O`, e.g. for
+/// counters driving decisions in other threads:
+///
+/// ```rust
+/// use metered::{atomic::{AcqRel, OrderedAtomicInt}, HitCount};
+///
+/// let hit_count: HitCount<OrderedAtomicInt<u64, AcqRel>> = HitCount::default();
+/// ```
+pub struct OrderedAtomicInt<T: Copy, O: MemoryOrdering> {
+    /// The inner atomic instance
+    pub inner: Atomic<T>,
+    ordering: PhantomData<fn() -> O>,
+}
+
+impl<T: Copy, O: MemoryOrdering> OrderedAtomicInt<T, O> {
+    /// Creates a new atomic integer
+    #[cfg(not(loom))]
+    pub const fn new(v: T) -> Self {
+        OrderedAtomicInt {
+            inner: Atomic::new(v),
+            ordering: PhantomData,
+        }
+    }
+
+    /// Creates a new atomic integer
+    #[cfg(loom)]
+    pub fn new(v: T) -> Self {
+        OrderedAtomicInt {
+            inner: Atomic::new(v),
+            ordering: PhantomData,
+        }
+    }
+
+    /// Returns the current value
+    pub fn get(&self) -> T {
+        self.inner.load(O::LOAD)
+    }
+}
+
+impl<T: Copy + Default, O: MemoryOrdering> Default for OrderedAtomicInt<T, O> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: Copy + Display, O: MemoryOrdering> Debug for OrderedAtomicInt<T, O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.get())
+    }
+}
+
 macro_rules! impl_blocks_for {
     ($int:path: $method_name:ident) => {
-        impl AtomicInt<$int> {
+        impl_blocks_for!($int: $method_name, [] AtomicInt<$int>, Relaxed);
+        impl_blocks_for!($int: $method_name, [O: MemoryOrdering] OrderedAtomicInt<$int, O>, O);
+    };
+    ($int:path: $method_name:ident, [$($generics:tt)*] $ty:ty, $ordering:ty) => {
+        impl<$($generics)*> $ty {
             /// Increments self
             ///
             /// Returns the previous count
             pub fn incr(&self) -> $int {
-                self.inner.fetch_add(1, Ordering::Relaxed)
+                self.inner.fetch_add(1, <$ordering>::RMW)
             }
 
             /// Increments self by count
             ///
             /// Returns the previous count
             pub fn incr_by(&self, count: $int) -> $int {
-                self.inner.fetch_add(count, Ordering::Relaxed)
+                self.inner.fetch_add(count, <$ordering>::RMW)
             }
 
             /// Decrements self
             ///
             /// Returns the previous count
             pub fn decr(&self) -> $int {
-                self.inner.fetch_sub(1, Ordering::Relaxed)
+                self.inner.fetch_sub(1, <$ordering>::RMW)
             }
 
             /// Decrements self by count
             ///
             /// Returns the previous count
             pub fn decr_by(&self, count: $int) -> $int {
-                self.inner.fetch_sub(count, Ordering::Relaxed)
+                self.inner.fetch_sub(count, <$ordering>::RMW)
             }
 
             /// Sets self to a new value
             pub fn set(&self, v: $int) {
-                self.inner.store(v, Ordering::Relaxed);
+                self.inner.store(v, <$ordering>::STORE);
             }
         }
 
-        impl Serialize for AtomicInt<$int> {
+        impl<$($generics)*> Serialize for $ty {
             fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
             where
                 S: Serializer,
@@ -85,6 +215,80 @@ impl_blocks_for!(u16: serialize_u16);
 impl_blocks_for!(u32: serialize_u32);
 impl_blocks_for!(u64: serialize_u64);
 impl_blocks_for!(u128: serialize_u128);
+impl_blocks_for!(i8: serialize_i8);
+impl_blocks_for!(i16: serialize_i16);
+impl_blocks_for!(i32: serialize_i32);
+impl_blocks_for!(i64: serialize_i64);
+
+/// An `f64` that can be updated atomically, stored as its bits in an
+/// `AtomicU64`.
+///
+/// Like [`AtomicInt`], all operations use a relaxed memory ordering.
+#[derive(Default)]
+pub struct AtomicF64 {
+    bits: AtomicU64,
+}
+
+impl AtomicF64 {
+    /// Creates a new atomic float
+    pub fn new(v: f64) -> Self {
+        AtomicF64 {
+            bits: AtomicU64::new(v.to_bits()),
+        }
+    }
+
+    /// Returns the current value
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+
+    /// Sets self to a new value
+    pub fn set(&self, v: f64) {
+        self.bits.store(v.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Sets self to a new value
+    ///
+    /// Returns the previous value
+    pub fn swap(&self, v: f64) -> f64 {
+        f64::from_bits(self.bits.swap(v.to_bits(), Ordering::Relaxed))
+    }
+
+    /// Adds `v` to self, in a compare-and-swap loop
+    ///
+    /// Returns the previous value
+    pub fn add(&self, v: f64) -> f64 {
+        let previous = self
+            .bits
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + v).to_bits())
+            })
+            .unwrap_or_else(|bits| bits);
+        f64::from_bits(previous)
+    }
+
+    /// Subtracts `v` from self, in a compare-and-swap loop
+    ///
+    /// Returns the previous value
+    pub fn sub(&self, v: f64) -> f64 {
+        self.add(-v)
+    }
+}
+
+impl Debug for AtomicF64 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.get())
+    }
+}
+
+impl Serialize for AtomicF64 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_f64(self.get())
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -105,4 +309,29 @@ mod tests {
         a.decr();
         assert_eq!(a.get(), 255u8);
     }
+
+    #[test]
+    fn test_atomic_orderings() {
+        use super::*;
+        use crate::metric::Counter;
+        use std::sync::atomic::AtomicBool;
+
+        let ready = AtomicBool::new(false);
+        let published: OrderedAtomicInt<u64, AcqRel> = OrderedAtomicInt::default();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                ready.store(true, Ordering::Relaxed);
+                Counter::incr(&published);
+            });
+            while published.get() == 0 {
+                std::hint::spin_loop();
+            }
+            // The release increment makes the preceding store visible
+            assert!(ready.load(Ordering::Relaxed));
+        });
+
+        let a: OrderedAtomicInt<i32, SeqCst> = OrderedAtomicInt::new(-1);
+        a.incr_by(2);
+        assert_eq!(serde_json::to_string(&a).unwrap(), "1");
+    }
 }
@@ -0,0 +1,222 @@
+//! A module breaking metrics down by the variant of an input of measured
+//! methods, giving label-like breakdowns without hashing at runtime.
+//!
+//! The `by` option of `measure` attributes names an argument of the method
+//! whose type implements [`Variant`], usually derived on enums: the metric is
+//! then kept once per variant, in a [`Breakdown`] indexed by the variant of
+//! each call.
+//!
+#![cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#![cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+//! use metered::{breakdown::Variant, metered, HitCount, ResponseTime};
+//!
+//! #[derive(Variant)]
+//! pub enum Request {
+//!     Get(String),
+//!     Put { key: String, value: u64 },
+//!     Flush,
+//! }
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Store {
+//!     metrics: StoreMetrics,
+//! }
+//!
+//! #[metered(registry = StoreMetrics)]
+//! impl Store {
+//!     #[measure(HitCount)]
+//!     #[measure(type = ResponseTime, by = request)]
+//!     pub fn handle(&self, request: Request) {}
+//! }
+//!
+//! let store = Store::default();
+//! store.handle(Request::Get("key".to_string()));
+//! store.handle(Request::Flush);
+//! store.handle(Request::Flush);
+//!
+//! let response_time = &store.metrics.handle.response_time;
+//! assert_eq!(response_time.get("Get").unwrap().histogram().len(), 1);
+//! assert_eq!(response_time.get("Put").unwrap().histogram().len(), 0);
+//! assert_eq!(response_time.get("Flush").unwrap().histogram().len(), 2);
+//! assert_eq!(store.metrics.handle.hit_count.get(), 3);
+//! ```
+//!
+//! Metrics are serialized by variant, with a `variant` label when serialized
+//! by `serde_prometheus`.
+
+use crate::{
+    clear::{Clear, Clearable},
+    merge::Merge,
+    metadata::{Describe, MetricMetadata},
+    serialization::MetricAlias,
+};
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use std::{fmt, marker::PhantomData};
+
+/// Derives [`Variant`] on enums, variants being named after their identifier.
+pub use metered_macro::Variant;
+
+/// A trait for types with a fixed set of variants, such as enums, that metrics
+/// can be broken down by.
+///
+/// It is usually derived on enums, or implemented by hand for other types:
+///
+/// ```rust
+/// use metered::breakdown::Variant;
+///
+/// pub struct Priority(u8);
+///
+/// impl Variant for Priority {
+///     const VARIANTS: &'static [&'static str] = &["low", "high"];
+///
+///     fn variant_index(&self) -> usize {
+///         (self.0 > 5) as usize
+///     }
+/// }
+/// ```
+pub trait Variant {
+    /// The names of the variants, each with its own metric
+    const VARIANTS: &'static [&'static str];
+
+    /// Get the index of the variant of the value in [`Variant::VARIANTS`]
+    fn variant_index(&self) -> usize;
+}
+
+/// A metric kept once per variant of `K`, as generated by the `by` option of
+/// `measure` attributes.
+pub struct Breakdown<K: Variant, M> {
+    metrics: Box<[M]>,
+    variant: PhantomData<fn(&K)>,
+}
+
+impl<K: Variant, M> Breakdown<K, M> {
+    /// Get the metric of the variant of `key`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the variant index of `key` is out of [`Variant::VARIANTS`].
+    #[inline]
+    pub fn by(&self, key: &K) -> &M {
+        &self.metrics[key.variant_index()]
+    }
+
+    /// Get the metric of a variant by name
+    pub fn get(&self, variant: &str) -> Option<&M> {
+        K::VARIANTS
+            .iter()
+            .position(|v| *v == variant)
+            .map(|i| &self.metrics[i])
+    }
+
+    /// Iterates over the variants and their metric
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &M)> {
+        K::VARIANTS.iter().copied().zip(self.metrics.iter())
+    }
+}
+
+impl<K: Variant, M: Default> Default for Breakdown<K, M> {
+    fn default() -> Self {
+        Breakdown {
+            metrics: K::VARIANTS.iter().map(|_| M::default()).collect(),
+            variant: PhantomData,
+        }
+    }
+}
+
+impl<K: Variant, M: Clear> Clear for Breakdown<K, M> {
+    fn clear(&self) {
+        for metric in self.metrics.iter() {
+            metric.clear();
+        }
+    }
+}
+
+impl<K: Variant, M: Clearable> Clearable for Breakdown<K, M> {
+    fn is_cleared(&self) -> bool {
+        self.metrics.iter().all(|metric| metric.is_cleared())
+    }
+}
+
+impl<K: Variant, M: Merge> Merge for Breakdown<K, M> {
+    fn merge_from(&self, other: &Self) {
+        for (metric, other) in self.metrics.iter().zip(other.metrics.iter()) {
+            metric.merge_from(other);
+        }
+    }
+}
+
+impl<K: Variant, M: Serialize> Serialize for Breakdown<K, M> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.metrics.len()))?;
+        for (variant, metric) in self.iter() {
+            map.serialize_entry(variant, &MetricAlias("!|variant==<", metric))?;
+        }
+        map.end()
+    }
+}
+
+impl<K: Variant, M: fmt::Debug> fmt::Debug for Breakdown<K, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Variant, M: Describe> Describe for Breakdown<K, M> {
+    fn metadata() -> MetricMetadata {
+        M::metadata()
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{flatten::to_samples, measure, HitCount};
+
+    enum Op {
+        Read,
+        Write,
+    }
+
+    impl Variant for Op {
+        const VARIANTS: &'static [&'static str] = &["read", "write"];
+
+        fn variant_index(&self) -> usize {
+            match self {
+                Op::Read => 0,
+                Op::Write => 1,
+            }
+        }
+    }
+
+    #[test]
+    fn measures_by_variant() {
+        let hits: Breakdown<Op, HitCount> = Breakdown::default();
+        measure!(hits.by(&Op::Write), ());
+        measure!(hits.by(&Op::Write), ());
+        measure!(hits.by(&Op::Read), ());
+
+        assert_eq!(hits.get("read").unwrap().get(), 1);
+        assert_eq!(hits.get("write").unwrap().get(), 2);
+        assert!(hits.get("delete").is_none());
+
+        let samples = to_samples(&hits).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[1].name, "");
+        assert_eq!(
+            samples[1].labels,
+            [("variant".to_string(), "write".to_string())]
+        );
+
+        hits.clear();
+        assert!(hits.is_cleared());
+    }
+}
@@ -1,7 +1,9 @@
 //! A module providing a Clear trait which signals metrics to clear their state
 //! if applicable.
 
-use std::sync::Arc;
+use crate::metric::Counter;
+use serde::{Serialize, Serializer};
+use std::{cell::Cell, sync::Arc};
 
 /// The `Clear` trait is used to signal metrics to clear their state if
 /// applicable
@@ -27,9 +29,289 @@ impl<T: Clear> Clear for &T {
     }
 }
 
+/// The `ClearPath` trait clears a single metric or sub-registry of a registry,
+/// addressed by the path of its serialized names, such as
+/// `["bar", "response_time"]`.
+///
+/// Registries generated by `#[metered]` implement it for the paths of their
+/// exported metrics, see their `METRIC_PATHS`, and of their sub-registries.
+/// Other registries can implement it with the default method, only accepting
+/// the empty path which clears the whole registry. See [`clear_path`] to clear
+/// a dotted path:
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{clear::clear_path, metered, HitCount, ResponseTime};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Biz {
+///     metrics: BizMetrics,
+/// }
+///
+/// #[metered(registry = BizMetrics)]
+/// impl Biz {
+///     #[measure([HitCount, ResponseTime])]
+///     pub fn bar(&self) {}
+/// }
+///
+/// let biz = Biz::default();
+/// biz.bar();
+///
+/// // e.g. from an admin endpoint, reset a noisy histogram only
+/// assert!(clear_path(&biz.metrics, "bar.response_time"));
+/// assert_eq!(biz.metrics.bar.response_time.histogram().len(), 0);
+/// assert_eq!(biz.metrics.bar.hit_count.get(), 1);
+///
+/// assert!(!clear_path(&biz.metrics, "bar.unknown"));
+/// ```
+pub trait ClearPath: Clear {
+    /// Clears the metric or sub-registry at a path, or the whole registry if
+    /// the path is empty, and returns false if there is nothing at the path
+    fn clear_path(&self, path: &[&str]) -> bool {
+        if path.is_empty() {
+            self.clear();
+        }
+        path.is_empty()
+    }
+}
+
+impl<T: ClearPath> ClearPath for Arc<T> {
+    fn clear_path(&self, path: &[&str]) -> bool {
+        (**self).clear_path(path)
+    }
+}
+
+impl<T: ClearPath> ClearPath for &T {
+    fn clear_path(&self, path: &[&str]) -> bool {
+        (*self).clear_path(path)
+    }
+}
+
+/// Clears the metric or sub-registry of a registry at a path of serialized
+/// names separated by dots, such as `bar.response_time`, or the whole registry
+/// if the path is empty, and returns false if there is nothing at the path
+pub fn clear_path<R: ClearPath + ?Sized>(registry: &R, path: &str) -> bool {
+    let path: Vec<&str> = path.split('.').filter(|name| !name.is_empty()).collect();
+    registry.clear_path(&path)
+}
+
+thread_local! {
+    /// Whether the thread is serializing with `serialize_and_clear`
+    static TAKING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Serializes a registry, clearing each metric as its value is serialized,
+/// for delta-based pipelines.
+///
+/// Serializing, then clearing a registry loses the values recorded between
+/// both. Instead, each metric supporting it serializes its value and clears it
+/// in one step, under the lock of histograms or with an atomic swap of
+/// counters, so that every value recorded is serialized exactly once over
+/// successive calls:
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{clear::serialize_and_clear, metered, HitCount, ResponseTime};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Biz {
+///     metrics: BizMetrics,
+/// }
+///
+/// #[metered(registry = BizMetrics)]
+/// impl Biz {
+///     #[measure([HitCount, ResponseTime])]
+///     pub fn bar(&self) {}
+/// }
+///
+/// let biz = Biz::default();
+/// biz.bar();
+/// biz.bar();
+///
+/// let mut json = Vec::new();
+/// serialize_and_clear(&biz.metrics, &mut serde_json::Serializer::new(&mut json)).unwrap();
+/// let delta: serde_json::Value = serde_json::from_slice(&json).unwrap();
+/// assert_eq!(delta["bar"]["hit_count"], 2);
+/// assert_eq!(delta["bar"]["response_time"]["samples"], 2);
+///
+/// assert_eq!(biz.metrics.bar.hit_count.get(), 0);
+/// assert_eq!(biz.metrics.bar.response_time.histogram().len(), 0);
+/// ```
+///
+/// Counters keep their serialized shape, such as the `count` and `overflows`
+/// of a `CheckedCounter`, in `HitCount`, `ErrorCount`, `NoneCount`,
+/// `ErrorCodeCount`, `AllocationCount`, `DeadlineMiss` and the rejections of
+/// `ConcurrencyLimit`. The thread-safe histograms of `ResponseTime` and other
+/// histogram metrics, `Throughput`, `RateOf`, error samples and the dropped
+/// keys of `Keyed` drain as well. Gauges are serialized without being
+/// cleared, since they are never cleared, and so are single-threaded `RefCell`
+/// histograms, serialized by serde, and the snapshots of `Published`. Custom
+/// metrics can support it by checking [`is_clearing_serialization`] when
+/// serialized, or with [`serialize_counter`] for counter fields.
+pub fn serialize_and_clear<T, S>(registry: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize + ?Sized,
+    S: Serializer,
+{
+    let _restore = Restore(TAKING.with(|taking| taking.replace(true)));
+    registry.serialize(serializer)
+}
+
+/// Restores the previous mode of the thread, even if serialization panics
+struct Restore(bool);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        TAKING.with(|taking| taking.set(self.0));
+    }
+}
+
+/// Runs a function reading the values of metrics without clearing them, even
+/// within [`serialize_and_clear`], e.g. to check if they are cleared before
+/// serializing them.
+pub(crate) fn without_clearing<R>(f: impl FnOnce() -> R) -> R {
+    let _restore = Restore(TAKING.with(|taking| taking.replace(false)));
+    f()
+}
+
+/// Returns true while the calling thread serializes a registry with
+/// [`serialize_and_clear`]: metrics should then clear their values as they
+/// serialize them.
+pub fn is_clearing_serialization() -> bool {
+    TAKING.with(Cell::get)
+}
+
+/// Serializes a counter, or takes it with [`serialize_and_clear`], in the same
+/// shape: metrics deriving `Serialize` use it for their counter fields, with
+/// `#[serde(serialize_with = "metered::clear::serialize_counter")]`.
+pub fn serialize_counter<C, S>(counter: &C, serializer: S) -> Result<S::Ok, S::Error>
+where
+    C: Counter,
+    S: Serializer,
+{
+    if is_clearing_serialization() {
+        counter.take_counter().serialize(serializer)
+    } else {
+        counter.serialize(serializer)
+    }
+}
+
 /// The `Clearable` trait is used to provide metadata around some types that can
 /// be cleared.
+///
+/// Registries generated with the `skip_cleared` option of `#[metered]`
+/// implement it, and skip serializing cleared metrics and sub-registries:
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{clear::{Clear, Clearable}, metered, HitCount, ResponseTime};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics, skip_cleared = true)]
+/// impl Service {
+///     #[measure([HitCount, ResponseTime])]
+///     pub fn hot(&self) {}
+///
+///     #[measure([HitCount, ResponseTime])]
+///     pub fn rare(&self) {}
+/// }
+///
+/// let service = Service::default();
+/// assert!(service.metrics.is_cleared());
+///
+/// service.hot();
+/// assert!(!service.metrics.is_cleared());
+/// assert!(!service.metrics.hot.response_time.is_cleared());
+/// // `rare` is left out of serialized metrics
+/// assert!(service.metrics.rare.is_cleared());
+///
+/// service.metrics.clear();
+/// assert!(service.metrics.is_cleared());
+/// ```
 pub trait Clearable {
     /// Returns true if self has been cleared and not yet been written to since.
     fn is_cleared(&self) -> bool;
 }
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{atomic::AtomicInt, int_counter::CheckedCounter, measure, HitCount};
+
+    #[test]
+    fn serializes_each_hit_once() {
+        let hit_count: Arc<HitCount> = Arc::default();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let hit_count = Arc::clone(&hit_count);
+                std::thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        measure!(&*hit_count, {});
+                    }
+                })
+            })
+            .collect();
+
+        let take = || {
+            let mut json = Vec::new();
+            serialize_and_clear(&*hit_count, &mut serde_json::Serializer::new(&mut json)).unwrap();
+            serde_json::from_slice::<u64>(&json).unwrap()
+        };
+        let mut total = 0;
+        while threads.iter().any(|thread| !thread.is_finished()) {
+            total += take();
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        total += take();
+
+        assert_eq!(total, 40_000);
+        assert!(!is_clearing_serialization());
+        // Plain serialization leaves the counter untouched
+        measure!(&*hit_count, {});
+        assert_eq!(serde_json::to_string(&*hit_count).unwrap(), "1");
+        assert_eq!(hit_count.get(), 1);
+    }
+
+    #[test]
+    fn keeps_the_shape_of_counters() {
+        let hit_count: HitCount<CheckedCounter<AtomicInt<u8>>> = HitCount::default();
+        for _ in 0..300 {
+            measure!(&hit_count, {});
+        }
+
+        let mut json = Vec::new();
+        serialize_and_clear(&hit_count, &mut serde_json::Serializer::new(&mut json)).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&json).unwrap(),
+            serde_json::json!({"count": 44, "overflows": 1}),
+        );
+        assert_eq!(
+            serde_json::to_value(&hit_count).unwrap(),
+            serde_json::json!({"count": 0, "overflows": 0}),
+        );
+    }
+}
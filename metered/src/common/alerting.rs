@@ -0,0 +1,257 @@
+//! A module providing the `Alerting` metric wrapper.
+
+use crate::{
+    clear::{Clear, Clearable},
+    metadata::{Describe, MetricMetadata},
+    metric::Metric,
+};
+use aspect::{Advice, Enter, OnResult};
+use serde::{Serialize, Serializer};
+use std::{
+    ops::Deref,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+/// A rule evaluated by an [`Alerting`] metric after each call.
+///
+/// Rules are built using `Default` like metrics, and may hold their own state.
+pub trait AlertRule<M>: Default {
+    /// How many consecutive evaluations must meet the condition before the
+    /// alert fires.
+    const CONSECUTIVE: u64 = 1;
+
+    /// Evaluate the rule every `EVALUATE_EVERY` calls, to amortize expensive
+    /// conditions such as histogram quantiles.
+    const EVALUATE_EVERY: u64 = 1;
+
+    /// Returns true if the alert condition is met.
+    fn is_triggered(&self, metric: &M) -> bool;
+
+    /// Called when the condition has been met for
+    /// [`CONSECUTIVE`](AlertRule::CONSECUTIVE) evaluations.
+    fn on_alert(&self, metric: &M);
+
+    /// Called on the first evaluation not meeting the condition after the
+    /// alert fired.
+    fn on_resolve(&self, _metric: &M) {}
+}
+
+/// A metric wrapper invoking an [`AlertRule`]'s callbacks when a condition on
+/// the inner metric is met.
+///
+/// The rule is evaluated after the inner metric has recorded a call, on the
+/// calling thread: callbacks should be quick, or hand work over to another
+/// thread. The alert fires once when the condition has been met for
+/// [`AlertRule::CONSECUTIVE`] evaluations and resolves once it is no longer
+/// met.
+///
+#[cfg_attr(not(feature = "histograms"), doc = "```ignore")]
+#[cfg_attr(feature = "histograms", doc = "```rust")]
+/// use metered::{common::{AlertRule, Alerting}, measure, ResponseTime};
+/// use std::sync::atomic::{AtomicBool, Ordering};
+///
+/// #[derive(Default)]
+/// struct SlowP99 {
+///     degraded: AtomicBool,
+/// }
+///
+/// impl AlertRule<ResponseTime> for SlowP99 {
+///     const CONSECUTIVE: u64 = 3;
+///
+///     fn is_triggered(&self, metric: &ResponseTime) -> bool {
+///         metric.histogram().p99() > 500
+///     }
+///
+///     fn on_alert(&self, _: &ResponseTime) {
+///         self.degraded.store(true, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let response_time: Alerting<ResponseTime, SlowP99> = Alerting::default();
+///
+/// measure!(&response_time, {
+///     std::thread::sleep(std::time::Duration::from_millis(1));
+/// });
+///
+/// assert!(!response_time.rule().degraded.load(Ordering::Relaxed));
+/// assert!(!response_time.is_firing());
+/// ```
+///
+/// Alerting serializes exactly like the inner metric.
+pub struct Alerting<M, A: AlertRule<M>> {
+    metric: M,
+    rule: A,
+    calls: AtomicU64,
+    consecutive: AtomicU64,
+    firing: AtomicBool,
+}
+
+impl<M, A: AlertRule<M>> Alerting<M, A> {
+    /// Wraps a metric with an alert rule
+    pub fn new(metric: M, rule: A) -> Self {
+        Alerting {
+            metric,
+            rule,
+            calls: AtomicU64::new(0),
+            consecutive: AtomicU64::new(0),
+            firing: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the alert rule
+    pub fn rule(&self) -> &A {
+        &self.rule
+    }
+
+    /// Returns true if the alert is currently firing
+    pub fn is_firing(&self) -> bool {
+        self.firing.load(Ordering::Relaxed)
+    }
+
+    /// Evaluates the rule against the inner metric, invoking callbacks if the
+    /// alert fires or resolves.
+    ///
+    /// This is called automatically after measured calls, but can be used to
+    /// evaluate rules periodically as well.
+    pub fn evaluate(&self) {
+        if self.rule.is_triggered(&self.metric) {
+            let consecutive = self.consecutive.fetch_add(1, Ordering::Relaxed) + 1;
+            if consecutive >= A::CONSECUTIVE && !self.firing.swap(true, Ordering::Relaxed) {
+                self.rule.on_alert(&self.metric);
+            }
+        } else {
+            self.consecutive.store(0, Ordering::Relaxed);
+            if self.firing.swap(false, Ordering::Relaxed) {
+                self.rule.on_resolve(&self.metric);
+            }
+        }
+    }
+
+    fn after_call(&self) {
+        let calls = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+        if calls.checked_rem(A::EVALUATE_EVERY.max(1)) == Some(0) {
+            self.evaluate();
+        }
+    }
+}
+
+impl<M: Default, A: AlertRule<M>> Default for Alerting<M, A> {
+    fn default() -> Self {
+        Alerting::new(M::default(), A::default())
+    }
+}
+
+impl<M: Metric<R> + OnResult<R>, A: AlertRule<M>, R> Metric<R> for Alerting<M, A> {}
+
+impl<M: Enter, A: AlertRule<M>> Enter for Alerting<M, A> {
+    type E = M::E;
+
+    fn enter(&self) -> M::E {
+        self.metric.enter()
+    }
+}
+
+impl<M: OnResult<R>, A: AlertRule<M>, R> OnResult<R> for Alerting<M, A> {
+    fn on_result(&self, enter: M::E, result: &R) -> Advice {
+        let advice = self.metric.on_result(enter, result);
+        self.after_call();
+        advice
+    }
+
+    fn leave_scope(&self, enter: M::E) -> Advice {
+        let advice = self.metric.leave_scope(enter);
+        self.after_call();
+        advice
+    }
+}
+
+impl<M: Clear, A: AlertRule<M>> Clear for Alerting<M, A> {
+    fn clear(&self) {
+        self.metric.clear();
+        self.calls.store(0, Ordering::Relaxed);
+        self.consecutive.store(0, Ordering::Relaxed);
+        self.firing.store(false, Ordering::Relaxed);
+    }
+}
+
+impl<M: Clearable, A: AlertRule<M>> Clearable for Alerting<M, A> {
+    fn is_cleared(&self) -> bool {
+        self.metric.is_cleared()
+    }
+}
+
+impl<M: Serialize, A: AlertRule<M>> Serialize for Alerting<M, A> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Serialize::serialize(&self.metric, serializer)
+    }
+}
+
+use std::{fmt, fmt::Debug};
+impl<M: Debug, A: AlertRule<M>> Debug for Alerting<M, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", &self.metric)
+    }
+}
+
+impl<M: Describe, A: AlertRule<M>> Describe for Alerting<M, A> {
+    fn metadata() -> MetricMetadata {
+        M::metadata()
+    }
+}
+
+impl<M, A: AlertRule<M>> Deref for Alerting<M, A> {
+    type Target = M;
+
+    fn deref(&self) -> &Self::Target {
+        &self.metric
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::ErrorCount;
+
+    #[derive(Default)]
+    struct TwoErrors {
+        alerts: AtomicU64,
+        resolutions: AtomicU64,
+    }
+
+    impl AlertRule<ErrorCount> for TwoErrors {
+        const CONSECUTIVE: u64 = 2;
+
+        fn is_triggered(&self, metric: &ErrorCount) -> bool {
+            metric.get() > 0
+        }
+
+        fn on_alert(&self, _: &ErrorCount) {
+            self.alerts.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_resolve(&self, _: &ErrorCount) {
+            self.resolutions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_fires_and_resolves() {
+        let errors: Alerting<ErrorCount, TwoErrors> = Alerting::default();
+        let call = |fail: bool| crate::measure!(&errors, if fail { Err(()) } else { Ok(()) });
+
+        let _ = call(true);
+        assert!(!errors.is_firing());
+        let _ = call(false);
+        let _ = call(true);
+        assert!(errors.is_firing());
+        assert_eq!(errors.rule().alerts.load(Ordering::Relaxed), 1);
+
+        errors.clear();
+        errors.evaluate();
+        assert!(!errors.is_firing());
+        assert_eq!(errors.rule().resolutions.load(Ordering::Relaxed), 0);
+    }
+}
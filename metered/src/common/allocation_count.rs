@@ -0,0 +1,93 @@
+//! A module providing the `AllocationCount` metric.
+
+use crate::{
+    allocator::AllocationStats,
+    atomic::AtomicInt,
+    clear::{self, Clear, Clearable},
+    merge::Merge,
+    metric::{Counter, Metric},
+};
+use aspect::{Advice, Enter, OnResult};
+use serde::Serialize;
+
+/// A metric counting how many allocations were performed, and how many bytes
+/// were allocated, while evaluating an expression.
+///
+/// It relies on [`CountingAllocator`](crate::allocator::CountingAllocator)
+/// being installed as the global allocator and will report nothing otherwise.
+///
+/// The allocator keeps thread-local counts, so only the allocations made by the
+/// thread entering the expression are reported. For `async` expressions that
+/// may resume on another thread, the measurement is only an approximation.
+///
+/// By default, `AllocationCount` uses lock-free `u64` `Counter`s, which makes
+/// sense in multithread scenarios. Non-threaded applications can gain
+/// performance by using a `std::cell:Cell<u64>` instead.
+#[derive(Clone, Default, Debug, Serialize)]
+pub struct AllocationCount<C: Counter = AtomicInt<u64>> {
+    /// The number of allocations
+    #[serde(serialize_with = "clear::serialize_counter")]
+    pub allocations: C,
+    /// The number of bytes allocated
+    #[serde(serialize_with = "clear::serialize_counter")]
+    pub bytes: C,
+}
+
+/// A plain copy of the values of an [`AllocationCount`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocationCountSnapshot {
+    /// The number of allocations
+    pub allocations: u64,
+    /// The number of bytes allocated
+    pub bytes: u64,
+}
+
+impl<C: Counter> From<&AllocationCount<C>> for AllocationCountSnapshot {
+    fn from(metric: &AllocationCount<C>) -> Self {
+        AllocationCountSnapshot {
+            allocations: metric.allocations.value(),
+            bytes: metric.bytes.value(),
+        }
+    }
+}
+
+impl<C: Counter, R> Metric<R> for AllocationCount<C> {}
+
+impl<C: Counter> Enter for AllocationCount<C> {
+    type E = AllocationStats;
+
+    fn enter(&self) -> AllocationStats {
+        AllocationStats::current_thread()
+    }
+}
+
+impl<C: Counter, R> OnResult<R> for AllocationCount<C> {
+    fn leave_scope(&self, enter: AllocationStats) -> Advice {
+        let now = AllocationStats::current_thread();
+        self.allocations
+            .incr_by(now.allocations.wrapping_sub(enter.allocations) as usize);
+        self.bytes
+            .incr_by(now.bytes.wrapping_sub(enter.bytes) as usize);
+        Advice::Return
+    }
+}
+
+impl<C: Counter> Clear for AllocationCount<C> {
+    fn clear(&self) {
+        self.allocations.clear();
+        self.bytes.clear();
+    }
+}
+
+impl<C: Counter> Clearable for AllocationCount<C> {
+    fn is_cleared(&self) -> bool {
+        self.allocations.is_cleared() && self.bytes.is_cleared()
+    }
+}
+
+impl<C: Counter + Merge> Merge for AllocationCount<C> {
+    fn merge_from(&self, other: &Self) {
+        self.allocations.merge_from(&other.allocations);
+        self.bytes.merge_from(&other.bytes);
+    }
+}
@@ -0,0 +1,223 @@
+//! A module providing the `ConcurrencyLimit` metric.
+
+use crate::{
+    atomic::AtomicInt,
+    clear::{self, Clear, Clearable},
+    common::InFlight,
+    metric::{Counter, Gate, Metric},
+    sync::{Condvar, Mutex},
+};
+use aspect::{Advice, Enter, OnResult};
+use serde::Serialize;
+use std::{marker::PhantomData, sync::atomic::Ordering};
+
+/// A metric limiting how many calls to an expression can be active at the same
+/// time.
+///
+/// It extends [`InFlight`]: once `N` calls are in flight, new calls are
+/// either rejected, with the [`RejectWhenFull`] policy (by default), or wait
+/// for a slot to free up, with the [`QueueWhenFull`] policy. Rejections are
+/// counted, and rejected calls evaluate the `abort` expression instead of the
+/// measured one, as for any [`Gate`]:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{common::ConcurrencyLimit, metered};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics)]
+/// impl Service {
+///     #[measure(type = ConcurrencyLimit<1>, abort = Err("busy"))]
+///     pub fn call(&self, reenter: bool) -> Result<(), &'static str> {
+///         if reenter {
+///             self.call(false)
+///         } else {
+///             Ok(())
+///         }
+///     }
+/// }
+///
+/// let service = Service::default();
+/// assert_eq!(service.call(true), Err("busy"));
+/// assert_eq!(service.metrics.call.concurrency_limit.rejections.get(), 1);
+/// assert_eq!(service.metrics.call.concurrency_limit.in_flight.get(), 0);
+/// ```
+///
+/// Queueing blocks the calling thread, and is thus not suitable for `async`
+/// methods.
+#[derive(Serialize)]
+pub struct ConcurrencyLimit<
+    const N: usize,
+    P: LimitPolicy = RejectWhenFull,
+    C: Counter = AtomicInt<u64>,
+> {
+    /// The number of calls currently active
+    pub in_flight: InFlight<AtomicInt<u64>>,
+    /// The number of calls rejected because the limit was reached
+    #[serde(serialize_with = "clear::serialize_counter")]
+    pub rejections: C,
+    #[serde(skip)]
+    lock: Mutex<()>,
+    #[serde(skip)]
+    released: Condvar,
+    #[serde(skip)]
+    _policy: PhantomData<P>,
+}
+
+/// The policy applied by a [`ConcurrencyLimit`] when the limit is reached.
+pub trait LimitPolicy {
+    /// True if calls should wait for a slot rather than being rejected
+    const QUEUE: bool;
+}
+
+/// Rejects calls while the [`ConcurrencyLimit`] is reached.
+#[derive(Debug)]
+pub struct RejectWhenFull;
+impl LimitPolicy for RejectWhenFull {
+    const QUEUE: bool = false;
+}
+
+/// Blocks calls until the [`ConcurrencyLimit`] is no longer reached.
+#[derive(Debug)]
+pub struct QueueWhenFull;
+impl LimitPolicy for QueueWhenFull {
+    const QUEUE: bool = true;
+}
+
+impl<const N: usize, P: LimitPolicy, C: Counter> ConcurrencyLimit<N, P, C> {
+    /// Returns the maximum number of concurrent calls
+    pub fn limit(&self) -> usize {
+        N
+    }
+
+    fn try_acquire(&self) -> bool {
+        (self.in_flight.0)
+            .inner
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |v| {
+                if v < N as u64 {
+                    Some(v + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+}
+
+impl<const N: usize, P: LimitPolicy, C: Counter> Default for ConcurrencyLimit<N, P, C> {
+    fn default() -> Self {
+        ConcurrencyLimit {
+            in_flight: InFlight::default(),
+            rejections: C::default(),
+            lock: Mutex::new(()),
+            released: Condvar::new(),
+            _policy: PhantomData,
+        }
+    }
+}
+
+impl<const N: usize, P: LimitPolicy, C: Counter, R> Metric<R> for ConcurrencyLimit<N, P, C> {}
+
+impl<const N: usize, P: LimitPolicy, C: Counter> Enter for ConcurrencyLimit<N, P, C> {
+    /// Whether the call was admitted
+    type E = bool;
+
+    fn enter(&self) -> bool {
+        loop {
+            if self.try_acquire() {
+                return true;
+            }
+            if !P::QUEUE {
+                self.rejections.incr();
+                return false;
+            }
+
+            // Check again while holding the lock, so that a release happening
+            // before we wait cannot be missed.
+            let lock = self.lock.lock();
+            if self.in_flight.get() >= N as u64 {
+                drop(self.released.wait(lock));
+            }
+        }
+    }
+}
+
+impl<const N: usize, P: LimitPolicy, C: Counter> Gate for ConcurrencyLimit<N, P, C> {
+    fn should_abort(&self, admitted: &bool) -> bool {
+        !admitted
+    }
+}
+
+impl<const N: usize, P: LimitPolicy, C: Counter, R> OnResult<R> for ConcurrencyLimit<N, P, C> {
+    fn leave_scope(&self, admitted: bool) -> Advice {
+        if admitted {
+            self.in_flight.0.decr();
+            if P::QUEUE {
+                let _lock = self.lock.lock();
+                self.released.notify_one();
+            }
+        }
+        Advice::Return
+    }
+}
+
+impl<const N: usize, P: LimitPolicy, C: Counter> Clear for ConcurrencyLimit<N, P, C> {
+    fn clear(&self) {
+        // The in-flight gauge is left untouched, see `InFlight`
+        self.rejections.clear();
+    }
+}
+
+impl<const N: usize, P: LimitPolicy, C: Counter> Clearable for ConcurrencyLimit<N, P, C> {
+    fn is_cleared(&self) -> bool {
+        self.rejections.is_cleared() && self.in_flight.get() == 0
+    }
+}
+
+use std::{fmt, fmt::Debug};
+impl<const N: usize, P: LimitPolicy, C: Counter + Debug> Debug for ConcurrencyLimit<N, P, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConcurrencyLimit")
+            .field("limit", &N)
+            .field("in_flight", &self.in_flight)
+            .field("rejections", &self.rejections)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread, time::Duration};
+
+    #[test]
+    fn test_queue_when_full() {
+        let limit: Arc<ConcurrencyLimit<2, QueueWhenFull>> = Arc::default();
+        let max_seen: Arc<AtomicInt<u64>> = Arc::default();
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let limit = Arc::clone(&limit);
+                let max_seen = Arc::clone(&max_seen);
+                thread::spawn(move || {
+                    crate::measure!(&*limit, {
+                        let v = limit.in_flight.get();
+                        max_seen.inner.fetch_max(v, Ordering::Relaxed);
+                        thread::sleep(Duration::from_millis(5));
+                    }, abort => unreachable!())
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert!(max_seen.get() <= 2);
+        assert_eq!(limit.in_flight.get(), 0);
+        assert_eq!(limit.rejections.get(), 0);
+    }
+}
@@ -0,0 +1,179 @@
+//! A module providing the `DeadlineMiss` metric.
+
+#[cfg(feature = "histograms")]
+use crate::{atomic::AtomicInt, hdr_histogram::AtomicHdrHistogram};
+use crate::{
+    clear::{self, Clear, Clearable},
+    metric::{Counter, Histogram, Metric},
+    time_source::{Instant, StdInstant},
+};
+use aspect::{Advice, Enter, OnResult};
+use serde::Serialize;
+use std::time::Duration;
+
+/// A metric counting how many times an expression took longer than a deadline
+/// to complete, and recording by how much the deadline was missed.
+///
+/// The deadline is a `Duration`, converted to the units of the time source
+/// `T`. As it has no default, registries build it with the `init` option of
+/// the `measure` attribute:
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{common::DeadlineMiss, metered};
+/// use std::time::Duration;
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics)]
+/// impl Service {
+///     // Calls taking more than 50ms are counted as misses
+///     #[measure(type = DeadlineMiss, init = DeadlineMiss::new(Duration::from_millis(50)))]
+///     pub fn call(&self) {
+///         std::thread::sleep(Duration::from_millis(60));
+///     }
+/// }
+///
+/// let service = Service::default();
+/// service.call();
+///
+/// let deadline_miss = &service.metrics.call.deadline_miss;
+/// assert_eq!(deadline_miss.misses.get(), 1);
+/// assert!(deadline_miss.overruns.histogram().min() >= 10);
+/// ```
+///
+/// Overruns are recorded to a histogram bound to 5 minutes, like
+/// [`ResponseTime`](crate::ResponseTime), in the units of the time source.
+///
+/// Because it retrieves the current time before calling the expression, this is
+/// a rather heavy-weight metric, although cheaper than `ResponseTime` as the
+/// histogram is only updated on misses.
+///
+/// Without the `histograms` feature, the counter and histogram have no
+/// default, e.g. `DeadlineMiss<AtomicInt<u64>, AtomicDdSketch>`.
+#[derive(Debug, Serialize)]
+pub struct DeadlineMiss<
+    #[cfg(feature = "histograms")] C: Counter = AtomicInt<u64>,
+    #[cfg(feature = "histograms")] H: Histogram = AtomicHdrHistogram,
+    #[cfg(not(feature = "histograms"))] C: Counter,
+    #[cfg(not(feature = "histograms"))] H: Histogram,
+    T: Instant = StdInstant,
+> {
+    /// The number of calls that missed the deadline
+    #[serde(serialize_with = "clear::serialize_counter")]
+    pub misses: C,
+    /// The histogram of how much time calls exceeded the deadline by
+    pub overruns: H,
+    #[serde(skip)]
+    deadline: Duration,
+    /// The deadline, in the time source's units
+    #[serde(skip)]
+    units: u64,
+    #[serde(skip)]
+    _phantom: std::marker::PhantomData<T>,
+}
+
+impl<C: Counter, H: Histogram, T: Instant> DeadlineMiss<C, H, T> {
+    /// Creates a metric counting calls taking longer than a deadline
+    pub fn new(deadline: Duration) -> Self {
+        DeadlineMiss {
+            misses: C::default(),
+            overruns: H::with_bound(5 * 60 * T::ONE_SEC),
+            deadline,
+            units: T::units(deadline),
+            _phantom: std::marker::PhantomData,
+        }
+    }
+
+    /// Returns the deadline
+    pub fn deadline(&self) -> Duration {
+        self.deadline
+    }
+}
+
+impl<C: Counter, H: Histogram, T: Instant, R> Metric<R> for DeadlineMiss<C, H, T> {}
+
+impl<C: Counter, H: Histogram, T: Instant> Enter for DeadlineMiss<C, H, T> {
+    type E = T;
+
+    fn enter(&self) -> T {
+        T::now()
+    }
+}
+
+impl<C: Counter, H: Histogram, T: Instant, R> OnResult<R> for DeadlineMiss<C, H, T> {
+    fn leave_scope(&self, enter: T) -> Advice {
+        let elapsed = enter.elapsed_time();
+        if elapsed > self.units {
+            self.misses.incr();
+            self.overruns.record(elapsed - self.units);
+        }
+        Advice::Return
+    }
+}
+
+impl<C: Counter, H: Histogram, T: Instant> Clear for DeadlineMiss<C, H, T> {
+    fn clear(&self) {
+        self.misses.clear();
+        self.overruns.clear();
+    }
+}
+
+impl<C: Counter, H: Histogram, T: Instant> Clearable for DeadlineMiss<C, H, T> {
+    fn is_cleared(&self) -> bool {
+        self.misses.is_cleared()
+    }
+}
+
+#[cfg(all(test, feature = "histograms", not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{
+        measure,
+        simulation::{SimInstant, Simulation},
+        time_source::StdInstantMicros,
+    };
+
+    #[test]
+    fn records_overruns_beyond_the_deadline() {
+        let simulation = Simulation::start(0);
+        let deadline_miss: DeadlineMiss<AtomicInt<u64>, AtomicHdrHistogram, SimInstant> =
+            DeadlineMiss::new(Duration::from_millis(50));
+
+        measure!(
+            &deadline_miss,
+            simulation.advance(Duration::from_millis(50))
+        );
+        assert!(deadline_miss.is_cleared());
+
+        measure!(
+            &deadline_miss,
+            simulation.advance(Duration::from_millis(80))
+        );
+        assert_eq!(deadline_miss.misses.get(), 1);
+        assert_eq!(deadline_miss.overruns.histogram().max(), 30);
+
+        deadline_miss.clear();
+        assert!(deadline_miss.is_cleared());
+        assert_eq!(deadline_miss.overruns.histogram().len(), 0);
+    }
+
+    #[test]
+    fn converts_the_deadline_to_the_time_source_units() {
+        let millis: DeadlineMiss = DeadlineMiss::new(Duration::from_millis(50));
+        let micros: DeadlineMiss<AtomicInt<u64>, AtomicHdrHistogram, StdInstantMicros> =
+            DeadlineMiss::new(Duration::from_millis(50));
+        assert_eq!(millis.units, 50);
+        assert_eq!(micros.units, 50_000);
+        assert_eq!(micros.deadline(), Duration::from_millis(50));
+    }
+}
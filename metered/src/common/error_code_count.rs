@@ -0,0 +1,294 @@
+//! A module providing the `ErrorCodeCount` metric.
+
+use crate::{
+    atomic::AtomicInt,
+    clear::{self, Clear, Clearable},
+    merge::Merge,
+    metadata::{Describe, MetricMetadata, MetricType, Unit},
+    metric::{Counter, Metric},
+    serialization::MetricAlias,
+};
+use aspect::{Advice, Enter, OnResult};
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use std::{fmt, io, marker::PhantomData};
+
+/// A trait classifying errors into a fixed set of classes, for errors that are
+/// not enums counted by `#[metered::error_count]`: status codes, errno values,
+/// opaque errors from other crates...
+///
+/// ```rust
+/// use metered::common::ErrorClass;
+///
+/// pub struct HttpError(u16);
+///
+/// impl ErrorClass for HttpError {
+///     const CLASSES: &'static [&'static str] = &["client", "server"];
+///
+///     fn class(&self) -> &'static str {
+///         match self.0 {
+///             400..=499 => "client",
+///             500..=599 => "server",
+///             _ => "other",
+///         }
+///     }
+/// }
+/// ```
+pub trait ErrorClass {
+    /// The classes errors fall into, each with its own counter
+    const CLASSES: &'static [&'static str];
+
+    /// Get the class of the error, errors of classes outside of
+    /// [`ErrorClass::CLASSES`] being counted as `other`
+    fn class(&self) -> &'static str;
+}
+
+/// The class of errors outside of [`ErrorClass::CLASSES`]
+const OTHER: &str = "other";
+
+/// A metric counting how many times an expression typed std `Result` returned
+/// an error, by class of error.
+///
+/// Counts are serialized by class, with a `class` label when serialized by
+/// `serde_prometheus`:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{common::{ErrorClass, ErrorCodeCount}, metered};
+///
+/// #[derive(Debug)]
+/// pub struct Status(u16);
+///
+/// impl ErrorClass for Status {
+///     const CLASSES: &'static [&'static str] = &["not_found", "unavailable"];
+///
+///     fn class(&self) -> &'static str {
+///         match self.0 {
+///             404 => "not_found",
+///             503 => "unavailable",
+///             _ => "other",
+///         }
+///     }
+/// }
+///
+/// #[derive(Default, Debug)]
+/// pub struct Client {
+///     metrics: ClientMetrics,
+/// }
+///
+/// #[metered(registry = ClientMetrics)]
+/// impl Client {
+///     #[measure(ErrorCodeCount<Status>)]
+///     pub fn get(&self, status: u16) -> Result<(), Status> {
+///         Err(Status(status))
+///     }
+/// }
+///
+/// let client = Client::default();
+/// let _ = client.get(404);
+/// let _ = client.get(418);
+///
+/// let errors = &client.metrics.get.error_code_count;
+/// assert_eq!(errors.get("not_found"), 1);
+/// assert_eq!(errors.get("unavailable"), 0);
+/// assert_eq!(errors.get("other"), 1);
+/// ```
+///
+/// This is a light-weight metric, looking the class up among the classes of the
+/// error.
+pub struct ErrorCodeCount<E: ErrorClass, C: Counter = AtomicInt<u64>> {
+    counts: Box<[C]>,
+    other: C,
+    error: PhantomData<fn(&E)>,
+}
+
+impl<E: ErrorClass, C: Counter> ErrorCodeCount<E, C> {
+    /// Get the number of errors of a class, whatever the counter backend
+    pub fn get(&self, class: &str) -> u64 {
+        self.counter(class).value()
+    }
+
+    /// Iterates over the classes and their number of errors, `other` last
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        self.counters()
+            .map(|(class, counter)| (class, counter.value()))
+    }
+
+    fn counter(&self, class: &str) -> &C {
+        E::CLASSES
+            .iter()
+            .position(|c| *c == class)
+            .map_or(&self.other, |i| &self.counts[i])
+    }
+
+    fn counters(&self) -> impl Iterator<Item = (&'static str, &C)> {
+        E::CLASSES
+            .iter()
+            .copied()
+            .zip(self.counts.iter())
+            .chain(std::iter::once((OTHER, &self.other)))
+    }
+}
+
+impl<E: ErrorClass, C: Counter> Default for ErrorCodeCount<E, C> {
+    fn default() -> Self {
+        ErrorCodeCount {
+            counts: E::CLASSES.iter().map(|_| C::default()).collect(),
+            other: C::default(),
+            error: PhantomData,
+        }
+    }
+}
+
+impl<E: ErrorClass, C: Counter, T> Metric<Result<T, E>> for ErrorCodeCount<E, C> {}
+
+impl<E: ErrorClass, C: Counter> Enter for ErrorCodeCount<E, C> {
+    type E = ();
+    fn enter(&self) {}
+}
+
+impl<E: ErrorClass, C: Counter, T> OnResult<Result<T, E>> for ErrorCodeCount<E, C> {
+    fn on_result(&self, _: (), r: &Result<T, E>) -> Advice {
+        if let Err(e) = r {
+            self.counter(e.class()).incr();
+        }
+        Advice::Return
+    }
+}
+
+impl<E: ErrorClass, C: Counter> Clear for ErrorCodeCount<E, C> {
+    fn clear(&self) {
+        for (_, counter) in self.counters() {
+            counter.clear();
+        }
+    }
+}
+
+impl<E: ErrorClass, C: Counter> Clearable for ErrorCodeCount<E, C> {
+    fn is_cleared(&self) -> bool {
+        self.counters().all(|(_, counter)| counter.is_cleared())
+    }
+}
+
+impl<E: ErrorClass, C: Counter + Merge> Merge for ErrorCodeCount<E, C> {
+    fn merge_from(&self, other: &Self) {
+        for ((_, counter), (_, other)) in self.counters().zip(other.counters()) {
+            counter.merge_from(other);
+        }
+    }
+}
+
+impl<E: ErrorClass, C: Counter> Serialize for ErrorCodeCount<E, C> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(E::CLASSES.len() + 1))?;
+        for (class, counter) in self.counters() {
+            if clear::is_clearing_serialization() {
+                let counter = counter.take_counter();
+                map.serialize_entry(class, &MetricAlias("!|class==<", &counter))?;
+            } else {
+                map.serialize_entry(class, &MetricAlias("!|class==<", counter))?;
+            }
+        }
+        map.end()
+    }
+}
+
+impl<E: ErrorClass, C: Counter + fmt::Debug> fmt::Debug for ErrorCodeCount<E, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.counters()).finish()
+    }
+}
+
+impl<E: ErrorClass, C: Counter> Describe for ErrorCodeCount<E, C> {
+    fn metadata() -> MetricMetadata {
+        MetricMetadata::new(MetricType::Counter, Unit::None)
+    }
+}
+
+/// I/O errors are classified by kind, such as `NotFound` or `TimedOut`.
+impl ErrorClass for io::Error {
+    const CLASSES: &'static [&'static str] = &[
+        "NotFound",
+        "PermissionDenied",
+        "ConnectionRefused",
+        "ConnectionReset",
+        "ConnectionAborted",
+        "NotConnected",
+        "AddrInUse",
+        "AddrNotAvailable",
+        "BrokenPipe",
+        "AlreadyExists",
+        "WouldBlock",
+        "InvalidInput",
+        "InvalidData",
+        "TimedOut",
+        "WriteZero",
+        "Interrupted",
+        "Unsupported",
+        "UnexpectedEof",
+        "OutOfMemory",
+    ];
+
+    fn class(&self) -> &'static str {
+        use io::ErrorKind::*;
+        match self.kind() {
+            NotFound => "NotFound",
+            PermissionDenied => "PermissionDenied",
+            ConnectionRefused => "ConnectionRefused",
+            ConnectionReset => "ConnectionReset",
+            ConnectionAborted => "ConnectionAborted",
+            NotConnected => "NotConnected",
+            AddrInUse => "AddrInUse",
+            AddrNotAvailable => "AddrNotAvailable",
+            BrokenPipe => "BrokenPipe",
+            AlreadyExists => "AlreadyExists",
+            WouldBlock => "WouldBlock",
+            InvalidInput => "InvalidInput",
+            InvalidData => "InvalidData",
+            TimedOut => "TimedOut",
+            WriteZero => "WriteZero",
+            Interrupted => "Interrupted",
+            Unsupported => "Unsupported",
+            UnexpectedEof => "UnexpectedEof",
+            OutOfMemory => "OutOfMemory",
+            _ => OTHER,
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{flatten::to_samples, measure};
+
+    #[test]
+    fn counts_io_errors_by_kind() {
+        let errors: ErrorCodeCount<io::Error> = ErrorCodeCount::default();
+        let _ = measure!(
+            &errors,
+            Err::<(), _>(io::Error::from(io::ErrorKind::TimedOut))
+        );
+        let _ = measure!(&errors, Err::<(), _>(io::Error::other("opaque")));
+        let _ = measure!(&errors, Ok::<(), io::Error>(()));
+
+        assert_eq!(errors.get("TimedOut"), 1);
+        assert_eq!(errors.get("other"), 1);
+        assert_eq!(errors.iter().map(|(_, count)| count).sum::<u64>(), 2);
+
+        let samples = to_samples(&errors).unwrap();
+        let timed_out = samples
+            .iter()
+            .find(|sample| sample.labels == [("class".to_string(), "TimedOut".to_string())])
+            .unwrap();
+        assert_eq!(timed_out.name, "");
+        assert_eq!(timed_out.value, 1.0);
+
+        let merged: ErrorCodeCount<io::Error> = ErrorCodeCount::default();
+        merged.merge_from(&errors);
+        assert_eq!(merged.get("other"), 1);
+        merged.clear();
+        assert!(merged.is_cleared());
+    }
+}
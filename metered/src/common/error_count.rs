@@ -2,12 +2,26 @@
 
 use crate::{
     atomic::AtomicInt,
-    clear::Clear,
+    clear::{self, Clear, Clearable},
+    merge::Merge,
+    metadata::{Describe, MetricMetadata, MetricType, Unit},
     metric::{Counter, Metric},
+    sync::{atomic::AtomicU64, Mutex},
 };
 use aspect::{Advice, Enter, OnResult};
-use serde::Serialize;
-use std::ops::Deref;
+use serde::{Serialize, Serializer};
+use std::{collections::VecDeque, fmt, ops::Deref, sync::atomic::Ordering};
+#[cfg(feature = "histograms")]
+use {
+    super::ResponseTime,
+    crate::{
+        hdr_histogram::AtomicHdrHistogram,
+        metric::Histogram,
+        serialization::MetricAlias,
+        time_source::{Instant, StdInstant},
+    },
+    serde::ser::SerializeStruct,
+};
 
 /// A metric counting how many times an expression typed std `Result` as
 /// returned an `Err` variant.
@@ -17,17 +31,41 @@ use std::ops::Deref;
 /// By default, `ErrorCount` uses a lock-free `u64` `Counter`, which makes sense
 /// in multithread scenarios. Non-threaded applications can gain performance by
 /// using a `std::cell:Cell<u64>` instead.
-#[derive(Clone, Default, Debug, Serialize)]
+#[derive(Clone, Default, Debug)]
 pub struct ErrorCount<C: Counter = AtomicInt<u64>>(pub C);
 
+impl<C: Counter> ErrorCount<C> {
+    /// Get the number of errors, whatever the counter backend
+    pub fn get(&self) -> u64 {
+        self.0.value()
+    }
+}
+
+/// A plain copy of the value of a [`ErrorCount`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ErrorCountSnapshot {
+    /// The number of errors
+    pub count: u64,
+}
+
+impl<C: Counter> From<&ErrorCount<C>> for ErrorCountSnapshot {
+    fn from(metric: &ErrorCount<C>) -> Self {
+        ErrorCountSnapshot {
+            count: metric.get(),
+        }
+    }
+}
+
 impl<C: Counter, T, E> Metric<Result<T, E>> for ErrorCount<C> {}
 
 impl<C: Counter> Enter for ErrorCount<C> {
     type E = ();
+    #[inline]
     fn enter(&self) {}
 }
 
 impl<C: Counter, T, E> OnResult<Result<T, E>> for ErrorCount<C> {
+    #[inline]
     fn on_result(&self, _: (), r: &Result<T, E>) -> Advice {
         if r.is_err() {
             self.0.incr();
@@ -42,6 +80,33 @@ impl<C: Counter> Clear for ErrorCount<C> {
     }
 }
 
+impl<C: Counter> Clearable for ErrorCount<C> {
+    fn is_cleared(&self) -> bool {
+        self.0.is_cleared()
+    }
+}
+
+impl<C: Counter> Serialize for ErrorCount<C> {
+    /// Serializes the counter, or takes its value with
+    /// [`serialize_and_clear`](crate::clear::serialize_and_clear)
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if clear::is_clearing_serialization() {
+            serializer.serialize_newtype_struct("ErrorCount", &self.0.take_counter())
+        } else {
+            serializer.serialize_newtype_struct("ErrorCount", &self.0)
+        }
+    }
+}
+
+impl<C: Counter + Merge> Merge for ErrorCount<C> {
+    fn merge_from(&self, other: &Self) {
+        self.0.merge_from(&other.0);
+    }
+}
+
 impl<C: Counter> Deref for ErrorCount<C> {
     type Target = C;
 
@@ -49,3 +114,249 @@ impl<C: Counter> Deref for ErrorCount<C> {
         &self.0
     }
 }
+
+impl<C: Counter> Describe for ErrorCount<C> {
+    fn metadata() -> MetricMetadata {
+        MetricMetadata::new(MetricType::Counter, Unit::None)
+    }
+}
+
+/// The last `N` errors of each of the `V` variants of an enum, as displayed,
+/// kept by `#[metered::error_count]` with the `samples = N` option to tell
+/// which inputs trigger a variant.
+///
+/// Only one in `EVERY` errors of each variant is sampled, starting with the
+/// first one: the others are neither displayed nor locked. Samples are read
+/// with the `samples` method of the generated struct, or
+/// [`ErrorSamples::get`], and serialized as lists keyed by variant with the
+/// `serialize_samples = true` option.
+pub struct ErrorSamples<const N: usize, const V: usize, const EVERY: u64 = 1> {
+    seen: [AtomicU64; V],
+    samples: Mutex<Vec<(&'static str, VecDeque<String>)>>,
+}
+
+impl<const N: usize, const V: usize, const EVERY: u64> ErrorSamples<N, V, EVERY> {
+    /// Records an error of the variant at `index`, if it is sampled, evicting
+    /// the oldest sample of the variant if it has `N`
+    pub fn record(&self, index: usize, variant: &'static str, error: &dyn fmt::Display) {
+        let seen = self.seen[index].fetch_add(1, Ordering::Relaxed);
+        if N == 0 || seen % EVERY.max(1) != 0 {
+            return;
+        }
+        let sample = error.to_string();
+        self.push(variant, sample);
+    }
+
+    fn push(&self, variant: &'static str, sample: String) {
+        let mut samples = self.samples.lock();
+        let index = match samples.iter().position(|(v, _)| *v == variant) {
+            Some(index) => index,
+            None => {
+                samples.push((variant, VecDeque::with_capacity(N)));
+                samples.len() - 1
+            }
+        };
+        let variant_samples = &mut samples[index].1;
+        if variant_samples.len() == N {
+            variant_samples.pop_front();
+        }
+        variant_samples.push_back(sample);
+    }
+
+    /// Get the samples of a variant, from oldest to newest
+    pub fn get(&self, variant: &str) -> Vec<String> {
+        self.samples
+            .lock()
+            .iter()
+            .find(|(v, _)| *v == variant)
+            .map(|(_, samples)| samples.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl<const N: usize, const V: usize, const EVERY: u64> Default for ErrorSamples<N, V, EVERY> {
+    fn default() -> Self {
+        ErrorSamples {
+            seen: std::array::from_fn(|_| AtomicU64::new(0)),
+            samples: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<const N: usize, const V: usize, const EVERY: u64> Clear for ErrorSamples<N, V, EVERY> {
+    fn clear(&self) {
+        for seen in &self.seen {
+            seen.store(0, Ordering::Relaxed);
+        }
+        self.samples.lock().clear();
+    }
+}
+
+impl<const N: usize, const V: usize, const EVERY: u64> Merge for ErrorSamples<N, V, EVERY> {
+    /// Appends the samples of `other` to the ones of self, as if they were
+    /// recorded after them. They were already sampled, so are all kept.
+    fn merge_from(&self, other: &Self) {
+        // Release other before locking self, in case other is self
+        let others = other.samples.lock().clone();
+        for (variant, samples) in others {
+            for sample in samples {
+                self.push(variant, sample);
+            }
+        }
+    }
+}
+
+impl<const N: usize, const V: usize, const EVERY: u64> Serialize for ErrorSamples<N, V, EVERY> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut samples = self.samples.lock();
+        let result = serializer.collect_map(samples.iter().map(|(v, samples)| (v, samples)));
+        if clear::is_clearing_serialization() {
+            samples.clear();
+        }
+        result
+    }
+}
+
+impl<const N: usize, const V: usize, const EVERY: u64> fmt::Debug for ErrorSamples<N, V, EVERY> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.samples.lock().iter().map(|(v, samples)| (v, samples)))
+            .finish()
+    }
+}
+
+/// The count of an error variant paired with the response times of the calls
+/// failing with it, generated by `#[metered::error_count]` with
+/// `with_latency = true`.
+///
+/// The count is serialized under the key of the variant, as without latencies,
+/// and response times under a `response_time` key: `serde_prometheus` reports
+/// them as `<error count>_response_time`, labeled by variant.
+#[cfg(feature = "histograms")]
+pub struct WithLatency<M, H: Histogram = AtomicHdrHistogram, T: Instant = StdInstant> {
+    /// The count of errors, a counter or a nested error count
+    pub count: M,
+    /// The response times of failed calls
+    pub response_time: ResponseTime<H, T>,
+}
+
+#[cfg(feature = "histograms")]
+impl<M, H: Histogram, T: Instant> WithLatency<M, H, T> {
+    /// Records the response time of a call failing since `start`
+    pub fn record_latency(&self, start: T) {
+        self.response_time.record(start.elapsed_time());
+    }
+}
+
+#[cfg(feature = "histograms")]
+impl<M: Default, H: Histogram, T: Instant> Default for WithLatency<M, H, T> {
+    fn default() -> Self {
+        WithLatency {
+            count: M::default(),
+            response_time: ResponseTime::default(),
+        }
+    }
+}
+
+#[cfg(feature = "histograms")]
+impl<M: Clear, H: Histogram, T: Instant> Clear for WithLatency<M, H, T> {
+    fn clear(&self) {
+        self.count.clear();
+        self.response_time.clear();
+    }
+}
+
+#[cfg(feature = "histograms")]
+impl<M: Clearable, H: Histogram + Clearable, T: Instant> Clearable for WithLatency<M, H, T> {
+    fn is_cleared(&self) -> bool {
+        self.count.is_cleared() && self.response_time.is_cleared()
+    }
+}
+
+#[cfg(feature = "histograms")]
+impl<M: Merge, H: Histogram + Merge, T: Instant> Merge for WithLatency<M, H, T> {
+    fn merge_from(&self, other: &Self) {
+        self.count.merge_from(&other.count);
+        self.response_time.merge_from(&other.response_time);
+    }
+}
+
+#[cfg(feature = "histograms")]
+impl<M: Serialize, H: Histogram + Serialize, T: Instant> Serialize for WithLatency<M, H, T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("WithLatency", 2)?;
+        // Drop the `count` key, so that counts keep their name
+        state.serialize_field("count", &MetricAlias("!|", &self.count))?;
+        state.serialize_field("response_time", &self.response_time)?;
+        state.end()
+    }
+}
+
+#[cfg(feature = "histograms")]
+impl<M: fmt::Debug, H: Histogram + fmt::Debug, T: Instant> fmt::Debug for WithLatency<M, H, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithLatency")
+            .field("count", &self.count)
+            .field("response_time", &self.response_time)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_evict_the_oldest_errors_of_their_variant() {
+        let samples: ErrorSamples<2, 2> = ErrorSamples::default();
+        for error in ["a", "b", "c"] {
+            samples.record(0, "First", &error);
+        }
+        samples.record(1, "Second", &"d");
+        assert_eq!(samples.get("First"), ["b", "c"]);
+        assert_eq!(samples.get("Second"), ["d"]);
+        assert!(samples.get("Third").is_empty());
+
+        samples.clear();
+        assert!(samples.get("First").is_empty());
+    }
+
+    #[test]
+    fn samples_keep_one_in_every_error_per_variant() {
+        let samples: ErrorSamples<3, 2, 2> = ErrorSamples::default();
+        for error in 0..6 {
+            samples.record(0, "First", &error);
+        }
+        samples.record(1, "Second", &"a");
+        assert_eq!(samples.get("First"), ["0", "2", "4"]);
+        assert_eq!(samples.get("Second"), ["a"]);
+    }
+
+    #[test]
+    fn merged_samples_are_appended_and_evicted() {
+        let samples: ErrorSamples<3, 2> = ErrorSamples::default();
+        samples.record(0, "First", &"a");
+        samples.record(0, "First", &"b");
+        let other: ErrorSamples<3, 2> = ErrorSamples::default();
+        other.record(0, "First", &"c");
+        other.record(0, "First", &"d");
+        other.record(1, "Second", &"e");
+
+        samples.merge_from(&other);
+        assert_eq!(samples.get("First"), ["b", "c", "d"]);
+        assert_eq!(samples.get("Second"), ["e"]);
+
+        samples.merge_from(&samples);
+        assert_eq!(samples.get("Second"), ["e", "e"]);
+        assert_eq!(
+            serde_json::to_string(&samples).unwrap(),
+            r#"{"First":["b","c","d"],"Second":["e","e"]}"#
+        );
+    }
+}
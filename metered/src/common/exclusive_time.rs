@@ -0,0 +1,160 @@
+//! A module providing the `ExclusiveTime` metric.
+
+#[cfg(feature = "histograms")]
+use crate::hdr_histogram::AtomicHdrHistogram;
+use crate::{
+    clear::{Clear, Clearable},
+    merge::Merge,
+    metadata::{Describe, MetricMetadata, MetricType, Unit},
+    metric::{Histogram, Metric},
+    nesting::Frame,
+    time_source::{Instant, StdInstant},
+};
+use aspect::{Advice, Enter, OnResult};
+use serde::{Serialize, Serializer};
+use std::{fmt, marker::PhantomData, ops::Deref, time::Duration};
+
+/// A metric measuring the exclusive time of an expression, that is its
+/// response time minus the time spent in the expressions measured by
+/// `ExclusiveTime` metrics it evaluates, on the same thread.
+///
+/// When measured methods call each other, their response times overlap:
+/// exclusive times instead add up to the total time, as in flame graphs.
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{common::ExclusiveTime, metered};
+/// use std::{thread, time::Duration};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Job {
+///     metrics: JobMetrics,
+/// }
+///
+/// #[metered(registry = JobMetrics)]
+/// impl Job {
+///     #[measure(ExclusiveTime)]
+///     pub fn step(&self) {
+///         thread::sleep(Duration::from_millis(50));
+///     }
+///
+///     #[measure(ExclusiveTime)]
+///     pub fn run(&self) {
+///         self.step();
+///         self.step();
+///     }
+/// }
+///
+/// let job = Job::default();
+/// job.run();
+/// assert!(job.metrics.step.exclusive_time.histogram().max() >= 50);
+/// assert!(job.metrics.run.exclusive_time.histogram().max() < 50);
+/// ```
+///
+/// Nested calls are tracked per thread, so this metric is meant for
+/// synchronous code. Like `ResponseTime`, it records durations in the units of
+/// its time source, up to 5 minutes by default.
+#[derive(Clone)]
+pub struct ExclusiveTime<
+    #[cfg(feature = "histograms")] H: Histogram = AtomicHdrHistogram,
+    #[cfg(not(feature = "histograms"))] H: Histogram,
+    T: Instant = StdInstant,
+>(pub H, PhantomData<T>);
+
+impl<H: Histogram, T: Instant> ExclusiveTime<H, T> {
+    /// Build an ExclusiveTime with a custom histogram bound
+    pub fn with_bound(bound: Duration) -> Self {
+        ExclusiveTime(H::with_bound(T::units(bound)), PhantomData)
+    }
+}
+
+impl<H: Histogram, T: Instant> Default for ExclusiveTime<H, T> {
+    fn default() -> Self {
+        ExclusiveTime(H::with_bound(5 * 60 * T::ONE_SEC), PhantomData)
+    }
+}
+
+/// Converts between units of time sources and nanoseconds, for nested calls
+/// measured with different time sources
+fn to_nanos<T: Instant>(units: u64) -> u64 {
+    (u128::from(units) * 1_000_000_000 / u128::from(T::ONE_SEC)) as u64
+}
+
+fn from_nanos<T: Instant>(nanos: u64) -> u64 {
+    (u128::from(nanos) * u128::from(T::ONE_SEC) / 1_000_000_000) as u64
+}
+
+impl<H: Histogram, T: Instant, R> Metric<R> for ExclusiveTime<H, T> {}
+
+impl<H: Histogram, T: Instant> Enter for ExclusiveTime<H, T> {
+    type E = (T, Frame);
+
+    #[inline]
+    fn enter(&self) -> (T, Frame) {
+        (T::now(), Frame::push())
+    }
+}
+
+impl<H: Histogram, T: Instant, R> OnResult<R> for ExclusiveTime<H, T> {
+    #[inline]
+    fn leave_scope(&self, (start, frame): (T, Frame)) -> Advice {
+        let elapsed = start.elapsed_time();
+        let nested = frame.finish(to_nanos::<T>(elapsed));
+        self.0
+            .record(elapsed.saturating_sub(from_nanos::<T>(nested)));
+        Advice::Return
+    }
+}
+
+impl<H: Histogram, T: Instant> Clear for ExclusiveTime<H, T> {
+    fn clear(&self) {
+        self.0.clear();
+    }
+}
+
+impl<H: Histogram + Clearable, T: Instant> Clearable for ExclusiveTime<H, T> {
+    fn is_cleared(&self) -> bool {
+        self.0.is_cleared()
+    }
+}
+
+impl<H: Histogram + Merge, T: Instant> Merge for ExclusiveTime<H, T> {
+    fn merge_from(&self, other: &Self) {
+        self.0.merge_from(&other.0);
+    }
+}
+
+impl<H: Histogram + Serialize, T: Instant> Serialize for ExclusiveTime<H, T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Serialize::serialize(&self.0, serializer)
+    }
+}
+
+impl<H: Histogram + fmt::Debug, T: Instant> fmt::Debug for ExclusiveTime<H, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", &self.0)
+    }
+}
+
+impl<H: Histogram, T: Instant> Deref for ExclusiveTime<H, T> {
+    type Target = H;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<H: Histogram, T: Instant> Describe for ExclusiveTime<H, T> {
+    fn metadata() -> MetricMetadata {
+        MetricMetadata::new(MetricType::Summary, Unit::from_time_resolution(T::ONE_SEC))
+    }
+}
@@ -0,0 +1,168 @@
+//! A module providing the `FloatGauge` metric.
+
+use crate::{
+    atomic::AtomicF64,
+    clear::{Clear, Clearable},
+    merge::Merge,
+    metadata::{Describe, MetricMetadata, MetricType, Unit},
+    metric::{FloatValue, Metric},
+};
+use aspect::{Advice, Enter, OnResult};
+use serde::Serialize;
+use std::ops::Deref;
+
+/// A metric providing a gauge holding a fractional value, such as a ratio, a
+/// temperature or a load average, set by code rather than by measured
+/// expressions.
+///
+/// Measuring an expression with a `FloatGauge` leaves it untouched, so that it
+/// can live in generated registries next to the metrics of a method:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{common::FloatGauge, metered};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Cache {
+///     metrics: CacheMetrics,
+/// }
+///
+/// #[metered(registry = CacheMetrics)]
+/// impl Cache {
+///     #[measure(FloatGauge)]
+///     pub fn get(&self, hits: u32, misses: u32) {
+///         let ratio = f64::from(hits) / f64::from(hits + misses);
+///         self.metrics.get.float_gauge.set(ratio);
+///     }
+/// }
+///
+/// let cache = Cache::default();
+/// cache.get(3, 1);
+/// assert_eq!(cache.metrics.get.float_gauge.get(), 0.75);
+/// ```
+///
+/// By default, `FloatGauge` uses a lock-free [`AtomicF64`]. Non-threaded
+/// applications can gain performance by using a `std::cell::Cell<f64>`
+/// instead.
+#[derive(Clone, Default, Debug, Serialize)]
+pub struct FloatGauge<G: FloatValue = AtomicF64>(pub G);
+
+impl<G: FloatValue> FloatGauge<G> {
+    /// Get the value of the gauge, whatever the backend
+    pub fn get(&self) -> f64 {
+        self.0.get()
+    }
+
+    /// Set the value of the gauge
+    pub fn set(&self, value: f64) {
+        self.0.set(value);
+    }
+
+    /// Add a value to the gauge, which may be negative
+    pub fn add(&self, value: f64) {
+        self.0.add(value);
+    }
+}
+
+/// A plain copy of the value of a [`FloatGauge`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FloatGaugeSnapshot {
+    /// The value of the gauge
+    pub value: f64,
+}
+
+impl<G: FloatValue> From<&FloatGauge<G>> for FloatGaugeSnapshot {
+    fn from(metric: &FloatGauge<G>) -> Self {
+        FloatGaugeSnapshot {
+            value: metric.get(),
+        }
+    }
+}
+
+impl<G: FloatValue, R> Metric<R> for FloatGauge<G> {}
+
+impl<G: FloatValue> Enter for FloatGauge<G> {
+    type E = ();
+    fn enter(&self) {}
+}
+
+impl<G: FloatValue, R> OnResult<R> for FloatGauge<G> {
+    fn leave_scope(&self, _: ()) -> Advice {
+        Advice::Return
+    }
+}
+
+impl<G: FloatValue> Clear for FloatGauge<G> {
+    fn clear(&self) {
+        // Do nothing: the gauge reflects a current state, which clearing
+        // would misreport
+    }
+}
+
+impl<G: FloatValue + Clearable> Clearable for FloatGauge<G> {
+    fn is_cleared(&self) -> bool {
+        self.0.is_cleared()
+    }
+}
+
+impl<G: FloatValue + Merge> Merge for FloatGauge<G> {
+    fn merge_from(&self, other: &Self) {
+        self.0.merge_from(&other.0);
+    }
+}
+
+impl<G: FloatValue> Deref for FloatGauge<G> {
+    type Target = G;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<G: FloatValue> Describe for FloatGauge<G> {
+    fn metadata() -> MetricMetadata {
+        MetricMetadata::new(MetricType::Gauge, Unit::None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{measure, metric::Gauge};
+    use std::cell::Cell;
+
+    #[test]
+    fn holds_fractional_values() {
+        let gauge: FloatGauge = FloatGauge::default();
+        measure!(&gauge, {});
+        assert_eq!(gauge.get(), 0.0);
+
+        gauge.set(0.5);
+        gauge.add(-1.25);
+        assert_eq!(gauge.get(), -0.75);
+        assert_eq!(gauge.value(), 0);
+        assert_eq!(FloatGaugeSnapshot::from(&gauge).value, -0.75);
+
+        let other: FloatGauge = FloatGauge::default();
+        other.incr_by(2);
+        gauge.merge_from(&other);
+        assert_eq!(gauge.get(), 1.25);
+        assert_eq!(gauge.value(), 1);
+    }
+
+    #[test]
+    fn adds_concurrently() {
+        let gauge: FloatGauge = FloatGauge::default();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| (0..1_000).for_each(|_| gauge.add(0.5)));
+            }
+        });
+        assert_eq!(gauge.get(), 2_000.0);
+
+        let gauge: FloatGauge<Cell<f64>> = FloatGauge::default();
+        gauge.add(0.1);
+        gauge.0.decr();
+        assert!((gauge.get() + 0.9).abs() < f64::EPSILON);
+    }
+}
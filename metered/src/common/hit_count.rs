@@ -2,11 +2,13 @@
 
 use crate::{
     atomic::AtomicInt,
-    clear::Clear,
+    clear::{self, Clear, Clearable},
+    merge::Merge,
+    metadata::{Describe, MetricMetadata, MetricType, Unit},
     metric::{Counter, Metric},
 };
 use aspect::{Enter, OnResult};
-use serde::Serialize;
+use serde::{Serialize, Serializer};
 use std::ops::Deref;
 
 /// A metric counting how many times an expression as been hit, before it
@@ -16,14 +18,49 @@ use std::ops::Deref;
 ///
 /// By default, `HitCount` uses a lock-free `u64` `Counter`, which makes sense
 /// in multithread scenarios. Non-threaded applications can gain performance by
-/// using a `std::cell:Cell<u64>` instead.
-#[derive(Clone, Default, Debug, Serialize)]
+/// using a `std::cell:Cell<u64>` instead. Counters wrap on overflow, a
+/// [`SaturatingCounter`](crate::int_counter::SaturatingCounter) clamps
+/// instead.
+#[derive(Clone, Default, Debug)]
 pub struct HitCount<C: Counter = AtomicInt<u64>>(pub C);
 
+impl<C: Counter> HitCount<C> {
+    /// Get the number of hits, whatever the counter backend
+    ///
+    #[cfg_attr(feature = "disabled", doc = "```ignore")]
+    #[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+    /// use metered::{measure, HitCount};
+    /// use std::cell::Cell;
+    ///
+    /// let hit_count: HitCount<Cell<u8>> = HitCount::default();
+    /// measure!(&hit_count, {});
+    /// assert_eq!(hit_count.get(), 1);
+    /// ```
+    pub fn get(&self) -> u64 {
+        self.0.value()
+    }
+}
+
+/// A plain copy of the value of a [`HitCount`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HitCountSnapshot {
+    /// The number of hits
+    pub count: u64,
+}
+
+impl<C: Counter> From<&HitCount<C>> for HitCountSnapshot {
+    fn from(metric: &HitCount<C>) -> Self {
+        HitCountSnapshot {
+            count: metric.get(),
+        }
+    }
+}
+
 impl<C: Counter, R> Metric<R> for HitCount<C> {}
 
 impl<C: Counter> Enter for HitCount<C> {
     type E = ();
+    #[inline]
     fn enter(&self) -> Self::E {
         self.0.incr();
     }
@@ -37,6 +74,33 @@ impl<C: Counter> Clear for HitCount<C> {
     }
 }
 
+impl<C: Counter> Clearable for HitCount<C> {
+    fn is_cleared(&self) -> bool {
+        self.0.is_cleared()
+    }
+}
+
+impl<C: Counter> Serialize for HitCount<C> {
+    /// Serializes the counter, or takes its value with
+    /// [`serialize_and_clear`](crate::clear::serialize_and_clear)
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if clear::is_clearing_serialization() {
+            serializer.serialize_newtype_struct("HitCount", &self.0.take_counter())
+        } else {
+            serializer.serialize_newtype_struct("HitCount", &self.0)
+        }
+    }
+}
+
+impl<C: Counter + Merge> Merge for HitCount<C> {
+    fn merge_from(&self, other: &Self) {
+        self.0.merge_from(&other.0);
+    }
+}
+
 impl<C: Counter> Deref for HitCount<C> {
     type Target = C;
 
@@ -44,3 +108,9 @@ impl<C: Counter> Deref for HitCount<C> {
         &self.0
     }
 }
+
+impl<C: Counter> Describe for HitCount<C> {
+    fn metadata() -> MetricMetadata {
+        MetricMetadata::new(MetricType::Counter, Unit::None)
+    }
+}
@@ -2,7 +2,9 @@
 
 use crate::{
     atomic::AtomicInt,
-    clear::Clear,
+    clear::{Clear, Clearable},
+    merge::Merge,
+    metadata::{Describe, MetricMetadata, MetricType, Unit},
     metric::{Gauge, Metric},
 };
 use aspect::{Advice, Enter, OnResult};
@@ -29,16 +31,52 @@ use std::ops::Deref;
 #[derive(Clone, Default, Debug, Serialize)]
 pub struct InFlight<G: Gauge = AtomicInt<u64>>(pub G);
 
+impl<G: Gauge> InFlight<G> {
+    /// Get the number of expressions currently running, whatever the gauge
+    /// backend
+    ///
+    #[cfg_attr(feature = "disabled", doc = "```ignore")]
+    #[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+    /// use metered::{measure, InFlight};
+    ///
+    /// let in_flight: InFlight = InFlight::default();
+    /// measure!(&in_flight, {
+    ///     assert_eq!(in_flight.current(), 1);
+    /// });
+    /// assert_eq!(in_flight.current(), 0);
+    /// ```
+    pub fn current(&self) -> u64 {
+        self.0.value()
+    }
+}
+
+/// A plain copy of the value of a [`InFlight`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InFlightSnapshot {
+    /// The number of expressions currently running
+    pub current: u64,
+}
+
+impl<G: Gauge> From<&InFlight<G>> for InFlightSnapshot {
+    fn from(metric: &InFlight<G>) -> Self {
+        InFlightSnapshot {
+            current: metric.current(),
+        }
+    }
+}
+
 impl<G: Gauge, R> Metric<R> for InFlight<G> {}
 
 impl<G: Gauge> Enter for InFlight<G> {
     type E = ();
+    #[inline]
     fn enter(&self) {
         self.0.incr();
     }
 }
 
 impl<G: Gauge, R> OnResult<R> for InFlight<G> {
+    #[inline]
     fn leave_scope(&self, _: ()) -> Advice {
         self.0.decr();
         Advice::Return
@@ -52,6 +90,18 @@ impl<G: Gauge> Clear for InFlight<G> {
     }
 }
 
+impl<G: Gauge + Clearable> Clearable for InFlight<G> {
+    fn is_cleared(&self) -> bool {
+        self.0.is_cleared()
+    }
+}
+
+impl<G: Gauge + Merge> Merge for InFlight<G> {
+    fn merge_from(&self, other: &Self) {
+        self.0.merge_from(&other.0);
+    }
+}
+
 impl<G: Gauge> Deref for InFlight<G> {
     type Target = G;
 
@@ -59,3 +109,9 @@ impl<G: Gauge> Deref for InFlight<G> {
         &self.0
     }
 }
+
+impl<G: Gauge> Describe for InFlight<G> {
+    fn metadata() -> MetricMetadata {
+        MetricMetadata::new(MetricType::Gauge, Unit::None)
+    }
+}
@@ -0,0 +1,208 @@
+//! A module providing the `LastCalled` and `LastResult` metrics.
+
+use crate::{
+    atomic::AtomicInt,
+    clear::{Clear, Clearable},
+    merge::Merge,
+    metadata::{Describe, MetricMetadata, MetricType, Unit},
+    metric::Metric,
+    staleness::LastUpdated,
+};
+use aspect::{Advice, Enter, OnResult};
+use serde::Serialize;
+use std::{
+    ops::Deref,
+    sync::atomic::Ordering,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Returns the wall-clock time in milliseconds since the Unix epoch.
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Returns the time elapsed since a timestamp, or `None` if it was never set.
+fn elapsed_since(timestamp: u64) -> Option<Duration> {
+    if timestamp == 0 {
+        None
+    } else {
+        Some(Duration::from_millis(
+            now_millis().saturating_sub(timestamp),
+        ))
+    }
+}
+
+/// A metric recording the wall-clock time of the most recent call to an
+/// expression, in milliseconds since the Unix epoch, or 0 if it was never
+/// called.
+///
+/// This is a light-weight metric, serialized as a gauge, and a cheap liveness
+/// indicator for rarely-run jobs:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{common::LastCalled, measure};
+///
+/// let last_called = LastCalled::default();
+/// assert_eq!(last_called.elapsed(), None);
+///
+/// measure!(&last_called, {});
+/// assert!(last_called.get() > 0);
+/// assert!(last_called.elapsed().unwrap().as_secs() < 60);
+/// ```
+///
+/// Like gauges, `LastCalled` is not reset when cleared. See [`LastResult`] to
+/// record the time of the last success and of the last error instead.
+#[derive(Default, Debug, Serialize)]
+pub struct LastCalled(pub AtomicInt<u64>);
+
+impl LastCalled {
+    /// Returns the time elapsed since the last call, or `None` if the
+    /// expression was never called.
+    pub fn elapsed(&self) -> Option<Duration> {
+        elapsed_since(self.0.get())
+    }
+}
+
+impl LastUpdated for LastCalled {
+    fn last_updated(&self) -> Option<SystemTime> {
+        match self.0.get() {
+            0 => None,
+            millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+        }
+    }
+}
+
+impl<R> Metric<R> for LastCalled {}
+
+impl Enter for LastCalled {
+    type E = ();
+    fn enter(&self) {
+        self.0.set(now_millis());
+    }
+}
+
+impl<R> OnResult<R> for LastCalled {}
+
+impl Clear for LastCalled {
+    fn clear(&self) {
+        // Do nothing: the last call did happen, clearing would make the
+        // expression look dead
+    }
+}
+
+impl Clearable for LastCalled {
+    fn is_cleared(&self) -> bool {
+        self.0.get() == 0
+    }
+}
+
+impl Merge for LastCalled {
+    fn merge_from(&self, other: &Self) {
+        // Keep the most recent call
+        self.0.inner.fetch_max(other.0.get(), Ordering::Relaxed);
+    }
+}
+
+impl Deref for LastCalled {
+    type Target = AtomicInt<u64>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// A metric recording the wall-clock time of the most recent `Ok` and `Err`
+/// results of an expression typed std `Result`, in milliseconds since the Unix
+/// epoch, or 0 if there was no such result.
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{common::LastResult, measure};
+///
+/// let last_result = LastResult::default();
+/// let _ = measure!(&last_result, Err::<(), ()>(()));
+///
+/// assert_eq!(last_result.last_success.get(), 0);
+/// assert!(last_result.last_error.get() > 0);
+/// ```
+///
+/// Like [`LastCalled`], `LastResult` is not reset when cleared.
+#[derive(Default, Debug, Serialize)]
+pub struct LastResult {
+    /// The time of the last `Ok` result
+    pub last_success: AtomicInt<u64>,
+    /// The time of the last `Err` result
+    pub last_error: AtomicInt<u64>,
+}
+
+impl LastResult {
+    /// Returns the time elapsed since the last success, or `None` if there was
+    /// no success.
+    pub fn since_success(&self) -> Option<Duration> {
+        elapsed_since(self.last_success.get())
+    }
+
+    /// Returns the time elapsed since the last error, or `None` if there was
+    /// no error.
+    pub fn since_error(&self) -> Option<Duration> {
+        elapsed_since(self.last_error.get())
+    }
+}
+
+impl<T, E> Metric<Result<T, E>> for LastResult {}
+
+impl Enter for LastResult {
+    type E = ();
+    fn enter(&self) {}
+}
+
+impl<T, E> OnResult<Result<T, E>> for LastResult {
+    fn on_result(&self, _: (), r: &Result<T, E>) -> Advice {
+        let now = now_millis();
+        if r.is_ok() {
+            self.last_success.set(now);
+        } else {
+            self.last_error.set(now);
+        }
+        Advice::Return
+    }
+}
+
+impl Clear for LastResult {
+    fn clear(&self) {
+        // Do nothing, see `LastCalled`
+    }
+}
+
+impl Clearable for LastResult {
+    fn is_cleared(&self) -> bool {
+        self.last_success.get() == 0 && self.last_error.get() == 0
+    }
+}
+
+impl Merge for LastResult {
+    fn merge_from(&self, other: &Self) {
+        // Keep the most recent results
+        let (success, error) = (other.last_success.get(), other.last_error.get());
+        self.last_success
+            .inner
+            .fetch_max(success, Ordering::Relaxed);
+        self.last_error.inner.fetch_max(error, Ordering::Relaxed);
+    }
+}
+
+impl Describe for LastCalled {
+    fn metadata() -> MetricMetadata {
+        MetricMetadata::new(MetricType::Gauge, Unit::Milliseconds)
+    }
+}
+
+impl Describe for LastResult {
+    fn metadata() -> MetricMetadata {
+        MetricMetadata::new(MetricType::Gauge, Unit::Milliseconds)
+    }
+}
@@ -1,15 +1,45 @@
 //! A module providing common metrics.
 
+mod alerting;
+#[cfg(feature = "allocation-count")]
+mod allocation_count;
+mod concurrency_limit;
+mod deadline_miss;
+mod error_code_count;
 mod error_count;
+mod exclusive_time;
+mod float_gauge;
 mod hit_count;
 mod in_flight;
+mod last_called;
 mod none_count;
+mod observed;
+mod rate_of;
 mod response_time;
+mod slo_budget;
+#[cfg(feature = "histograms")]
 mod throughput;
+mod value_histogram;
 
-pub use error_count::ErrorCount;
-pub use hit_count::HitCount;
-pub use in_flight::InFlight;
-pub use none_count::NoneCount;
-pub use response_time::ResponseTime;
-pub use throughput::{AtomicTxPerSec, RecordThroughput, Throughput, TxPerSec};
+pub use alerting::{AlertRule, Alerting};
+#[cfg(feature = "allocation-count")]
+pub use allocation_count::{AllocationCount, AllocationCountSnapshot};
+pub use concurrency_limit::{ConcurrencyLimit, LimitPolicy, QueueWhenFull, RejectWhenFull};
+pub use deadline_miss::DeadlineMiss;
+pub use error_code_count::{ErrorClass, ErrorCodeCount};
+pub use error_count::{ErrorCount, ErrorCountSnapshot, ErrorSamples};
+#[cfg(feature = "histograms")]
+pub use error_count::WithLatency;
+pub use exclusive_time::ExclusiveTime;
+pub use float_gauge::{FloatGauge, FloatGaugeSnapshot};
+pub use hit_count::{HitCount, HitCountSnapshot};
+pub use in_flight::{InFlight, InFlightSnapshot};
+pub use last_called::{LastCalled, LastResult};
+pub use none_count::{NoneCount, NoneCountSnapshot};
+pub use observed::{MetricEvent, Observed};
+pub use rate_of::RateOf;
+pub use response_time::{ResponseTime, ResponseTimeBuilder, ResponseTimeSnapshot};
+pub use slo_budget::SloBudget;
+#[cfg(feature = "histograms")]
+pub use throughput::{AtomicTxPerSec, RecordThroughput, Throughput, ThroughputSnapshot, TxPerSec};
+pub use value_histogram::ValueHistogram;
@@ -2,11 +2,13 @@
 
 use crate::{
     atomic::AtomicInt,
-    clear::Clear,
+    clear::{self, Clear, Clearable},
+    merge::Merge,
+    metadata::{Describe, MetricMetadata, MetricType, Unit},
     metric::{Counter, Metric},
 };
 use aspect::{Advice, Enter, OnResult};
-use serde::Serialize;
+use serde::{Serialize, Serializer};
 use std::ops::Deref;
 
 /// A metric counting how many times the return value is Ok(None) or None.
@@ -18,9 +20,31 @@ use std::ops::Deref;
 /// By default, `NoneCount` uses a lock-free `u64` `Counter`, which makes sense
 /// in multithread scenarios. Non-threaded applications can gain performance by
 /// using a `std::cell:Cell<u64>` instead.
-#[derive(Clone, Default, Debug, Serialize)]
+#[derive(Clone, Default, Debug)]
 pub struct NoneCount<C: Counter = AtomicInt<u64>>(pub C);
 
+impl<C: Counter> NoneCount<C> {
+    /// Get the number of `None` results, whatever the counter backend
+    pub fn get(&self) -> u64 {
+        self.0.value()
+    }
+}
+
+/// A plain copy of the value of a [`NoneCount`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NoneCountSnapshot {
+    /// The number of `None` results
+    pub count: u64,
+}
+
+impl<C: Counter> From<&NoneCount<C>> for NoneCountSnapshot {
+    fn from(metric: &NoneCount<C>) -> Self {
+        NoneCountSnapshot {
+            count: metric.get(),
+        }
+    }
+}
+
 impl<C: Counter, T, E> Metric<Result<Option<T>, E>> for NoneCount<C> {}
 
 impl<C: Counter, T> Metric<Option<T>> for NoneCount<C> {}
@@ -54,6 +78,33 @@ impl<C: Counter> Clear for NoneCount<C> {
     }
 }
 
+impl<C: Counter> Clearable for NoneCount<C> {
+    fn is_cleared(&self) -> bool {
+        self.0.is_cleared()
+    }
+}
+
+impl<C: Counter> Serialize for NoneCount<C> {
+    /// Serializes the counter, or takes its value with
+    /// [`serialize_and_clear`](crate::clear::serialize_and_clear)
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if clear::is_clearing_serialization() {
+            serializer.serialize_newtype_struct("NoneCount", &self.0.take_counter())
+        } else {
+            serializer.serialize_newtype_struct("NoneCount", &self.0)
+        }
+    }
+}
+
+impl<C: Counter + Merge> Merge for NoneCount<C> {
+    fn merge_from(&self, other: &Self) {
+        self.0.merge_from(&other.0);
+    }
+}
+
 impl<C: Counter> Deref for NoneCount<C> {
     type Target = C;
 
@@ -61,3 +112,9 @@ impl<C: Counter> Deref for NoneCount<C> {
         &self.0
     }
 }
+
+impl<C: Counter> Describe for NoneCount<C> {
+    fn metadata() -> MetricMetadata {
+        MetricMetadata::new(MetricType::Counter, Unit::None)
+    }
+}
@@ -0,0 +1,193 @@
+//! A module providing the `Observed` metric wrapper.
+
+use crate::{
+    clear::{Clear, Clearable},
+    metadata::{Describe, MetricMetadata},
+    metric::{Gate, Metric},
+    sync::RwLock,
+};
+use aspect::{Advice, Enter, OnResult};
+use serde::{Serialize, Serializer};
+use std::{
+    ops::Deref,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// An event reported to the observers of an [`Observed`] metric.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MetricEvent {
+    /// A call to the measured expression started, and was recorded by the
+    /// metric.
+    Entered,
+    /// A call to the measured expression completed, and its result was
+    /// recorded by the metric.
+    Recorded,
+    /// The metric was cleared.
+    Cleared,
+}
+
+type Observer<M> = Box<dyn Fn(MetricEvent, &M) + Send + Sync>;
+
+/// A metric wrapper notifying subscribed observers of the inner metric's
+/// events.
+///
+/// Observers are called on the measuring thread, after the inner metric has
+/// handled the event, and can read its current values, e.g. to mirror them
+/// into another system or maintain derived metrics:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{common::{MetricEvent, Observed}, metered, HitCount};
+/// use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics)]
+/// impl Service {
+///     #[measure(Observed<HitCount>)]
+///     pub fn call(&self) {}
+/// }
+///
+/// let service = Service::default();
+/// let mirrored = Arc::new(AtomicU64::new(0));
+/// let mirror = Arc::clone(&mirrored);
+/// service.metrics.call.observed.subscribe(move |event, hit_count| {
+///     if event == MetricEvent::Recorded {
+///         mirror.store(hit_count.get(), Ordering::Relaxed);
+///     }
+/// });
+///
+/// service.call();
+/// service.call();
+/// assert_eq!(mirrored.load(Ordering::Relaxed), 2);
+/// ```
+///
+/// Until an observer subscribes, the only overhead over the inner metric is an
+/// atomic load per event. Observed serializes exactly like the inner
+/// metric.
+pub struct Observed<M> {
+    metric: M,
+    observed: AtomicBool,
+    observers: RwLock<Vec<Observer<M>>>,
+}
+
+impl<M> Observed<M> {
+    /// Wraps a metric, with no observers
+    pub fn new(metric: M) -> Self {
+        Observed {
+            metric,
+            observed: AtomicBool::new(false),
+            observers: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Subscribes an observer to the metric's events
+    pub fn subscribe<F>(&self, observer: F)
+    where
+        F: Fn(MetricEvent, &M) + Send + Sync + 'static,
+    {
+        self.observers.write().push(Box::new(observer));
+        self.observed.store(true, Ordering::Release);
+    }
+
+    /// Removes all observers
+    pub fn unsubscribe_all(&self) {
+        let mut observers = self.observers.write();
+        self.observed.store(false, Ordering::Release);
+        observers.clear();
+    }
+
+    #[inline]
+    fn notify(&self, event: MetricEvent) {
+        if self.observed.load(Ordering::Acquire) {
+            for observer in self.observers.read().iter() {
+                observer(event, &self.metric);
+            }
+        }
+    }
+}
+
+impl<M: Default> Default for Observed<M> {
+    fn default() -> Self {
+        Observed::new(M::default())
+    }
+}
+
+impl<M: Metric<R> + OnResult<R>, R> Metric<R> for Observed<M> {}
+
+impl<M: Enter> Enter for Observed<M> {
+    type E = M::E;
+
+    fn enter(&self) -> M::E {
+        let enter = self.metric.enter();
+        self.notify(MetricEvent::Entered);
+        enter
+    }
+}
+
+impl<M: Gate> Gate for Observed<M> {
+    fn should_abort(&self, enter: &M::E) -> bool {
+        self.metric.should_abort(enter)
+    }
+}
+
+impl<M: OnResult<R>, R> OnResult<R> for Observed<M> {
+    fn on_result(&self, enter: M::E, result: &R) -> Advice {
+        let advice = self.metric.on_result(enter, result);
+        self.notify(MetricEvent::Recorded);
+        advice
+    }
+
+    fn leave_scope(&self, enter: M::E) -> Advice {
+        let advice = self.metric.leave_scope(enter);
+        self.notify(MetricEvent::Recorded);
+        advice
+    }
+}
+
+impl<M: Clear> Clear for Observed<M> {
+    fn clear(&self) {
+        self.metric.clear();
+        self.notify(MetricEvent::Cleared);
+    }
+}
+
+impl<M: Clearable> Clearable for Observed<M> {
+    fn is_cleared(&self) -> bool {
+        self.metric.is_cleared()
+    }
+}
+
+impl<M: Serialize> Serialize for Observed<M> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Serialize::serialize(&self.metric, serializer)
+    }
+}
+
+use std::{fmt, fmt::Debug};
+impl<M: Debug> Debug for Observed<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", &self.metric)
+    }
+}
+
+impl<M: Describe> Describe for Observed<M> {
+    fn metadata() -> MetricMetadata {
+        M::metadata()
+    }
+}
+
+impl<M> Deref for Observed<M> {
+    type Target = M;
+
+    fn deref(&self) -> &Self::Target {
+        &self.metric
+    }
+}
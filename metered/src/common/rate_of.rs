@@ -0,0 +1,287 @@
+//! A module providing the `RateOf` metric wrapper.
+
+use crate::{
+    clear::{self, Clear, Clearable},
+    metadata::{Describe, MetricMetadata, MetricType, Unit},
+    metric::{Counter, Metric},
+    sync::Mutex,
+    time_source::{Instant, StdInstant},
+};
+use aspect::{Advice, Enter, OnResult};
+use serde::{Serialize, Serializer};
+use std::{fmt, ops::Deref};
+
+/// The value of the counter when last observed
+#[derive(Clone, Copy, Debug)]
+struct Observation {
+    value: u64,
+    /// When the value was observed, in units of the time source since the
+    /// creation of the metric
+    at: u64,
+    rate: f64,
+}
+
+/// A metric wrapper reporting the increase per second of a counter metric,
+/// such as a [`HitCount`](crate::HitCount), since it was last observed.
+///
+/// Prometheus computes rates from counters, but plain JSON or log sinks
+/// can't: `RateOf` serializes the rate instead of the counter. Each
+/// serialization, or call to [`RateOf::rate`], is an observation, from which
+/// the next rate is computed:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{common::RateOf, measure, simulation::{SimInstant, Simulation}, HitCount};
+/// use std::time::Duration;
+///
+/// let simulation = Simulation::start(0);
+/// let hit_count: RateOf<HitCount, SimInstant> = RateOf::default();
+///
+/// for _ in 0..30 {
+///     measure!(&hit_count, {});
+/// }
+/// simulation.advance(Duration::from_secs(10));
+/// assert_eq!(hit_count.rate(), 3.0);
+///
+/// simulation.advance(Duration::from_secs(10));
+/// assert_eq!(serde_json::to_string(&hit_count).unwrap(), "0.0");
+/// assert_eq!(hit_count.get(), 30);
+/// ```
+///
+/// Several consumers serializing the same registry, e.g. an exporter and an
+/// admin endpoint, each see the rate since the other's observation: rates are
+/// meant for a single periodic consumer. Observations made less than a unit
+/// of the time source after the previous one report the previous rate.
+pub struct RateOf<M, T: Instant = StdInstant> {
+    metric: M,
+    start: T,
+    last: Mutex<Observation>,
+}
+
+impl<M, T: Instant> RateOf<M, T>
+where
+    M: Deref,
+    M::Target: Counter,
+{
+    /// Wraps a counter metric, observing it a first time
+    pub fn new(metric: M) -> Self {
+        let value = metric.value();
+        RateOf {
+            metric,
+            start: T::now(),
+            last: Mutex::new(Observation {
+                value,
+                at: 0,
+                rate: 0.0,
+            }),
+        }
+    }
+
+    /// Observes the counter, and returns its increase per second since the
+    /// previous observation
+    pub fn rate(&self) -> f64 {
+        self.observe(false)
+    }
+
+    /// Observes the counter, taking its value if `take` is true
+    fn observe(&self, take: bool) -> f64 {
+        let mut last = self.last.lock();
+        let now = self.start.elapsed_time();
+        if now <= last.at {
+            return last.rate;
+        }
+
+        let value = if take {
+            Counter::take(&*self.metric)
+        } else {
+            self.metric.value()
+        };
+        // A counter going down was cleared or wrapped: it restarted from 0
+        let increase = if value >= last.value {
+            value - last.value
+        } else {
+            value
+        };
+        let rate = increase as f64 * T::ONE_SEC as f64 / (now - last.at) as f64;
+        *last = Observation {
+            value: if take { 0 } else { value },
+            at: now,
+            rate,
+        };
+        rate
+    }
+}
+
+impl<M, T: Instant> Default for RateOf<M, T>
+where
+    M: Default + Deref,
+    M::Target: Counter,
+{
+    fn default() -> Self {
+        RateOf::new(M::default())
+    }
+}
+
+impl<M, T: Instant, R> Metric<R> for RateOf<M, T>
+where
+    M: Metric<R> + OnResult<R> + Deref,
+    M::Target: Counter,
+{
+}
+
+impl<M: Enter, T: Instant> Enter for RateOf<M, T> {
+    type E = M::E;
+
+    fn enter(&self) -> M::E {
+        self.metric.enter()
+    }
+}
+
+impl<M: OnResult<R>, T: Instant, R> OnResult<R> for RateOf<M, T> {
+    fn on_result(&self, enter: M::E, result: &R) -> Advice {
+        self.metric.on_result(enter, result)
+    }
+
+    fn leave_scope(&self, enter: M::E) -> Advice {
+        self.metric.leave_scope(enter)
+    }
+}
+
+impl<M: Clear, T: Instant> Clear for RateOf<M, T> {
+    /// Clears the counter, and observes it again from 0
+    fn clear(&self) {
+        let mut last = self.last.lock();
+        self.metric.clear();
+        *last = Observation {
+            value: 0,
+            at: self.start.elapsed_time(),
+            rate: 0.0,
+        };
+    }
+}
+
+impl<M: Clearable, T: Instant> Clearable for RateOf<M, T> {
+    fn is_cleared(&self) -> bool {
+        self.metric.is_cleared()
+    }
+}
+
+impl<M, T: Instant> Serialize for RateOf<M, T>
+where
+    M: Deref,
+    M::Target: Counter,
+{
+    /// Serializes the rate, as an observation, taking the counter with
+    /// [`serialize_and_clear`](crate::clear::serialize_and_clear)
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_f64(self.observe(clear::is_clearing_serialization()))
+    }
+}
+
+impl<M, T: Instant> Deref for RateOf<M, T> {
+    type Target = M;
+
+    fn deref(&self) -> &M {
+        &self.metric
+    }
+}
+
+impl<M: fmt::Debug, T: Instant> fmt::Debug for RateOf<M, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateOf")
+            .field("metric", &self.metric)
+            .field("last", &*self.last.lock())
+            .finish()
+    }
+}
+
+impl<M: Describe, T: Instant> Describe for RateOf<M, T> {
+    fn metadata() -> MetricMetadata {
+        let unit = match M::metadata().unit {
+            Unit::Requests => Unit::RequestsPerSecond,
+            _ => Unit::None,
+        };
+        MetricMetadata::new(MetricType::Gauge, unit)
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{
+        measure,
+        simulation::{SimInstant, Simulation},
+        HitCount,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn computes_rates_between_observations() {
+        let simulation = Simulation::start(0);
+        let hit_count: RateOf<HitCount, SimInstant> = RateOf::default();
+
+        for _ in 0..5 {
+            measure!(&hit_count, {});
+        }
+        simulation.advance(Duration::from_millis(500));
+        assert_eq!(hit_count.rate(), 10.0);
+        // Too soon for another observation
+        measure!(&hit_count, {});
+        assert_eq!(hit_count.rate(), 10.0);
+
+        simulation.advance(Duration::from_secs(2));
+        assert_eq!(hit_count.rate(), 0.5);
+
+        hit_count.clear();
+        measure!(&hit_count, {});
+        simulation.advance(Duration::from_secs(1));
+        assert_eq!(hit_count.rate(), 1.0);
+    }
+
+    #[test]
+    fn restarts_from_counter_resets() {
+        let simulation = Simulation::start(0);
+        let hit_count: RateOf<HitCount, SimInstant> = RateOf::default();
+        for _ in 0..10 {
+            measure!(&hit_count, {});
+        }
+        simulation.advance(Duration::from_secs(1));
+        assert_eq!(hit_count.rate(), 10.0);
+
+        // Cleared behind the wrapper's back
+        hit_count.metric.clear();
+        measure!(&hit_count, {});
+        simulation.advance(Duration::from_secs(1));
+        assert_eq!(hit_count.rate(), 1.0);
+    }
+
+    #[test]
+    fn takes_the_counter_when_serialized_and_cleared() {
+        let simulation = Simulation::start(0);
+        let hit_count: RateOf<HitCount, SimInstant> = RateOf::default();
+        let take = || {
+            let mut json = Vec::new();
+            crate::clear::serialize_and_clear(
+                &hit_count,
+                &mut serde_json::Serializer::new(&mut json),
+            )
+            .unwrap();
+            serde_json::from_slice::<f64>(&json).unwrap()
+        };
+
+        for _ in 0..4 {
+            measure!(&hit_count, {});
+        }
+        simulation.advance(Duration::from_secs(2));
+        assert_eq!(take(), 2.0);
+        assert_eq!(hit_count.get(), 0);
+
+        measure!(&hit_count, {});
+        simulation.advance(Duration::from_secs(1));
+        assert_eq!(take(), 1.0);
+        assert_eq!(hit_count.get(), 0);
+    }
+}
@@ -1,14 +1,21 @@
 //! A module providing the `ResponseTime` metric.
 
+#[cfg(feature = "histograms")]
+use crate::hdr_histogram::{AtomicHdrBuckets, AtomicHdrHistogram, HdrHistogram};
 use crate::{
-    clear::Clear,
-    hdr_histogram::AtomicHdrHistogram,
-    metric::{Histogram, Metric},
+    clear::{Clear, Clearable},
+    dd_sketch::{AtomicDdSketch, DdSketch},
+    merge::Merge,
+    metadata::{Describe, MetricMetadata, MetricType, Unit},
+    metric::{Histogram, HistogramSnapshot, Metric, MetricBuilder, SnapshotHistogram},
+    null::NullHistogram,
+    reservoir::{Reservoir, ReservoirHistogram},
+    t_digest::{TDigest, TDigestHistogram},
     time_source::{Instant, StdInstant},
 };
 use aspect::{Advice, Enter, OnResult};
 use serde::{Serialize, Serializer};
-use std::{ops::Deref, time::Duration};
+use std::{cell::RefCell, ops::Deref, time::Duration};
 
 /// A metric measuring the response time of an expression, that is the duration
 /// the expression needed to complete.
@@ -21,37 +28,223 @@ use std::{ops::Deref, time::Duration};
 /// time source, which work better in multithread scenarios. Non-threaded
 /// applications can gain performance by using unsynchronized structures
 /// instead.
+///
+/// Without the `histograms` feature, the histogram has no default and is
+/// chosen among the other backends, e.g. `ResponseTime<AtomicDdSketch>`.
 #[derive(Clone)]
-pub struct ResponseTime<H: Histogram = AtomicHdrHistogram, T: Instant = StdInstant>(
-    pub H,
-    std::marker::PhantomData<T>,
-);
+pub struct ResponseTime<
+    #[cfg(feature = "histograms")] H: Histogram = AtomicHdrHistogram,
+    #[cfg(not(feature = "histograms"))] H: Histogram,
+    T: Instant = StdInstant,
+>(pub H, std::marker::PhantomData<T>);
 
 impl<H: Histogram, T: Instant> ResponseTime<H, T> {
     /// Build a ResponseTime with a custom histogram bound
-    /// 
-    /// ```rust
+    ///
+    #[cfg_attr(not(feature = "histograms"), doc = "```ignore")]
+    #[cfg_attr(feature = "histograms", doc = "```rust")]
     /// use std::time::Duration;
     /// use metered::{ResponseTime, hdr_histogram::AtomicHdrHistogram, time_source::StdInstantMicros};
-    /// 
+    ///
     /// let response_time_millis: ResponseTime =
     ///     ResponseTime::with_bound(Duration::from_secs(4));
-    /// 
+    ///
     /// assert_eq!(response_time_millis.histogram().bound(), 4_000);
-    /// 
+    ///
     /// let response_time_micros: ResponseTime<AtomicHdrHistogram, StdInstantMicros> =
     ///     ResponseTime::with_bound(Duration::from_secs(4));
-    /// 
+    ///
     /// assert_eq!(response_time_micros.histogram().bound(), 4_000_000);
     /// ```
     pub fn with_bound(bound: Duration) -> Self {
         ResponseTime(H::with_bound(T::units(bound)), std::marker::PhantomData)
     }
+
+    /// Get a copy of the recorded response times, whatever the histogram
+    /// backend, e.g. an `HdrHistogram` for the default one
+    ///
+    #[cfg_attr(
+        any(feature = "disabled", not(feature = "histograms")),
+        doc = "```ignore"
+    )]
+    #[cfg_attr(
+        all(not(feature = "disabled"), feature = "histograms"),
+        doc = "```rust"
+    )]
+    /// use metered::{measure, ResponseTime};
+    ///
+    /// let response_time: ResponseTime = ResponseTime::default();
+    /// measure!(&response_time, {});
+    ///
+    /// let histogram = response_time.snapshot_histogram();
+    /// for bucket in histogram.buckets() {
+    ///     println!("{}ms: {}", bucket.value, bucket.count);
+    /// }
+    /// assert_eq!(histogram.len(), 1);
+    /// ```
+    pub fn snapshot_histogram(&self) -> H::Snapshot
+    where
+        H: SnapshotHistogram,
+    {
+        self.0.snapshot_histogram()
+    }
+}
+
+impl<H: Histogram> ResponseTime<H> {
+    /// Get a builder configuring the bound, precision and time unit of a
+    /// ResponseTime
+    ///
+    #[cfg_attr(not(feature = "histograms"), doc = "```ignore")]
+    #[cfg_attr(feature = "histograms", doc = "```rust")]
+    /// use metered::{
+    ///     hdr_histogram::AtomicHdrHistogram, time_source::StdInstantMicros, Histogram, ResponseTime,
+    /// };
+    /// use std::time::Duration;
+    ///
+    /// let response_time: ResponseTime<AtomicHdrHistogram, StdInstantMicros> = ResponseTime::builder()
+    ///     .bound(Duration::from_secs(10))
+    ///     .sig_figs(3)
+    ///     .time_unit::<StdInstantMicros>()
+    ///     .build();
+    ///
+    /// assert!(response_time.histogram().bound() >= 10_000_000);
+    ///
+    /// // Three significant figures keep values within 0.1%
+    /// response_time.record(12_345);
+    /// assert!(response_time.histogram().max() - 12_345 < 12);
+    /// ```
+    pub fn builder() -> ResponseTimeBuilder<H> {
+        ResponseTimeBuilder {
+            bound: Duration::from_secs(5 * 60),
+            sig_figs: None,
+            marker: std::marker::PhantomData,
+        }
+    }
+}
+
+/// A builder for [`ResponseTime`] metrics, see [`ResponseTime::builder`].
+///
+/// By default, it builds the same metric as `ResponseTime::default()`.
+pub struct ResponseTimeBuilder<
+    #[cfg(feature = "histograms")] H: Histogram = AtomicHdrHistogram,
+    #[cfg(not(feature = "histograms"))] H: Histogram,
+    T: Instant = StdInstant,
+> {
+    bound: Duration,
+    sig_figs: Option<u8>,
+    marker: std::marker::PhantomData<fn() -> (H, T)>,
+}
+
+impl<H: Histogram, T: Instant> ResponseTimeBuilder<H, T> {
+    /// Sets the highest response time the histogram records, higher ones
+    /// saturating to it. It defaults to 5 minutes.
+    pub fn bound(mut self, bound: Duration) -> Self {
+        self.bound = bound;
+        self
+    }
+
+    /// Sets the number of significant figures the histogram keeps, from 0 to
+    /// 5, if it supports it like HdrHistograms. It defaults to the precision
+    /// of `Histogram::with_bound`.
+    pub fn sig_figs(mut self, sig_figs: u8) -> Self {
+        self.sig_figs = Some(sig_figs);
+        self
+    }
+
+    /// Sets the time source, and therefore the unit of response times
+    pub fn time_unit<U: Instant>(self) -> ResponseTimeBuilder<H, U> {
+        ResponseTimeBuilder {
+            bound: self.bound,
+            sig_figs: self.sig_figs,
+            marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Builds the ResponseTime
+    ///
+    /// # Panics
+    ///
+    /// Panics if the histogram does not support the configuration, e.g. an
+    /// HdrHistogram with more than 5 significant figures.
+    pub fn build(self) -> ResponseTime<H, T> {
+        let bound = T::units(self.bound);
+        let histogram = match self.sig_figs {
+            Some(sig_figs) => H::with_precision(bound, sig_figs),
+            None => H::with_bound(bound),
+        };
+        ResponseTime(histogram, std::marker::PhantomData)
+    }
+}
+
+impl<H: Histogram, T: Instant> MetricBuilder<ResponseTime<H, T>> for ResponseTimeBuilder<H, T> {
+    fn build_metric(self) -> ResponseTime<H, T> {
+        self.build()
+    }
 }
 
+impl<H: Histogram, T: Instant> Debug for ResponseTimeBuilder<H, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseTimeBuilder")
+            .field("bound", &self.bound)
+            .field("sig_figs", &self.sig_figs)
+            .finish()
+    }
+}
+
+/// A plain copy of the statistics of a [`ResponseTime`], in the units of its
+/// time source.
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{common::ResponseTimeSnapshot, measure, ResponseTime};
+///
+/// let response_time: ResponseTime = ResponseTime::default();
+/// measure!(&response_time, {});
+///
+/// let snapshot = ResponseTimeSnapshot::from(&response_time);
+/// assert_eq!(snapshot.count, 1);
+/// ```
+pub type ResponseTimeSnapshot = HistogramSnapshot;
+
+macro_rules! impl_snapshot_for {
+    ($($histogram:ty),*) => {
+        $(
+            impl<T: Instant> From<&ResponseTime<$histogram, T>> for ResponseTimeSnapshot {
+                fn from(response_time: &ResponseTime<$histogram, T>) -> Self {
+                    HistogramSnapshot::from(&response_time.0)
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "histograms")]
+impl_snapshot_for!(
+    AtomicHdrHistogram,
+    AtomicHdrBuckets<true>,
+    AtomicHdrBuckets<false>,
+    RefCell<HdrHistogram>
+);
+
+impl_snapshot_for!(
+    AtomicDdSketch,
+    RefCell<DdSketch>,
+    ReservoirHistogram,
+    RefCell<Reservoir>,
+    TDigestHistogram,
+    RefCell<TDigest>,
+    NullHistogram
+);
+
 impl<H: Histogram, T: Instant> Default for ResponseTime<H, T> {
     fn default() -> Self {
-        // A HdrHistogram measuring latencies from 1ms to 5minutes
+        // A histogram measuring latencies from 1ms to 5minutes
         // All recordings will be saturating, that is, a value higher than 5 minutes
         // will be replace by 5 minutes...
         ResponseTime(H::with_bound(5 * 60 * T::ONE_SEC), std::marker::PhantomData)
@@ -63,12 +256,14 @@ impl<H: Histogram, T: Instant, R> Metric<R> for ResponseTime<H, T> {}
 impl<H: Histogram, T: Instant> Enter for ResponseTime<H, T> {
     type E = T;
 
+    #[inline]
     fn enter(&self) -> T {
         T::now()
     }
 }
 
 impl<H: Histogram, T: Instant, R> OnResult<R> for ResponseTime<H, T> {
+    #[inline]
     fn leave_scope(&self, enter: T) -> Advice {
         let elapsed = enter.elapsed_time();
         self.0.record(elapsed);
@@ -82,6 +277,18 @@ impl<H: Histogram, T: Instant> Clear for ResponseTime<H, T> {
     }
 }
 
+impl<H: Histogram + Clearable, T: Instant> Clearable for ResponseTime<H, T> {
+    fn is_cleared(&self) -> bool {
+        self.0.is_cleared()
+    }
+}
+
+impl<H: Histogram + Merge, T: Instant> Merge for ResponseTime<H, T> {
+    fn merge_from(&self, other: &Self) {
+        self.0.merge_from(&other.0);
+    }
+}
+
 impl<H: Histogram + Serialize, T: Instant> Serialize for ResponseTime<H, T> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -105,3 +312,9 @@ impl<H: Histogram, T: Instant> Deref for ResponseTime<H, T> {
         &self.0
     }
 }
+
+impl<H: Histogram, T: Instant> Describe for ResponseTime<H, T> {
+    fn metadata() -> MetricMetadata {
+        MetricMetadata::new(MetricType::Summary, Unit::from_time_resolution(T::ONE_SEC))
+    }
+}
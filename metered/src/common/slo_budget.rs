@@ -0,0 +1,293 @@
+//! A module providing the `SloBudget` metric.
+
+use crate::{
+    clear::{Clear, Clearable},
+    metric::Metric,
+    sync::Mutex,
+    time_source::{Instant, StdInstant},
+};
+use aspect::{Advice, Enter, OnResult};
+use serde::{Serialize, Serializer};
+
+/// The number of buckets the SLO window is divided into.
+const BUCKETS: usize = 30;
+
+/// A metric tracking the error budget of a service level objective over a
+/// rolling window, from the `Ok`/`Err` outcomes of an expression typed std
+/// `Result`.
+///
+/// The objective is expressed in basis points of successful calls (9990, i.e.
+/// 99.9%, by default) over a window of `WINDOW_SECS` seconds (30 days by
+/// default):
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{common::SloBudget, measure};
+///
+/// // 99% of calls should succeed over an hour
+/// let slo: SloBudget<9900, 3600> = SloBudget::default();
+///
+/// for i in 0..200 {
+///     let _ = measure!(&slo, if i == 0 { Err(()) } else { Ok(()) });
+/// }
+///
+/// assert_eq!(slo.errors(), 1);
+/// // 1 error out of a budget of 2
+/// assert_eq!(slo.remaining_budget(), 0.5);
+/// ```
+///
+/// The window is divided into 30 buckets, which expire one at a time: with
+/// the default window, outcomes are forgotten a day at a time. The burn rate,
+/// how fast the budget is being consumed relative to the objective, is
+/// computed over the most recent bucket: a burn rate of 1 exactly exhausts the
+/// budget at the end of the window.
+pub struct SloBudget<
+    const OBJECTIVE_BP: u32 = 9990,
+    const WINDOW_SECS: u64 = 2_592_000,
+    T: Instant = StdInstant,
+> {
+    inner: Mutex<SloWindow<T>>,
+}
+
+struct SloWindow<T: Instant> {
+    start: T,
+    // Pairs of bucket index since `start` and outcome counts
+    buckets: [(u64, Outcomes); BUCKETS],
+}
+
+#[derive(Clone, Copy, Default)]
+struct Outcomes {
+    total: u64,
+    errors: u64,
+}
+
+impl<const OBJECTIVE_BP: u32, const WINDOW_SECS: u64, T: Instant>
+    SloBudget<OBJECTIVE_BP, WINDOW_SECS, T>
+{
+    /// Returns the objective, the ratio of calls that should succeed
+    pub fn objective(&self) -> f64 {
+        f64::from(OBJECTIVE_BP.min(10_000)) / 10_000.0
+    }
+
+    /// The ratio of calls allowed to fail
+    fn allowed_error_ratio() -> f64 {
+        f64::from(10_000 - OBJECTIVE_BP.min(10_000)) / 10_000.0
+    }
+
+    /// The duration of a bucket, in `T` units
+    fn bucket_len() -> u64 {
+        (WINDOW_SECS.saturating_mul(T::ONE_SEC) / BUCKETS as u64).max(1)
+    }
+
+    /// Sums the outcomes of the buckets starting from `since` buckets ago
+    fn outcomes(&self, since: u64) -> Outcomes {
+        let inner = self.inner.lock();
+        let current = inner.start.elapsed_time() / Self::bucket_len();
+        let mut sum = Outcomes::default();
+        for (bucket, outcomes) in inner.buckets.iter() {
+            if *bucket <= current && current - *bucket <= since {
+                sum.total += outcomes.total;
+                sum.errors += outcomes.errors;
+            }
+        }
+        sum
+    }
+
+    fn record(&self, is_err: bool) {
+        let mut inner = self.inner.lock();
+        let current = inner.start.elapsed_time() / Self::bucket_len();
+        let (bucket, outcomes) = &mut inner.buckets[(current % BUCKETS as u64) as usize];
+        if *bucket != current {
+            *bucket = current;
+            *outcomes = Outcomes::default();
+        }
+        outcomes.total += 1;
+        if is_err {
+            outcomes.errors += 1;
+        }
+    }
+
+    /// Get the number of calls in the window
+    pub fn total(&self) -> u64 {
+        self.outcomes(BUCKETS as u64 - 1).total
+    }
+
+    /// Get the number of errors in the window
+    pub fn errors(&self) -> u64 {
+        self.outcomes(BUCKETS as u64 - 1).errors
+    }
+
+    /// Get the remaining ratio of the error budget in the window: 1 when no
+    /// error occurred, 0 when the objective is exactly met, and negative when
+    /// the objective is missed.
+    pub fn remaining_budget(&self) -> f64 {
+        let outcomes = self.outcomes(BUCKETS as u64 - 1);
+        let budget = outcomes.total as f64 * Self::allowed_error_ratio();
+        if outcomes.errors == 0 {
+            1.0
+        } else if budget == 0.0 {
+            f64::NEG_INFINITY
+        } else {
+            1.0 - outcomes.errors as f64 / budget
+        }
+    }
+
+    /// Get the burn rate over the most recent bucket: the observed error ratio
+    /// divided by the error ratio allowed by the objective.
+    pub fn burn_rate(&self) -> f64 {
+        let outcomes = self.outcomes(0);
+        let allowed = Self::allowed_error_ratio();
+        if outcomes.errors == 0 {
+            0.0
+        } else if allowed == 0.0 {
+            f64::INFINITY
+        } else {
+            outcomes.errors as f64 / outcomes.total as f64 / allowed
+        }
+    }
+}
+
+impl<const OBJECTIVE_BP: u32, const WINDOW_SECS: u64, T: Instant> Default
+    for SloBudget<OBJECTIVE_BP, WINDOW_SECS, T>
+{
+    fn default() -> Self {
+        SloBudget {
+            inner: Mutex::new(SloWindow {
+                start: T::now(),
+                // No bucket has been used yet
+                buckets: [(u64::MAX, Outcomes::default()); BUCKETS],
+            }),
+        }
+    }
+}
+
+impl<const OBJECTIVE_BP: u32, const WINDOW_SECS: u64, T: Instant, V, E> Metric<Result<V, E>>
+    for SloBudget<OBJECTIVE_BP, WINDOW_SECS, T>
+{
+}
+
+impl<const OBJECTIVE_BP: u32, const WINDOW_SECS: u64, T: Instant> Enter
+    for SloBudget<OBJECTIVE_BP, WINDOW_SECS, T>
+{
+    type E = ();
+    fn enter(&self) {}
+}
+
+impl<const OBJECTIVE_BP: u32, const WINDOW_SECS: u64, T: Instant, V, E> OnResult<Result<V, E>>
+    for SloBudget<OBJECTIVE_BP, WINDOW_SECS, T>
+{
+    fn on_result(&self, _: (), r: &Result<V, E>) -> Advice {
+        self.record(r.is_err());
+        Advice::Return
+    }
+}
+
+impl<const OBJECTIVE_BP: u32, const WINDOW_SECS: u64, T: Instant> Clear
+    for SloBudget<OBJECTIVE_BP, WINDOW_SECS, T>
+{
+    fn clear(&self) {
+        let mut inner = self.inner.lock();
+        inner.buckets = [(u64::MAX, Outcomes::default()); BUCKETS];
+    }
+}
+
+impl<const OBJECTIVE_BP: u32, const WINDOW_SECS: u64, T: Instant> Clearable
+    for SloBudget<OBJECTIVE_BP, WINDOW_SECS, T>
+{
+    fn is_cleared(&self) -> bool {
+        self.total() == 0
+    }
+}
+
+impl<const OBJECTIVE_BP: u32, const WINDOW_SECS: u64, T: Instant> Serialize
+    for SloBudget<OBJECTIVE_BP, WINDOW_SECS, T>
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeMap;
+
+        let outcomes = self.outcomes(BUCKETS as u64 - 1);
+        let mut map = serializer.serialize_map(Some(5))?;
+        map.serialize_entry("objective", &self.objective())?;
+        map.serialize_entry("total", &outcomes.total)?;
+        map.serialize_entry("errors", &outcomes.errors)?;
+        map.serialize_entry("remaining_budget", &self.remaining_budget())?;
+        map.serialize_entry("burn_rate", &self.burn_rate())?;
+        map.end()
+    }
+}
+
+use std::{fmt, fmt::Debug};
+impl<const OBJECTIVE_BP: u32, const WINDOW_SECS: u64, T: Instant> Debug
+    for SloBudget<OBJECTIVE_BP, WINDOW_SECS, T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "SloBudget {{ objective: {}, total: {}, errors: {}, remaining_budget: {}, burn_rate: {} }}",
+            self.objective(),
+            self.total(),
+            self.errors(),
+            self.remaining_budget(),
+            self.burn_rate()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    thread_local! {
+        static CLOCK: Cell<u64> = const { Cell::new(0) };
+    }
+
+    /// A time source in seconds, manually advanced by the test
+    struct TestInstant(u64);
+    impl Instant for TestInstant {
+        const ONE_SEC: u64 = 1;
+
+        fn now() -> Self {
+            TestInstant(CLOCK.with(Cell::get))
+        }
+
+        fn elapsed_time(&self) -> u64 {
+            CLOCK.with(Cell::get) - self.0
+        }
+
+        fn units(duration: std::time::Duration) -> u64 {
+            duration.as_secs()
+        }
+    }
+
+    #[test]
+    fn test_budget_expires_with_window() {
+        // 90% over 300 seconds, that is buckets of 10 seconds
+        let slo = SloBudget::<9000, 300, TestInstant>::default();
+        assert_eq!(slo.remaining_budget(), 1.0);
+
+        slo.record(true);
+        for _ in 0..19 {
+            slo.record(false);
+        }
+        assert_eq!(slo.remaining_budget(), 0.5);
+        assert_eq!(slo.burn_rate(), 0.5);
+
+        CLOCK.with(|c| c.set(100));
+        slo.record(true);
+        slo.record(true);
+        assert_eq!(slo.total(), 22);
+        assert_eq!(slo.errors(), 3);
+        assert_eq!(slo.burn_rate(), 10.0);
+
+        CLOCK.with(|c| c.set(305));
+        assert_eq!(slo.total(), 2);
+        assert!(slo.remaining_budget() < 0.0);
+
+        slo.clear();
+        assert_eq!(slo.total(), 0);
+    }
+}
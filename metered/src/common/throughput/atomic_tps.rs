@@ -1,44 +1,154 @@
 use super::{tx_per_sec::TxPerSec, RecordThroughput};
 use crate::{
-    clear::Clear,
+    clear::{self, Clear, Clearable},
     hdr_histogram::HdrHistogram,
+    merge::Merge,
+    metric::SnapshotHistogram,
+    sync::{atomic::AtomicU64, Mutex},
     time_source::{Instant, StdInstant},
 };
-use parking_lot::Mutex;
 use serde::{Serialize, Serializer};
+use std::sync::{atomic::Ordering, OnceLock};
 
-/// Thread-safe implementation of [`super::RecordThroughput`]. It uses a `Mutex`
-/// to wrap `TxPerSec`.
+/// The state of the current window: its index in the high bits, and its count
+/// in the low bits
+const COUNT_BITS: u32 = 32;
+const COUNT_MASK: u64 = (1 << COUNT_BITS) - 1;
+
+fn pack(window: u64, count: u64) -> u64 {
+    (window << COUNT_BITS) | (count & COUNT_MASK)
+}
+
+fn unpack(state: u64) -> (u64, u64) {
+    (state >> COUNT_BITS, state & COUNT_MASK)
+}
+
+/// Thread-safe implementation of [`super::RecordThroughput`].
+///
+/// Recording a transaction is a single atomic increment of the count of the
+/// current 1-second window, packed with the window's index in an `AtomicU64`.
+/// The first caller noticing that the window has closed swaps in the next one,
+/// and records the closed window to the histogram, the only locked operation.
+///
+/// A window counts at most `u32::MAX` transactions.
 pub struct AtomicTxPerSec<T: Instant = StdInstant> {
-    /// The inner mutex protecting the `TxPerSec` value holding the histogram
-    pub inner: Mutex<TxPerSec<T>>,
+    start_time: OnceLock<T>,
+    window: AtomicU64,
+    histogram: Mutex<HdrHistogram>,
 }
 
 impl<T: Instant> AtomicTxPerSec<T> {
     /// Returns a cloned snapshot of the inner histogram.
     pub fn histogram(&self) -> HdrHistogram {
-        self.inner.lock().hdr_histogram.clone()
+        self.histogram.lock().clone()
+    }
+
+    /// Get the index of the current window since the first transaction
+    #[inline]
+    fn current_window(&self) -> u64 {
+        self.start_time.get_or_init(T::now).elapsed_time() / T::ONE_SEC
+    }
+
+    /// Record a closed window, and the windows with no samples since, at most
+    /// once per second
+    #[cold]
+    fn record_window(&self, count: u64, empty_windows: u64) {
+        let mut histogram = lock_histogram!(self.histogram);
+        histogram.record(count);
+        if empty_windows > 0 {
+            histogram.record_n(0, empty_windows);
+        }
+    }
+
+    /// Get the histogram of closed windows and the count of the current one
+    pub(crate) fn window(&self) -> (HdrHistogram, u64) {
+        (
+            self.histogram(),
+            unpack(self.window.load(Ordering::Relaxed)).1,
+        )
+    }
+}
+
+impl<T: Instant> SnapshotHistogram for AtomicTxPerSec<T> {
+    type Snapshot = HdrHistogram;
+
+    fn snapshot_histogram(&self) -> HdrHistogram {
+        self.histogram()
     }
 }
 
 impl<T: Instant> RecordThroughput for AtomicTxPerSec<T> {
     #[inline]
     fn on_result(&self) {
-        self.inner.lock().on_result()
+        let this_window = self.current_window();
+        let mut state = self.window.load(Ordering::Relaxed);
+        loop {
+            let (last_window, count) = unpack(state);
+            if this_window <= last_window {
+                // The window may have advanced since it was loaded, counting
+                // the transaction in the next window: close enough
+                self.window.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+
+            match self.window.compare_exchange_weak(
+                state,
+                pack(this_window, 1),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    self.record_window(count, this_window - last_window - 1);
+                    return;
+                }
+                Err(actual) => state = actual,
+            }
+        }
     }
 }
 
 impl<T: Instant> Default for AtomicTxPerSec<T> {
     fn default() -> Self {
         AtomicTxPerSec {
-            inner: Mutex::new(TxPerSec::default()),
+            start_time: OnceLock::new(),
+            window: AtomicU64::new(0),
+            histogram: Mutex::new(TxPerSec::<T>::default().hdr_histogram),
         }
     }
 }
 
 impl<T: Instant> Clear for AtomicTxPerSec<T> {
     fn clear(&self) {
-        self.inner.lock().clear();
+        let mut histogram = self.histogram.lock();
+        histogram.clear();
+        // Restart counting in the current window
+        let this_window = match self.start_time.get() {
+            Some(start_time) => start_time.elapsed_time() / T::ONE_SEC,
+            None => 0,
+        };
+        self.window.store(pack(this_window, 0), Ordering::Relaxed);
+    }
+}
+
+impl<T: Instant> Clearable for AtomicTxPerSec<T> {
+    fn is_cleared(&self) -> bool {
+        let (histogram, count) = self.window();
+        histogram.is_empty() && count == 0
+    }
+}
+
+impl<T: Instant> Merge for AtomicTxPerSec<T> {
+    fn merge_from(&self, other: &Self) {
+        // Release other before locking self, in case other is self
+        let (histogram, count) = other.window();
+        self.histogram.lock().add(&histogram);
+        // Saturates the count, which would otherwise carry into the window
+        let _ = self
+            .window
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |state| {
+                let (window, current) = unpack(state);
+                Some(pack(window, (current + count).min(COUNT_MASK)))
+            });
     }
 }
 
@@ -47,15 +157,97 @@ impl<T: Instant> Serialize for AtomicTxPerSec<T> {
     where
         S: Serializer,
     {
-        let inner = self.inner.lock();
-        Serialize::serialize(&*inner, serializer)
+        let mut histogram = self.histogram.lock();
+        let result = Serialize::serialize(&*histogram, serializer);
+        // Under the same lock, so that closed windows are serialized exactly
+        // once: the current window is recorded when it closes
+        if clear::is_clearing_serialization() {
+            histogram.clear();
+        }
+        result
     }
 }
 
 use std::{fmt, fmt::Debug};
 impl<T: Instant> Debug for AtomicTxPerSec<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let inner = self.inner.lock();
-        write!(f, "{:?}", &*inner)
+        let histogram = self.histogram.lock();
+        write!(f, "{:?}", &*histogram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::{SimInstant, Simulation};
+    use std::time::Duration;
+
+    #[test]
+    fn closes_windows_lock_free() {
+        let simulation = Simulation::start(0);
+        let tps: AtomicTxPerSec<SimInstant> = AtomicTxPerSec::default();
+        for _ in 0..3 {
+            tps.on_result();
+        }
+        simulation.advance(Duration::from_millis(2_500));
+        tps.on_result();
+        assert_eq!(tps.window().1, 1);
+
+        let histogram = tps.histogram();
+        assert_eq!(histogram.len(), 2);
+        assert_eq!(histogram.max(), 3);
+        assert_eq!(histogram.min(), 0);
+
+        tps.clear();
+        assert!(tps.is_cleared());
+        tps.on_result();
+        assert_eq!(tps.window().1, 1);
+        assert!(tps.histogram().is_empty());
+    }
+
+    #[test]
+    fn saturates_merged_counts() {
+        let tps: AtomicTxPerSec<SimInstant> = AtomicTxPerSec::default();
+        let other: AtomicTxPerSec<SimInstant> = AtomicTxPerSec::default();
+        tps.window.store(pack(3, COUNT_MASK - 1), Ordering::Relaxed);
+        other.window.store(pack(0, 5), Ordering::Relaxed);
+
+        tps.merge_from(&other);
+        assert_eq!(unpack(tps.window.load(Ordering::Relaxed)), (3, COUNT_MASK));
+    }
+
+    #[test]
+    fn counts_concurrent_transactions() {
+        // Without a running simulation, the clock stays in the first window
+        let tps: AtomicTxPerSec<SimInstant> = AtomicTxPerSec::default();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| (0..1_000).for_each(|_| tps.on_result()));
+            }
+        });
+        let (histogram, count) = tps.window();
+        assert!(histogram.is_empty());
+        assert_eq!(count, 4_000);
+    }
+
+    #[test]
+    fn drains_closed_windows_when_serialized_and_cleared() {
+        let simulation = Simulation::start(0);
+        let tps: AtomicTxPerSec<SimInstant> = AtomicTxPerSec::default();
+        tps.on_result();
+        tps.on_result();
+        simulation.advance(Duration::from_millis(1_500));
+        tps.on_result();
+
+        let mut json = Vec::new();
+        crate::clear::serialize_and_clear(&tps, &mut serde_json::Serializer::new(&mut json))
+            .unwrap();
+        let taken: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(taken["samples"], 1);
+        assert_eq!(taken["max"], 2);
+
+        assert!(tps.histogram().is_empty());
+        // The current window is still open
+        assert_eq!(tps.window().1, 1);
     }
 }
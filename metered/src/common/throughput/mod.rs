@@ -1,13 +1,15 @@
 //! A module providing the `Throughput` metric.
 
 use crate::{
-    clear::Clear,
-    metric::Metric,
+    clear::{Clear, Clearable},
+    merge::Merge,
+    metadata::{Describe, MetricMetadata, MetricType, Unit},
+    metric::{HistogramSnapshot, Metric, SnapshotHistogram},
     time_source::{Instant, StdInstant},
 };
 use aspect::{Advice, Enter, OnResult};
 use serde::{Serialize, Serializer};
-use std::ops::Deref;
+use std::{cell::RefCell, ops::Deref};
 
 mod atomic_tps;
 mod tx_per_sec;
@@ -32,12 +34,49 @@ pub struct Throughput<T: Instant = StdInstant, P: RecordThroughput = AtomicTxPer
     std::marker::PhantomData<T>,
 );
 
+impl<T: Instant, P: RecordThroughput> Throughput<T, P> {
+    /// Get a copy of the histogram of transactions per second, whatever the
+    /// backend
+    ///
+    /// ```rust
+    /// use metered::{measure, Throughput};
+    ///
+    /// let throughput: Throughput = Throughput::default();
+    /// measure!(&throughput, {});
+    ///
+    /// // No 1-second window has closed yet
+    /// assert!(throughput.snapshot_histogram().is_empty());
+    /// ```
+    pub fn snapshot_histogram(&self) -> P::Snapshot
+    where
+        P: SnapshotHistogram,
+    {
+        self.0.snapshot_histogram()
+    }
+}
+
 /// Trait to record the throughput on a [`Throughput`] instance.
 pub trait RecordThroughput: Default {
     /// Called after the execution that the throughput metric is measuring.
     fn on_result(&self);
 }
 
+/// A plain copy of the statistics of a [`Throughput`]: the number of closed
+/// 1-second windows and the distribution of transactions per window.
+pub type ThroughputSnapshot = HistogramSnapshot;
+
+impl<T: Instant> From<&Throughput<T, AtomicTxPerSec<T>>> for ThroughputSnapshot {
+    fn from(throughput: &Throughput<T, AtomicTxPerSec<T>>) -> Self {
+        HistogramSnapshot::from(&throughput.0.histogram())
+    }
+}
+
+impl<T: Instant> From<&Throughput<T, RefCell<TxPerSec<T>>>> for ThroughputSnapshot {
+    fn from(throughput: &Throughput<T, RefCell<TxPerSec<T>>>) -> Self {
+        HistogramSnapshot::from(&throughput.0.borrow().hdr_histogram)
+    }
+}
+
 impl<P: RecordThroughput, T: Instant> Default for Throughput<T, P> {
     fn default() -> Self {
         Throughput(P::default(), std::marker::PhantomData)
@@ -49,6 +88,7 @@ impl<P: RecordThroughput + Serialize + Clear, T: Instant, R> Metric<R> for Throu
 impl<P: RecordThroughput, T: Instant> Enter for Throughput<T, P> {
     type E = ();
 
+    #[inline]
     fn enter(&self) {}
 }
 
@@ -58,7 +98,20 @@ impl<P: RecordThroughput + Clear, T: Instant> Clear for Throughput<T, P> {
     }
 }
 
+impl<P: RecordThroughput + Clearable, T: Instant> Clearable for Throughput<T, P> {
+    fn is_cleared(&self) -> bool {
+        self.0.is_cleared()
+    }
+}
+
+impl<P: RecordThroughput + Merge, T: Instant> Merge for Throughput<T, P> {
+    fn merge_from(&self, other: &Self) {
+        self.0.merge_from(&other.0);
+    }
+}
+
 impl<P: RecordThroughput + Serialize, T: Instant, R> OnResult<R> for Throughput<T, P> {
+    #[inline]
     fn leave_scope(&self, _enter: ()) -> Advice {
         self.0.on_result();
         Advice::Return
@@ -88,3 +141,9 @@ impl<P: RecordThroughput, T: Instant> Deref for Throughput<T, P> {
         &self.0
     }
 }
+
+impl<T: Instant, P: RecordThroughput> Describe for Throughput<T, P> {
+    fn metadata() -> MetricMetadata {
+        MetricMetadata::new(MetricType::Summary, Unit::RequestsPerSecond)
+    }
+}
@@ -1,7 +1,9 @@
 use super::RecordThroughput;
 use crate::{
-    clear::Clear,
+    clear::{Clear, Clearable},
     hdr_histogram::HdrHistogram,
+    merge::Merge,
+    metric::SnapshotHistogram,
     time_source::{Instant, StdInstant},
 };
 use serde::{Serialize, Serializer};
@@ -44,36 +46,90 @@ impl<T: Instant> Clear for std::cell::RefCell<TxPerSec<T>> {
     }
 }
 
+impl<T: Instant> Clearable for std::cell::RefCell<TxPerSec<T>> {
+    fn is_cleared(&self) -> bool {
+        self.borrow().is_cleared()
+    }
+}
+
+impl<T: Instant> Merge for std::cell::RefCell<TxPerSec<T>> {
+    fn merge_from(&self, other: &Self) {
+        let (histogram, count) = other.borrow().window();
+        self.borrow_mut().merge(&histogram, count);
+    }
+}
+
+impl<T: Instant> SnapshotHistogram for std::cell::RefCell<TxPerSec<T>> {
+    type Snapshot = HdrHistogram;
+
+    fn snapshot_histogram(&self) -> HdrHistogram {
+        self.borrow().hdr_histogram.clone()
+    }
+}
+
+impl<T: Instant> Clearable for TxPerSec<T> {
+    fn is_cleared(&self) -> bool {
+        self.hdr_histogram.is_empty() && self.count == 0
+    }
+}
+
 impl<T: Instant> TxPerSec<T> {
     /// Record previous count if the 1-sec window has closed and advance time window
+    #[inline]
     fn update(&mut self) {
         if let Some(ref start_time) = self.start_time {
             let elapsed = start_time.elapsed_time();
             let this_window = elapsed / T::ONE_SEC;
             if this_window > self.last_window {
-                // Record this window
-                self.hdr_histogram.record(self.count);
-                self.count = 0;
-
-                // Record windows with no samples
-                let empty_windows = this_window - self.last_window - 1;
-                if empty_windows > 0 {
-                    self.hdr_histogram.record_n(0, empty_windows)
-                }
-
-                // Advance window
-                self.last_window = this_window;
+                self.close_window(this_window);
             }
         } else {
-            // Set first window start time
-            self.start_time = Some(T::now());
+            self.start();
         };
     }
+
+    /// Set first window start time
+    #[cold]
+    fn start(&mut self) {
+        self.start_time = Some(T::now());
+    }
+
+    /// Record the closed window, and the windows with no samples since, at
+    /// most once per second
+    #[cold]
+    fn close_window(&mut self, this_window: u64) {
+        // Record this window
+        self.hdr_histogram.record(self.count);
+        self.count = 0;
+
+        // Record windows with no samples
+        let empty_windows = this_window - self.last_window - 1;
+        if empty_windows > 0 {
+            self.hdr_histogram.record_n(0, empty_windows)
+        }
+
+        // Advance window
+        self.last_window = this_window;
+    }
+
+    #[inline]
     pub(crate) fn on_result(&mut self) {
         self.update();
         self.count += 1;
     }
 
+    /// Get the histogram of closed windows and the count of the current one
+    pub(crate) fn window(&self) -> (HdrHistogram, u64) {
+        (self.hdr_histogram.clone(), self.count)
+    }
+
+    /// Adds the closed windows of another throughput to the histogram, and its
+    /// current count to the current window
+    pub(crate) fn merge(&mut self, histogram: &HdrHistogram, count: u64) {
+        self.hdr_histogram.add(histogram);
+        self.count += count;
+    }
+
     pub(crate) fn clear(&mut self) {
         self.hdr_histogram.clear();
         self.start_time = None;
@@ -0,0 +1,142 @@
+//! A module providing the `ValueHistogram` metric.
+
+#[cfg(feature = "histograms")]
+use crate::hdr_histogram::AtomicHdrHistogram;
+use crate::{
+    clear::{Clear, Clearable},
+    merge::Merge,
+    metadata::{Describe, MetricMetadata, MetricType, Unit},
+    metric::{Histogram, Metric, RecordValue},
+};
+use aspect::{Enter, OnResult};
+use serde::{Serialize, Serializer};
+use std::ops::Deref;
+
+/// A metric recording values derived from the results of an expression, such
+/// as the size of responses, into a histogram.
+///
+/// It records nothing on its own: values are given by the `record` option of
+/// the `measure` attribute, an expression evaluated after the measured method
+/// with `result` bound to a reference to its result:
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{common::ValueHistogram, metered};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Store {
+///     metrics: StoreMetrics,
+/// }
+///
+/// #[metered(registry = StoreMetrics)]
+/// impl Store {
+///     #[measure(type = ValueHistogram, record = result.len() as u64)]
+///     pub fn scan(&self, limit: usize) -> Vec<u8> {
+///         vec![0; limit]
+///     }
+/// }
+///
+/// let store = Store::default();
+/// store.scan(10);
+/// store.scan(30);
+///
+/// let histogram = store.metrics.scan.value_histogram.histogram();
+/// assert_eq!(histogram.len(), 2);
+/// assert_eq!(histogram.max(), 30);
+/// ```
+///
+/// The expression can also read the method's arguments that are still
+/// available after its body ran, e.g. references and `Copy` values.
+///
+/// By default, values up to `u32::MAX` are recorded, higher values saturating
+/// to it.
+#[derive(Clone)]
+pub struct ValueHistogram<
+    #[cfg(feature = "histograms")] H: Histogram = AtomicHdrHistogram,
+    #[cfg(not(feature = "histograms"))] H: Histogram,
+>(pub H);
+
+impl<H: Histogram> ValueHistogram<H> {
+    /// Build a ValueHistogram with a custom histogram bound
+    pub fn with_bound(bound: u64) -> Self {
+        ValueHistogram(H::with_bound(bound))
+    }
+}
+
+impl<H: Histogram> Default for ValueHistogram<H> {
+    fn default() -> Self {
+        ValueHistogram::with_bound(u32::MAX.into())
+    }
+}
+
+impl<H: Histogram> RecordValue for ValueHistogram<H> {
+    #[inline]
+    fn record_value(&self, value: u64) {
+        self.0.record(value);
+    }
+}
+
+impl<H: Histogram, R> Metric<R> for ValueHistogram<H> {}
+
+impl<H: Histogram> Enter for ValueHistogram<H> {
+    type E = ();
+
+    #[inline]
+    fn enter(&self) {}
+}
+
+impl<H: Histogram, R> OnResult<R> for ValueHistogram<H> {}
+
+impl<H: Histogram> Clear for ValueHistogram<H> {
+    fn clear(&self) {
+        self.0.clear();
+    }
+}
+
+impl<H: Histogram + Clearable> Clearable for ValueHistogram<H> {
+    fn is_cleared(&self) -> bool {
+        self.0.is_cleared()
+    }
+}
+
+impl<H: Histogram + Merge> Merge for ValueHistogram<H> {
+    fn merge_from(&self, other: &Self) {
+        self.0.merge_from(&other.0);
+    }
+}
+
+impl<H: Histogram + Serialize> Serialize for ValueHistogram<H> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Serialize::serialize(&self.0, serializer)
+    }
+}
+
+use std::{fmt, fmt::Debug};
+impl<H: Histogram + Debug> Debug for ValueHistogram<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", &self.0)
+    }
+}
+
+impl<H: Histogram> Deref for ValueHistogram<H> {
+    type Target = H;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<H: Histogram> Describe for ValueHistogram<H> {
+    fn metadata() -> MetricMetadata {
+        MetricMetadata::new(MetricType::Summary, Unit::None)
+    }
+}
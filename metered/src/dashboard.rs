@@ -0,0 +1,260 @@
+//! A module providing a terminal dashboard refreshing a view of registries, for
+//! load-testing sessions where setting up a monitoring stack is overkill.
+//!
+//! This module is only available when the `dashboard` feature is enabled.
+
+use crate::{
+    flatten::Value,
+    metadata::{DescribeMetrics, MetricType},
+    pretty::{self, Row},
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, Write as _},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+const HEADER: [&str; 9] = [
+    "metric", "type", "unit", "count", "rate/s", "mean", "p50", "p90", "p99",
+];
+
+/// Clears the screen and moves the cursor to its top-left corner
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+type Render = Box<dyn Fn() -> Result<Vec<Row>, crate::flatten::Error> + Send>;
+
+/// A terminal dashboard printing watched registries as tables, refreshed every
+/// second by default.
+///
+/// Besides the values printed by registries' `Display` implementation, each
+/// counter and histogram shows its current rate: how much its value or sample
+/// count increased per second since the previous refresh, e.g. the current
+/// TPS of a `HitCount`.
+///
+/// ```rust,no_run
+/// use metered::{dashboard::Dashboard, metered, HitCount, ResponseTime};
+/// use std::sync::Arc;
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics)]
+/// impl Service {
+///     #[measure([HitCount, ResponseTime])]
+///     pub fn call(&self) {}
+/// }
+///
+/// let service = Arc::new(Service::default());
+/// let dashboard = Dashboard::new()
+///     .watch("service", Arc::clone(&service), |service| &service.metrics)
+///     .spawn();
+///
+/// for _ in 0..1_000_000 {
+///     service.call();
+/// }
+/// dashboard.stop();
+/// ```
+pub struct Dashboard {
+    registries: Vec<(String, Render)>,
+    interval: Duration,
+    previous: HashMap<(usize, String), f64>,
+    last_render: Option<Instant>,
+}
+
+impl Default for Dashboard {
+    fn default() -> Self {
+        Dashboard::new()
+    }
+}
+
+impl Dashboard {
+    /// Creates a dashboard watching no registries, refreshed every second
+    pub fn new() -> Self {
+        Dashboard {
+            registries: Vec::new(),
+            interval: Duration::from_secs(1),
+            previous: HashMap::new(),
+            last_render: None,
+        }
+    }
+
+    /// Watches a registry owned by a shared value, usually the measured
+    /// service, under a title
+    pub fn watch<T, R>(
+        mut self,
+        title: impl Into<String>,
+        owner: Arc<T>,
+        registry: fn(&T) -> &R,
+    ) -> Self
+    where
+        T: Send + Sync + 'static,
+        R: Serialize + DescribeMetrics + 'static,
+    {
+        let render = move || pretty::rows(registry(&owner));
+        self.registries.push((title.into(), Box::new(render)));
+        self
+    }
+
+    /// Refreshes the dashboard at another interval
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Renders the watched registries, computing rates since the previous
+    /// render
+    pub fn render(&mut self) -> String {
+        let now = Instant::now();
+        let elapsed = self
+            .last_render
+            .replace(now)
+            .map(|last_render| now.duration_since(last_render).as_secs_f64());
+
+        let mut out = String::new();
+        for (i, (title, render)) in self.registries.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            let _ = writeln!(out, "{}", title);
+            let rows = match render() {
+                Ok(rows) => rows,
+                Err(e) => {
+                    let _ = writeln!(out, "  could not render registry: {}", e);
+                    continue;
+                }
+            };
+
+            let previous = &mut self.previous;
+            let rows = rows.iter().map(|row| {
+                let count = row.count.as_f64();
+                let last = previous.insert((i, row.name.clone()), count);
+                let increases = row.metric_type == MetricType::Counter || row.stats.is_some();
+                let rate = match (last, elapsed) {
+                    (Some(last), Some(elapsed)) if increases && elapsed > 0.0 && count >= last => {
+                        Some(Value::Float((count - last) / elapsed))
+                    }
+                    _ => None,
+                };
+
+                let mut cells = row.leading_cells();
+                cells.push(pretty::format_value(row.count));
+                cells.push(rate.map_or_else(String::new, pretty::format_value));
+                cells.extend(row.stats.iter().flatten().map(|s| pretty::format_value(*s)));
+                cells
+            });
+            let _ = pretty::write_table(&mut out, &HEADER, rows);
+        }
+        out
+    }
+
+    /// Spawns a thread clearing the terminal and printing the dashboard to the
+    /// standard output at every refresh, until the returned handle is stopped
+    /// or dropped
+    pub fn spawn(mut self) -> DashboardHandle {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("metered-dashboard".to_string())
+            .spawn(move || loop {
+                print!("{}{}", CLEAR_SCREEN, self.render());
+                let _ = io::stdout().flush();
+
+                match stopped.recv_timeout(self.interval) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => break,
+                }
+            })
+            .expect("could not spawn the dashboard thread");
+
+        DashboardHandle {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+/// A handle on a running [`Dashboard`], stopping it when dropped.
+pub struct DashboardHandle {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DashboardHandle {
+    /// Stops the dashboard, waiting for its thread to exit
+    pub fn stop(self) {
+        drop(self)
+    }
+}
+
+impl Drop for DashboardHandle {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{
+        measure,
+        metadata::{MetricDescription, MetricMetadata, Unit},
+        HitCount,
+    };
+
+    #[derive(Default, Serialize)]
+    struct Registry {
+        hit_count: HitCount,
+    }
+
+    impl DescribeMetrics for Registry {
+        fn describe_metrics() -> Vec<MetricDescription> {
+            vec![MetricDescription {
+                path: vec!["hit_count"],
+                metadata: MetricMetadata::new(MetricType::Counter, Unit::None),
+            }]
+        }
+    }
+
+    #[test]
+    fn renders_rates_since_previous_render() {
+        let registry = Arc::new(Registry::default());
+        let mut dashboard = Dashboard::new().watch("test", Arc::clone(&registry), |r| r);
+
+        let frame = dashboard.render();
+        let mut lines = frame.lines();
+        assert_eq!(lines.next(), Some("test"));
+        assert!(lines.next().unwrap().starts_with("metric"));
+        assert_eq!(lines.next(), Some("hit_count  counter            0"));
+
+        thread::sleep(Duration::from_millis(10));
+        for _ in 0..10 {
+            measure!(&registry.hit_count, {});
+        }
+        let frame = dashboard.render();
+        let row: Vec<&str> = frame.lines().nth(2).unwrap().split_whitespace().collect();
+        assert_eq!(&row[..3], ["hit_count", "counter", "10"]);
+        let rate: f64 = row[3].parse().unwrap();
+        assert!(rate > 0.0 && rate <= 1000.0);
+    }
+
+    #[test]
+    fn stops_when_dropped() {
+        let registry = Arc::new(Registry::default());
+        let handle = Dashboard::new()
+            .watch("test", registry, |r| r)
+            .with_interval(Duration::from_secs(3600))
+            .spawn();
+        handle.stop();
+    }
+}
@@ -0,0 +1,485 @@
+//! A module providing thread-safe and unsynchronized implementations of
+//! DDSketch, a mergeable quantile sketch with relative-error guarantees.
+
+use crate::{
+    clear::{self, Clear, Clearable},
+    common::ResponseTime,
+    merge::Merge,
+    metric::{Histogram, HistogramSnapshot, SnapshotHistogram},
+    serialization::{self, MetricAlias},
+    sync::Mutex,
+    time_source::StdInstant,
+};
+use serde::{Serialize, Serializer};
+use std::cell::RefCell;
+
+/// A metric measuring the response time of an expression, backed by a
+/// [`DdSketch`].
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{dd_sketch::DdSketchResponseTime, measure};
+///
+/// let response_time: DdSketchResponseTime = DdSketchResponseTime::default();
+///
+/// measure!(&response_time, {
+///     std::thread::sleep(std::time::Duration::from_millis(10));
+/// });
+///
+/// assert!(response_time.histogram().quantile(0.99) >= 10);
+/// ```
+pub type DdSketchResponseTime<T = StdInstant> = ResponseTime<AtomicDdSketch, T>;
+
+/// The relative accuracy of sketches built with [`Histogram::with_bound`].
+pub const DEFAULT_RELATIVE_ACCURACY: f64 = 0.01;
+
+/// A thread-safe implementation of DDSketch
+pub struct AtomicDdSketch {
+    inner: Mutex<DdSketch>,
+}
+
+impl AtomicDdSketch {
+    /// Returns a cloned snapshot of the inner sketch.
+    pub fn histogram(&self) -> DdSketch {
+        self.inner.lock().clone()
+    }
+
+    /// Merges another sketch into this one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sketches do not have the same relative accuracy.
+    pub fn merge(&self, other: &DdSketch) {
+        self.inner.lock().merge(other);
+    }
+}
+
+impl SnapshotHistogram for AtomicDdSketch {
+    type Snapshot = DdSketch;
+
+    fn snapshot_histogram(&self) -> DdSketch {
+        self.histogram()
+    }
+}
+
+impl Histogram for AtomicDdSketch {
+    fn with_bound(max_value: u64) -> Self {
+        AtomicDdSketch {
+            inner: Mutex::new(DdSketch::with_bound(max_value)),
+        }
+    }
+
+    fn record(&self, value: u64) {
+        lock_histogram!(self.inner).record(value);
+    }
+}
+
+impl Clear for AtomicDdSketch {
+    fn clear(&self) {
+        self.inner.lock().clear();
+    }
+}
+
+impl Clearable for AtomicDdSketch {
+    fn is_cleared(&self) -> bool {
+        self.inner.lock().is_empty()
+    }
+}
+
+impl Merge for AtomicDdSketch {
+    fn merge_from(&self, other: &Self) {
+        // Clone first, in case other is self
+        self.merge(&other.histogram());
+    }
+}
+
+impl From<&AtomicDdSketch> for HistogramSnapshot {
+    fn from(histogram: &AtomicDdSketch) -> Self {
+        HistogramSnapshot::from(&*histogram.inner.lock())
+    }
+}
+
+impl Serialize for AtomicDdSketch {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        internal_cost!(serialization, {
+            let mut inner = self.inner.lock();
+            let result = Serialize::serialize(&*inner, serializer);
+            // Under the same lock, so that values are serialized exactly once
+            if clear::is_clearing_serialization() {
+                inner.clear();
+            }
+            result
+        })
+    }
+}
+
+use std::{fmt, fmt::Debug};
+impl Debug for AtomicDdSketch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock();
+        write!(f, "AtomicDdSketch {{ {:?} }}", &*inner)
+    }
+}
+
+/// A DDSketch quantile sketch
+///
+/// DDSketch maps values to logarithmically-sized buckets, so that any quantile
+/// it returns is within a configured relative error of the exact quantile.
+/// Two sketches with the same relative accuracy can be merged without loss,
+/// which makes them suitable for aggregating metrics across processes.
+///
+/// Buckets are allocated up front for values up to `max_value`, so recording a
+/// value never allocates.
+#[derive(Clone)]
+pub struct DdSketch {
+    relative_accuracy: f64,
+    gamma_ln: f64,
+    max_value: u64,
+    zero_count: u64,
+    bins: Vec<u64>,
+    count: u64,
+    sum: f64,
+    min: u64,
+    max: u64,
+}
+
+impl DdSketch {
+    /// Instantiates a new sketch with a `max_value` and a 1% relative accuracy.
+    pub fn with_bound(max_value: u64) -> Self {
+        Self::with_accuracy(max_value, DEFAULT_RELATIVE_ACCURACY)
+    }
+
+    /// Instantiates a new sketch with a `max_value` and a relative accuracy,
+    /// which must be in `]0, 1[`.
+    pub fn with_accuracy(max_value: u64, relative_accuracy: f64) -> Self {
+        assert!(
+            relative_accuracy > 0.0 && relative_accuracy < 1.0,
+            "relative accuracy must be in ]0, 1["
+        );
+        let gamma = (1.0 + relative_accuracy) / (1.0 - relative_accuracy);
+        let gamma_ln = gamma.ln();
+        let max_value = max_value.max(1);
+        let bins = vec![0; Self::index_of(gamma_ln, max_value) + 1];
+
+        DdSketch {
+            relative_accuracy,
+            gamma_ln,
+            max_value,
+            zero_count: 0,
+            bins,
+            count: 0,
+            sum: 0.0,
+            min: 0,
+            max: 0,
+        }
+    }
+
+    fn index_of(gamma_ln: f64, value: u64) -> usize {
+        ((value as f64).ln() / gamma_ln).ceil() as usize
+    }
+
+    fn value_of(&self, index: usize) -> u64 {
+        let gamma = self.gamma_ln.exp();
+        (2.0 * (index as f64 * self.gamma_ln).exp() / (gamma + 1.0)).round() as u64
+    }
+
+    /// Get the sketch's relative accuracy
+    pub fn relative_accuracy(&self) -> f64 {
+        self.relative_accuracy
+    }
+
+    /// Get the sketch bound
+    pub fn bound(&self) -> u64 {
+        self.max_value
+    }
+
+    /// Records a value to the sketch
+    ///
+    /// This is a saturating record: if the value is higher than `max_value`,
+    /// max_value will be recorded instead.
+    pub fn record(&mut self, value: u64) {
+        let value = value.min(self.max_value);
+        if value == 0 {
+            self.zero_count += 1;
+        } else {
+            let index = Self::index_of(self.gamma_ln, value);
+            self.bins[index] += 1;
+        }
+
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        if value > self.max {
+            self.max = value;
+        }
+        self.count += 1;
+        self.sum += value as f64;
+    }
+
+    /// Merges another sketch into this one.
+    ///
+    /// If `other` has a higher bound, this sketch's buckets grow to fit its
+    /// values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sketches do not have the same relative accuracy.
+    pub fn merge(&mut self, other: &DdSketch) {
+        assert!(
+            (self.gamma_ln - other.gamma_ln).abs() < f64::EPSILON,
+            "cannot merge sketches with different relative accuracies"
+        );
+        if other.count == 0 {
+            return;
+        }
+
+        if other.bins.len() > self.bins.len() {
+            self.bins.resize(other.bins.len(), 0);
+            self.max_value = other.max_value;
+        }
+        for (bin, count) in self.bins.iter_mut().zip(other.bins.iter()) {
+            *bin += count;
+        }
+        self.zero_count += other.zero_count;
+
+        if self.count == 0 || other.min < self.min {
+            self.min = other.min;
+        }
+        self.max = self.max.max(other.max);
+        self.count += other.count;
+        self.sum += other.sum;
+    }
+
+    /// Clears the values of the sketch
+    pub fn clear(&mut self) {
+        self.zero_count = 0;
+        self.bins.iter_mut().for_each(|bin| *bin = 0);
+        self.count = 0;
+        self.sum = 0.0;
+        self.min = 0;
+        self.max = 0;
+    }
+
+    /// Get the number of recorded values in the sketch.
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    /// Check if the sketch's recorded values are empty
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Get the lowest recorded value in the sketch.
+    /// If the sketch has no recorded values, the value returned will be 0.
+    pub fn min(&self) -> u64 {
+        self.min
+    }
+
+    /// Get the highest recorded value in the sketch.
+    /// If the sketch has no recorded values, the value returned will be 0.
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Get the exact mean value of all recorded values in the sketch.
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum / self.count as f64
+        }
+    }
+
+    /// Get the value at a quantile in `[0, 1]`, within the sketch's relative
+    /// accuracy.
+    /// If the sketch has no recorded values, the value returned will be 0.
+    pub fn quantile(&self, quantile: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = (quantile.clamp(0.0, 1.0) * (self.count - 1) as f64) as u64;
+
+        let mut seen = self.zero_count;
+        if seen > rank {
+            return 0;
+        }
+        for (index, count) in self.bins.iter().enumerate() {
+            seen += count;
+            if seen > rank {
+                return self.value_of(index).clamp(self.min, self.max);
+            }
+        }
+        self.max
+    }
+
+    /// Iterates over the non-empty buckets of the sketch, as pairs of bucket
+    /// index and count. Values of 0 are not included, see
+    /// [`DdSketch::zero_count`].
+    ///
+    /// Bucket `i` holds values in `]γ^(i-1), γ^i]` where
+    /// `γ = (1 + α) / (1 - α)` for a relative accuracy `α`.
+    pub fn bins(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.bins
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(index, count)| (index, *count))
+    }
+
+    /// Get the number of recorded values equal to 0.
+    pub fn zero_count(&self) -> u64 {
+        self.zero_count
+    }
+}
+
+impl From<&DdSketch> for HistogramSnapshot {
+    fn from(histogram: &DdSketch) -> Self {
+        HistogramSnapshot::new(
+            histogram.len(),
+            histogram.min(),
+            histogram.max(),
+            histogram.mean(),
+            |q| histogram.quantile(q),
+        )
+    }
+}
+
+impl Serialize for DdSketch {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        /// A 'qualified' metric name, see `HdrHistogram`'s serialization.
+        macro_rules! qual {
+            ($e:expr) => {
+                &MetricAlias("<|", $e)
+            };
+        }
+
+        use serde::ser::SerializeMap;
+
+        let mut tup = serializer.serialize_map(Some(4 + serialization::quantile_count()))?;
+        tup.serialize_entry("samples", qual!(self.len()))?;
+        tup.serialize_entry("min", qual!(self.min()))?;
+        tup.serialize_entry("max", qual!(self.max()))?;
+        tup.serialize_entry("mean", qual!(self.mean()))?;
+        serialization::serialize_quantiles(&mut tup, |q| self.quantile(q))?;
+        tup.end()
+    }
+}
+
+impl Debug for DdSketch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "DdSketch {{
+            samples: {}, min: {}, max: {}, mean: {},
+            90%ile = {}, 95%ile = {}, 99%ile = {}, 99.9%ile = {}, 99.99%ile = {} }}",
+            self.len(),
+            self.min(),
+            self.max(),
+            self.mean(),
+            self.quantile(0.9),
+            self.quantile(0.95),
+            self.quantile(0.99),
+            self.quantile(0.999),
+            self.quantile(0.9999)
+        )
+    }
+}
+
+impl Histogram for RefCell<DdSketch> {
+    fn with_bound(max_value: u64) -> Self {
+        RefCell::new(DdSketch::with_bound(max_value))
+    }
+
+    fn record(&self, value: u64) {
+        self.borrow_mut().record(value);
+    }
+}
+
+impl Clear for RefCell<DdSketch> {
+    fn clear(&self) {
+        self.borrow_mut().clear();
+    }
+}
+
+impl Clearable for RefCell<DdSketch> {
+    fn is_cleared(&self) -> bool {
+        self.borrow().is_empty()
+    }
+}
+
+impl SnapshotHistogram for RefCell<DdSketch> {
+    type Snapshot = DdSketch;
+
+    fn snapshot_histogram(&self) -> DdSketch {
+        self.borrow().clone()
+    }
+}
+
+impl From<&RefCell<DdSketch>> for HistogramSnapshot {
+    fn from(histogram: &RefCell<DdSketch>) -> Self {
+        HistogramSnapshot::from(&*histogram.borrow())
+    }
+}
+
+impl Merge for RefCell<DdSketch> {
+    fn merge_from(&self, other: &Self) {
+        let other = other.borrow().clone();
+        self.borrow_mut().merge(&other);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_accuracy() {
+        let mut sketch = DdSketch::with_bound(1_000_000);
+        for v in 1..=100_000 {
+            sketch.record(v);
+        }
+
+        for &(q, expected) in &[(0.5, 50_000.0), (0.9, 90_000.0), (0.99, 99_000.0)] {
+            let actual = sketch.quantile(q) as f64;
+            assert!(
+                (actual - expected).abs() / expected <= DEFAULT_RELATIVE_ACCURACY,
+                "q{}: expected {} got {}",
+                q,
+                expected,
+                actual
+            );
+        }
+        assert_eq!(sketch.min(), 1);
+        assert_eq!(sketch.max(), 100_000);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a = DdSketch::with_bound(1_000);
+        let mut b = DdSketch::with_bound(100_000);
+        let mut all = DdSketch::with_bound(100_000);
+        for v in 0..1_000 {
+            a.record(v);
+            all.record(v);
+        }
+        for v in 1_000..50_000 {
+            b.record(v);
+            all.record(v);
+        }
+
+        a.merge(&b);
+        assert_eq!(a.len(), all.len());
+        assert_eq!(a.min(), 0);
+        assert_eq!(a.max(), 49_999);
+        for &q in &[0.0, 0.1, 0.5, 0.99, 1.0] {
+            assert_eq!(a.quantile(q), all.quantile(q));
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! A module gathering the registries of a binary in a distributed slice, so
+//! that a central exporter can discover them without manual aggregation code.
+//!
+//! Registries generated with `distributed_slice = true` register a
+//! [`RegistryDescriptor`] in [`REGISTRIES`] at link time:
+//!
+#![cfg_attr(feature = "disabled", doc = "```ignore")]
+#![cfg_attr(not(feature = "disabled"), doc = "```rust")]
+//! use metered::{discovery::REGISTRIES, metered, HitCount};
+//!
+//! #[metered(registry = StorageMetrics, distributed_slice = true)]
+//! mod storage {
+//!     #[measure(metered::HitCount)]
+//!     pub fn compact() {}
+//! }
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Service {
+//!     metrics: ServiceMetrics,
+//! }
+//!
+//! #[metered(registry = ServiceMetrics, distributed_slice = true)]
+//! impl Service {
+//!     #[measure(HitCount)]
+//!     pub fn call(&self) {}
+//! }
+//!
+//! storage::compact();
+//!
+//! let storage = REGISTRIES.iter().find(|r| r.name == "StorageMetrics").unwrap();
+//! let samples = storage.samples().unwrap().unwrap();
+//! assert_eq!(samples[0].name, "functions_compact_hit_count");
+//! assert_eq!(samples[0].value, 1.0);
+//!
+//! // Registries held by instances are only described
+//! let service = REGISTRIES.iter().find(|r| r.name == "ServiceMetrics").unwrap();
+//! assert_eq!(service.describe_metrics()[0].path, ["call", "hit_count"]);
+//! assert!(service.samples().is_none());
+//! ```
+//!
+//! Registries of `impl` blocks usually live in the fields of their instances,
+//! which the slice cannot reach, so their descriptors only describe their
+//! metrics. Module registries held by their `METRICS` static can also be
+//! sampled.
+//!
+//! This module is only available when the `discovery` feature is enabled.
+
+use crate::{
+    flatten::{self, Sample},
+    metadata::MetricDescription,
+};
+use std::fmt;
+
+type Samples = fn() -> Result<Vec<Sample>, flatten::Error>;
+
+/// The registries of the binary generated with `distributed_slice = true`.
+#[linkme::distributed_slice]
+pub static REGISTRIES: [RegistryDescriptor];
+
+/// A registry registered in [`REGISTRIES`].
+pub struct RegistryDescriptor {
+    /// The serialized name of the registry
+    pub name: &'static str,
+    #[doc(hidden)]
+    pub describe_metrics: fn() -> Vec<MetricDescription>,
+    #[doc(hidden)]
+    pub samples: Option<Samples>,
+}
+
+impl RegistryDescriptor {
+    /// Describes the metrics of the registry, see
+    /// [`DescribeMetrics`](crate::metadata::DescribeMetrics)
+    pub fn describe_metrics(&self) -> Vec<MetricDescription> {
+        (self.describe_metrics)()
+    }
+
+    /// Flattens the registry into samples, see [`flatten::to_samples`], or
+    /// returns `None` when the registry is not held in a static
+    pub fn samples(&self) -> Option<Result<Vec<Sample>, flatten::Error>> {
+        self.samples.map(|samples| samples())
+    }
+}
+
+impl fmt::Debug for RegistryDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegistryDescriptor")
+            .field("name", &self.name)
+            .field("sampled", &self.samples.is_some())
+            .finish()
+    }
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use linkme;
+}
@@ -0,0 +1,490 @@
+//! A module sending registries to a DogStatsD agent over UDP, reporting
+//! histogram-backed metrics as Datadog distributions.
+//!
+//! Percentiles computed by each process cannot be aggregated across hosts. To
+//! let Datadog compute percentiles server-side, histogram-backed metrics can
+//! be backed by a [`Distribution`], which keeps a sample of the raw values
+//! recorded since the last send: [`DogStatsd`] sends these values, rather than
+//! the percentiles computed locally.
+//!
+//! ```rust,no_run
+//! use metered::{
+//!     dogstatsd::{Distribution, DogStatsd},
+//!     metered, HitCount, ResponseTime,
+//! };
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Service {
+//!     metrics: ServiceMetrics,
+//! }
+//!
+//! #[metered(registry = ServiceMetrics)]
+//! impl Service {
+//!     #[measure([HitCount, ResponseTime<Distribution>])]
+//!     pub fn call(&self) {}
+//! }
+//!
+//! let service = Service::default();
+//! let dogstatsd = DogStatsd::new("127.0.0.1:8125")
+//!     .unwrap()
+//!     .with_prefix("service")
+//!     .with_tag("env", "prod")
+//!     .with_sample_rate(0.5);
+//!
+//! service.call();
+//! dogstatsd.send(&service.metrics).unwrap();
+//! ```
+//!
+//! Other values are sent as gauges, counters reporting their running total.
+//!
+//! This module is only available when the `dogstatsd` feature is enabled.
+
+use crate::{
+    clear::Clear,
+    flatten::{self, Sample},
+    hdr_histogram::AtomicHdrHistogram,
+    metric::Histogram,
+    namespace::Namespace,
+    reservoir::Reservoir,
+    serialization::MetricAlias,
+    sync::Mutex,
+};
+use serde::{Serialize, Serializer};
+use std::{
+    cell::RefCell,
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+    ops::Deref,
+};
+
+/// The number of values a [`Distribution`] keeps between sends by default
+pub const DEFAULT_PENDING_VALUES: usize = 4096;
+
+/// A label marking the samples of distributions, whose value is their index in
+/// the distributions collected on the thread
+const DISTRIBUTION_LABEL: &str = "__dogstatsd_distribution";
+const DISTRIBUTION_ALIAS: &str = "|__dogstatsd_distribution=1";
+
+/// The values taken from a distribution, and the number of values recorded
+type Pending = (Vec<u64>, u64);
+
+thread_local! {
+    /// The distributions serialized while sending a registry
+    static COLLECTED: RefCell<Option<Vec<Pending>>> = const { RefCell::new(None) };
+}
+
+/// A histogram keeping a uniform sample of the raw values recorded since the
+/// last send to DogStatsD, besides recording them to another histogram.
+///
+/// It is meant as the histogram of metrics such as `ResponseTime` or
+/// `Throughput`: these metrics are then sent to DogStatsD as distributions,
+/// while serializing them otherwise serializes the inner histogram. When more
+/// values are recorded between two sends than the sample can hold, the sample
+/// rate sent with the values accounts for the values left out.
+pub struct Distribution<H: Histogram = AtomicHdrHistogram> {
+    inner: H,
+    pending: Mutex<Reservoir>,
+}
+
+impl<H: Histogram> Distribution<H> {
+    /// Build a distribution keeping up to `size` values between sends
+    pub fn with_size(max_value: u64, size: usize) -> Self {
+        Distribution {
+            inner: H::with_bound(max_value),
+            pending: Mutex::new(Reservoir::with_size(max_value, size)),
+        }
+    }
+
+    /// Takes the values recorded since the last call, and the number of
+    /// values recorded
+    fn take_pending(&self) -> Pending {
+        let mut pending = self.pending.lock();
+        let empty = Reservoir::with_size(pending.bound(), pending.size());
+        let taken = std::mem::replace(&mut *pending, empty);
+        (taken.values().to_vec(), taken.len())
+    }
+}
+
+impl<H: Histogram> Histogram for Distribution<H> {
+    fn with_bound(max_value: u64) -> Self {
+        Self::with_size(max_value, DEFAULT_PENDING_VALUES)
+    }
+
+    fn with_precision(max_value: u64, sig_figs: u8) -> Self {
+        Distribution {
+            inner: H::with_precision(max_value, sig_figs),
+            pending: Mutex::new(Reservoir::with_size(max_value, DEFAULT_PENDING_VALUES)),
+        }
+    }
+
+    fn record(&self, value: u64) {
+        self.inner.record(value);
+        self.pending.lock().record(value);
+    }
+}
+
+impl<H: Histogram> Clear for Distribution<H> {
+    fn clear(&self) {
+        self.inner.clear();
+        self.pending.lock().clear();
+    }
+}
+
+impl<H: Histogram> Serialize for Distribution<H> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let index = COLLECTED.with(|collected| {
+            let mut collected = collected.borrow_mut();
+            let collected = collected.as_mut()?;
+            collected.push(self.take_pending());
+            Some(collected.len() - 1)
+        });
+        match index {
+            Some(index) => MetricAlias(DISTRIBUTION_ALIAS, index).serialize(serializer),
+            None => self.inner.serialize(serializer),
+        }
+    }
+}
+
+impl<H: Histogram> Deref for Distribution<H> {
+    type Target = H;
+
+    fn deref(&self) -> &H {
+        &self.inner
+    }
+}
+
+impl<H: Histogram + fmt::Debug> fmt::Debug for Distribution<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Distribution {{ {:?} }}", &self.inner)
+    }
+}
+
+/// A client sending registries to a DogStatsD agent, see the
+/// [module documentation](crate::dogstatsd).
+///
+/// Registries are flattened with [`flatten::to_samples`]: each sample is sent
+/// as a metric named after it, with an optional prefix followed by a dot, and
+/// its labels and the tags common to the client as tags.
+#[derive(Debug)]
+pub struct DogStatsd {
+    socket: UdpSocket,
+    prefix: Option<String>,
+    tags: Vec<String>,
+    sample_rate: f64,
+    max_packet_size: usize,
+}
+
+impl DogStatsd {
+    /// Creates a client sending to the address of a DogStatsD agent, usually
+    /// port 8125 of the host
+    pub fn new(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        Ok(DogStatsd {
+            socket,
+            prefix: None,
+            tags: Vec::new(),
+            sample_rate: 1.0,
+            max_packet_size: 1432,
+        })
+    }
+
+    /// Prepends a prefix, followed by a dot, to the name of every metric
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Adds a tag to every metric
+    pub fn with_tag(mut self, key: impl AsRef<str>, value: impl AsRef<str>) -> Self {
+        self.tags.push(tag(key.as_ref(), value.as_ref()));
+        self
+    }
+
+    /// Applies a namespace to every metric: its prefix replaces the prefix of
+    /// the client, and its labels are added as tags
+    pub fn with_namespace(mut self, namespace: Namespace) -> Self {
+        self.prefix = namespace.prefix().map(str::to_string);
+        self.tags.extend(
+            namespace
+                .labels()
+                .iter()
+                .map(|(key, value)| tag(key, value)),
+        );
+        self
+    }
+
+    /// Only sends a random fraction of the values of distributions, between 0
+    /// and 1, to reduce traffic: Datadog scales counts accordingly
+    pub fn with_sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sample_rate = sample_rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Packs lines into datagrams of up to a number of bytes, 1432 by default
+    /// to fit common MTUs, or 8192 for agents listening on Unix sockets
+    pub fn with_max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size = max_packet_size;
+        self
+    }
+
+    /// Sends the values of a registry, taking the values recorded by its
+    /// distributions since the last send
+    pub fn send<R: Serialize + ?Sized>(&self, registry: &R) -> Result<(), DogStatsdError> {
+        let lines = self.format(registry).map_err(DogStatsdError::Flatten)?;
+
+        let mut packet = String::new();
+        for line in lines {
+            if !packet.is_empty() && packet.len() + 1 + line.len() > self.max_packet_size {
+                self.socket
+                    .send(packet.as_bytes())
+                    .map_err(DogStatsdError::Io)?;
+                packet.clear();
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            packet.push_str(&line);
+        }
+        if !packet.is_empty() {
+            self.socket
+                .send(packet.as_bytes())
+                .map_err(DogStatsdError::Io)?;
+        }
+        Ok(())
+    }
+
+    /// Formats the values of a registry as DogStatsD lines, taking the values
+    /// recorded by its distributions like [`DogStatsd::send`]
+    pub fn format<R: Serialize + ?Sized>(
+        &self,
+        registry: &R,
+    ) -> Result<Vec<String>, flatten::Error> {
+        COLLECTED.with(|collected| *collected.borrow_mut() = Some(Vec::new()));
+        let samples = flatten::to_samples(registry);
+        let distributions = COLLECTED
+            .with(|collected| collected.borrow_mut().take())
+            .unwrap_or_default();
+        let samples = samples?;
+
+        let mut rng = RandomState::new().build_hasher().finish() | 1;
+        let mut lines = Vec::new();
+        for Sample {
+            name,
+            mut labels,
+            value,
+        } in samples
+        {
+            let distribution = labels
+                .iter()
+                .position(|(key, _)| key == DISTRIBUTION_LABEL)
+                .map(|i| labels.remove(i))
+                .and_then(|_| distributions.get(value as usize));
+
+            let name = match self.prefix {
+                Some(ref prefix) => format!("{}.{}", prefix, name),
+                None => name,
+            };
+            let mut tags = self.tags.clone();
+            tags.extend(labels.iter().map(|(key, value)| tag(key, value)));
+            let tags = if tags.is_empty() {
+                String::new()
+            } else {
+                format!("|#{}", tags.join(","))
+            };
+
+            let (values, recorded) = match distribution {
+                Some(distribution) => distribution,
+                None => {
+                    if value.is_finite() {
+                        lines.push(format!("{}:{}|g{}", name, value, tags));
+                    }
+                    continue;
+                }
+            };
+
+            let sent: Vec<u64> = values
+                .iter()
+                .copied()
+                .filter(|_| self.sample_rate >= 1.0 || next_unit(&mut rng) < self.sample_rate)
+                .collect();
+            if sent.is_empty() {
+                continue;
+            }
+            let rate = self.sample_rate * values.len() as f64 / *recorded as f64;
+            let rate = if rate < 1.0 {
+                format!("|@{}", rate)
+            } else {
+                String::new()
+            };
+
+            // Packs values in lines fitting in packets
+            let suffix = format!("|d{}{}", rate, tags);
+            let mut line = name.clone();
+            for value in sent {
+                let value = value.to_string();
+                if line.len() > name.len()
+                    && line.len() + 1 + value.len() + suffix.len() > self.max_packet_size
+                {
+                    line.push_str(&suffix);
+                    lines.push(std::mem::replace(&mut line, name.clone()));
+                }
+                line.push(':');
+                line.push_str(&value);
+            }
+            line.push_str(&suffix);
+            lines.push(line);
+        }
+        Ok(lines)
+    }
+}
+
+/// Formats a tag, replacing the characters DogStatsD reserves
+fn tag(key: &str, value: &str) -> String {
+    let sanitize = |s: &str| s.replace(['|', ',', '#', '\n'], "_");
+    format!("{}:{}", sanitize(key), sanitize(value))
+}
+
+/// A xorshift64* pseudo-random number between 0 and 1
+fn next_unit(rng: &mut u64) -> f64 {
+    *rng ^= *rng >> 12;
+    *rng ^= *rng << 25;
+    *rng ^= *rng >> 27;
+    (rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// An error raised while sending to a DogStatsD agent.
+#[derive(Debug)]
+pub enum DogStatsdError {
+    /// The registry could not be flattened into samples
+    Flatten(flatten::Error),
+    /// A datagram could not be sent
+    Io(io::Error),
+}
+
+impl fmt::Display for DogStatsdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DogStatsdError::Flatten(e) => write!(f, "could not flatten registry: {}", e),
+            DogStatsdError::Io(e) => write!(f, "could not send to DogStatsD: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DogStatsdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DogStatsdError::Flatten(e) => Some(e),
+            DogStatsdError::Io(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{measure, HitCount, ResponseTime};
+    use std::time::Duration;
+
+    #[derive(Default, Serialize)]
+    struct Registry {
+        hit_count: HitCount,
+        response_time: ResponseTime<Distribution>,
+    }
+
+    fn client() -> DogStatsd {
+        DogStatsd::new("127.0.0.1:8125").unwrap()
+    }
+
+    #[test]
+    fn formats_distributions() {
+        let registry = Registry::default();
+        for value in [3, 5, 8] {
+            registry.response_time.0.record(value);
+        }
+        measure!(&registry.hit_count, {});
+
+        let dogstatsd = client().with_prefix("app").with_tag("env", "prod");
+        assert_eq!(
+            dogstatsd.format(&registry).unwrap(),
+            [
+                "app.hit_count:1|g|#env:prod",
+                "app.response_time:3:5:8|d|#env:prod",
+            ]
+        );
+
+        // Values are only sent once, but are kept by the inner histogram
+        assert_eq!(
+            dogstatsd.format(&registry).unwrap(),
+            ["app.hit_count:1|g|#env:prod"]
+        );
+        assert_eq!(registry.response_time.histogram().len(), 3);
+        let samples = flatten::to_samples(&registry).unwrap();
+        assert!(samples.iter().any(|s| s.name == "response_time_samples"));
+
+        let namespace = Namespace::new()
+            .with_prefix("checkout")
+            .with_label("version", "1.0");
+        measure!(&registry.hit_count, {});
+        assert_eq!(
+            client()
+                .with_namespace(namespace)
+                .format(&registry)
+                .unwrap(),
+            ["checkout.hit_count:2|g|#version:1.0"]
+        );
+    }
+
+    #[test]
+    fn samples_values() {
+        let distribution: Distribution = Distribution::with_size(1_000, 10);
+        for value in 0..40 {
+            distribution.record(value);
+        }
+
+        let lines = client().format(&distribution).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with("|d|@0.25"), "{}", lines[0]);
+        assert_eq!(lines[0].split(':').count(), 11);
+
+        distribution.record(1);
+        let dogstatsd = client().with_sample_rate(0.0);
+        assert!(dogstatsd.format(&distribution).unwrap().is_empty());
+    }
+
+    #[test]
+    fn splits_packets() {
+        let distribution: Distribution = Distribution::with_size(1_000_000, 1_000);
+        for value in 0..1_000 {
+            distribution.record(100_000 + value);
+        }
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let dogstatsd = DogStatsd::new(receiver.local_addr().unwrap())
+            .unwrap()
+            .with_max_packet_size(512);
+        dogstatsd.send(&distribution).unwrap();
+
+        let mut values = 0;
+        let mut buf = [0; 1024];
+        while values < 1_000 {
+            let len = receiver.recv(&mut buf).unwrap();
+            assert!(len <= 512);
+            let packet = std::str::from_utf8(&buf[..len]).unwrap();
+            for line in packet.lines() {
+                assert!(line.ends_with("|d"));
+                values += line.split(':').count() - 1;
+            }
+        }
+        assert_eq!(values, 1_000);
+    }
+}
@@ -0,0 +1,393 @@
+//! A module rendering registries in the CloudWatch Embedded Metric Format
+//! (EMF), so that metrics logged to the standard output by Lambda functions or
+//! ECS tasks reach CloudWatch without running an agent.
+//!
+//! Each line rendered by [`Emf`] is a JSON document holding the values of a
+//! registry sharing the same labels, which become CloudWatch dimensions:
+//!
+#![cfg_attr(feature = "disabled", doc = "```ignore")]
+#![cfg_attr(not(feature = "disabled"), doc = "```rust")]
+//! use metered::{emf::Emf, metered, HitCount};
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Service {
+//!     metrics: ServiceMetrics,
+//! }
+//!
+//! #[metered(registry = ServiceMetrics)]
+//! impl Service {
+//!     #[measure(HitCount)]
+//!     pub fn call(&self) {}
+//! }
+//!
+//! let service = Service::default();
+//! service.call();
+//!
+//! let emf = Emf::new("Service").with_dimension("Stage", "prod");
+//! assert_eq!(
+//!     emf.render_at(&service.metrics, 1_700_000_000_000).unwrap(),
+//!     concat!(
+//!         r#"{"_aws":{"Timestamp":1700000000000,"CloudWatchMetrics":[{"Namespace":"Service","#,
+//!         r#""Dimensions":[["Stage"]],"Metrics":[{"Name":"call_hit_count","Unit":"Count"}]}]},"#,
+//!         r#""Stage":"prod","call_hit_count":1}"#,
+//!         "\n"
+//!     )
+//! );
+//! ```
+
+use crate::{
+    flatten::{self, Sample},
+    metadata::{DescribeMetrics, MetricMetadata, MetricType, Unit},
+    namespace::Namespace,
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The most metrics CloudWatch accepts in a single document
+const MAX_METRICS: usize = 100;
+
+/// A metric of a document: its name, CloudWatch unit and value
+type Metric = (String, &'static str, f64);
+
+/// A formatter rendering registries as CloudWatch EMF documents, one per line.
+///
+/// Registries are flattened with [`flatten::to_samples`]: each sample is a
+/// metric named after it, in the unit of the metric it was flattened from, and
+/// samples are grouped in documents by labels, the labels and the dimensions
+/// common to the formatter being the dimensions of the document. Durations in
+/// nanoseconds are converted to microseconds, and non-finite values are
+/// skipped.
+#[derive(Clone, Debug)]
+pub struct Emf {
+    namespace: String,
+    prefix: Option<String>,
+    dimensions: Vec<(String, String)>,
+}
+
+impl Emf {
+    /// Creates a formatter reporting metrics to a CloudWatch namespace
+    pub fn new(namespace: impl Into<String>) -> Self {
+        Emf {
+            namespace: namespace.into(),
+            prefix: None,
+            dimensions: Vec::new(),
+        }
+    }
+
+    /// Adds a dimension to every document, such as the service name
+    pub fn with_dimension(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.dimensions.push((key.into(), value.into()));
+        self
+    }
+
+    /// Applies a [`Namespace`] to every document: its prefix, followed by an
+    /// underscore, is prepended to the name of every metric, and its labels
+    /// are added as dimensions
+    pub fn with_namespace(mut self, namespace: Namespace) -> Self {
+        self.prefix = namespace.prefix().map(str::to_string);
+        self.dimensions.extend(namespace.labels().iter().cloned());
+        self
+    }
+
+    /// Renders a snapshot of a registry, timestamped with the current time
+    pub fn render<R>(&self, registry: &R) -> Result<String, flatten::Error>
+    where
+        R: Serialize + DescribeMetrics + ?Sized,
+    {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        self.render_at(registry, timestamp_ms)
+    }
+
+    /// Renders a snapshot of a registry, timestamped in milliseconds since
+    /// the Unix epoch
+    pub fn render_at<R>(&self, registry: &R, timestamp_ms: u64) -> Result<String, flatten::Error>
+    where
+        R: Serialize + DescribeMetrics + ?Sized,
+    {
+        let samples = flatten::to_samples(registry)?;
+        let descriptions = R::describe_metrics();
+
+        let mut documents: BTreeMap<Vec<(String, String)>, Vec<Metric>> = BTreeMap::new();
+        for Sample {
+            name,
+            labels,
+            value,
+        } in samples.iter()
+        {
+            let (unit, value) = match flatten::describe_sample(name, &descriptions) {
+                Some(description) => cloudwatch_unit(name, description.metadata, *value),
+                None => ("None", *value),
+            };
+            if !value.is_finite() {
+                continue;
+            }
+
+            let mut dimensions = self.dimensions.clone();
+            for (key, value) in labels {
+                match dimensions.iter_mut().find(|(k, _)| k == key) {
+                    Some((_, previous)) => *previous = value.clone(),
+                    None => dimensions.push((key.clone(), value.clone())),
+                }
+            }
+            let name = match self.prefix {
+                Some(ref prefix) => format!("{}_{}", prefix, name),
+                None => name.clone(),
+            };
+            documents
+                .entry(dimensions)
+                .or_default()
+                .push((name, unit, value));
+        }
+
+        let mut out = String::new();
+        for (dimensions, metrics) in documents.iter() {
+            for metrics in metrics.chunks(MAX_METRICS) {
+                self.write_document(&mut out, timestamp_ms, dimensions, metrics)
+                    .expect("writing to a String cannot fail");
+            }
+        }
+        Ok(out)
+    }
+
+    fn write_document(
+        &self,
+        out: &mut String,
+        timestamp_ms: u64,
+        dimensions: &[(String, String)],
+        metrics: &[Metric],
+    ) -> fmt::Result {
+        write!(out, r#"{{"_aws":{{"Timestamp":{},"#, timestamp_ms)?;
+        out.push_str(r#""CloudWatchMetrics":[{"Namespace":"#);
+        write_string(out, &self.namespace)?;
+        out.push_str(r#","Dimensions":[["#);
+        for (i, (key, _)) in dimensions.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_string(out, key)?;
+        }
+        out.push_str(r#"]],"Metrics":["#);
+        for (i, (name, unit, _)) in metrics.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push_str(r#"{"Name":"#);
+            write_string(out, name)?;
+            write!(out, r#","Unit":"{}"}}"#, unit)?;
+        }
+        out.push_str("]}]}");
+
+        for (key, value) in dimensions {
+            out.push(',');
+            write_string(out, key)?;
+            out.push(':');
+            write_string(out, value)?;
+        }
+        for (name, _, value) in metrics {
+            out.push(',');
+            write_string(out, name)?;
+            write!(out, ":{}", value)?;
+        }
+        out.push_str("}\n");
+        Ok(())
+    }
+}
+
+/// Get the CloudWatch unit of a sample, converting its value if needed
+fn cloudwatch_unit(name: &str, metadata: MetricMetadata, value: f64) -> (&'static str, f64) {
+    // Histograms of durations also count their samples
+    if name.ends_with("_samples") {
+        return ("Count", value);
+    }
+    match metadata.unit {
+        Unit::Seconds => ("Seconds", value),
+        Unit::Milliseconds => ("Milliseconds", value),
+        Unit::Microseconds => ("Microseconds", value),
+        Unit::Nanoseconds => ("Microseconds", value / 1_000.0),
+        Unit::Bytes => ("Bytes", value),
+        Unit::Requests => ("Count", value),
+        Unit::RequestsPerSecond => ("Count/Second", value),
+        Unit::Ratio => ("Percent", value * 100.0),
+        _ if metadata.metric_type == MetricType::Counter => ("Count", value),
+        _ => ("None", value),
+    }
+}
+
+/// Writes a JSON string
+fn write_string(out: &mut String, value: &str) -> fmt::Result {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    Ok(())
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{
+        breakdown::{Breakdown, Variant},
+        measure,
+        metadata::MetricDescription,
+        HitCount,
+    };
+
+    enum Op {
+        Read,
+        Write,
+    }
+
+    impl Variant for Op {
+        const VARIANTS: &'static [&'static str] = &["read", "write"];
+
+        fn variant_index(&self) -> usize {
+            match self {
+                Op::Read => 0,
+                Op::Write => 1,
+            }
+        }
+    }
+
+    #[derive(Default, Serialize)]
+    struct Registry {
+        hit_count: HitCount,
+        by_op: Breakdown<Op, HitCount>,
+        ratio: f64,
+    }
+
+    impl DescribeMetrics for Registry {
+        fn describe_metrics() -> Vec<MetricDescription> {
+            vec![
+                MetricDescription {
+                    path: vec!["hit_count"],
+                    metadata: MetricMetadata::new(MetricType::Counter, Unit::None),
+                },
+                MetricDescription {
+                    path: vec!["by_op"],
+                    metadata: MetricMetadata::new(MetricType::Counter, Unit::Requests),
+                },
+                MetricDescription {
+                    path: vec!["ratio"],
+                    metadata: MetricMetadata::new(MetricType::Gauge, Unit::Ratio),
+                },
+            ]
+        }
+    }
+
+    #[test]
+    fn renders_documents_by_dimensions() {
+        let registry = Registry {
+            ratio: 0.25,
+            ..Registry::default()
+        };
+        measure!(&registry.hit_count, {});
+        measure!(registry.by_op.by(&Op::Read), {});
+        measure!(registry.by_op.by(&Op::Read), {});
+        measure!(registry.by_op.by(&Op::Write), {});
+
+        let rendered = Emf::new("App\"s")
+            .with_dimension("Stage", "prod")
+            .render_at(&registry, 42)
+            .unwrap();
+        let documents: Vec<serde_json::Value> = rendered
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(documents.len(), 3);
+
+        assert_eq!(
+            documents[0],
+            serde_json::json!({
+                "_aws": {
+                    "Timestamp": 42,
+                    "CloudWatchMetrics": [{
+                        "Namespace": "App\"s",
+                        "Dimensions": [["Stage"]],
+                        "Metrics": [
+                            {"Name": "hit_count", "Unit": "Count"},
+                            {"Name": "ratio", "Unit": "Percent"},
+                        ],
+                    }],
+                },
+                "Stage": "prod",
+                "hit_count": 1,
+                "ratio": 25,
+            })
+        );
+        assert_eq!(
+            documents[2]["_aws"]["CloudWatchMetrics"][0]["Dimensions"],
+            serde_json::json!([["Stage", "variant"]])
+        );
+        assert_eq!(documents[1]["variant"], "read");
+        assert_eq!(documents[1]["by_op"], 2);
+        assert_eq!(documents[2]["variant"], "write");
+        assert_eq!(documents[2]["by_op"], 1);
+    }
+
+    #[test]
+    fn applies_namespace() {
+        let registry = Registry::default();
+        measure!(&registry.hit_count, {});
+
+        let namespace = Namespace::new()
+            .with_prefix("checkout")
+            .with_label("version", "1.0");
+        let rendered = Emf::new("App")
+            .with_namespace(namespace)
+            .render_at(&registry, 0)
+            .unwrap();
+        let document: serde_json::Value =
+            serde_json::from_str(rendered.lines().next().unwrap()).unwrap();
+        assert_eq!(
+            document["_aws"]["CloudWatchMetrics"][0]["Dimensions"],
+            serde_json::json!([["version"]])
+        );
+        assert_eq!(
+            document["_aws"]["CloudWatchMetrics"][0]["Metrics"][0],
+            serde_json::json!({"Name": "checkout_hit_count", "Unit": "Count"})
+        );
+        assert_eq!(document["checkout_hit_count"], 1);
+        assert_eq!(document["version"], "1.0");
+    }
+
+    #[test]
+    fn splits_large_documents() {
+        #[derive(Serialize)]
+        struct Many(BTreeMap<String, u64>);
+
+        impl DescribeMetrics for Many {
+            fn describe_metrics() -> Vec<MetricDescription> {
+                Vec::new()
+            }
+        }
+
+        let registry = Many((0..250).map(|i| (format!("m{}", i), i)).collect());
+        let rendered = Emf::new("App").render_at(&registry, 0).unwrap();
+        let sizes: Vec<usize> = rendered
+            .lines()
+            .map(|line| {
+                let document: serde_json::Value = serde_json::from_str(line).unwrap();
+                document["_aws"]["CloudWatchMetrics"][0]["Metrics"]
+                    .as_array()
+                    .unwrap()
+                    .len()
+            })
+            .collect();
+        assert_eq!(sizes, [100, 100, 50]);
+    }
+}
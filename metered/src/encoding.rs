@@ -0,0 +1,153 @@
+//! A module encoding registries in compact binary formats, to ship snapshots
+//! between services.
+//!
+//! MessagePack is available with the `msgpack` feature, and CBOR with the
+//! `cbor` feature.
+//!
+//! Registries are encoded with the same schema as with `serde_json`: structs
+//! are encoded as maps keyed by field names, so that decoders keep working as
+//! methods and metrics are added to registries. The newtype struct names some
+//! metrics use to attach labels for `serde_prometheus` (e.g. the quantiles of
+//! `HdrHistogram`) are not part of these formats, and are dropped. To keep
+//! labels, encode the [`Sample`]s of [`flatten::to_samples`] instead:
+//!
+//! ```rust
+//! # #[cfg(feature = "msgpack")]
+//! # {
+//! use metered::{encoding, flatten, metered, ResponseTime};
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Service {
+//!     metrics: ServiceMetrics,
+//! }
+//!
+//! #[metered(registry = ServiceMetrics)]
+//! impl Service {
+//!     #[measure(ResponseTime)]
+//!     pub fn call(&self) {}
+//! }
+//!
+//! let service = Service::default();
+//! service.call();
+//!
+//! // `{"call": {"response_time": {"samples": 1, "min": 0, ...}}}`
+//! let snapshot = encoding::to_msgpack(&service.metrics).unwrap();
+//! // `[{"name": "call_response_time", "labels": [["quantile", "0.9"]], ...}, ...]`
+//! let samples = flatten::to_samples(&service.metrics).unwrap();
+//! let samples = encoding::to_msgpack(&samples).unwrap();
+//! # let _ = (snapshot, samples);
+//! # }
+//! ```
+//!
+//! [`Sample`]: crate::flatten::Sample
+//! [`flatten::to_samples`]: crate::flatten::to_samples
+
+use serde::Serialize;
+
+/// Encodes a registry as MessagePack.
+#[cfg(feature = "msgpack")]
+pub fn to_msgpack<T: Serialize + ?Sized>(
+    registry: &T,
+) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+    rmp_serde::to_vec_named(registry)
+}
+
+/// Encodes a registry as CBOR.
+#[cfg(feature = "cbor")]
+pub fn to_cbor<T: Serialize + ?Sized>(
+    registry: &T,
+) -> Result<Vec<u8>, ciborium::ser::Error<std::io::Error>> {
+    let mut buf = Vec::new();
+    ciborium::ser::into_writer(registry, &mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(all(test, feature = "histograms", not(feature = "disabled")))]
+mod tests {
+    use crate::{flatten, measure, HitCount, ResponseTime};
+    use serde::Serialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Default, Serialize)]
+    struct ServiceMetrics {
+        call: CallMetrics,
+    }
+
+    #[derive(Default, Serialize)]
+    struct CallMetrics {
+        hit_count: HitCount,
+        response_time: ResponseTime,
+    }
+
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct Decoded {
+        call: DecodedCall,
+    }
+
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct DecodedCall {
+        hit_count: u64,
+        response_time: BTreeMap<String, Number>,
+    }
+
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    #[serde(untagged)]
+    enum Number {
+        Integer(u64),
+        Float(f64),
+    }
+
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct DecodedSample {
+        name: String,
+        labels: Vec<(String, String)>,
+        value: f64,
+    }
+
+    fn metrics() -> ServiceMetrics {
+        let metrics = ServiceMetrics::default();
+        for _ in 0..2 {
+            let call = &metrics.call;
+            measure!([&call.hit_count, &call.response_time], {});
+        }
+        metrics
+    }
+
+    fn check(decoded: Decoded) {
+        assert_eq!(decoded.call.hit_count, 2);
+        assert_eq!(decoded.call.response_time["samples"], Number::Integer(2));
+        assert!(decoded.call.response_time.contains_key("99%ile"));
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn round_trips_msgpack() {
+        let metrics = metrics();
+
+        let encoded = super::to_msgpack(&metrics).unwrap();
+        check(rmp_serde::from_slice(&encoded).unwrap());
+
+        let samples = flatten::to_samples(&metrics).unwrap();
+        let encoded = super::to_msgpack(&samples).unwrap();
+        let decoded: Vec<DecodedSample> = rmp_serde::from_slice(&encoded).unwrap();
+        assert_eq!(decoded.len(), samples.len());
+        assert!(decoded
+            .iter()
+            .any(|sample| sample.labels == [("quantile".to_string(), "0.9".to_string())]));
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn round_trips_cbor() {
+        let metrics = metrics();
+
+        let encoded = super::to_cbor(&metrics).unwrap();
+        check(ciborium::de::from_reader(&encoded[..]).unwrap());
+
+        let samples = flatten::to_samples(&metrics).unwrap();
+        let encoded = super::to_cbor(&samples).unwrap();
+        let decoded: Vec<DecodedSample> = ciborium::de::from_reader(&encoded[..]).unwrap();
+        assert_eq!(decoded[0].name, "call_hit_count");
+        assert_eq!(decoded[0].value, 2.0);
+    }
+}
@@ -0,0 +1,889 @@
+//! A module flattening serialized registries into labeled samples, for
+//! exporters to monitoring systems.
+
+use crate::{clear, labels, metadata::MetricDescription};
+use serde::{
+    ser::{self, Impossible, SerializeMap},
+    Serialize, Serializer,
+};
+use std::{convert::TryFrom, fmt};
+
+/// A single value of a flattened registry.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Sample {
+    /// The name of the sample: the serialization keys leading to the value
+    /// joined with underscores, and sanitized to only contain ASCII
+    /// alphanumeric characters, `_` and `:`
+    pub name: String,
+    /// The labels attached to the value
+    pub labels: Vec<(String, String)>,
+    /// The value
+    pub value: f64,
+}
+
+/// Flattens a registry into samples, one for each numeric value it serializes.
+///
+/// Newtype struct names are interpreted the way `serde_prometheus` does, which
+/// stock metrics use to add labels and drop keys from names: `HdrHistogram`
+/// quantiles become a `quantile` label, constant registry labels are attached
+/// to the values of the registry, etc. Strings and missing values are skipped,
+/// and booleans are reported as 0 or 1.
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{flatten::to_samples, metered, HitCount, ResponseTime};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics)]
+/// impl Service {
+///     #[measure([HitCount, ResponseTime])]
+///     pub fn call(&self) {}
+/// }
+///
+/// let service = Service::default();
+/// service.call();
+///
+/// let samples = to_samples(&service.metrics).unwrap();
+/// assert_eq!(samples[0].name, "call_hit_count");
+/// assert_eq!(samples[0].value, 1.0);
+///
+/// let p90 = samples
+///     .iter()
+///     .find(|sample| sample.labels == [("quantile".to_string(), "0.9".to_string())])
+///     .unwrap();
+/// assert_eq!(p90.name, "call_response_time");
+/// ```
+pub fn to_samples<T: Serialize + ?Sized>(value: &T) -> Result<Vec<Sample>, Error> {
+    let mut flattener = Flattener::default();
+    value.serialize(&mut flattener)?;
+    let samples = flattener
+        .entries
+        .into_iter()
+        .map(|entry| Sample {
+            name: sample_name(&entry.path),
+            labels: entry.labels,
+            value: entry.value.as_f64(),
+        })
+        .collect();
+    Ok(samples)
+}
+
+/// Joins serialization keys into the name of a sample, as in [`to_samples`]
+pub(crate) fn sample_name<S: AsRef<str>>(path: &[S]) -> String {
+    let mut name = String::new();
+    for (i, key) in path.iter().enumerate() {
+        if i > 0 {
+            name.push('_');
+        }
+        name.extend(key.as_ref().chars().map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == ':' {
+                c
+            } else {
+                '_'
+            }
+        }));
+    }
+    name
+}
+
+/// Finds the description of the metric a sample was flattened from, by name:
+/// the description with the longest path whose name prefixes the sample's
+pub(crate) fn describe_sample<'a>(
+    name: &str,
+    descriptions: &'a [MetricDescription],
+) -> Option<&'a MetricDescription> {
+    descriptions
+        .iter()
+        .map(|description| (sample_name(&description.path), description))
+        .filter(|(prefix, _)| {
+            name.strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('_'))
+        })
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, description)| description)
+}
+
+/// Checks if every numeric value serialized by a value is zero, as is the
+/// case for cleared stock metrics. The value is left untouched within
+/// [`serialize_and_clear`](crate::clear::serialize_and_clear).
+pub(crate) fn is_zero<T: Serialize + ?Sized>(value: &T) -> bool {
+    let mut flattener = Flattener {
+        raw: true,
+        ..Flattener::default()
+    };
+    clear::without_clearing(|| value.serialize(&mut flattener)).is_ok()
+        && flattener
+            .entries
+            .iter()
+            .all(|entry| entry.value.as_f64() == 0.0)
+}
+
+/// Flattens a value into its numeric values, keyed by the serialization keys
+/// leading to them and ignoring newtype struct names like [`Flat`]. The value
+/// is left untouched within
+/// [`serialize_and_clear`](crate::clear::serialize_and_clear).
+pub(crate) fn raw_values<T: Serialize + ?Sized>(
+    value: &T,
+) -> Result<Vec<(Vec<String>, Value)>, Error> {
+    let mut flattener = Flattener {
+        raw: true,
+        ..Flattener::default()
+    };
+    clear::without_clearing(|| value.serialize(&mut flattener))?;
+    Ok(flattener
+        .entries
+        .into_iter()
+        .map(|entry| (entry.path, entry.value))
+        .collect())
+}
+
+/// A wrapper serializing a registry as a single-level map, keyed by the
+/// serialization keys leading to each value joined with dots, as expected by
+/// StatsD and Graphite pipelines or some log systems.
+///
+/// Unlike [`to_samples`], keys are left untouched: newtype struct names are
+/// ignored, and `HdrHistogram` quantiles are keyed as e.g.
+/// `call.response_time.99%ile`. Values keep their type, and strings and missing
+/// values are skipped.
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{flatten::Flat, metered, HitCount};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics)]
+/// impl Service {
+///     #[measure(HitCount)]
+///     pub fn call(&self) {}
+/// }
+///
+/// let service = Service::default();
+/// service.call();
+///
+/// let flat = serde_json::to_string(&Flat::new(&service.metrics)).unwrap();
+/// assert_eq!(flat, r#"{"call.hit_count":1}"#);
+///
+/// let flat = Flat::new(&service.metrics).with_separator("/");
+/// assert_eq!(serde_json::to_string(&flat).unwrap(), r#"{"call/hit_count":1}"#);
+/// ```
+pub struct Flat<'a, T: ?Sized> {
+    registry: &'a T,
+    separator: &'a str,
+}
+
+impl<'a, T: ?Sized> Flat<'a, T> {
+    /// Wraps a registry, joining keys with dots
+    pub fn new(registry: &'a T) -> Self {
+        Flat {
+            registry,
+            separator: ".",
+        }
+    }
+
+    /// Joins keys with another separator
+    pub fn with_separator(mut self, separator: &'a str) -> Self {
+        self.separator = separator;
+        self
+    }
+}
+
+impl<T: Serialize + ?Sized> Serialize for Flat<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut flattener = Flattener {
+            raw: true,
+            ..Flattener::default()
+        };
+        self.registry
+            .serialize(&mut flattener)
+            .map_err(ser::Error::custom)?;
+
+        let mut map = serializer.serialize_map(Some(flattener.entries.len()))?;
+        for entry in flattener.entries.iter() {
+            map.serialize_entry(&entry.path.join(self.separator), &entry.value)?;
+        }
+        map.end()
+    }
+}
+
+/// An error raised while flattening a registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+/// A value, keeping integers exact.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Value {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+}
+
+impl Value {
+    pub(crate) fn as_f64(self) -> f64 {
+        match self {
+            Value::Unsigned(v) => v as f64,
+            Value::Signed(v) => v as f64,
+            Value::Float(v) => v,
+        }
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Value::Unsigned(v) => serializer.serialize_u64(v),
+            Value::Signed(v) => serializer.serialize_i64(v),
+            Value::Float(v) => serializer.serialize_f64(v),
+        }
+    }
+}
+
+struct Entry {
+    path: Vec<String>,
+    labels: Vec<(String, String)>,
+    value: Value,
+}
+
+#[derive(Default)]
+struct Flattener {
+    /// Whether to ignore newtype struct names
+    raw: bool,
+    path: Vec<String>,
+    labels: Vec<(String, String)>,
+    entries: Vec<Entry>,
+}
+
+impl Flattener {
+    fn record(&mut self, value: Value) -> Result<(), Error> {
+        self.entries.push(Entry {
+            path: self.path.clone(),
+            labels: self.labels.clone(),
+            value,
+        });
+        Ok(())
+    }
+
+    fn with_key<F>(&mut self, key: String, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Self) -> Result<(), Error>,
+    {
+        self.path.push(key);
+        let result = f(self);
+        self.path.pop();
+        result
+    }
+
+    /// Applies a `keymodifiers|key=value,key2==modifiers|:internal=value`
+    /// newtype struct name.
+    fn apply_modifiers(&mut self, name: &str) {
+        let mut sections = name.split('|');
+        let key_modifiers = sections.next().unwrap_or_default();
+        let labels = sections.next().unwrap_or_default();
+
+        for definition in labels.split(',').filter(|def| !def.is_empty()) {
+            let (key, value) = match definition.split_once('=') {
+                Some(key_value) => key_value,
+                None => continue,
+            };
+            // `key==modifiers` takes the value from the path
+            let value = match value.strip_prefix('=') {
+                Some(modifiers) => self.value_from_path(modifiers),
+                None => value.trim_matches('"').to_string(),
+            };
+            // `key[sep]=value` appends to a previous value of the label
+            let (key, separator) = match key.split_once('[') {
+                Some((key, separator)) => (key, separator.strip_suffix(']')),
+                None => (key, None),
+            };
+
+            match self.labels.iter_mut().find(|(k, _)| k == key) {
+                Some((_, previous)) => match separator {
+                    Some(separator) => {
+                        previous.push_str(separator);
+                        previous.push_str(&value);
+                    }
+                    None => *previous = value,
+                },
+                None => self.labels.push((key.to_string(), value)),
+            }
+        }
+
+        // Names are built from the whole path, so only dropping keys matters
+        for modifier in key_modifiers.chars() {
+            if modifier == '!' {
+                self.path.pop();
+            }
+        }
+    }
+
+    fn value_from_path(&self, modifiers: &str) -> String {
+        let mut path = self.path.clone();
+        let mut parts = Vec::new();
+        for modifier in modifiers.chars() {
+            match modifier {
+                '!' => {
+                    path.pop();
+                }
+                '<' => parts.extend(path.pop()),
+                _ => {}
+            }
+        }
+        parts.reverse();
+        parts.join("_")
+    }
+}
+
+macro_rules! record_as {
+    ($variant:ident: $($method:ident: $ty:ty),*) => {
+        $(
+            fn $method(self, v: $ty) -> Result<(), Error> {
+                self.record(Value::$variant(v.into()))
+            }
+        )*
+    };
+}
+
+impl<'a> Serializer for &'a mut Flattener {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = Compound<'a>;
+    type SerializeTuple = Compound<'a>;
+    type SerializeTupleStruct = Compound<'a>;
+    type SerializeTupleVariant = Compound<'a>;
+    type SerializeMap = Compound<'a>;
+    type SerializeStruct = Compound<'a>;
+    type SerializeStructVariant = Compound<'a>;
+
+    record_as! {
+        Unsigned: serialize_u8: u8, serialize_u16: u16, serialize_u32: u32, serialize_u64: u64
+    }
+    record_as! {
+        Signed: serialize_i8: i8, serialize_i16: i16, serialize_i32: i32, serialize_i64: i64
+    }
+    record_as! {
+        Float: serialize_f32: f32, serialize_f64: f64
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<(), Error> {
+        match i64::try_from(v) {
+            Ok(v) => self.record(Value::Signed(v)),
+            Err(_) => self.record(Value::Float(v as f64)),
+        }
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<(), Error> {
+        match u64::try_from(v) {
+            Ok(v) => self.record(Value::Unsigned(v)),
+            Err(_) => self.record(Value::Float(v as f64)),
+        }
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.record(Value::Unsigned(v.into()))
+    }
+
+    fn serialize_char(self, _v: char) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        if self.raw || !name.contains('|') {
+            return value.serialize(self);
+        }
+
+        let path = self.path.clone();
+        let labels = self.labels.clone();
+        if name == labels::DYNAMIC_ALIAS {
+            let name = labels::take_dynamic_alias().unwrap_or_default();
+            self.apply_modifiers(&name);
+        } else {
+            self.apply_modifiers(name);
+        }
+        let result = value.serialize(&mut *self);
+        self.path = path;
+        self.labels = labels;
+        result
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.with_key(variant.to_string(), |this| value.serialize(this))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'a>, Error> {
+        Ok(Compound::new(self, None))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Compound<'a>, Error> {
+        Ok(Compound::new(self, None))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, Error> {
+        Ok(Compound::new(self, None))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, Error> {
+        Ok(Compound::new(self, Some(variant)))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Compound<'a>, Error> {
+        Ok(Compound::new(self, None))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Compound<'a>, Error> {
+        Ok(Compound::new(self, None))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, Error> {
+        Ok(Compound::new(self, Some(variant)))
+    }
+}
+
+/// Flattens sequences, maps and structs, keying sequence elements by index.
+struct Compound<'a> {
+    flattener: &'a mut Flattener,
+    variant: bool,
+    index: usize,
+    key: Option<String>,
+}
+
+impl<'a> Compound<'a> {
+    fn new(flattener: &'a mut Flattener, variant: Option<&'static str>) -> Self {
+        if let Some(variant) = variant {
+            flattener.path.push(variant.to_string());
+        }
+        Compound {
+            flattener,
+            variant: variant.is_some(),
+            index: 0,
+            key: None,
+        }
+    }
+
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self.index.to_string();
+        self.index += 1;
+        self.flattener
+            .with_key(key, |flattener| value.serialize(flattener))
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<(), Error> {
+        self.flattener
+            .with_key(key.to_string(), |flattener| value.serialize(flattener))
+    }
+
+    fn end(self) -> Result<(), Error> {
+        if self.variant {
+            self.flattener.path.pop();
+        }
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTuple for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeMap for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| Error("map value serialized before its key".to_string()))?;
+        self.field(&key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeStruct for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+/// Serializes map keys to strings.
+struct KeySerializer;
+
+macro_rules! key_to_string {
+    ($($method:ident: $ty:ty),*) => {
+        $(
+            fn $method(self, v: $ty) -> Result<String, Error> {
+                Ok(v.to_string())
+            }
+        )*
+    };
+}
+
+impl Serializer for KeySerializer {
+    type Ok = String;
+    type Error = Error;
+
+    type SerializeSeq = Impossible<String, Error>;
+    type SerializeTuple = Impossible<String, Error>;
+    type SerializeTupleStruct = Impossible<String, Error>;
+    type SerializeTupleVariant = Impossible<String, Error>;
+    type SerializeMap = Impossible<String, Error>;
+    type SerializeStruct = Impossible<String, Error>;
+    type SerializeStructVariant = Impossible<String, Error>;
+
+    key_to_string! {
+        serialize_bool: bool, serialize_i8: i8, serialize_i16: i16, serialize_i32: i32,
+        serialize_i64: i64, serialize_i128: i128, serialize_u8: u8, serialize_u16: u16,
+        serialize_u32: u32, serialize_u64: u64, serialize_u128: u128, serialize_f32: f32,
+        serialize_f64: f64, serialize_char: char, serialize_str: &str
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String, Error> {
+        Err(unsupported_key())
+    }
+
+    fn serialize_none(self) -> Result<String, Error> {
+        Err(unsupported_key())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<String, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<String, Error> {
+        Err(unsupported_key())
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<String, Error> {
+        Ok(name.to_string())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<String, Error> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<String, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<String, Error> {
+        Err(unsupported_key())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Err(unsupported_key())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        Err(unsupported_key())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(unsupported_key())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(unsupported_key())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(unsupported_key())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        Err(unsupported_key())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(unsupported_key())
+    }
+}
+
+fn unsupported_key() -> Error {
+    Error("map keys must be strings, numbers or unit variants".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Labeled(&'static str, u64);
+
+    impl Serialize for Labeled {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_newtype_struct(self.0, &self.1)
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Registry {
+        kept: Labeled,
+        dropped: Labeled,
+        from_path: Labeled,
+        nested: Vec<Labeled>,
+    }
+
+    fn label(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn applies_newtype_name_modifiers() {
+        let registry = Registry {
+            kept: Labeled("|a=1,b=\"2\"", 1),
+            dropped: Labeled("!|a=1", 2),
+            from_path: Labeled("!|kind==<", 3),
+            nested: vec![Labeled("|a[::]=x", 4)],
+        };
+
+        let samples = to_samples(&registry).unwrap();
+        assert_eq!(samples.len(), 4);
+
+        assert_eq!(samples[0].name, "kept");
+        assert_eq!(samples[0].labels, [label("a", "1"), label("b", "2")]);
+        assert_eq!(samples[1].name, "");
+        assert_eq!(samples[1].labels, [label("a", "1")]);
+        assert_eq!(samples[2].name, "");
+        assert_eq!(samples[2].labels, [label("kind", "from_path")]);
+        assert_eq!(samples[3].name, "nested_0");
+        assert_eq!(samples[3].value, 4.0);
+    }
+
+    #[test]
+    fn concatenates_labels() {
+        struct Outer(Labeled);
+        impl Serialize for Outer {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_newtype_struct("|a=x", &self.0)
+            }
+        }
+
+        let samples = to_samples(&Outer(Labeled("|a[::]=y", 1))).unwrap();
+        assert_eq!(samples[0].labels, [label("a", "x::y")]);
+    }
+
+    #[test]
+    fn flattens_with_raw_keys() {
+        let registry = Registry {
+            kept: Labeled("|a=1", 1),
+            dropped: Labeled("!|a=1", 2),
+            from_path: Labeled("!|kind==<", 3),
+            nested: vec![Labeled("|a=1", 4), Labeled("|a=1", 5)],
+        };
+
+        let flat = serde_json::to_value(Flat::new(&registry)).unwrap();
+        assert_eq!(
+            flat,
+            serde_json::json!({
+                "kept": 1,
+                "dropped": 2,
+                "from_path": 3,
+                "nested.0": 4,
+                "nested.1": 5,
+            })
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! A module providing thread-safe and unsynchronized implementations for
+//! Counters and Gauges on `f64`.
+
+use crate::{
+    atomic::AtomicF64,
+    clear::{Clear, Clearable},
+    merge::Merge,
+    metric::{Counter, FloatValue, Gauge},
+};
+use std::cell::Cell;
+
+impl FloatValue for Cell<f64> {
+    fn get(&self) -> f64 {
+        Cell::get(self)
+    }
+
+    fn set(&self, value: f64) {
+        Cell::set(self, value);
+    }
+
+    fn add(&self, value: f64) {
+        Cell::set(self, Cell::get(self) + value);
+    }
+}
+
+impl Counter for Cell<f64> {
+    fn incr_by(&self, count: usize) {
+        FloatValue::add(self, count as f64);
+    }
+
+    fn value(&self) -> u64 {
+        // Saturates, and truncates the fractional part
+        Cell::get(self) as u64
+    }
+
+    fn take(&self) -> u64 {
+        self.replace(0.0) as u64
+    }
+
+    fn take_counter(&self) -> Self {
+        Cell::new(self.replace(0.0))
+    }
+}
+
+impl Gauge for Cell<f64> {
+    fn incr_by(&self, count: usize) {
+        FloatValue::add(self, count as f64);
+    }
+
+    fn decr_by(&self, count: usize) {
+        FloatValue::sub(self, count as f64);
+    }
+
+    fn value(&self) -> u64 {
+        Cell::get(self) as u64
+    }
+}
+
+impl Clear for Cell<f64> {
+    fn clear(&self) {
+        Cell::set(self, 0.0);
+    }
+}
+
+impl Clearable for Cell<f64> {
+    fn is_cleared(&self) -> bool {
+        Cell::get(self) == 0.0
+    }
+}
+
+impl Merge for Cell<f64> {
+    fn merge_from(&self, other: &Self) {
+        FloatValue::add(self, Cell::get(other));
+    }
+}
+
+impl FloatValue for AtomicF64 {
+    fn get(&self) -> f64 {
+        AtomicF64::get(self)
+    }
+
+    fn set(&self, value: f64) {
+        AtomicF64::set(self, value);
+    }
+
+    fn add(&self, value: f64) {
+        AtomicF64::add(self, value);
+    }
+}
+
+impl Counter for AtomicF64 {
+    fn incr_by(&self, count: usize) {
+        AtomicF64::add(self, count as f64);
+    }
+
+    fn value(&self) -> u64 {
+        // Saturates, and truncates the fractional part
+        AtomicF64::get(self) as u64
+    }
+
+    fn take(&self) -> u64 {
+        self.swap(0.0) as u64
+    }
+
+    fn take_counter(&self) -> Self {
+        AtomicF64::new(self.swap(0.0))
+    }
+}
+
+impl Gauge for AtomicF64 {
+    fn incr_by(&self, count: usize) {
+        AtomicF64::add(self, count as f64);
+    }
+
+    fn decr_by(&self, count: usize) {
+        AtomicF64::sub(self, count as f64);
+    }
+
+    fn value(&self) -> u64 {
+        AtomicF64::get(self) as u64
+    }
+}
+
+impl Clear for AtomicF64 {
+    fn clear(&self) {
+        AtomicF64::set(self, 0.0);
+    }
+}
+
+impl Clearable for AtomicF64 {
+    fn is_cleared(&self) -> bool {
+        AtomicF64::get(self) == 0.0
+    }
+}
+
+impl Merge for AtomicF64 {
+    fn merge_from(&self, other: &Self) {
+        AtomicF64::add(self, other.get());
+    }
+}
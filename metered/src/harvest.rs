@@ -0,0 +1,183 @@
+//! A module providing per-thread replicas of a registry, merged on scrape, for
+//! extremely hot metrics in thread-per-core runtimes.
+//!
+//! Metrics shared between threads bounce their cache lines between cores on
+//! every update. A [`Harvest`] instead gives each thread its own replica of a
+//! registry, only ever updated by that thread, and merges the replicas with
+//! the [`Merge`] trait when the registry is read:
+//!
+#![cfg_attr(feature = "disabled", doc = "```ignore")]
+#![cfg_attr(not(feature = "disabled"), doc = "```rust")]
+//! use metered::{harvest::Harvest, metered, HitCount};
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Worker {
+//!     metrics: Harvest<WorkerMetrics>,
+//! }
+//!
+//! #[metered(registry = WorkerMetrics, registry_expr = self.metrics.local(), merge = true)]
+//! impl Worker {
+//!     #[measure(HitCount)]
+//!     pub fn call(&self) {}
+//! }
+//!
+//! let worker = Worker::default();
+//! std::thread::scope(|scope| {
+//!     for _ in 0..4 {
+//!         scope.spawn(|| (0..1_000).for_each(|_| worker.call()));
+//!     }
+//! });
+//!
+//! let metrics = worker.metrics.harvest();
+//! assert_eq!(metrics.call.hit_count.get(), 4_000);
+//! ```
+//!
+//! A `Harvest` serializes, describes and prints as its harvested registry, so
+//! it can be exported like any other registry.
+
+use crate::{
+    clear::Clear,
+    merge::{self, Merge},
+    metadata::{DescribeMetrics, MetricDescription},
+    pretty::Pretty,
+    sync::Mutex,
+};
+use serde::{Serialize, Serializer};
+use std::{
+    any::Any,
+    cell::RefCell,
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A source of unique harvest ids, to find the replicas of a harvest in
+/// thread-local storage
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The replicas of the current thread, by harvest id
+    static REPLICAS: RefCell<Vec<(usize, &'static (dyn Any + Send + Sync))>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+/// A registry replicated on each thread using it, see the
+/// [module documentation](crate::harvest).
+///
+/// Replicas are allocated on the first use of the harvest by a thread, and
+/// never freed: they must outlive the thread, whose counts remain part of the
+/// harvest. A harvest is meant to be long-lived, used by long-lived threads.
+pub struct Harvest<R: 'static> {
+    id: usize,
+    replicas: Mutex<Vec<&'static R>>,
+}
+
+impl<R: Default + Send + Sync + 'static> Harvest<R> {
+    /// Creates a harvest without replicas
+    pub fn new() -> Self {
+        Harvest {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            replicas: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Get the replica of the current thread, creating it on first use
+    pub fn local(&self) -> &R {
+        REPLICAS.with(|replicas| {
+            if let Some((_, replica)) = replicas.borrow().iter().find(|(id, _)| *id == self.id) {
+                return replica.downcast_ref::<R>().expect("harvest ids are unique");
+            }
+
+            let replica: &'static R = Box::leak(Box::default());
+            self.replicas.lock().push(replica);
+            replicas.borrow_mut().push((self.id, replica));
+            replica
+        })
+    }
+
+    /// Get the number of threads that used the harvest
+    pub fn replica_count(&self) -> usize {
+        self.replicas.lock().len()
+    }
+}
+
+impl<R: Merge + Default + Send + Sync + 'static> Harvest<R> {
+    /// Merges the replicas into a new registry, see [`merge::aggregate`]
+    pub fn harvest(&self) -> R {
+        merge::aggregate(self.replicas.lock().iter().copied())
+    }
+}
+
+impl<R: Default + Send + Sync + 'static> Default for Harvest<R> {
+    fn default() -> Self {
+        Harvest::new()
+    }
+}
+
+impl<R: Clear> Clear for Harvest<R> {
+    fn clear(&self) {
+        for replica in self.replicas.lock().iter() {
+            replica.clear();
+        }
+    }
+}
+
+impl<R: Merge + Default + Send + Sync + Serialize + 'static> Serialize for Harvest<R> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.harvest().serialize(serializer)
+    }
+}
+
+impl<R: DescribeMetrics> DescribeMetrics for Harvest<R> {
+    fn describe_metrics() -> Vec<MetricDescription> {
+        R::describe_metrics()
+    }
+}
+
+impl<R> fmt::Display for Harvest<R>
+where
+    R: Merge + Default + Send + Sync + Serialize + DescribeMetrics + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Pretty::new(&self.harvest()).fmt(f)
+    }
+}
+
+impl<R> fmt::Debug for Harvest<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Harvest")
+            .field("replicas", &self.replicas.lock().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HitCount;
+
+    #[test]
+    fn merges_thread_replicas() {
+        let harvest: Harvest<HitCount> = Harvest::new();
+        let other: Harvest<HitCount> = Harvest::new();
+        std::thread::scope(|scope| {
+            for _ in 0..3 {
+                scope.spawn(|| {
+                    assert!(std::ptr::eq(harvest.local(), harvest.local()));
+                    harvest.local().incr_by(2);
+                    other.local().incr_by(1);
+                });
+            }
+        });
+
+        assert_eq!(harvest.replica_count(), 3);
+        assert_eq!(harvest.harvest().get(), 6);
+        assert_eq!(other.harvest().get(), 3);
+        assert_eq!(serde_json::to_string(&harvest).unwrap(), "6");
+
+        harvest.clear();
+        assert_eq!(harvest.harvest().get(), 0);
+    }
+}
@@ -1,8 +1,13 @@
 //! A module providing thread-safe and unsynchronized implementations for
 //! Histograms, based on HdrHistogram.
 
-use crate::{clear::Clear, metric::Histogram};
-use parking_lot::Mutex;
+use crate::{
+    clear::{self, Clear, Clearable},
+    merge::Merge,
+    metric::{Histogram, HistogramSnapshot, SnapshotHistogram},
+    serialization::{self, MetricAlias},
+    sync::Mutex,
+};
 use serde::{Serialize, Serializer};
 
 /// A thread-safe implementation of HdrHistogram
@@ -15,6 +20,37 @@ impl AtomicHdrHistogram {
     pub fn histogram(&self) -> HdrHistogram {
         self.inner.lock().clone()
     }
+
+    /// Adds the values recorded by another histogram, see
+    /// [`HdrHistogram::add`].
+    pub fn add(&self, other: &HdrHistogram) {
+        self.inner.lock().add(other);
+    }
+
+    /// Removes the values recorded by another histogram, see
+    /// [`HdrHistogram::subtract`].
+    pub fn subtract(&self, other: &HdrHistogram) {
+        self.inner.lock().subtract(other);
+    }
+
+    /// Swaps in an empty histogram, and returns the values recorded so far.
+    ///
+    /// The empty histogram is allocated outside the lock, so that recording
+    /// threads only wait for the swap rather than for all buckets to be
+    /// zeroed. Recording still takes the lock, so a value being recorded
+    /// during the swap is in either histogram, never lost: there is no
+    /// lock-free recording whose completion the swap would wait for.
+    pub fn take(&self) -> HdrHistogram {
+        take_histogram(&self.inner)
+    }
+}
+
+impl SnapshotHistogram for AtomicHdrHistogram {
+    type Snapshot = HdrHistogram;
+
+    fn snapshot_histogram(&self) -> HdrHistogram {
+        self.histogram()
+    }
 }
 
 impl Histogram for AtomicHdrHistogram {
@@ -24,14 +60,41 @@ impl Histogram for AtomicHdrHistogram {
         AtomicHdrHistogram { inner }
     }
 
+    fn with_precision(max_bound: u64, sig_figs: u8) -> Self {
+        let inner = Mutex::new(HdrHistogram::with_precision(max_bound, sig_figs));
+        AtomicHdrHistogram { inner }
+    }
+
+    #[inline]
     fn record(&self, value: u64) {
-        self.inner.lock().record(value);
+        lock_histogram!(self.inner).record(value);
     }
 }
 
 impl Clear for AtomicHdrHistogram {
+    /// Swaps in an empty histogram rather than resetting it in place, see
+    /// [`take`](Self::take)
     fn clear(&self) {
-        self.inner.lock().clear();
+        drop(self.take());
+    }
+}
+
+impl Clearable for AtomicHdrHistogram {
+    fn is_cleared(&self) -> bool {
+        self.inner.lock().is_empty()
+    }
+}
+
+impl Merge for AtomicHdrHistogram {
+    fn merge_from(&self, other: &Self) {
+        // Clone first, in case other is self
+        self.add(&other.histogram());
+    }
+}
+
+impl From<&AtomicHdrHistogram> for HistogramSnapshot {
+    fn from(histogram: &AtomicHdrHistogram) -> Self {
+        HistogramSnapshot::from(&*histogram.inner.lock())
     }
 }
 
@@ -41,12 +104,31 @@ impl Serialize for AtomicHdrHistogram {
         S: Serializer,
     {
         use std::ops::Deref;
-        let inner = self.inner.lock();
-        let inner = inner.deref();
-        Serialize::serialize(inner, serializer)
+        internal_cost!(serialization, {
+            if clear::is_clearing_serialization() {
+                Serialize::serialize(&self.take(), serializer)
+            } else {
+                let inner = self.inner.lock();
+                let inner = inner.deref();
+                Serialize::serialize(inner, serializer)
+            }
+        })
     }
 }
 
+/// Swaps the histogram behind a lock with an empty one of the same bound and
+/// precision, allocated outside the lock, and returns it: dropping it frees it
+/// after the lock is released.
+pub(crate) fn take_histogram(inner: &Mutex<HdrHistogram>) -> HdrHistogram {
+    // The bound and precision never change after creation
+    let (bound, precision) = {
+        let histogram = inner.lock();
+        (histogram.bound(), histogram.precision())
+    };
+    let empty = HdrHistogram::with_precision(bound, precision);
+    std::mem::replace(&mut *inner.lock(), empty)
+}
+
 use std::{fmt, fmt::Debug};
 impl Debug for AtomicHdrHistogram {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -71,7 +153,20 @@ impl HdrHistogram {
     /// For instance, a max_bound of 60 * 60 * 1000 will allow to record
     /// durations varying from 1 millisecond to 1 hour.
     pub fn with_bound(max_bound: u64) -> Self {
-        let histo = hdrhistogram::Histogram::<u64>::new_with_bounds(1, max_bound, 2)
+        Self::with_precision(max_bound, 2)
+    }
+
+    /// Instantiates a new HdrHistogram with a max_bound, keeping `sig_figs`
+    /// significant figures of recorded values
+    ///
+    /// More significant figures make quantiles more precise, at the cost of
+    /// memory: each one multiplies the number of buckets by 10.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sig_figs` is higher than 5, or `max_bound` lower than 2.
+    pub fn with_precision(max_bound: u64, sig_figs: u8) -> Self {
+        let histo = hdrhistogram::Histogram::<u64>::new_with_bounds(1, max_bound, sig_figs)
             .expect("Could not instantiate HdrHistogram");
 
         HdrHistogram { histo }
@@ -82,10 +177,21 @@ impl HdrHistogram {
         self.histo.high()
     }
 
+    /// Get the number of significant figures kept of recorded values
+    pub fn precision(&self) -> u8 {
+        self.histo.sigfig()
+    }
+
+    /// Get an empty histogram with the same bound and precision
+    pub fn empty(&self) -> Self {
+        Self::with_precision(self.bound(), self.precision())
+    }
+
     /// Records a value to the histogram
     ///
     /// This is a saturating record: if the value is higher than `max_bound`,
     /// max_bound will be recorded instead.
+    #[inline]
     pub fn record(&mut self, value: u64) {
         // All recordings will be saturating
         self.histo.saturating_record(value);
@@ -95,11 +201,61 @@ impl HdrHistogram {
     ///
     /// This is a saturating record: if the value is higher than `max_bound`,
     /// max_bound will be recorded instead.
+    #[inline]
     pub fn record_n(&mut self, value: u64, count: u64) {
         // All recordings will be saturating
         self.histo.saturating_record_n(value, count);
     }
 
+    /// Adds the values recorded by another histogram to this histogram
+    ///
+    /// This is a saturating add: values of `other` higher than `max_bound`
+    /// are recorded as max_bound.
+    pub fn add(&mut self, other: &HdrHistogram) {
+        if self.histo.add(&other.histo).is_err() {
+            for bucket in other.histo.iter_recorded() {
+                self.histo
+                    .saturating_record_n(bucket.value_iterated_to(), bucket.count_at_value());
+            }
+        }
+    }
+
+    /// Removes the values recorded by another histogram from this histogram,
+    /// e.g. an earlier snapshot to get the values recorded since.
+    ///
+    /// This is a saturating subtract: values of `other` higher than
+    /// `max_bound` are removed as max_bound, and counts of `other` higher than
+    /// the counts of this histogram leave them at zero.
+    ///
+    /// ```rust
+    /// use metered::{hdr_histogram::AtomicHdrHistogram, Histogram};
+    ///
+    /// let histogram = AtomicHdrHistogram::with_bound(1000);
+    /// histogram.record(100);
+    /// let previous = histogram.histogram();
+    /// histogram.record(5);
+    /// histogram.record(5);
+    ///
+    /// let mut window = histogram.histogram();
+    /// window.subtract(&previous);
+    /// assert_eq!(window.len(), 2);
+    /// assert_eq!(window.max(), 5);
+    /// ```
+    pub fn subtract(&mut self, other: &HdrHistogram) {
+        // Clamp the counts to remove, so that subtracting cannot fail halfway
+        let mut clamped = self.histo.clone();
+        clamped.reset();
+        let high = self.histo.high();
+        for bucket in other.histo.iter_recorded() {
+            let value = bucket.value_iterated_to().min(high);
+            let available = self.histo.count_at(value) - clamped.count_at(value);
+            clamped.saturating_record_n(value, bucket.count_at_value().min(available));
+        }
+        self.histo
+            .subtract(&clamped)
+            .expect("Could not subtract clamped HdrHistogram");
+    }
+
     /// Clears the values of the histogram
     pub fn clear(&mut self) {
         self.histo.reset();
@@ -163,6 +319,218 @@ impl HdrHistogram {
     pub fn p9999(&self) -> u64 {
         self.histo.value_at_quantile(0.9999)
     }
+
+    /// Iterates over the buckets holding recorded values, in increasing
+    /// order.
+    ///
+    /// Summing the counts of buckets with the same value across histograms
+    /// with the same bound gives the exact buckets of the merged histogram.
+    ///
+    /// ```rust
+    /// use metered::hdr_histogram::{HdrBucket, HdrHistogram};
+    ///
+    /// let mut histogram = HdrHistogram::with_bound(1000);
+    /// histogram.record(5);
+    /// histogram.record(5);
+    /// histogram.record(10);
+    ///
+    /// let buckets: Vec<_> = histogram.buckets().collect();
+    /// assert_eq!(
+    ///     buckets,
+    ///     [HdrBucket { value: 5, count: 2 }, HdrBucket { value: 10, count: 1 }]
+    /// );
+    /// ```
+    pub fn buckets(&self) -> impl Iterator<Item = HdrBucket> + '_ {
+        self.histo.iter_recorded().map(|bucket| HdrBucket {
+            value: bucket.value_iterated_to(),
+            count: bucket.count_at_value(),
+        })
+    }
+
+    /// Iterates over quantiles of the recorded values, in increasing order,
+    /// e.g. to dump the full distribution of values.
+    ///
+    /// Quantiles get closer to 1 exponentially: starting from the lowest
+    /// recorded value at quantile 0, `ticks_per_half_distance` quantiles are
+    /// reported up to 0.5, as many up to 0.75, and so on until the highest
+    /// recorded value, at quantile 1.
+    ///
+    /// ```rust
+    /// use metered::hdr_histogram::HdrHistogram;
+    ///
+    /// let mut histogram = HdrHistogram::with_bound(1000);
+    /// for value in 1..=100 {
+    ///     histogram.record(value);
+    /// }
+    ///
+    /// let quantiles: Vec<_> = histogram.quantiles(2).collect();
+    /// assert_eq!((quantiles[1].quantile, quantiles[1].value), (0.25, 25));
+    /// assert_eq!((quantiles[2].quantile, quantiles[2].value), (0.5, 50));
+    ///
+    /// let last = quantiles.last().unwrap();
+    /// assert_eq!((last.quantile, last.value), (1.0, 100));
+    /// assert_eq!(quantiles.iter().map(|q| q.count).sum::<u64>(), 100);
+    /// ```
+    pub fn quantiles(
+        &self,
+        ticks_per_half_distance: u32,
+    ) -> impl Iterator<Item = HdrQuantile> + '_ {
+        self.histo
+            .iter_quantiles(ticks_per_half_distance)
+            .map(|step| HdrQuantile {
+                quantile: step.quantile_iterated_to(),
+                value: step.value_iterated_to(),
+                count: step.count_since_last_iteration(),
+            })
+    }
+}
+
+/// A quantile of the recorded values of an [`HdrHistogram`].
+///
+/// See [`HdrHistogram::quantiles`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct HdrQuantile {
+    /// The quantile, in `[0, 1]`
+    pub quantile: f64,
+    /// The highest value equivalent to the value at the quantile, within the
+    /// histogram's precision
+    pub value: u64,
+    /// The number of values recorded since the previous quantile, up to this
+    /// one
+    pub count: u64,
+}
+
+/// A bucket of recorded values of an [`HdrHistogram`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct HdrBucket {
+    /// The highest value equivalent to the values of the bucket, within the
+    /// histogram's precision
+    pub value: u64,
+    /// The number of values recorded in the bucket
+    pub count: u64,
+}
+
+/// A thread-safe HdrHistogram serializing its recorded buckets, so that
+/// downstream systems can re-aggregate exact histograms across instances.
+///
+/// With `SUMMARY` (the default), buckets are serialized under a `buckets` key
+/// in addition to the summary statistics of [`HdrHistogram`]. Without it, only
+/// the number of samples and the buckets are serialized:
+///
+/// ```rust
+/// use metered::{hdr_histogram::AtomicHdrBuckets, ResponseTime};
+///
+/// // Response times serialized as `samples` and `buckets` only
+/// let response_time: ResponseTime<AtomicHdrBuckets<false>> = ResponseTime::default();
+/// ```
+///
+/// See [`HdrHistogram::buckets`].
+pub struct AtomicHdrBuckets<const SUMMARY: bool = true> {
+    inner: Mutex<HdrHistogram>,
+}
+
+impl<const SUMMARY: bool> AtomicHdrBuckets<SUMMARY> {
+    /// Returns a cloned snapshot of the inner histogram.
+    pub fn histogram(&self) -> HdrHistogram {
+        self.inner.lock().clone()
+    }
+
+    /// Swaps in an empty histogram, and returns the values recorded so far.
+    ///
+    /// The empty histogram is allocated outside the lock, so that recording
+    /// threads only wait for the swap rather than for all buckets to be
+    /// zeroed. Recording still takes the lock, so a value being recorded
+    /// during the swap is in either histogram, never lost: there is no
+    /// lock-free recording whose completion the swap would wait for.
+    pub fn take(&self) -> HdrHistogram {
+        take_histogram(&self.inner)
+    }
+}
+
+impl<const SUMMARY: bool> SnapshotHistogram for AtomicHdrBuckets<SUMMARY> {
+    type Snapshot = HdrHistogram;
+
+    fn snapshot_histogram(&self) -> HdrHistogram {
+        self.histogram()
+    }
+}
+
+impl<const SUMMARY: bool> Histogram for AtomicHdrBuckets<SUMMARY> {
+    fn with_bound(max_bound: u64) -> Self {
+        let inner = Mutex::new(HdrHistogram::with_bound(max_bound));
+        AtomicHdrBuckets { inner }
+    }
+
+    fn with_precision(max_bound: u64, sig_figs: u8) -> Self {
+        let inner = Mutex::new(HdrHistogram::with_precision(max_bound, sig_figs));
+        AtomicHdrBuckets { inner }
+    }
+
+    #[inline]
+    fn record(&self, value: u64) {
+        lock_histogram!(self.inner).record(value);
+    }
+}
+
+impl<const SUMMARY: bool> Clear for AtomicHdrBuckets<SUMMARY> {
+    /// Swaps in an empty histogram rather than resetting it in place, see
+    /// [`take`](Self::take)
+    fn clear(&self) {
+        drop(self.take());
+    }
+}
+
+impl<const SUMMARY: bool> Clearable for AtomicHdrBuckets<SUMMARY> {
+    fn is_cleared(&self) -> bool {
+        self.inner.lock().is_empty()
+    }
+}
+
+impl<const SUMMARY: bool> Merge for AtomicHdrBuckets<SUMMARY> {
+    fn merge_from(&self, other: &Self) {
+        // Clone first, in case other is self
+        let other = other.histogram();
+        self.inner.lock().add(&other);
+    }
+}
+
+impl<const SUMMARY: bool> From<&AtomicHdrBuckets<SUMMARY>> for HistogramSnapshot {
+    fn from(histogram: &AtomicHdrBuckets<SUMMARY>) -> Self {
+        HistogramSnapshot::from(&*histogram.inner.lock())
+    }
+}
+
+impl<const SUMMARY: bool> Serialize for AtomicHdrBuckets<SUMMARY> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        internal_cost!(serialization, {
+            if clear::is_clearing_serialization() {
+                self.take().serialize_entries(serializer, SUMMARY, true)
+            } else {
+                self.inner
+                    .lock()
+                    .serialize_entries(serializer, SUMMARY, true)
+            }
+        })
+    }
+}
+
+impl<const SUMMARY: bool> Debug for AtomicHdrBuckets<SUMMARY> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let histo = self.inner.lock();
+        write!(f, "AtomicHdrBuckets {{ {:?} }}", &*histo)
+    }
+}
+
+impl From<&HdrHistogram> for HistogramSnapshot {
+    fn from(histogram: &HdrHistogram) -> Self {
+        let hdr = &histogram.histo;
+        HistogramSnapshot::new(hdr.len(), hdr.min(), hdr.max(), hdr.mean(), |q| {
+            hdr.value_at_quantile(q)
+        })
+    }
 }
 
 impl Serialize for HdrHistogram {
@@ -170,16 +538,22 @@ impl Serialize for HdrHistogram {
     where
         S: Serializer,
     {
-        let hdr = &self.histo;
+        self.serialize_entries(serializer, true, false)
+    }
+}
 
-        /// A percentile of this histogram - for supporting serializers this
-        /// will ignore the key (such as `90%ile`) and instead add a
-        /// dimension to the metrics (such as `quantile=0.9`).
-        macro_rules! ile {
-            ($e:expr) => {
-                &MetricAlias(concat!("!|quantile=", $e), hdr.value_at_quantile($e))
-            };
-        }
+impl HdrHistogram {
+    /// Serializes the summary statistics and/or the recorded buckets as a map
+    fn serialize_entries<S>(
+        &self,
+        serializer: S,
+        summary: bool,
+        buckets: bool,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let hdr = &self.histo;
 
         /// A 'qualified' metric name - for supporting serializers this will
         /// prepend the metric name to this key, outputting
@@ -192,36 +566,29 @@ impl Serialize for HdrHistogram {
 
         use serde::ser::SerializeMap;
 
-        let mut tup = serializer.serialize_map(Some(10))?;
+        let summary_len = if summary {
+            4 + serialization::quantile_count()
+        } else {
+            0
+        };
+        let len = 1 + summary_len + if buckets { 1 } else { 0 };
+        let mut tup = serializer.serialize_map(Some(len))?;
         tup.serialize_entry("samples", qual!(hdr.len()))?;
-        tup.serialize_entry("min", qual!(hdr.min()))?;
-        tup.serialize_entry("max", qual!(hdr.max()))?;
-        tup.serialize_entry("mean", qual!(hdr.mean()))?;
-        tup.serialize_entry("stdev", qual!(hdr.stdev()))?;
-        tup.serialize_entry("90%ile", ile!(0.9))?;
-        tup.serialize_entry("95%ile", ile!(0.95))?;
-        tup.serialize_entry("99%ile", ile!(0.99))?;
-        tup.serialize_entry("99.9%ile", ile!(0.999))?;
-        tup.serialize_entry("99.99%ile", ile!(0.9999))?;
+        if summary {
+            tup.serialize_entry("min", qual!(hdr.min()))?;
+            tup.serialize_entry("max", qual!(hdr.max()))?;
+            tup.serialize_entry("mean", qual!(hdr.mean()))?;
+            tup.serialize_entry("stdev", qual!(hdr.stdev()))?;
+            serialization::serialize_quantiles(&mut tup, |q| hdr.value_at_quantile(q))?;
+        }
+        if buckets {
+            let buckets: Vec<HdrBucket> = self.buckets().collect();
+            tup.serialize_entry("buckets", &buckets)?;
+        }
         tup.end()
     }
 }
 
-/// This is a mocked 'newtype' (eg. `A(u64)`) that instead allows us to
-/// define our own type name that doesn't have to abide by Rust's constraints
-/// on type names. This allows us to do some manipulation of our metrics,
-/// allowing us to add dimensionality to our metrics via key=value pairs, or
-/// key manipulation on serializers that support it.
-struct MetricAlias<T: Serialize>(&'static str, T);
-impl<T: Serialize> Serialize for MetricAlias<T> {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        serializer.serialize_newtype_struct(self.0, &self.1)
-    }
-}
-
 impl Debug for HdrHistogram {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let hdr = &self.histo;
@@ -252,6 +619,10 @@ impl Histogram for RefCell<HdrHistogram> {
         RefCell::new(HdrHistogram::with_bound(max_value))
     }
 
+    fn with_precision(max_value: u64, sig_figs: u8) -> Self {
+        RefCell::new(HdrHistogram::with_precision(max_value, sig_figs))
+    }
+
     fn record(&self, value: u64) {
         self.borrow_mut().record(value);
     }
@@ -262,3 +633,83 @@ impl Clear for RefCell<HdrHistogram> {
         self.borrow_mut().clear();
     }
 }
+
+impl Clearable for RefCell<HdrHistogram> {
+    fn is_cleared(&self) -> bool {
+        self.borrow().is_empty()
+    }
+}
+
+impl From<&RefCell<HdrHistogram>> for HistogramSnapshot {
+    fn from(histogram: &RefCell<HdrHistogram>) -> Self {
+        HistogramSnapshot::from(&*histogram.borrow())
+    }
+}
+
+impl SnapshotHistogram for RefCell<HdrHistogram> {
+    type Snapshot = HdrHistogram;
+
+    fn snapshot_histogram(&self) -> HdrHistogram {
+        self.borrow().clone()
+    }
+}
+
+impl Merge for RefCell<HdrHistogram> {
+    fn merge_from(&self, other: &Self) {
+        let other = other.borrow().clone();
+        self.borrow_mut().add(&other);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saturating_arithmetic() {
+        let mut small = HdrHistogram::with_bound(100);
+        let mut large = HdrHistogram::with_bound(10_000);
+        small.record(10);
+        large.record(10);
+        large.record(10);
+        large.record(5_000);
+
+        small.add(&large);
+        assert_eq!(small.len(), 4);
+        assert_eq!(small.max(), 100);
+
+        small.subtract(&large);
+        assert_eq!(small.len(), 1);
+        small.subtract(&large);
+        assert!(small.is_empty());
+    }
+
+    #[test]
+    fn takes_values_without_losing_any() {
+        let histogram = std::sync::Arc::new(AtomicHdrHistogram::with_precision(1_000, 3));
+        let recorders: Vec<_> = (0..4)
+            .map(|_| {
+                let histogram = std::sync::Arc::clone(&histogram);
+                std::thread::spawn(move || {
+                    for value in 0..10_000 {
+                        histogram.record(value % 1_000);
+                    }
+                })
+            })
+            .collect();
+
+        let mut taken = 0;
+        while recorders.iter().any(|recorder| !recorder.is_finished()) {
+            let values = histogram.take();
+            assert_eq!((values.bound(), values.precision()), (1_000, 3));
+            taken += values.len();
+        }
+        recorders
+            .into_iter()
+            .for_each(|recorder| recorder.join().unwrap());
+        taken += histogram.take().len();
+
+        assert!(histogram.is_cleared());
+        assert_eq!(taken, 40_000);
+    }
+}
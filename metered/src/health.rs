@@ -0,0 +1,239 @@
+//! A module providing health reports derived from metric registries.
+
+use serde::Serialize;
+use std::fmt;
+
+/// The health status of a check or of a whole report.
+///
+/// Statuses are ordered by severity, so that the status of a report is the
+/// maximum of the statuses of its checks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// Everything is fine
+    #[default]
+    Healthy,
+    /// The service works, but some checks crossed a warning threshold
+    Degraded,
+    /// Some checks crossed a critical threshold
+    Unhealthy,
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HealthStatus::Healthy => "healthy",
+            HealthStatus::Degraded => "degraded",
+            HealthStatus::Unhealthy => "unhealthy",
+        })
+    }
+}
+
+/// The result of a single health check.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HealthCheck {
+    /// The name of the check
+    pub name: String,
+    /// The status of the check
+    pub status: HealthStatus,
+    /// A human-readable description of the checked value
+    pub message: String,
+}
+
+/// A trait for registries that can evaluate their own health rules.
+///
+/// Implement it on a metric registry, adding one check per rule to the report:
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{
+///     health::{CheckHealth, HealthReport, HealthStatus},
+///     metered, ErrorCount, HitCount, ResponseTime,
+/// };
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics)]
+/// impl Service {
+///     #[measure([HitCount, ErrorCount, ResponseTime])]
+///     pub fn call(&self, fail: bool) -> Result<(), ()> {
+///         if fail { Err(()) } else { Ok(()) }
+///     }
+/// }
+///
+/// impl CheckHealth for ServiceMetrics {
+///     fn check_health(&self, report: &mut HealthReport) {
+///         let call = &self.call;
+///         report.error_ratio("call errors", call.error_count.get(), call.hit_count.get(), 0.1, 0.5);
+///         report.upper_bound("call p99", call.response_time.histogram().p99(), 500, 2_000);
+///     }
+/// }
+///
+/// let service = Service::default();
+/// for fail in &[false, false, false, true] {
+///     let _ = service.call(*fail);
+/// }
+///
+/// let report = HealthReport::from_registry(&service.metrics);
+/// assert_eq!(report.status(), HealthStatus::Degraded);
+/// assert_eq!(report.checks()[0].status, HealthStatus::Degraded);
+/// assert_eq!(report.checks()[1].status, HealthStatus::Healthy);
+/// ```
+pub trait CheckHealth {
+    /// Evaluates health rules against the current metric values, adding
+    /// checks to the report.
+    fn check_health(&self, report: &mut HealthReport);
+}
+
+impl<T: CheckHealth + ?Sized> CheckHealth for &T {
+    fn check_health(&self, report: &mut HealthReport) {
+        (**self).check_health(report);
+    }
+}
+
+/// A structured health report, suitable for `/healthz` endpoints.
+///
+/// The report is `Healthy` until a check reports a worse status. It
+/// serializes as its overall status and the list of its checks.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct HealthReport {
+    status: HealthStatus,
+    checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    /// Creates an empty, healthy report.
+    pub fn new() -> Self {
+        HealthReport::default()
+    }
+
+    /// Creates a report by evaluating a registry's health rules.
+    pub fn from_registry<R: CheckHealth + ?Sized>(registry: &R) -> Self {
+        let mut report = HealthReport::new();
+        registry.check_health(&mut report);
+        report
+    }
+
+    /// Get the overall status, the worst status of all checks.
+    pub fn status(&self) -> HealthStatus {
+        self.status
+    }
+
+    /// Check if the overall status is `Healthy`.
+    pub fn is_healthy(&self) -> bool {
+        self.status == HealthStatus::Healthy
+    }
+
+    /// Get the checks, in the order they were added.
+    pub fn checks(&self) -> &[HealthCheck] {
+        &self.checks
+    }
+
+    /// Adds a check with an explicit status.
+    pub fn check(
+        &mut self,
+        name: impl Into<String>,
+        status: HealthStatus,
+        message: impl Into<String>,
+    ) -> &mut Self {
+        self.status = self.status.max(status);
+        self.checks.push(HealthCheck {
+            name: name.into(),
+            status,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Adds a check on the ratio of `errors` over `total` calls, which is
+    /// degraded above the `degraded` ratio and unhealthy above the `unhealthy`
+    /// ratio.
+    ///
+    /// A ratio of 0 is reported when there was no call.
+    pub fn error_ratio(
+        &mut self,
+        name: impl Into<String>,
+        errors: u64,
+        total: u64,
+        degraded: f64,
+        unhealthy: f64,
+    ) -> &mut Self {
+        let ratio = if total == 0 {
+            0.0
+        } else {
+            errors as f64 / total as f64
+        };
+        let status = if ratio > unhealthy {
+            HealthStatus::Unhealthy
+        } else if ratio > degraded {
+            HealthStatus::Degraded
+        } else {
+            HealthStatus::Healthy
+        };
+        self.check(
+            name,
+            status,
+            format!("error ratio {} ({}/{})", ratio, errors, total),
+        )
+    }
+
+    /// Adds a check on a value, which is degraded above `degraded` and
+    /// unhealthy above `unhealthy`.
+    ///
+    /// This covers latency bounds (e.g. a p99) as well as staleness, checking
+    /// the time elapsed since a metric was last updated.
+    pub fn upper_bound(
+        &mut self,
+        name: impl Into<String>,
+        value: u64,
+        degraded: u64,
+        unhealthy: u64,
+    ) -> &mut Self {
+        let status = if value > unhealthy {
+            HealthStatus::Unhealthy
+        } else if value > degraded {
+            HealthStatus::Degraded
+        } else {
+            HealthStatus::Healthy
+        };
+        self.check(
+            name,
+            status,
+            format!(
+                "value {} (degraded above {}, unhealthy above {})",
+                value, degraded, unhealthy
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_worst_status_wins() {
+        let mut report = HealthReport::new();
+        assert!(report.is_healthy());
+
+        report
+            .upper_bound("staleness", 30, 60, 300)
+            .error_ratio("errors", 0, 0, 0.01, 0.1);
+        assert!(report.is_healthy());
+
+        report.upper_bound("p99", 1_000, 200, 500);
+        report.error_ratio("errors", 2, 100, 0.01, 0.1);
+        assert_eq!(report.status(), HealthStatus::Unhealthy);
+        assert_eq!(report.checks().len(), 4);
+        assert_eq!(report.checks()[3].status, HealthStatus::Degraded);
+    }
+}
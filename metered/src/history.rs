@@ -0,0 +1,288 @@
+//! A module retaining the last snapshots of a registry in memory, to serve
+//! sparklines or compute short-term trends within the process.
+//!
+//! A [`History`] keeps up to a fixed number of timestamped
+//! [`Snapshot`](crate::snapshot::Snapshot)s, the oldest ones being dropped
+//! first. Snapshots are recorded on demand, or periodically by a thread:
+//!
+#![cfg_attr(feature = "disabled", doc = "```ignore")]
+#![cfg_attr(not(feature = "disabled"), doc = "```rust")]
+//! use metered::{history::History, metered, HitCount};
+//! use std::{sync::Arc, time::Duration};
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Service {
+//!     metrics: ServiceMetrics,
+//! }
+//!
+//! #[metered(registry = ServiceMetrics)]
+//! impl Service {
+//!     #[measure(HitCount)]
+//!     pub fn call(&self) {}
+//! }
+//!
+//! let service = Arc::new(Service::default());
+//! let history = Arc::new(History::new(60));
+//! let recording = history.spawn(Arc::clone(&service), |s| &s.metrics, Duration::from_secs(1));
+//!
+//! service.call();
+//! history.record(&service.metrics).unwrap();
+//!
+//! let hits: Vec<f64> = history.series("call.hit_count").into_iter().map(|(_, v)| v).collect();
+//! assert_eq!(hits.last(), Some(&1.0));
+//! recording.stop();
+//! ```
+
+use crate::{clear::Clear, flatten, metadata::DescribeMetrics, snapshot::Snapshot, sync::Mutex};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    fmt,
+    marker::PhantomData,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+/// A snapshot of a registry recorded in a [`History`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recorded {
+    /// When the snapshot was taken
+    pub at: SystemTime,
+    /// The values of the registry, not normalized
+    pub snapshot: Snapshot,
+}
+
+/// The last snapshots of registries of type `R`, see the
+/// [module documentation](crate::history).
+pub struct History<R: ?Sized> {
+    capacity: usize,
+    recorded: Mutex<VecDeque<Recorded>>,
+    registry: PhantomData<fn(&R)>,
+}
+
+impl<R: ?Sized> History<R> {
+    /// Creates a history retaining up to `capacity` snapshots, at least one
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        History {
+            capacity,
+            recorded: Mutex::new(VecDeque::with_capacity(capacity)),
+            registry: PhantomData,
+        }
+    }
+
+    /// Get the number of snapshots retained at most
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get the number of snapshots retained
+    pub fn len(&self) -> usize {
+        self.recorded.lock().len()
+    }
+
+    /// Returns true if no snapshot was recorded
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get a copy of the retained snapshots, from oldest to newest
+    pub fn recorded(&self) -> Vec<Recorded> {
+        self.recorded.lock().iter().cloned().collect()
+    }
+
+    /// Get the most recent snapshot, if any
+    pub fn latest(&self) -> Option<Recorded> {
+        self.recorded.lock().back().cloned()
+    }
+
+    /// Get the values of a name, such as `call.hit_count`, from oldest to
+    /// newest, skipping snapshots without it
+    pub fn series(&self, name: &str) -> Vec<(SystemTime, f64)> {
+        self.recorded
+            .lock()
+            .iter()
+            .filter_map(|recorded| Some((recorded.at, recorded.snapshot.get(name)?)))
+            .collect()
+    }
+
+    /// Get how much the value of a name increased per second between the
+    /// oldest and newest snapshots having it, e.g. the recent rate of a
+    /// counter, or `None` without two snapshots taken at different times
+    pub fn rate(&self, name: &str) -> Option<f64> {
+        let series = self.series(name);
+        let (first, last) = (series.first()?, series.last()?);
+        let elapsed = last.0.duration_since(first.0).ok()?.as_secs_f64();
+        if elapsed > 0.0 {
+            Some((last.1 - first.1) / elapsed)
+        } else {
+            None
+        }
+    }
+
+    /// Records a snapshot taken at a given time, dropping the oldest one if
+    /// the history is full
+    pub fn push(&self, at: SystemTime, snapshot: Snapshot) {
+        let mut recorded = self.recorded.lock();
+        if recorded.len() == self.capacity {
+            recorded.pop_front();
+        }
+        recorded.push_back(Recorded { at, snapshot });
+    }
+}
+
+impl<R: Serialize + DescribeMetrics + ?Sized> History<R> {
+    /// Records a snapshot of a registry, taken now
+    pub fn record(&self, registry: &R) -> Result<(), flatten::Error> {
+        let snapshot = Snapshot::exact(registry)?;
+        self.push(SystemTime::now(), snapshot);
+        Ok(())
+    }
+}
+
+impl<R: Serialize + DescribeMetrics + 'static> History<R> {
+    /// Spawns a thread recording a snapshot of a registry owned by a shared
+    /// value at every interval, until the returned handle is stopped or
+    /// dropped
+    pub fn spawn<T>(
+        self: &Arc<Self>,
+        owner: Arc<T>,
+        registry: fn(&T) -> &R,
+        interval: Duration,
+    ) -> HistoryHandle
+    where
+        T: Send + Sync + 'static,
+    {
+        let history = Arc::clone(self);
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("metered-history".to_string())
+            .spawn(move || loop {
+                // Registries failing to serialize are retried at the next
+                // interval
+                let _ = history.record(registry(&owner));
+
+                match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => break,
+                }
+            })
+            .expect("could not spawn the history thread");
+
+        HistoryHandle {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl<R: ?Sized> Clear for History<R> {
+    fn clear(&self) {
+        self.recorded.lock().clear();
+    }
+}
+
+impl<R: ?Sized> fmt::Debug for History<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("History")
+            .field("capacity", &self.capacity)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// A handle on a thread recording a [`History`], stopping it when dropped.
+pub struct HistoryHandle {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HistoryHandle {
+    /// Stops recording, waiting for the thread to exit
+    pub fn stop(self) {
+        drop(self)
+    }
+}
+
+impl Drop for HistoryHandle {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl fmt::Debug for HistoryHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HistoryHandle").finish()
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{
+        measure,
+        metadata::{MetricDescription, MetricMetadata, MetricType, Unit},
+        HitCount,
+    };
+
+    #[derive(Default, Serialize)]
+    struct Registry {
+        hit_count: HitCount,
+    }
+
+    impl DescribeMetrics for Registry {
+        fn describe_metrics() -> Vec<MetricDescription> {
+            vec![MetricDescription {
+                path: vec!["hit_count"],
+                metadata: MetricMetadata::new(MetricType::Counter, Unit::None),
+            }]
+        }
+    }
+
+    #[test]
+    fn retains_last_snapshots() {
+        let registry = Registry::default();
+        let history = History::<Registry>::new(3);
+        let start = SystemTime::UNIX_EPOCH;
+
+        for i in 0..5 {
+            measure!(&registry.hit_count, {});
+            let snapshot = Snapshot::exact(&registry).unwrap();
+            history.push(start + Duration::from_secs(i * 10), snapshot);
+        }
+
+        assert_eq!(history.len(), 3);
+        let series = history.series("hit_count");
+        assert_eq!(
+            series.iter().map(|(_, v)| *v).collect::<Vec<_>>(),
+            [3.0, 4.0, 5.0]
+        );
+        assert_eq!(series[0].0, start + Duration::from_secs(20));
+        assert_eq!(history.rate("hit_count"), Some(0.1));
+        assert_eq!(history.rate("missing"), None);
+        assert_eq!(
+            history.latest().unwrap().snapshot.get("hit_count"),
+            Some(5.0)
+        );
+
+        history.clear();
+        assert!(history.is_empty());
+        assert_eq!(history.rate("hit_count"), None);
+    }
+
+    #[test]
+    fn records_periodically() {
+        let registry = Arc::new(Registry::default());
+        let history = Arc::new(History::new(10));
+        let handle = history.spawn(registry, |r| r, Duration::from_secs(3600));
+        handle.stop();
+        assert_eq!(history.len(), 1);
+    }
+}
@@ -0,0 +1,159 @@
+//! A module providing the `InfoMetric` metric, exposing static information
+//! such as the version of a service as labels.
+
+use crate::{
+    clear::Clear,
+    labels::LabelSet,
+    metadata::{Describe, MetricMetadata, MetricType, Unit},
+};
+use serde::{Serialize, Serializer};
+
+/// A metric with the constant value 1, whose labels hold static information
+/// about a service, in the conventional `build_info` style.
+///
+/// Joining on its labels, dashboards and alerts can tell which version of a
+/// service produced the other metrics. The [`build_info!`](crate::build_info)
+/// macro builds one from the version of the crate it is called from:
+///
+/// ```rust
+/// use metered::{build_info, flatten, info::InfoMetric, metered, HitCount};
+/// use serde::Serialize;
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics)]
+/// impl Service {
+///     #[measure(HitCount)]
+///     pub fn call(&self) {}
+/// }
+///
+/// #[derive(Serialize)]
+/// struct AppMetrics<'a> {
+///     build_info: &'a InfoMetric,
+///     service: &'a ServiceMetrics,
+/// }
+///
+/// let build_info = build_info!(component = "checkout");
+/// let service = Service::default();
+/// let samples = flatten::to_samples(&AppMetrics {
+///     build_info: &build_info,
+///     service: &service.metrics,
+/// })
+/// .unwrap();
+///
+/// assert_eq!(samples[0].name, "build_info");
+/// assert_eq!(samples[0].value, 1.0);
+/// assert_eq!(build_info.labels().get("version"), Some(env!("CARGO_PKG_VERSION")));
+/// assert_eq!(build_info.labels().get("component"), Some("checkout"));
+/// ```
+///
+/// Like [`ConstLabels`](crate::labels::ConstLabels), the labels are attached
+/// with a newtype struct name, for serializers supporting it like
+/// `serde_prometheus`, while other serializers only see the value 1. Label
+/// values should not contain `,` or `|`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InfoMetric {
+    labels: LabelSet,
+}
+
+impl InfoMetric {
+    /// Creates an info metric from key/value pairs
+    pub fn new(labels: Vec<(&'static str, String)>) -> Self {
+        InfoMetric {
+            labels: LabelSet::new(labels),
+        }
+    }
+
+    /// Get the labels of the metric
+    pub fn labels(&self) -> &LabelSet {
+        &self.labels
+    }
+}
+
+impl Clear for InfoMetric {
+    /// Does nothing, as the information is constant
+    fn clear(&self) {}
+}
+
+impl Serialize for InfoMetric {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.labels.serialize_labeled(&1u64, serializer)
+    }
+}
+
+impl Describe for InfoMetric {
+    fn metadata() -> MetricMetadata {
+        MetricMetadata::new(MetricType::Gauge, Unit::None)
+    }
+}
+
+/// Builds an [`InfoMetric`](crate::info::InfoMetric) describing the crate it is
+/// called from.
+///
+/// The labels are:
+/// - `version`, the version of the crate, from `CARGO_PKG_VERSION`,
+/// - `git_sha`, from a `GIT_SHA` environment variable at build time,
+/// - `rustc`, from a `RUSTC_VERSION` environment variable at build time,
+///
+/// followed by the `key = "value"` pairs given to the macro. The build time
+/// variables are usually set by a build script, with
+/// `println!("cargo:rustc-env=GIT_SHA={}", sha)`, and are `unknown` when not
+/// set.
+///
+/// ```rust
+/// let build_info = metered::build_info!(component = "cache", region = "eu-west-1");
+/// assert_eq!(build_info.labels().get("region"), Some("eu-west-1"));
+/// ```
+#[macro_export]
+macro_rules! build_info {
+    ($($key:ident = $value:expr),* $(,)?) => {
+        $crate::info::InfoMetric::new(vec![
+            ("version", env!("CARGO_PKG_VERSION").to_string()),
+            ("git_sha", option_env!("GIT_SHA").unwrap_or("unknown").to_string()),
+            ("rustc", option_env!("RUSTC_VERSION").unwrap_or("unknown").to_string()),
+            $((stringify!($key), $value.to_string()),)*
+        ])
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatten::to_samples;
+
+    #[test]
+    fn serializes_labels() {
+        let info = InfoMetric::new(vec![
+            ("version", "1.2.3".to_string()),
+            ("git_sha", "abc123".to_string()),
+        ]);
+        let samples = to_samples(&info).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].value, 1.0);
+        assert_eq!(
+            samples[0].labels,
+            [
+                ("version".to_string(), "1.2.3".to_string()),
+                ("git_sha".to_string(), "abc123".to_string()),
+            ]
+        );
+        assert_eq!(serde_json::to_string(&info).unwrap(), "1");
+    }
+
+    #[test]
+    fn builds_from_the_calling_crate() {
+        let info = build_info!(component = "cache");
+        assert_eq!(
+            info.labels().get("version"),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+        assert!(info.labels().get("git_sha").is_some());
+        assert_eq!(info.labels().get("component"), Some("cache"));
+    }
+}
@@ -2,12 +2,14 @@
 //! Counters on various unsized integers.
 
 use crate::{
-    atomic::AtomicInt,
+    atomic::{AtomicInt, MemoryOrdering, OrderedAtomicInt, Relaxed},
     clear::{Clear, Clearable},
+    merge::Merge,
     metric::Counter,
     num_wrapper::NumWrapper,
 };
-use std::cell::Cell;
+use serde::Serialize;
+use std::{cell::Cell, convert::TryFrom};
 
 macro_rules! impl_counter_for {
     ($int:path) => {
@@ -16,6 +18,18 @@ macro_rules! impl_counter_for {
                 let v = NumWrapper::<$int>::wrap(count);
                 self.set(self.get().wrapping_add(v));
             }
+
+            fn value(&self) -> u64 {
+                u64::try_from(self.get()).unwrap_or(u64::MAX)
+            }
+
+            fn take(&self) -> u64 {
+                u64::try_from(self.replace(0)).unwrap_or(u64::MAX)
+            }
+
+            fn take_counter(&self) -> Self {
+                Cell::new(self.replace(0))
+            }
         }
 
         impl Clear for Cell<$int> {
@@ -30,22 +44,50 @@ macro_rules! impl_counter_for {
             }
         }
 
-        impl Counter for AtomicInt<$int> {
+        impl Merge for Cell<$int> {
+            fn merge_from(&self, other: &Self) {
+                self.set(self.get().wrapping_add(other.get()));
+            }
+        }
+
+        impl_counter_for!($int, [] AtomicInt<$int>, Relaxed);
+        impl_counter_for!($int, [O: MemoryOrdering,] OrderedAtomicInt<$int, O>, O);
+    };
+    ($int:path, [$($generics:tt)*] $ty:ty, $ordering:ty) => {
+        impl<$($generics)*> Counter for $ty {
             fn incr_by(&self, count: usize) {
                 let v = NumWrapper::<$int>::wrap(count);
-                AtomicInt::<$int>::incr_by(&self, v);
+                <$ty>::incr_by(&self, v);
+            }
+
+            fn value(&self) -> u64 {
+                u64::try_from(<$ty>::get(&self)).unwrap_or(u64::MAX)
+            }
+
+            fn take(&self) -> u64 {
+                u64::try_from(self.inner.swap(0, <$ordering>::RMW)).unwrap_or(u64::MAX)
+            }
+
+            fn take_counter(&self) -> Self {
+                <$ty>::new(self.inner.swap(0, <$ordering>::RMW))
             }
         }
 
-        impl Clear for AtomicInt<$int> {
+        impl<$($generics)*> Clear for $ty {
             fn clear(&self) {
-                AtomicInt::<$int>::set(&self, 0);
+                <$ty>::set(&self, 0);
             }
         }
 
-        impl Clearable for AtomicInt<$int> {
+        impl<$($generics)*> Clearable for $ty {
             fn is_cleared(&self) -> bool {
-                AtomicInt::<$int>::get(&self) == 0
+                <$ty>::get(&self) == 0
+            }
+        }
+
+        impl<$($generics)*> Merge for $ty {
+            fn merge_from(&self, other: &Self) {
+                <$ty>::incr_by(&self, other.get());
             }
         }
     };
@@ -56,3 +98,304 @@ impl_counter_for!(u16);
 impl_counter_for!(u32);
 impl_counter_for!(u64);
 impl_counter_for!(u128);
+
+/// A counter backend clamping at the maximum value of its integer instead of
+/// wrapping, for audit-style counters where a silent wrap to ~0 would be worse
+/// than a stuck count.
+///
+/// It wraps a `Cell` or an [`AtomicInt`] over an unsigned integer, and is
+/// selected through the counter type of a metric:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{atomic::AtomicInt, int_counter::SaturatingCounter, measure, HitCount};
+///
+/// let hit_count: HitCount<SaturatingCounter<AtomicInt<u8>>> = HitCount::default();
+/// for _ in 0..300 {
+///     measure!(&hit_count, {});
+/// }
+/// assert_eq!(hit_count.get(), 255);
+/// ```
+#[derive(Clone, Default, Debug, Serialize)]
+pub struct SaturatingCounter<C = AtomicInt<u64>>(pub C);
+
+macro_rules! impl_saturating_counter_for {
+    ($int:path) => {
+        impl Counter for SaturatingCounter<Cell<$int>> {
+            fn incr_by(&self, count: usize) {
+                let v = <$int>::try_from(count).unwrap_or(<$int>::MAX);
+                self.0.set(self.0.get().saturating_add(v));
+            }
+
+            fn value(&self) -> u64 {
+                self.0.value()
+            }
+
+            fn take(&self) -> u64 {
+                Counter::take(&self.0)
+            }
+
+            fn take_counter(&self) -> Self {
+                SaturatingCounter(self.0.take_counter())
+            }
+        }
+
+        impl Merge for SaturatingCounter<Cell<$int>> {
+            fn merge_from(&self, other: &Self) {
+                self.0.set(self.0.get().saturating_add(other.0.get()));
+            }
+        }
+
+        impl_saturating_counter_for!($int, [] AtomicInt<$int>, Relaxed);
+        impl_saturating_counter_for!($int, [O: MemoryOrdering,] OrderedAtomicInt<$int, O>, O);
+    };
+    ($int:path, [$($generics:tt)*] $ty:ty, $ordering:ty) => {
+        impl<$($generics)*> Counter for SaturatingCounter<$ty> {
+            fn incr_by(&self, count: usize) {
+                let v = <$int>::try_from(count).unwrap_or(<$int>::MAX);
+                self.0.saturating_add(v);
+            }
+
+            fn value(&self) -> u64 {
+                self.0.value()
+            }
+
+            fn take(&self) -> u64 {
+                Counter::take(&self.0)
+            }
+
+            fn take_counter(&self) -> Self {
+                SaturatingCounter(self.0.take_counter())
+            }
+        }
+
+        impl<$($generics)*> Merge for SaturatingCounter<$ty> {
+            fn merge_from(&self, other: &Self) {
+                self.0.saturating_add(other.0.get());
+            }
+        }
+
+        impl<$($generics)*> $ty {
+            /// Adds `v` to self, clamping at the maximum value
+            fn saturating_add(&self, v: $int) {
+                // The closure never fails
+                let _ = self
+                    .inner
+                    .fetch_update(<$ordering>::RMW, <$ordering>::LOAD, |count| {
+                        Some(count.saturating_add(v))
+                    });
+            }
+        }
+    };
+}
+
+impl_saturating_counter_for!(u8);
+impl_saturating_counter_for!(u16);
+impl_saturating_counter_for!(u32);
+impl_saturating_counter_for!(u64);
+impl_saturating_counter_for!(u128);
+
+impl<C: Clear> Clear for SaturatingCounter<C> {
+    fn clear(&self) {
+        self.0.clear();
+    }
+}
+
+impl<C: Clearable> Clearable for SaturatingCounter<C> {
+    fn is_cleared(&self) -> bool {
+        self.0.is_cleared()
+    }
+}
+
+/// A counter backend counting how many times it wrapped, so that dashboards
+/// can tell a small counter wrapping apart from activity dropping.
+///
+/// It wraps a `Cell` or an [`AtomicInt`] over an unsigned integer, and records
+/// wraps to a second counter, `W`. It serializes both, as `count` and
+/// `overflows`:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{atomic::AtomicInt, int_counter::CheckedCounter, measure, HitCount};
+///
+/// let hit_count: HitCount<CheckedCounter<AtomicInt<u8>>> = HitCount::default();
+/// for _ in 0..300 {
+///     measure!(&hit_count, {});
+/// }
+/// assert_eq!(hit_count.get(), 44);
+/// assert_eq!(hit_count.overflows.get(), 1);
+/// assert_eq!(
+///     serde_json::to_string(&hit_count).unwrap(),
+///     r#"{"count":44,"overflows":1}"#
+/// );
+/// ```
+#[derive(Clone, Default, Debug, Serialize)]
+pub struct CheckedCounter<C = AtomicInt<u64>, W = AtomicInt<u64>> {
+    /// The wrapping count
+    pub count: C,
+    /// The number of times the count wrapped
+    pub overflows: W,
+}
+
+/// Get how many times adding `count` to `value` wraps an integer
+macro_rules! wraps {
+    ($int:path, $value:expr, $count:expr) => {{
+        let (_, overflow) = $value.overflowing_add(NumWrapper::<$int>::wrap($count));
+        // Counts larger than the integer's capacity wrap it several times
+        let full = ($count as u128).checked_shr(<$int>::BITS).unwrap_or(0);
+        u64::try_from(full).unwrap_or(u64::MAX) + u64::from(overflow)
+    }};
+}
+
+macro_rules! impl_checked_counter_for {
+    ($int:path) => {
+        impl<W: Counter> Counter for CheckedCounter<Cell<$int>, W> {
+            fn incr_by(&self, count: usize) {
+                let wraps = wraps!($int, self.count.get(), count);
+                self.count.incr_by(count);
+                if wraps > 0 {
+                    self.overflows.incr_by(wraps as usize);
+                }
+            }
+
+            fn value(&self) -> u64 {
+                self.count.value()
+            }
+
+            fn take(&self) -> u64 {
+                let count = Counter::take(&self.count);
+                self.overflows.take();
+                count
+            }
+
+            fn take_counter(&self) -> Self {
+                CheckedCounter {
+                    count: self.count.take_counter(),
+                    overflows: self.overflows.take_counter(),
+                }
+            }
+        }
+
+        impl<W: Counter + Merge> Merge for CheckedCounter<Cell<$int>, W> {
+            fn merge_from(&self, other: &Self) {
+                let (count, overflow) = self.count.get().overflowing_add(other.count.get());
+                self.overflows.merge_from(&other.overflows);
+                self.count.set(count);
+                if overflow {
+                    self.overflows.incr();
+                }
+            }
+        }
+
+        impl_checked_counter_for!($int, [] AtomicInt<$int>, Relaxed);
+        impl_checked_counter_for!($int, [O: MemoryOrdering,] OrderedAtomicInt<$int, O>, O);
+    };
+    ($int:path, [$($generics:tt)*] $ty:ty, $ordering:ty) => {
+        impl<$($generics)* W: Counter> Counter for CheckedCounter<$ty, W> {
+            fn incr_by(&self, count: usize) {
+                let v = NumWrapper::<$int>::wrap(count);
+                let previous = self.count.incr_by(v);
+                let wraps = wraps!($int, previous, count);
+                if wraps > 0 {
+                    self.overflows.incr_by(wraps as usize);
+                }
+            }
+
+            fn value(&self) -> u64 {
+                self.count.value()
+            }
+
+            fn take(&self) -> u64 {
+                let count = Counter::take(&self.count);
+                self.overflows.take();
+                count
+            }
+
+            fn take_counter(&self) -> Self {
+                CheckedCounter {
+                    count: self.count.take_counter(),
+                    overflows: self.overflows.take_counter(),
+                }
+            }
+        }
+
+        impl<$($generics)* W: Counter + Merge> Merge for CheckedCounter<$ty, W> {
+            fn merge_from(&self, other: &Self) {
+                let other_count = other.count.get();
+                self.overflows.merge_from(&other.overflows);
+                let previous = self.count.incr_by(other_count);
+                if previous.overflowing_add(other_count).1 {
+                    self.overflows.incr();
+                }
+            }
+        }
+    };
+}
+
+impl_checked_counter_for!(u8);
+impl_checked_counter_for!(u16);
+impl_checked_counter_for!(u32);
+impl_checked_counter_for!(u64);
+impl_checked_counter_for!(u128);
+
+impl<C: Clear, W: Clear> Clear for CheckedCounter<C, W> {
+    fn clear(&self) {
+        self.count.clear();
+        self.overflows.clear();
+    }
+}
+
+impl<C: Clearable, W: Clearable> Clearable for CheckedCounter<C, W> {
+    fn is_cleared(&self) -> bool {
+        self.count.is_cleared() && self.overflows.is_cleared()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saturating_counters_clamp() {
+        let counter: SaturatingCounter<Cell<u8>> = SaturatingCounter::default();
+        counter.incr_by(200);
+        counter.incr_by(usize::MAX);
+        assert_eq!(counter.value(), 255);
+        counter.merge_from(&counter);
+        assert_eq!(counter.value(), 255);
+        counter.clear();
+        assert!(counter.is_cleared());
+
+        let counter: SaturatingCounter<AtomicInt<u16>> = SaturatingCounter::default();
+        counter.incr_by(60_000);
+        counter.incr_by(60_000);
+        assert_eq!(counter.value(), u64::from(u16::MAX));
+        assert_eq!(serde_json::to_string(&counter).unwrap(), "65535");
+
+        // Wrapping counters wrap instead
+        let counter: Cell<u8> = Cell::default();
+        counter.incr_by(300);
+        assert_eq!(Counter::value(&counter), 44);
+    }
+
+    #[test]
+    fn checked_counters_count_overflows() {
+        let counter: CheckedCounter<Cell<u8>, Cell<u64>> = CheckedCounter::default();
+        counter.incr_by(255);
+        assert_eq!(counter.overflows.get(), 0);
+        counter.incr();
+        assert_eq!((counter.value(), counter.overflows.get()), (0, 1));
+        counter.incr_by(1_000);
+        assert_eq!((counter.value(), counter.overflows.get()), (232, 4));
+
+        counter.merge_from(&counter);
+        assert_eq!((counter.value(), counter.overflows.get()), (208, 9));
+        counter.clear();
+        assert!(counter.is_cleared());
+
+        let counter: CheckedCounter<AtomicInt<u64>> = CheckedCounter::default();
+        counter.count.set(u64::MAX);
+        counter.incr_by(2);
+        assert_eq!((counter.value(), counter.overflows.get()), (1, 1));
+    }
+}
@@ -1,8 +1,14 @@
 //! A module providing thread-safe and unsynchronized implementations for Gauges
-//! on various unsized integers.
+//! on various unsized and signed integers.
 
-use crate::{atomic::AtomicInt, metric::Gauge, num_wrapper::NumWrapper};
-use std::cell::Cell;
+use crate::{
+    atomic::{AtomicInt, MemoryOrdering, OrderedAtomicInt},
+    clear::{Clear, Clearable},
+    merge::Merge,
+    metric::Gauge,
+    num_wrapper::NumWrapper,
+};
+use std::{cell::Cell, convert::TryFrom};
 
 macro_rules! impl_gauge_for {
     ($int:path) => {
@@ -16,17 +22,29 @@ macro_rules! impl_gauge_for {
                 let v = NumWrapper::<$int>::wrap(count);
                 self.set(self.get().wrapping_sub(v));
             }
+
+            fn value(&self) -> u64 {
+                u64::try_from(self.get()).unwrap_or(u64::MAX)
+            }
         }
 
-        impl Gauge for AtomicInt<$int> {
+        impl_gauge_for!($int, [] AtomicInt<$int>);
+        impl_gauge_for!($int, [O: MemoryOrdering,] OrderedAtomicInt<$int, O>);
+    };
+    ($int:path, [$($generics:tt)*] $ty:ty) => {
+        impl<$($generics)*> Gauge for $ty {
             fn incr_by(&self, count: usize) {
                 let v = NumWrapper::<$int>::wrap(count);
-                AtomicInt::<$int>::incr_by(&self, v);
+                <$ty>::incr_by(&self, v);
             }
 
             fn decr_by(&self, count: usize) {
                 let v = NumWrapper::<$int>::wrap(count);
-                AtomicInt::<$int>::decr_by(&self, v);
+                <$ty>::decr_by(&self, v);
+            }
+
+            fn value(&self) -> u64 {
+                u64::try_from(<$ty>::get(&self)).unwrap_or(u64::MAX)
             }
         }
     };
@@ -37,3 +55,116 @@ impl_gauge_for!(u16);
 impl_gauge_for!(u32);
 impl_gauge_for!(u64);
 impl_gauge_for!(u128);
+
+/// Signed gauges can legitimately go below zero, e.g. a remaining budget.
+///
+/// They wrap like their unsigned counterpart of the same width, and their
+/// [`Gauge::value`] saturates at 0 when negative: read the signed value with
+/// `get`, or from their serialization.
+macro_rules! impl_signed_gauge_for {
+    ($int:path: $uint:path) => {
+        impl Gauge for Cell<$int> {
+            fn incr_by(&self, count: usize) {
+                let v = NumWrapper::<$uint>::wrap(count) as $int;
+                self.set(self.get().wrapping_add(v));
+            }
+
+            fn decr_by(&self, count: usize) {
+                let v = NumWrapper::<$uint>::wrap(count) as $int;
+                self.set(self.get().wrapping_sub(v));
+            }
+
+            fn value(&self) -> u64 {
+                u64::try_from(self.get()).unwrap_or(0)
+            }
+        }
+
+        impl Clear for Cell<$int> {
+            fn clear(&self) {
+                self.set(0);
+            }
+        }
+
+        impl Clearable for Cell<$int> {
+            fn is_cleared(&self) -> bool {
+                self.get() == 0
+            }
+        }
+
+        impl Merge for Cell<$int> {
+            fn merge_from(&self, other: &Self) {
+                self.set(self.get().wrapping_add(other.get()));
+            }
+        }
+
+        impl_signed_gauge_for!($int: $uint, [] AtomicInt<$int>);
+        impl_signed_gauge_for!($int: $uint, [O: MemoryOrdering,] OrderedAtomicInt<$int, O>);
+    };
+    ($int:path: $uint:path, [$($generics:tt)*] $ty:ty) => {
+        impl<$($generics)*> Gauge for $ty {
+            fn incr_by(&self, count: usize) {
+                let v = NumWrapper::<$uint>::wrap(count) as $int;
+                <$ty>::incr_by(&self, v);
+            }
+
+            fn decr_by(&self, count: usize) {
+                let v = NumWrapper::<$uint>::wrap(count) as $int;
+                <$ty>::decr_by(&self, v);
+            }
+
+            fn value(&self) -> u64 {
+                u64::try_from(<$ty>::get(&self)).unwrap_or(0)
+            }
+        }
+
+        impl<$($generics)*> Clear for $ty {
+            fn clear(&self) {
+                <$ty>::set(&self, 0);
+            }
+        }
+
+        impl<$($generics)*> Clearable for $ty {
+            fn is_cleared(&self) -> bool {
+                <$ty>::get(&self) == 0
+            }
+        }
+
+        impl<$($generics)*> Merge for $ty {
+            fn merge_from(&self, other: &Self) {
+                <$ty>::incr_by(&self, other.get());
+            }
+        }
+    };
+}
+
+impl_signed_gauge_for!(i8: u8);
+impl_signed_gauge_for!(i16: u16);
+impl_signed_gauge_for!(i32: u32);
+impl_signed_gauge_for!(i64: u64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InFlight;
+
+    #[test]
+    fn signed_gauges_go_negative() {
+        let gauge: AtomicInt<i64> = AtomicInt::default();
+        Gauge::decr_by(&gauge, 3);
+        Gauge::incr(&gauge);
+        assert_eq!(gauge.get(), -2);
+        assert_eq!(Gauge::value(&gauge), 0);
+        assert_eq!(serde_json::to_string(&gauge).unwrap(), "-2");
+
+        let gauge: Cell<i8> = Cell::new(0);
+        Gauge::decr_by(&gauge, 300);
+        assert_eq!(gauge.get(), -44);
+        Gauge::incr_by(&gauge, 300);
+        assert_eq!(gauge.get(), 0);
+
+        let in_flight: InFlight<AtomicInt<i32>> = InFlight::default();
+        in_flight.0.decr();
+        in_flight.merge_from(&in_flight);
+        assert_eq!(in_flight.0.get(), -2);
+    }
+}
@@ -0,0 +1,207 @@
+//! A module measuring the overhead of Metered itself, to quantify the cost of
+//! instrumentation in production.
+//!
+//! With the `internals` feature, Metered records into a global
+//! [`MeteredInternals`] registry:
+//!
+//! * the time spent entering metrics and recording results, in `measure!` and
+//!   measured methods,
+//! * the time spent waiting for histogram locks held by other threads,
+//! * the time spent serializing histograms, which dominates the serialization
+//!   of registries.
+//!
+#![cfg_attr(feature = "disabled", doc = "```ignore")]
+#![cfg_attr(not(feature = "disabled"), doc = "```rust")]
+//! use metered::{internals, measure, HitCount};
+//!
+//! let hit_count: HitCount = HitCount::default();
+//! measure!(&hit_count, {});
+//!
+//! let overhead = &internals::internals().measure;
+//! assert!(overhead.count() >= 1);
+//! println!("{}", internals::internals());
+//! ```
+//!
+//! Each cost is recorded with a few atomic operations and two clock reads, so
+//! the measurement roughly doubles the overhead of light-weight metrics such
+//! as `HitCount`: the feature is meant to be enabled for a while, not
+//! permanently.
+
+use crate::{
+    clear::Clear,
+    metadata::{DescribeMetrics, MetricDescription, MetricMetadata, MetricType, Unit},
+    pretty::Pretty,
+};
+use serde::Serialize;
+use std::{
+    convert::TryFrom,
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+static INTERNALS: MeteredInternals = MeteredInternals {
+    measure: Cost::new(),
+    lock_wait: Cost::new(),
+    serialization: Cost::new(),
+};
+
+/// Get the global registry of Metered's overhead
+pub fn internals() -> &'static MeteredInternals {
+    &INTERNALS
+}
+
+/// The registry of Metered's overhead.
+///
+/// See the [module documentation](crate::internals).
+#[derive(Debug, Serialize)]
+pub struct MeteredInternals {
+    /// The time spent entering metrics and recording results
+    pub measure: Cost,
+    /// The time spent waiting for histogram locks, when contended
+    pub lock_wait: Cost,
+    /// The time spent serializing histograms
+    pub serialization: Cost,
+}
+
+impl Clear for MeteredInternals {
+    fn clear(&self) {
+        self.measure.clear();
+        self.lock_wait.clear();
+        self.serialization.clear();
+    }
+}
+
+impl DescribeMetrics for MeteredInternals {
+    fn describe_metrics() -> Vec<MetricDescription> {
+        ["measure", "lock_wait", "serialization"]
+            .iter()
+            .flat_map(|cost| {
+                vec![
+                    MetricDescription {
+                        path: vec![cost, "count"],
+                        metadata: MetricMetadata::new(MetricType::Counter, Unit::None),
+                    },
+                    MetricDescription {
+                        path: vec![cost, "total_nanos"],
+                        metadata: MetricMetadata::new(MetricType::Counter, Unit::Nanoseconds),
+                    },
+                    MetricDescription {
+                        path: vec![cost, "max_nanos"],
+                        metadata: MetricMetadata::new(MetricType::Gauge, Unit::Nanoseconds),
+                    },
+                ]
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for MeteredInternals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Pretty::new(self).fmt(f)
+    }
+}
+
+/// A cost of instrumentation: how many times it was paid, for how long in
+/// total and at most.
+#[derive(Debug, Default, Serialize)]
+pub struct Cost {
+    count: AtomicU64,
+    total_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl Cost {
+    const fn new() -> Self {
+        Cost {
+            count: AtomicU64::new(0),
+            total_nanos: AtomicU64::new(0),
+            max_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn record(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// Get how many times the cost was paid
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Get the total time spent
+    pub fn total(&self) -> Duration {
+        Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Get the longest time spent at once
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Get the mean time spent, or zero if the cost was never paid
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::ZERO,
+            count => self.total() / u32::try_from(count).unwrap_or(u32::MAX),
+        }
+    }
+}
+
+impl Clear for Cost {
+    fn clear(&self) {
+        self.count.store(0, Ordering::Relaxed);
+        self.total_nanos.store(0, Ordering::Relaxed);
+        self.max_nanos.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Runs a closure, recording the time it took as a cost
+#[inline]
+pub(crate) fn timed<F: FnOnce() -> R, R>(cost: &Cost, f: F) -> R {
+    let start = Instant::now();
+    let result = f();
+    cost.record(start.elapsed());
+    result
+}
+
+/// Locks a histogram, recording the time spent waiting if it is contended
+#[cfg(feature = "histograms")]
+#[inline]
+pub(crate) fn lock<T>(mutex: &crate::sync::Mutex<T>) -> crate::sync::MutexGuard<'_, T> {
+    if let Some(guard) = mutex.try_lock() {
+        return guard;
+    }
+    timed(&INTERNALS.lock_wait, || mutex.lock())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_costs() {
+        let cost = Cost::default();
+        assert_eq!(cost.mean(), Duration::ZERO);
+        cost.record(Duration::from_nanos(10));
+        cost.record(Duration::from_nanos(30));
+        assert_eq!(cost.count(), 2);
+        assert_eq!(cost.total(), Duration::from_nanos(40));
+        assert_eq!(cost.max(), Duration::from_nanos(30));
+        assert_eq!(cost.mean(), Duration::from_nanos(20));
+
+        cost.clear();
+        assert_eq!(cost.count(), 0);
+        assert_eq!(cost.max(), Duration::ZERO);
+    }
+
+    #[test]
+    fn describes_costs() {
+        let table = internals().to_string();
+        assert!(table.contains("measure.total_nanos"));
+        assert!(table.contains("serialization.max_nanos"));
+    }
+}
@@ -0,0 +1,525 @@
+//! A module keeping metrics per key known at runtime, such as a tenant or an
+//! endpoint, with entries expiring when left untouched.
+//!
+//! Unlike a [`Breakdown`](crate::breakdown::Breakdown), whose variants are
+//! fixed, a [`Keyed`] metric creates an entry the first time a key is used.
+//! To keep the number of entries bounded, entries not used for a time to live
+//! are dropped, both from serialization and from memory:
+//!
+#![cfg_attr(feature = "disabled", doc = "```ignore")]
+#![cfg_attr(not(feature = "disabled"), doc = "```rust")]
+//! use metered::{keyed::Keyed, measure, HitCount};
+//! use std::time::Duration;
+//!
+//! let requests: Keyed<String, HitCount> = Keyed::with_ttl(Duration::from_secs(600));
+//!
+//! measure!(&*requests.by("tenant-a"), {});
+//! measure!(&*requests.by("tenant-a"), {});
+//! measure!(&*requests.by("tenant-b"), {});
+//!
+//! assert_eq!(requests.get("tenant-a").unwrap().get(), 2);
+//! assert_eq!(requests.len(), 2);
+//! ```
+//!
+//! Keys often come from user input, such as request paths: a limit on the
+//! number of keys protects monitoring systems from label explosions. Beyond
+//! the limit, new keys share an `_overflow` entry, and the number of distinct
+//! keys dropped is estimated under `_dropped_keys`. The estimate comes from a
+//! fixed-size sketch, so that dropped keys take no memory: it is exact for a
+//! few keys, within a few percent up to thousands of keys, and saturates
+//! around 30,000 keys. Expired entries only free room when the keyed metric is
+//! serialized or expired:
+//!
+#![cfg_attr(feature = "disabled", doc = "```ignore")]
+#![cfg_attr(not(feature = "disabled"), doc = "```rust")]
+//! use metered::{keyed::Keyed, measure, HitCount};
+//!
+//! let requests: Keyed<String, HitCount> = Keyed::new().with_max_keys(2);
+//!
+//! for path in ["/", "/login", "/random-1", "/random-2", "/login", "/random-1"] {
+//!     measure!(&*requests.by(path), {});
+//! }
+//!
+//! assert_eq!(requests.len(), 2);
+//! assert_eq!(requests.get("/login").unwrap().get(), 2);
+//! assert_eq!(requests.overflow().unwrap().get(), 3);
+//! assert_eq!(requests.dropped_keys(), 2);
+//! ```
+//!
+//! Metrics are serialized by key, with a `key` label when serialized by
+//! `serde_prometheus`. Keys starting with `_` are escaped with another leading
+//! `_`, so that they never collide with `_overflow` and `_dropped_keys`.
+
+use crate::{
+    clear::{self, Clear},
+    metadata::{Describe, MetricMetadata},
+    serialization::MetricAlias,
+    sync::RwLock,
+    time_source::{Instant, StdInstant},
+};
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    fmt,
+    hash::{BuildHasher, Hash},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+struct Entry<M> {
+    metric: Arc<M>,
+    /// When the entry was last used, in units of the time source since the
+    /// creation of the keyed metric
+    touched: AtomicU64,
+}
+
+struct Entries<K, M> {
+    map: HashMap<K, Entry<M>>,
+    /// The entry shared by keys beyond the limit, once used
+    overflow: Option<Arc<M>>,
+}
+
+/// A metric kept once per key, see the [module documentation](crate::keyed).
+///
+/// Time to live is measured with the time source `T`, which can be a
+/// [`SimInstant`](crate::simulation::SimInstant) in tests.
+pub struct Keyed<K, M, T: Instant = StdInstant> {
+    start: T,
+    ttl: Option<u64>,
+    max_keys: Option<usize>,
+    entries: RwLock<Entries<K, M>>,
+    dropped_keys: DroppedKeys,
+}
+
+impl<K: Eq + Hash, M, T: Instant> Keyed<K, M, T> {
+    /// Creates a keyed metric whose entries never expire
+    pub fn new() -> Self {
+        Keyed {
+            start: T::now(),
+            ttl: None,
+            max_keys: None,
+            entries: RwLock::new(Entries {
+                map: HashMap::new(),
+                overflow: None,
+            }),
+            dropped_keys: DroppedKeys::new(),
+        }
+    }
+
+    /// Creates a keyed metric dropping the entries not used for a duration
+    pub fn with_ttl(ttl: Duration) -> Self {
+        Keyed {
+            ttl: Some(T::units(ttl)),
+            ..Self::new()
+        }
+    }
+
+    /// Limits the number of keys, keys beyond the limit sharing an overflow
+    /// entry
+    pub fn with_max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = Some(max_keys);
+        self
+    }
+
+    /// Get the metric of a key, creating it if needed, and marks it as used.
+    ///
+    /// If the number of keys is limited and reached, new keys get the overflow
+    /// entry instead.
+    pub fn by<Q>(&self, key: &Q) -> Arc<M>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+        M: Default,
+    {
+        let now = self.start.elapsed_time();
+        {
+            let entries = self.entries.read();
+            if let Some(entry) = entries.map.get(key) {
+                entry.touched.store(now, Ordering::Relaxed);
+                return Arc::clone(&entry.metric);
+            }
+            if let (true, Some(overflow)) = (self.is_full(&entries.map), &entries.overflow) {
+                self.drop_key(&entries.map, key);
+                return Arc::clone(overflow);
+            }
+        }
+
+        let mut entries = self.entries.write();
+        let entries = &mut *entries;
+        if self.is_full(&entries.map) && !entries.map.contains_key(key) {
+            self.drop_key(&entries.map, key);
+            return Arc::clone(entries.overflow.get_or_insert_with(Arc::default));
+        }
+
+        // Expires entries as new ones are created, so that the number of
+        // entries stays bounded even if never serialized
+        self.retain_live(&mut entries.map, now);
+        let entry = entries.map.entry(key.to_owned()).or_insert_with(|| Entry {
+            metric: Arc::default(),
+            touched: AtomicU64::new(now),
+        });
+        entry.touched.store(now, Ordering::Relaxed);
+        Arc::clone(&entry.metric)
+    }
+
+    /// Get the metric of a key, if it exists, without marking it as used
+    pub fn get<Q>(&self, key: &Q) -> Option<Arc<M>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.entries
+            .read()
+            .map
+            .get(key)
+            .map(|entry| Arc::clone(&entry.metric))
+    }
+
+    /// Get the overflow entry, if keys went beyond the limit
+    pub fn overflow(&self) -> Option<Arc<M>> {
+        self.entries.read().overflow.clone()
+    }
+
+    /// Get an estimate of the number of distinct keys beyond the limit that
+    /// got the overflow entry, see the [module documentation](crate::keyed)
+    pub fn dropped_keys(&self) -> u64 {
+        self.dropped_keys.estimate()
+    }
+
+    /// Get the number of entries, besides the overflow entry, including
+    /// expired entries not dropped yet
+    pub fn len(&self) -> usize {
+        self.entries.read().map.len()
+    }
+
+    /// Returns true if there are no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops the entries not used for the time to live, and returns how many
+    /// were dropped
+    pub fn expire(&self) -> usize {
+        let mut entries = self.entries.write();
+        let len = entries.map.len();
+        self.retain_live(&mut entries.map, self.start.elapsed_time());
+        len - entries.map.len()
+    }
+
+    fn is_full(&self, entries: &HashMap<K, Entry<M>>) -> bool {
+        self.max_keys
+            .is_some_and(|max_keys| entries.len() >= max_keys)
+    }
+
+    /// Counts a key beyond the limit, by the hash of the entries
+    fn drop_key<Q>(&self, entries: &HashMap<K, Entry<M>>, key: &Q)
+    where
+        Q: Hash + ?Sized,
+    {
+        self.dropped_keys.insert(entries.hasher().hash_one(key));
+    }
+
+    fn retain_live(&self, entries: &mut HashMap<K, Entry<M>>, now: u64) {
+        if let Some(ttl) = self.ttl {
+            entries.retain(|_, entry| !is_expired(entry, now, ttl));
+        }
+    }
+}
+
+fn is_expired<M>(entry: &Entry<M>, now: u64, ttl: u64) -> bool {
+    now.saturating_sub(entry.touched.load(Ordering::Relaxed)) > ttl
+}
+
+impl<K: Eq + Hash, M, T: Instant> Default for Keyed<K, M, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Eq + Hash, M, T: Instant> Clear for Keyed<K, M, T> {
+    /// Drops all entries, as new entries start cleared
+    fn clear(&self) {
+        let mut entries = self.entries.write();
+        entries.map.clear();
+        entries.overflow = None;
+        self.dropped_keys.take();
+    }
+}
+
+impl<K, M, T> Serialize for Keyed<K, M, T>
+where
+    K: Eq + Hash + fmt::Display,
+    M: Serialize,
+    T: Instant,
+{
+    /// Serializes the live entries by key, after dropping expired ones, then
+    /// the overflow entry and the count of dropped keys if the number of keys
+    /// is limited
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.expire();
+        let entries = self.entries.read();
+        let mut sorted: Vec<(String, &Arc<M>)> = entries
+            .map
+            .iter()
+            .map(|(key, entry)| (escape(key.to_string()), &entry.metric))
+            .collect();
+        sorted.sort_by(|a, b| a.0.cmp(&b.0));
+        sorted.extend(
+            entries
+                .overflow
+                .iter()
+                .map(|overflow| ("_overflow".to_string(), overflow)),
+        );
+
+        let mut map = serializer.serialize_map(None)?;
+        for (key, metric) in sorted {
+            map.serialize_entry(&key, &MetricAlias("!|key==<", &**metric))?;
+        }
+        if self.max_keys.is_some() {
+            let dropped_keys = if clear::is_clearing_serialization() {
+                self.dropped_keys.take()
+            } else {
+                self.dropped_keys()
+            };
+            map.serialize_entry("_dropped_keys", &dropped_keys)?;
+        }
+        map.end()
+    }
+}
+
+/// The number of words of the sketch of dropped keys, of 64 bits each
+const SKETCH_WORDS: usize = 64;
+
+/// A sketch estimating the number of distinct keys dropped, by linear
+/// counting: each key sets the bit picked by its hash, and the estimate
+/// follows from the share of bits left unset.
+struct DroppedKeys {
+    bits: [AtomicU64; SKETCH_WORDS],
+}
+
+impl DroppedKeys {
+    fn new() -> Self {
+        DroppedKeys {
+            bits: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    fn insert(&self, hash: u64) {
+        let bit = hash as usize % (SKETCH_WORDS * 64);
+        let mask = 1 << (bit % 64);
+        let word = &self.bits[bit / 64];
+        // Keys dropped again only read their word
+        if word.load(Ordering::Relaxed) & mask == 0 {
+            word.fetch_or(mask, Ordering::Relaxed);
+        }
+    }
+
+    fn estimate(&self) -> u64 {
+        Self::estimate_from(self.bits.iter().map(|word| word.load(Ordering::Relaxed)))
+    }
+
+    /// Get the estimate and resets the sketch, so that each key dropped is
+    /// counted in a single estimate
+    fn take(&self) -> u64 {
+        Self::estimate_from(self.bits.iter().map(|word| word.swap(0, Ordering::Relaxed)))
+    }
+
+    fn estimate_from(words: impl Iterator<Item = u64>) -> u64 {
+        let set: u32 = words.map(u64::count_ones).sum();
+        if set == 0 {
+            return 0;
+        }
+        let bits = (SKETCH_WORDS * 64) as f64;
+        // A full sketch is saturated: its estimate is kept at one bit unset
+        let unset = (bits - f64::from(set)).max(1.0);
+        (bits * (bits / unset).ln()).round() as u64
+    }
+}
+
+/// Escapes keys starting with `_` with another one, to tell them apart from
+/// `_overflow` and `_dropped_keys`
+fn escape(key: String) -> String {
+    if key.starts_with('_') {
+        format!("_{}", key)
+    } else {
+        key
+    }
+}
+
+impl<K: Eq + Hash + fmt::Debug, M: fmt::Debug, T: Instant> fmt::Debug for Keyed<K, M, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = self.entries.read();
+        f.debug_map()
+            .entries(entries.map.iter().map(|(key, entry)| (key, &entry.metric)))
+            .finish()
+    }
+}
+
+impl<K, M: Describe, T: Instant> Describe for Keyed<K, M, T> {
+    fn metadata() -> MetricMetadata {
+        M::metadata()
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{
+        flatten::to_samples,
+        measure, measure_block,
+        simulation::{SimInstant, Simulation},
+        ErrorCount, HitCount,
+    };
+
+    #[test]
+    fn expires_untouched_entries() {
+        let simulation = Simulation::start(0);
+        let hits: Keyed<String, HitCount, SimInstant> = Keyed::with_ttl(Duration::from_secs(60));
+
+        measure!(&*hits.by("a"), {});
+        simulation.advance(Duration::from_secs(45));
+        measure!(&*hits.by("b"), {});
+        simulation.advance(Duration::from_secs(30));
+        measure!(&*hits.by("b"), {});
+
+        let samples = to_samples(&hits).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].labels, [("key".to_string(), "b".to_string())]);
+        assert_eq!(samples[0].value, 2.0);
+        assert!(hits.get("a").is_none());
+
+        simulation.advance(Duration::from_secs(61));
+        measure!(&*hits.by("c"), {});
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits.expire(), 0);
+
+        hits.clear();
+        assert!(hits.is_empty());
+    }
+
+    #[test]
+    fn never_expires_without_ttl() {
+        let simulation = Simulation::start(0);
+        let hits: Keyed<u32, HitCount, SimInstant> = Keyed::new();
+        measure!(&*hits.by(&7), {});
+        simulation.advance(Duration::from_secs(3600 * 24));
+        assert_eq!(hits.expire(), 0);
+        assert_eq!(hits.get(&7).unwrap().get(), 1);
+    }
+
+    #[test]
+    fn limits_keys() {
+        let simulation = Simulation::start(0);
+        let hits: Keyed<u32, HitCount, SimInstant> =
+            Keyed::with_ttl(Duration::from_secs(60)).with_max_keys(2);
+        for key in [1, 2, 3, 1, 4, 3] {
+            measure!(&*hits.by(&key), {});
+        }
+        assert_eq!(hits.get(&1).unwrap().get(), 2);
+        assert!(hits.get(&3).is_none());
+        assert_eq!(hits.overflow().unwrap().get(), 3);
+        // Distinct keys are dropped once
+        assert_eq!(hits.dropped_keys(), 2);
+
+        let samples = to_samples(&hits).unwrap();
+        let values: Vec<_> = samples
+            .iter()
+            .map(|sample| (sample.name.as_str(), sample.labels.clone(), sample.value))
+            .collect();
+        let key = |key: &str| vec![("key".to_string(), key.to_string())];
+        assert_eq!(
+            values,
+            [
+                ("", key("1"), 2.0),
+                ("", key("2"), 1.0),
+                ("", key("_overflow"), 3.0),
+                ("_dropped_keys", Vec::new(), 2.0),
+            ]
+        );
+
+        // Expired keys free room for new keys once expired
+        simulation.advance(Duration::from_secs(61));
+        measure!(&*hits.by(&5), {});
+        assert!(hits.get(&5).is_none());
+        assert_eq!(hits.expire(), 2);
+        measure!(&*hits.by(&5), {});
+        assert_eq!(hits.get(&5).unwrap().get(), 1);
+        assert_eq!(hits.dropped_keys(), 3);
+
+        hits.clear();
+        assert!(hits.overflow().is_none());
+        assert_eq!(hits.dropped_keys(), 0);
+    }
+
+    #[test]
+    fn estimates_dropped_keys_in_fixed_memory() {
+        let hits: Keyed<u32, HitCount> = Keyed::new().with_max_keys(1);
+        for key in 0..=2_000 {
+            let _ = hits.by(&key);
+        }
+        let dropped = hits.dropped_keys() as f64;
+        assert!((dropped - 2_000.0).abs() < 100.0, "{}", dropped);
+
+        for key in 0..1_000_000 {
+            let _ = hits.by(&key);
+        }
+        assert!(hits.dropped_keys() > 30_000);
+        assert_eq!(hits.len(), 1);
+
+        hits.dropped_keys.take();
+        assert_eq!(hits.dropped_keys(), 0);
+    }
+
+    #[test]
+    fn escapes_keys_colliding_with_reserved_entries() {
+        let hits: Keyed<String, HitCount> = Keyed::new().with_max_keys(3);
+        for key in ["_overflow", "_dropped_keys", "__overflow", "other"] {
+            measure!(&*hits.by(key), {});
+        }
+
+        let samples = to_samples(&hits).unwrap();
+        let values: Vec<_> = samples
+            .iter()
+            .map(|sample| (sample.name.as_str(), sample.labels.clone(), sample.value))
+            .collect();
+        let key = |key: &str| vec![("key".to_string(), key.to_string())];
+        assert_eq!(
+            values,
+            [
+                ("", key("___overflow"), 1.0),
+                ("", key("__dropped_keys"), 1.0),
+                ("", key("__overflow"), 1.0),
+                ("", key("_overflow"), 1.0),
+                ("_dropped_keys", Vec::new(), 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn measures_blocks_by_label() {
+        let hits: Keyed<String, HitCount> = Keyed::new();
+        let errors: Keyed<String, ErrorCount> = Keyed::new();
+
+        for line in ["1", "x", "3"] {
+            let _: Result<u32, _> = measure_block!("parse", [&hits, &errors], line.parse());
+        }
+        let stored = measure_block!("store", &hits, 2);
+        assert_eq!(stored, 2);
+
+        let samples = to_samples(&hits).unwrap();
+        let values: Vec<_> = samples
+            .iter()
+            .map(|sample| (sample.labels[0].1.as_str(), sample.value))
+            .collect();
+        assert_eq!(values, [("parse", 3.0), ("store", 1.0)]);
+        assert_eq!(errors.get("parse").unwrap().get(), 1);
+        assert!(errors.get("store").is_none());
+    }
+}
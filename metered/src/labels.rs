@@ -0,0 +1,163 @@
+//! A module providing constant labels attached to every metric of a registry.
+
+use crate::sync::Mutex;
+use serde::{Serialize, Serializer};
+use std::{cell::RefCell, collections::HashSet, sync::OnceLock};
+
+/// The most distinct label sets whose names are leaked for serializers, such
+/// as `serde_prometheus`, requiring `'static` newtype struct names
+const MAX_STATIC_ALIASES: usize = 256;
+
+/// The newtype struct name of label sets beyond [`MAX_STATIC_ALIASES`], whose
+/// name is passed to the flattener with [`take_dynamic_alias`]
+pub(crate) const DYNAMIC_ALIAS: &str = "|";
+
+thread_local! {
+    /// The name of the label set serialized under [`DYNAMIC_ALIAS`]
+    static DYNAMIC: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// A set of constant labels, built once when a registry is first serialized.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LabelSet {
+    labels: Vec<(&'static str, String)>,
+    alias: String,
+}
+
+impl LabelSet {
+    /// Builds a label set from key/value pairs.
+    pub fn new(labels: Vec<(&'static str, String)>) -> Self {
+        let alias = labels
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(",");
+        // Labels only, without key modifiers: the method name is kept
+        LabelSet {
+            labels,
+            alias: format!("|{}", alias),
+        }
+    }
+
+    /// Get the labels, in declaration order.
+    pub fn labels(&self) -> &[(&'static str, String)] {
+        &self.labels
+    }
+
+    /// Get the value of a label.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+impl LabelSet {
+    /// Serializes a value wrapped in a newtype struct named after the labels,
+    /// for serializers supporting it like `serde_prometheus`.
+    ///
+    /// Serde requires `'static` names: names are leaked once per distinct label
+    /// set, for up to 256 label sets. Further label sets are only attached by
+    /// the flattener of this crate, e.g. with
+    /// [`to_samples`](crate::flatten::to_samples).
+    pub(crate) fn serialize_labeled<T, S>(
+        &self,
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize + ?Sized,
+        S: Serializer,
+    {
+        /// Forgets the name, even if the serializer did not take it
+        struct Forget;
+
+        impl Drop for Forget {
+            fn drop(&mut self) {
+                take_dynamic_alias();
+            }
+        }
+
+        if let Some(alias) = self.static_alias() {
+            return serializer.serialize_newtype_struct(alias, value);
+        }
+        DYNAMIC.with(|dynamic| *dynamic.borrow_mut() = Some(self.alias.clone()));
+        let _forget = Forget;
+        serializer.serialize_newtype_struct(DYNAMIC_ALIAS, value)
+    }
+
+    /// Get the leaked name of the label set, unless too many were leaked
+    fn static_alias(&self) -> Option<&'static str> {
+        static ALIASES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+
+        let mut aliases = ALIASES.get_or_init(Mutex::default).lock();
+        if let Some(alias) = aliases.get(self.alias.as_str()) {
+            return Some(alias);
+        }
+        if aliases.len() >= MAX_STATIC_ALIASES {
+            return None;
+        }
+        let alias: &'static str = Box::leak(self.alias.clone().into_boxed_str());
+        aliases.insert(alias);
+        Some(alias)
+    }
+}
+
+/// Takes the name of the label set serialized under [`DYNAMIC_ALIAS`]
+pub(crate) fn take_dynamic_alias() -> Option<String> {
+    DYNAMIC.with(|dynamic| dynamic.borrow_mut().take())
+}
+
+/// Reads a label value from an environment variable, or returns an empty
+/// string if it is not set or not valid unicode.
+pub fn env_label(var: &str) -> String {
+    std::env::var(var).unwrap_or_default()
+}
+
+/// A trait for registries with constant labels, implemented by registries
+/// generated with the `labels` option of `#[metered]`.
+///
+/// Label values are either string literals, or read from an environment
+/// variable with `env("VAR")` the first time they are needed:
+///
+/// ```rust
+/// use metered::{labels::ConstLabels, metered, HitCount};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Cache {
+///     metrics: CacheMetrics,
+/// }
+///
+/// #[metered(registry = CacheMetrics, labels(component = "cache", region = env("REGION")))]
+/// impl Cache {
+///     #[measure(HitCount)]
+///     pub fn get(&self) {}
+/// }
+///
+/// std::env::set_var("REGION", "eu-west-1");
+///
+/// let labels = CacheMetrics::const_labels();
+/// assert_eq!(labels.get("component"), Some("cache"));
+/// assert_eq!(labels.get("region"), Some("eu-west-1"));
+/// ```
+///
+/// Each method sub-registry is serialized wrapped in a newtype struct named
+/// after the labels, the way `HdrHistogram` adds quantile labels, so that
+/// serializers supporting it, like `serde_prometheus`, attach them to every
+/// metric of the registry. Other serializers ignore them.
+pub trait ConstLabels {
+    /// Returns the registry's constant labels
+    fn const_labels() -> &'static LabelSet;
+}
+
+/// Serializes a value of a registry `L` with its constant labels.
+#[doc(hidden)]
+pub fn serialize_labeled<L, T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    L: ConstLabels,
+    T: Serialize,
+    S: Serializer,
+{
+    serializer.serialize_newtype_struct(&L::const_labels().alias, value)
+}
@@ -13,6 +13,17 @@
 //! * [`Throughput`]: statistics backed by an HdrHistogram of how many times an
 //!   expression is called per second.
 //!
+//! HdrHistograms, `Throughput` and the HdrHistogram default of `ResponseTime`
+//! are provided by the default `histograms` feature. Disabling default
+//! features gives a lite build without the `hdrhistogram` dependency, smaller
+//! and faster to compile, e.g. for CLI tools only tracking hit and error
+//! counts. Histogram metrics remain available there with the other backends,
+//! such as `ResponseTime<AtomicDdSketch>`.
+//!
+//! Thread-safe metrics lock with `parking_lot`, through the default
+//! `parking_lot` feature. Without it, or with the `std-sync` feature, they use
+//! `std::sync` locks instead, e.g. to minimize dependencies.
+//!
 //! These metrics are usually applied to methods, using provided procedural
 //! macros that generate the boilerplate.
 //!
@@ -47,7 +58,8 @@
 //! See the demos for more examples.
 //!
 //! Metered will generate metric registries that derive [`std::fmt::Debug`] and
-//! [`serde::Serialize`] to extract your metrics easily. Metered generates one
+//! [`serde::Serialize`] to extract your metrics easily, and implement
+//! [`std::fmt::Display`] to print them as a table (see [`pretty::Pretty`]). Metered generates one
 //! sub-registry per method annotated with the `measure` attribute, hence
 //! organizing metrics hierarchically. This ensures access time to metrics in
 //! generated registries is always constant (and, when possible,
@@ -64,7 +76,8 @@
 //!
 //! ## Example using procedural macros (recommended)
 //!
-//! ```
+#![cfg_attr(not(feature = "histograms"), doc = "```ignore")]
+#![cfg_attr(feature = "histograms", doc = "```rust")]
 //! # extern crate metered;
 //! # extern crate rand;
 //!
@@ -139,17 +152,108 @@
 #![deny(missing_docs)]
 #![deny(warnings)]
 
+/// Records the time taken by an expression as one of the costs of
+/// [`internals::MeteredInternals`], with the `internals` feature.
+#[cfg(feature = "internals")]
+macro_rules! internal_cost {
+    ($cost:ident, $e:expr) => {
+        crate::internals::timed(&crate::internals::internals().$cost, || $e)
+    };
+}
+
+#[cfg(not(feature = "internals"))]
+macro_rules! internal_cost {
+    ($cost:ident, $e:expr) => {
+        $e
+    };
+}
+
+/// Locks the mutex of a histogram to record a value, recording the time spent
+/// waiting for it with the `internals` feature.
+#[cfg(feature = "internals")]
+macro_rules! lock_histogram {
+    ($mutex:expr) => {
+        crate::internals::lock(&$mutex)
+    };
+}
+
+#[cfg(not(feature = "internals"))]
+macro_rules! lock_histogram {
+    ($mutex:expr) => {
+        $mutex.lock()
+    };
+}
+
+#[cfg(feature = "admin")]
+pub mod admin;
+#[cfg(feature = "allocation-count")]
+pub mod allocator;
 pub mod atomic;
+pub mod breakdown;
 pub mod clear;
 pub mod common;
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
+pub mod dd_sketch;
+#[cfg(feature = "discovery")]
+pub mod discovery;
+#[cfg(feature = "dogstatsd")]
+pub mod dogstatsd;
+pub mod emf;
+#[cfg(any(feature = "msgpack", feature = "cbor"))]
+pub mod encoding;
+pub mod flatten;
+pub mod float;
+pub mod harvest;
+#[cfg(feature = "histograms")]
 pub mod hdr_histogram;
+pub mod health;
+pub mod history;
+pub mod info;
 pub mod int_counter;
 pub mod int_gauge;
+#[cfg(feature = "internals")]
+pub mod internals;
+pub mod keyed;
+pub mod labels;
+#[cfg(all(test, loom))]
+mod loom_tests;
+pub mod metadata;
+pub mod merge;
 pub mod metric;
+pub mod moving_average;
+#[cfg(feature = "multiprocess")]
+pub mod multiprocess;
+pub mod namespace;
+pub mod nesting;
+pub mod null;
 pub(crate) mod num_wrapper;
+pub mod p2_quantile;
+pub mod pretty;
+#[cfg(feature = "published")]
+pub mod published;
+#[cfg(feature = "process")]
+pub mod process;
+#[cfg(feature = "remote-write")]
+pub mod remote_write;
+pub mod reservoir;
+pub mod serialization;
+pub mod simulation;
+pub mod sink;
+pub mod sliding_window;
+pub mod snapshot;
+pub mod staleness;
+pub(crate) mod sync;
+pub mod t_digest;
+pub mod test;
 pub mod time_source;
+pub mod toggle;
 
-pub use common::{ErrorCount, HitCount, InFlight, ResponseTime, Throughput};
+#[cfg(feature = "allocation-count")]
+pub use common::AllocationCount;
+#[cfg(feature = "histograms")]
+pub use common::Throughput;
+pub use common::{ErrorCount, HitCount, InFlight, ResponseTime};
 pub use metered_macro::{error_count, metered};
 pub use metric::{Counter, Gauge, Histogram, Metric};
 
@@ -161,7 +265,14 @@ pub use aspect::Enter;
 ///
 /// It applies the metric and the expression is returned unchanged.
 /// 
-/// ```rust
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
 /// use metered::{ResponseTime, measure};
 /// 
 /// let response_time: ResponseTime = ResponseTime::default();
@@ -175,7 +286,14 @@ pub use aspect::Enter;
 /// 
 /// It also allows to pass an array of references, which will expand recursively.
 /// 
-/// ```rust
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
 /// use metered::{HitCount, ResponseTime, measure};
 /// 
 /// let hit_count: HitCount = HitCount::default();
@@ -189,6 +307,48 @@ pub use aspect::Enter;
 /// assert!(response_time.histogram().mean() > 0.0);
 /// ```
 ///
+/// Metrics implementing [`metric::Gate`] can reject the expression before it
+/// is evaluated, in which case the `abort` expression is returned instead. It
+/// must have the same type as the measured expression, or diverge (e.g
+/// `panic!()`).
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{measure, metric::Gate, Enter, HitCount};
+/// # use metered::{clear::Clear, metric::{Advice, OnResult, Metric}};
+/// # use serde::Serialize;
+///
+/// // A gate rejecting every call
+/// #[derive(Default, Serialize)]
+/// struct Closed;
+/// # impl Clear for Closed { fn clear(&self) {} }
+/// # impl<R> Metric<R> for Closed {}
+/// # impl<R> OnResult<R> for Closed {}
+///
+/// impl Enter for Closed {
+///     type E = ();
+///     fn enter(&self) {}
+/// }
+///
+/// impl Gate for Closed {
+///     fn should_abort(&self, _: &()) -> bool {
+///         true
+///     }
+/// }
+///
+/// let closed = Closed;
+/// let hit_count: HitCount = HitCount::default();
+///
+/// let result: Result<(), &str> = measure!(&closed, {
+///     measure!(&hit_count, Ok(()))
+/// }, abort => Err("closed"));
+///
+/// assert_eq!(result, Err("closed"));
+/// assert_eq!(hit_count.get(), 0);
+/// ```
+///
+/// With the `disabled` feature, `measure!` only evaluates the metric and the
+/// expression: metrics record nothing, and gates never abort.
 #[macro_export]
 macro_rules! measure {
     ([$metric:expr], $expr:expr) => {{
@@ -199,6 +359,75 @@ macro_rules! measure {
         $crate::measure!($metric, $crate::measure!([$($metrics),*], $expr))
     };
 
+    ($metric:expr, $e:expr) => {
+        $crate::__measure_one!($metric, $e)
+    };
+
+    ($metric:expr, $e:expr, abort => $abort:expr) => {
+        $crate::__measure_one!($metric, $e, abort => $abort)
+    };
+}
+
+/// Measures a block of code under a static label, such as a stage of a
+/// pipeline, into [`Keyed`](crate::keyed::Keyed) metrics.
+///
+/// `measure_block!` takes a label, one or a list of references to keyed
+/// metrics, and an expression: the expression is measured by the entry of the
+/// label in each keyed metric, as with [`measure!`]. This instruments the
+/// stages of a single large function, where `#[measure]` on methods doesn't
+/// fit:
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{keyed::Keyed, measure_block, HitCount, ResponseTime};
+///
+/// #[derive(Default)]
+/// struct Stages {
+///     hits: Keyed<String, HitCount>,
+///     time: Keyed<String, ResponseTime>,
+/// }
+///
+/// fn ingest(stages: &Stages, lines: &[&str]) -> usize {
+///     let parsed: Vec<u64> = measure_block!("parse", [&stages.hits, &stages.time], {
+///         lines.iter().filter_map(|line| line.parse().ok()).collect()
+///     });
+///     measure_block!("store", &stages.hits, {
+///         parsed.len()
+///     })
+/// }
+///
+/// let stages = Stages::default();
+/// assert_eq!(ingest(&stages, &["1", "2", "x"]), 2);
+///
+/// assert_eq!(stages.hits.get("parse").unwrap().get(), 1);
+/// assert_eq!(stages.hits.get("store").unwrap().get(), 1);
+/// assert_eq!(stages.time.get("parse").unwrap().histogram().len(), 1);
+/// assert!(stages.time.get("store").is_none());
+/// ```
+///
+/// Labels are serialized as the `key` label of the keyed metrics.
+#[macro_export]
+macro_rules! measure_block {
+    ($label:expr, [$($metric:expr),+ $(,)?], $e:expr) => {{
+        let label: &'static str = $label;
+        $crate::measure!([$(&*($metric).by(label)),+], $e)
+    }};
+
+    ($label:expr, $metric:expr, $e:expr) => {
+        $crate::measure_block!($label, [$metric], $e)
+    };
+}
+
+#[cfg(not(feature = "disabled"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __measure_one {
     ($metric:expr, $e:expr) => {{
         let metric = $metric;
         let guard = $crate::metric::ExitGuard::new(metric);
@@ -206,20 +435,97 @@ macro_rules! measure {
         guard.on_result(&mut result);
         result
     }};
+
+    ($metric:expr, $e:expr, abort => $abort:expr) => {{
+        let metric = $metric;
+        let guard = $crate::metric::ExitGuard::new(metric);
+        if guard.should_abort() {
+            drop(guard);
+            $abort
+        } else {
+            let mut result = $e;
+            guard.on_result(&mut result);
+            result
+        }
+    }};
+}
+
+/// Calls the closure wrapping the body of a measured method, for registries
+/// generated with the `inline_closure` option.
+#[doc(hidden)]
+#[inline(always)]
+pub fn __call_inline<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
+
+/// Measures an expression only if `$enabled`, for registries generated with
+/// the `toggle` option.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __measure_if {
+    ($enabled:expr, $metric:expr, $e:expr) => {{
+        let metric = $metric;
+        let guard = if $enabled {
+            Some($crate::metric::ExitGuard::new(metric))
+        } else {
+            None
+        };
+        let mut result = $e;
+        if let Some(guard) = guard {
+            guard.on_result(&mut result);
+        }
+        result
+    }};
+
+    ($enabled:expr, $metric:expr, $e:expr, abort => $abort:expr) => {{
+        let metric = $metric;
+        let guard = if $enabled {
+            Some($crate::metric::ExitGuard::new(metric))
+        } else {
+            None
+        };
+        if guard.as_ref().map_or(false, |guard| guard.should_abort()) {
+            drop(guard);
+            $abort
+        } else {
+            let mut result = $e;
+            if let Some(guard) = guard {
+                guard.on_result(&mut result);
+            }
+            result
+        }
+    }};
+}
+
+/// With the `disabled` feature, metrics are evaluated but never entered, and
+/// never abort.
+#[cfg(feature = "disabled")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __measure_one {
+    ($metric:expr, $e:expr) => {{
+        let _ = $metric;
+        $e
+    }};
+
+    ($metric:expr, $e:expr, abort => $abort:expr) => {{
+        let _ = $metric;
+        $e
+    }};
 }
 
 /// Serializer for values within a struct generated by
-/// `metered::metered_error_variants` that adds an `error_kind` label when being
+/// `metered::metered_error_variants` that adds a `variant` label when being
 /// serialized by `serde_prometheus`.
 pub fn error_variant_serializer<S: serde::Serializer, T: serde::Serialize>(
     value: &T,
     serializer: S,
 ) -> Result<S::Ok, S::Error> {
-    serializer.serialize_newtype_struct("!|variant[::]==<", value)
+    error_variant_serializer_as("!|variant[::]==<", value, serializer)
 }
 
 /// Serializer for values within a struct generated by
-/// `metered::metered_error_variants` that adds an `error_kind` label when being
+/// `metered::metered_error_variants` that adds a `variant` label when being
 /// serialized by `serde_prometheus`. If the `value` has been cleared. This
 /// operation is a no-op and the value wont be written to the `serializer`.
 pub fn error_variant_serializer_skip_cleared<
@@ -228,11 +534,35 @@ pub fn error_variant_serializer_skip_cleared<
 >(
     value: &T,
     serializer: S,
+) -> Result<S::Ok, S::Error> {
+    error_variant_serializer_skip_cleared_as("!|variant[::]==<", value, serializer)
+}
+
+/// Serializer for values within a struct generated by `metered::error_count`,
+/// wrapping them in a newtype struct named `alias` instead of the one adding a
+/// `variant` label, as configured by the `label` and `label_alias` options.
+pub fn error_variant_serializer_as<S: serde::Serializer, T: serde::Serialize>(
+    alias: &'static str,
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_newtype_struct(alias, value)
+}
+
+/// Like [`error_variant_serializer_as`], but skipping cleared values like
+/// [`error_variant_serializer_skip_cleared`].
+pub fn error_variant_serializer_skip_cleared_as<
+    S: serde::Serializer,
+    T: serde::Serialize + clear::Clearable,
+>(
+    alias: &'static str,
+    value: &T,
+    serializer: S,
 ) -> Result<S::Ok, S::Error> {
     if value.is_cleared() {
         serializer.serialize_none()
     } else {
-        error_variant_serializer(value, serializer)
+        error_variant_serializer_as(alias, value, serializer)
     }
 }
 
@@ -245,7 +575,66 @@ pub trait ErrorBreakdown<C: metric::Counter> {
 
 /// Generic trait for `ErrorBreakdown::ErrorCount` to increase error count for a
 /// specific variant by 1.
-pub trait ErrorBreakdownIncr<E> {
+pub trait ErrorBreakdownIncr<E: ?Sized> {
     /// Increase count for given variant by 1.
     fn incr(&self, e: &E);
 }
+
+/// Trait listing the counts of `ErrorBreakdown::ErrorCount` by variant, behind
+/// the `iter` and `total` methods of the structs generated by
+/// `#[metered::error_count]`.
+pub trait ErrorBreakdownCounts {
+    /// Get the name and count of each variant, nested variants being named by
+    /// their path joined with `::`.
+    fn counts(&self) -> Vec<(String, u64)>;
+}
+
+// Nested errors may be boxed, e.g. to keep recursive error enums sized
+impl<C: metric::Counter, E: ErrorBreakdown<C> + ?Sized> ErrorBreakdown<C> for Box<E> {
+    type ErrorCount = E::ErrorCount;
+}
+
+impl<C: metric::Counter, E: ErrorBreakdown<C> + ?Sized> ErrorBreakdown<C> for std::sync::Arc<E> {
+    type ErrorCount = E::ErrorCount;
+}
+
+impl<E: ?Sized, T: ErrorBreakdownIncr<E>> ErrorBreakdownIncr<Box<E>> for T {
+    fn incr(&self, e: &Box<E>) {
+        ErrorBreakdownIncr::<E>::incr(self, e)
+    }
+}
+
+impl<E: ?Sized, T: ErrorBreakdownIncr<E>> ErrorBreakdownIncr<std::sync::Arc<E>> for T {
+    fn incr(&self, e: &std::sync::Arc<E>) {
+        ErrorBreakdownIncr::<E>::incr(self, e)
+    }
+}
+
+/// Trait projecting an error wrapping an error enum into it, for the
+/// `wrapped_by` option of `#[metered::error_count]`.
+///
+/// It is implemented by `std::io::Error` and boxed trait objects, which
+/// downcast the error they wrap. Wrappers generic over the error, given with a
+/// `_` placeholder, are projected with `AsRef` instead.
+pub trait ProjectError<E> {
+    /// Get the wrapped error, if it is an `E`
+    fn project_error(&self) -> Option<&E>;
+}
+
+impl<E: std::error::Error + Send + Sync + 'static> ProjectError<E> for std::io::Error {
+    fn project_error(&self) -> Option<&E> {
+        self.get_ref().and_then(|e| e.downcast_ref())
+    }
+}
+
+impl<E: std::error::Error + 'static> ProjectError<E> for Box<dyn std::error::Error> {
+    fn project_error(&self) -> Option<&E> {
+        self.downcast_ref()
+    }
+}
+
+impl<E: std::error::Error + 'static> ProjectError<E> for Box<dyn std::error::Error + Send + Sync> {
+    fn project_error(&self) -> Option<&E> {
+        self.downcast_ref()
+    }
+}
@@ -0,0 +1,179 @@
+//! Model-checks the interleavings of thread-safe metrics with loom, which
+//! explores every ordering of their atomic operations and locks:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test -p metered --release --lib loom_tests
+//! ```
+//!
+//! Other tests do not run within loom models, and thus fail with `--cfg loom`.
+
+use crate::{
+    atomic::{AcqRel, AtomicF64, AtomicInt, OrderedAtomicInt},
+    clear::Clear,
+    common::{ConcurrencyLimit, InFlight, QueueWhenFull},
+    int_counter::{CheckedCounter, SaturatingCounter},
+    measure,
+    metric::Counter,
+};
+use loom::{cell::UnsafeCell, sync::Arc, thread};
+
+/// Runs `f` on two loom threads, and returns the shared value once joined
+fn in_two_threads<T, F>(value: T, f: F) -> Arc<T>
+where
+    T: Send + Sync + 'static,
+    F: Fn(&T) + Send + Sync + 'static,
+{
+    let value = Arc::new(value);
+    let f = Arc::new(f);
+    let threads: Vec<_> = (0..2)
+        .map(|_| {
+            let value = Arc::clone(&value);
+            let f = Arc::clone(&f);
+            thread::spawn(move || f(&value))
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    value
+}
+
+#[test]
+fn atomic_int_increments_are_not_lost() {
+    loom::model(|| {
+        let count = in_two_threads(AtomicInt::<u64>::new(0), |count| {
+            count.incr();
+        });
+        assert_eq!(count.get(), 2);
+    });
+}
+
+#[test]
+fn acq_rel_atomic_int_publishes_preceding_writes() {
+    loom::model(|| {
+        let data = Arc::new(UnsafeCell::new(0));
+        let ready: Arc<OrderedAtomicInt<u64, AcqRel>> = Arc::default();
+
+        let writer = {
+            let (data, ready) = (Arc::clone(&data), Arc::clone(&ready));
+            thread::spawn(move || {
+                // SAFETY: the reader only reads once the increment is visible
+                data.with_mut(|data| unsafe { *data = 42 });
+                ready.incr();
+            })
+        };
+
+        if ready.get() == 1 {
+            // Loom reports a data race if the write is not visible
+            assert_eq!(data.with(|data| unsafe { *data }), 42);
+        }
+        writer.join().unwrap();
+    });
+}
+
+#[test]
+fn in_flight_returns_to_zero() {
+    loom::model(|| {
+        let in_flight = in_two_threads(InFlight::<AtomicInt<u64>>::default(), |in_flight| {
+            measure!(in_flight, {
+                assert!(in_flight.get() >= 1);
+            });
+        });
+        assert_eq!(in_flight.get(), 0);
+    });
+}
+
+#[test]
+fn saturating_counter_clamps_concurrent_increments() {
+    loom::model(|| {
+        let counter = SaturatingCounter(AtomicInt::<u8>::new(250));
+        let counter = in_two_threads(counter, |counter| counter.incr_by(5));
+        assert_eq!(counter.0.get(), u8::MAX);
+    });
+}
+
+#[test]
+fn checked_counter_counts_one_overflow() {
+    loom::model(|| {
+        let counter: CheckedCounter<AtomicInt<u8>> = CheckedCounter::default();
+        counter.count.set(250);
+        let counter = in_two_threads(counter, |counter| counter.incr_by(5));
+        assert_eq!(counter.count.get(), 4);
+        assert_eq!(counter.overflows.get(), 1);
+    });
+}
+
+#[test]
+fn float_additions_are_not_lost() {
+    loom::model(|| {
+        let gauge = in_two_threads(AtomicF64::new(0.0), |gauge| {
+            gauge.add(0.5);
+        });
+        assert_eq!(gauge.get(), 1.0);
+    });
+}
+
+#[test]
+fn queued_calls_are_woken_up() {
+    loom::model(|| {
+        let limit: ConcurrencyLimit<1, QueueWhenFull> = ConcurrencyLimit::default();
+        let limit = in_two_threads(limit, |limit| {
+            measure!(limit, {
+                assert_eq!(limit.in_flight.get(), 1);
+            }, abort => unreachable!());
+        });
+        assert_eq!(limit.in_flight.get(), 0);
+        assert_eq!(limit.rejections.get(), 0);
+    });
+}
+
+#[cfg(feature = "histograms")]
+mod histograms {
+    use super::*;
+    use crate::{
+        common::{AtomicTxPerSec, RecordThroughput},
+        hdr_histogram::AtomicHdrHistogram,
+        metric::Histogram,
+        simulation::{SimInstant, Simulation},
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn serialization_sees_whole_records() {
+        loom::model(|| {
+            let histogram = Arc::new(AtomicHdrHistogram::with_bound(1_000));
+            let recorder = {
+                let histogram = Arc::clone(&histogram);
+                thread::spawn(move || {
+                    histogram.record(1);
+                    histogram.record(2);
+                })
+            };
+
+            let serialized = serde_json::to_value(&*histogram).unwrap();
+            let samples = serialized["samples"].as_u64().unwrap();
+            assert!(samples <= 2);
+            recorder.join().unwrap();
+            assert_eq!(histogram.histogram().len(), 2);
+
+            histogram.clear();
+            assert!(histogram.histogram().is_empty());
+        });
+    }
+
+    #[test]
+    fn one_thread_closes_a_throughput_window() {
+        loom::model(|| {
+            let simulation = Simulation::start(0);
+            let tps: AtomicTxPerSec<SimInstant> = AtomicTxPerSec::default();
+            tps.on_result();
+            simulation.advance(Duration::from_millis(1_500));
+
+            let tps = in_two_threads(tps, |tps| tps.on_result());
+            let (histogram, count) = tps.window();
+            assert_eq!(histogram.len(), 1);
+            assert_eq!(histogram.max(), 1);
+            assert_eq!(count, 2);
+        });
+    }
+}
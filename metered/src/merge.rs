@@ -0,0 +1,188 @@
+//! A module providing a Merge trait, to combine per-worker or per-shard
+//! registries into one before export.
+
+use std::sync::Arc;
+
+/// The `Merge` trait adds the values recorded by another metric of the same
+/// type to a metric: counters and gauges are summed, and histograms combine
+/// their samples.
+///
+/// Stock counters, gauges and histograms implement it, along with the stock
+/// metrics built on them, except those whose state cannot be combined such as
+/// reservoirs, sliding windows, moving averages or P² estimates. Registries
+/// generated with the `merge = true` option of `#[metered]` implement it by
+/// merging their metrics:
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{merge::Merge, metered, HitCount, ResponseTime};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Worker {
+///     metrics: WorkerMetrics,
+/// }
+///
+/// #[metered(registry = WorkerMetrics, merge = true)]
+/// impl Worker {
+///     #[measure([HitCount, ResponseTime])]
+///     pub fn call(&self) {}
+/// }
+///
+/// let workers = [Worker::default(), Worker::default()];
+/// workers[0].call();
+/// workers[1].call();
+/// workers[1].call();
+///
+/// let total = WorkerMetrics::default();
+/// for worker in workers.iter() {
+///     total.merge_from(&worker.metrics);
+/// }
+/// assert_eq!(total.call.hit_count.get(), 3);
+/// assert_eq!(total.call.response_time.histogram().len(), 3);
+/// ```
+///
+/// Metrics recording the time of events, such as `LastCalled`, keep the most
+/// recent time.
+pub trait Merge {
+    /// Adds the values of `other` to self.
+    fn merge_from(&self, other: &Self);
+}
+
+/// Merges registries into a new one, e.g. to report the metrics of a pool of
+/// workers as a single service-level view.
+///
+/// The result is a point-in-time copy: it can be serialized, printed or
+/// passed to [`Snapshot::of`], but does not follow later updates of the
+/// registries.
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{merge, metered, snapshot::Snapshot, HitCount};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Worker {
+///     metrics: WorkerMetrics,
+/// }
+///
+/// #[metered(registry = WorkerMetrics, merge = true)]
+/// impl Worker {
+///     #[measure(HitCount)]
+///     pub fn call(&self) {}
+/// }
+///
+/// let workers: Vec<Worker> = (0..4).map(|_| Worker::default()).collect();
+/// for worker in workers.iter() {
+///     worker.call();
+/// }
+///
+/// let service = merge::aggregate(workers.iter().map(|worker| &worker.metrics));
+/// let snapshot = Snapshot::of(&service).unwrap();
+/// assert_eq!(snapshot.get("call.hit_count"), Some(4.0));
+/// ```
+///
+/// [`Snapshot::of`]: crate::snapshot::Snapshot::of
+pub fn aggregate<'a, R, I>(registries: I) -> R
+where
+    R: Merge + Default + 'a,
+    I: IntoIterator<Item = &'a R>,
+{
+    let aggregate = R::default();
+    for registry in registries {
+        aggregate.merge_from(registry);
+    }
+    aggregate
+}
+
+impl<T: Merge> Merge for Arc<T> {
+    fn merge_from(&self, other: &Self) {
+        (**self).merge_from(other);
+    }
+}
+
+impl<T: Merge> Merge for &T {
+    fn merge_from(&self, other: &Self) {
+        (*self).merge_from(other);
+    }
+}
+
+#[cfg(all(test, feature = "histograms", not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{
+        common::{LastCalled, Throughput},
+        hdr_histogram::AtomicHdrHistogram,
+        measure, Histogram, HitCount, InFlight, ResponseTime,
+    };
+    use std::cell::Cell;
+
+    #[test]
+    fn sums_counters_and_gauges() {
+        let a: HitCount = HitCount::default();
+        let b: HitCount = HitCount::default();
+        measure!(&a, {});
+        measure!(&b, {});
+        measure!(&b, {});
+        a.merge_from(&b);
+        assert_eq!(a.get(), 3);
+        assert_eq!(b.get(), 2);
+
+        let in_flight: InFlight<Cell<u64>> = InFlight::default();
+        in_flight.0.set(2);
+        in_flight.merge_from(&in_flight);
+        assert_eq!(in_flight.current(), 4);
+    }
+
+    #[test]
+    fn combines_histograms() {
+        let a = AtomicHdrHistogram::with_bound(1_000);
+        let b = AtomicHdrHistogram::with_bound(10);
+        a.record(5);
+        b.record(10);
+        b.record(10);
+        a.merge_from(&b);
+        assert_eq!(a.histogram().len(), 3);
+        assert_eq!(a.histogram().max(), 10);
+
+        // Values above the bound saturate
+        b.merge_from(&a);
+        assert_eq!(b.histogram().len(), 5);
+
+        // Merging with itself does not deadlock
+        let response_time: ResponseTime = ResponseTime::default();
+        response_time.record(1);
+        response_time.merge_from(&response_time);
+        assert_eq!(response_time.histogram().len(), 2);
+
+        let throughput: Throughput = Throughput::default();
+        measure!(&throughput, {});
+        throughput.merge_from(&throughput);
+        assert_eq!(throughput.window().1, 2);
+    }
+
+    #[test]
+    fn aggregates_registries() {
+        let counters: Vec<HitCount> = (0..3).map(|_| HitCount::default()).collect();
+        for (counter, count) in counters.iter().zip(0..) {
+            counter.incr_by(count);
+        }
+        assert_eq!(aggregate(&counters).get(), 3);
+        assert_eq!(aggregate::<HitCount, _>(&[]).get(), 0);
+    }
+
+    #[test]
+    fn keeps_most_recent_call() {
+        let a = LastCalled::default();
+        let b = LastCalled::default();
+        measure!(&b, {});
+        a.merge_from(&b);
+        assert_eq!(a.get(), b.get());
+        a.merge_from(&LastCalled::default());
+        assert_eq!(a.get(), b.get());
+    }
+}
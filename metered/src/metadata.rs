@@ -0,0 +1,306 @@
+//! A module providing metric metadata, such as types, units and descriptions,
+//! for exporters.
+
+use std::fmt;
+
+/// The type of a metric, in the Prometheus sense.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum MetricType {
+    /// A monotonically increasing value
+    Counter,
+    /// A value that can go up and down
+    Gauge,
+    /// A distribution reported as quantiles
+    Summary,
+    /// A distribution reported as buckets
+    Histogram,
+    /// A metric of unknown or composite type
+    #[default]
+    Untyped,
+}
+
+impl MetricType {
+    /// Get the name of the type, as written in Prometheus `# TYPE` lines
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+            MetricType::Summary => "summary",
+            MetricType::Histogram => "histogram",
+            MetricType::Untyped => "untyped",
+        }
+    }
+}
+
+impl fmt::Display for MetricType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The unit of the values of a metric.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Unit {
+    /// A plain number, such as a count
+    #[default]
+    None,
+    /// Seconds
+    Seconds,
+    /// Milliseconds
+    Milliseconds,
+    /// Microseconds
+    Microseconds,
+    /// Nanoseconds
+    Nanoseconds,
+    /// Bytes
+    Bytes,
+    /// Requests, or calls
+    Requests,
+    /// Requests, or calls, per second
+    RequestsPerSecond,
+    /// A ratio between 0 and 1
+    Ratio,
+}
+
+impl Unit {
+    /// Get the unit of time measured by a time source, from its `ONE_SEC`
+    /// resolution.
+    pub fn from_time_resolution(one_sec: u64) -> Self {
+        match one_sec {
+            1 => Unit::Seconds,
+            1_000 => Unit::Milliseconds,
+            1_000_000 => Unit::Microseconds,
+            1_000_000_000 => Unit::Nanoseconds,
+            _ => Unit::None,
+        }
+    }
+
+    /// Get the base unit values should be converted to by exporters following
+    /// Prometheus conventions, and the factor to multiply values by.
+    ///
+    /// Durations are converted to seconds, other units are left untouched.
+    ///
+    /// ```rust
+    /// use metered::metadata::Unit;
+    ///
+    /// assert_eq!(Unit::Milliseconds.to_base(), (Unit::Seconds, 0.001));
+    /// assert_eq!(Unit::Bytes.to_base(), (Unit::Bytes, 1.0));
+    /// ```
+    pub fn to_base(&self) -> (Unit, f64) {
+        match self {
+            Unit::Milliseconds => (Unit::Seconds, 1e-3),
+            Unit::Microseconds => (Unit::Seconds, 1e-6),
+            Unit::Nanoseconds => (Unit::Seconds, 1e-9),
+            unit => (*unit, 1.0),
+        }
+    }
+
+    /// Get the suffix exporters following Prometheus conventions append to
+    /// metric names, e.g. `_seconds`, or an empty string for plain numbers.
+    ///
+    /// Exporters converting values with [`Unit::to_base`] should use the suffix
+    /// of the base unit.
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Unit::None => "",
+            Unit::Seconds => "_seconds",
+            Unit::Milliseconds => "_milliseconds",
+            Unit::Microseconds => "_microseconds",
+            Unit::Nanoseconds => "_nanoseconds",
+            Unit::Bytes => "_bytes",
+            Unit::Requests => "_requests",
+            Unit::RequestsPerSecond => "_requests_per_second",
+            Unit::Ratio => "_ratio",
+        }
+    }
+}
+
+/// The metadata of a metric.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MetricMetadata {
+    /// The type of the metric
+    pub metric_type: MetricType,
+    /// The unit of the metric's values
+    pub unit: Unit,
+    /// A human-readable description of the metric
+    pub help: Option<&'static str>,
+}
+
+impl MetricMetadata {
+    /// Creates metadata without description
+    pub const fn new(metric_type: MetricType, unit: Unit) -> Self {
+        MetricMetadata {
+            metric_type,
+            unit,
+            help: None,
+        }
+    }
+
+    /// Sets the description
+    pub const fn with_help(mut self, help: &'static str) -> Self {
+        self.help = Some(help);
+        self
+    }
+}
+
+/// A trait for metrics declaring their metadata.
+///
+/// Metrics that do not implement it are reported as untyped, unitless metrics
+/// by registries.
+pub trait Describe {
+    /// Returns the metadata of the metric
+    fn metadata() -> MetricMetadata;
+}
+
+/// The metadata of a metric in a registry.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MetricDescription {
+    /// The path to the metric in the registry, i.e. its serialization keys
+    pub path: Vec<&'static str>,
+    /// The metadata of the metric
+    pub metadata: MetricMetadata,
+}
+
+impl MetricDescription {
+    /// Get the name of the metric for Prometheus exporters: the path joined
+    /// with underscores, followed by the suffix of the metric's base unit.
+    pub fn prometheus_name(&self) -> String {
+        let mut name = self.path.join("_");
+        name.push_str(self.metadata.unit.to_base().0.suffix());
+        name
+    }
+
+    /// Writes the Prometheus `# HELP` (if there is a description) and
+    /// `# TYPE` lines of the metric.
+    pub fn write_prometheus_header<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        let name = self.prometheus_name();
+        if let Some(help) = self.metadata.help {
+            let help = help.replace('\\', "\\\\").replace('\n', "\\n");
+            writeln!(w, "# HELP {} {}", name, help)?;
+        }
+        writeln!(w, "# TYPE {} {}", name, self.metadata.metric_type)
+    }
+}
+
+/// A trait for registries describing the metrics they contain, implemented by
+/// the registries generated by `#[metered]`.
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{
+///     common::DeadlineMiss,
+///     metadata::{DescribeMetrics, MetricType, Unit},
+///     metered, HitCount, ResponseTime,
+/// };
+/// use std::time::Duration;
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics)]
+/// impl Service {
+///     #[measure([HitCount, ResponseTime])]
+///     #[measure(type = DeadlineMiss, init = DeadlineMiss::new(Duration::from_millis(50)))]
+///     pub fn call(&self) {}
+/// }
+///
+/// let descriptions = ServiceMetrics::describe_metrics();
+/// assert_eq!(descriptions[1].path, ["call", "response_time"]);
+/// assert_eq!(descriptions[1].metadata.metric_type, MetricType::Summary);
+/// assert_eq!(descriptions[1].metadata.unit, Unit::Milliseconds);
+/// // Metrics not implementing `Describe` are untyped
+/// assert_eq!(descriptions[2].metadata.metric_type, MetricType::Untyped);
+///
+/// let mut header = String::new();
+/// descriptions[1].write_prometheus_header(&mut header).unwrap();
+/// assert_eq!(header, "# TYPE call_response_time_seconds summary\n");
+/// ```
+///
+/// Descriptions are provided with the `help` option of the `measure`
+/// attribute:
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{metadata::DescribeMetrics, metered, ResponseTime};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Page {
+///     metrics: PageMetrics,
+/// }
+///
+/// #[metered(registry = PageMetrics)]
+/// impl Page {
+///     #[measure(type = ResponseTime, help = "Time to render a page")]
+///     pub fn render(&self) {}
+/// }
+///
+/// let descriptions = PageMetrics::describe_metrics();
+/// assert_eq!(descriptions[0].metadata.help, Some("Time to render a page"));
+///
+/// let mut header = String::new();
+/// descriptions[0].write_prometheus_header(&mut header).unwrap();
+/// let mut lines = header.lines();
+/// assert_eq!(lines.next(), Some("# HELP render_response_time_seconds Time to render a page"));
+/// assert_eq!(lines.next(), Some("# TYPE render_response_time_seconds summary"));
+/// ```
+pub trait DescribeMetrics {
+    /// Returns the descriptions of the registry's metrics, in serialization
+    /// order.
+    fn describe_metrics() -> Vec<MetricDescription>;
+}
+
+/// Helpers for the code generated by `#[metered]`, falling back to default
+/// metadata for metrics not implementing [`Describe`].
+///
+/// `(&MetadataOf::<T>::new()).metric_metadata()` resolves to `ViaDescribe`
+/// when `T: Describe`, and to `ViaDefault`, which needs one more auto-ref,
+/// otherwise.
+#[doc(hidden)]
+pub mod __private {
+    use super::{Describe, MetricMetadata};
+    use std::marker::PhantomData;
+
+    pub struct MetadataOf<T>(PhantomData<T>);
+
+    impl<T> MetadataOf<T> {
+        #[allow(clippy::new_without_default)]
+        pub fn new() -> Self {
+            MetadataOf(PhantomData)
+        }
+    }
+
+    pub trait ViaDescribe {
+        fn metric_metadata(&self) -> MetricMetadata;
+    }
+
+    impl<T: Describe> ViaDescribe for MetadataOf<T> {
+        fn metric_metadata(&self) -> MetricMetadata {
+            T::metadata()
+        }
+    }
+
+    pub trait ViaDefault {
+        fn metric_metadata(&self) -> MetricMetadata;
+    }
+
+    impl<T> ViaDefault for &MetadataOf<T> {
+        fn metric_metadata(&self) -> MetricMetadata {
+            MetricMetadata::default()
+        }
+    }
+}
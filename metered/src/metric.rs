@@ -4,7 +4,7 @@ use crate::clear::{Clear, Clearable};
 /// Re-export `aspect-rs`'s types to avoid crates depending on it.
 pub use aspect::{Advice, Enter, OnResult, OnResultMut};
 use serde::Serialize;
-use std::marker::PhantomData;
+use std::{convert::TryFrom, marker::PhantomData};
 
 /// A trait to implement to be used in the `measure!` macro
 ///
@@ -12,10 +12,69 @@ use std::marker::PhantomData;
 ///
 /// The return type, R, of the expression can be captured to perform special
 /// handling.
-pub trait Metric<R>: Default + OnResultMut<R> + Clear + Serialize {}
+///
+/// Metrics usually implement `Default`, to be built by registries. Metrics
+/// needing runtime configuration can instead be built from a
+/// [`MetricBuilder`], with the `init` option of the `measure` attribute.
+///
+/// `Default` is not a supertrait of `Metric`: generic code building metrics
+/// should require `M: Metric<R> + Default` explicitly.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot measure expressions returning `{R}`",
+    label = "`{Self}` does not measure `{R}`",
+    note = "a `measure` attribute on an impl block applies its metrics to every measured \
+            method of the block, whatever it returns"
+)]
+pub trait Metric<R>: OnResultMut<R> + Clear + Serialize {}
+
+/// A trait for values building metrics, such as configured builders.
+///
+/// Registries generated by `#[metered]` build metrics with `Default`, unless
+/// the `init` option of the `measure` attribute gives an expression evaluating
+/// to a `MetricBuilder`, e.g. to configure the histogram of a `ResponseTime` or
+/// metrics without `Default`:
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{metered, ResponseTime};
+/// use std::time::Duration;
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics)]
+/// impl Service {
+///     #[measure(type = ResponseTime, init = ResponseTime::builder().bound(Duration::from_secs(1)))]
+///     pub fn call(&self) {}
+/// }
+///
+/// let service = Service::default();
+/// assert_eq!(service.metrics.call.response_time.histogram().bound(), 1_000);
+/// ```
+///
+/// Every metric is its own builder, so `init` also accepts a metric.
+pub trait MetricBuilder<M> {
+    /// Builds the metric
+    fn build_metric(self) -> M;
+}
+
+impl<M> MetricBuilder<M> for M {
+    fn build_metric(self) -> M {
+        self
+    }
+}
 
 // Needed to force `measure!` to work only with the [`Metric`] trait.
 #[doc(hidden)]
+#[inline]
 pub fn on_result<R, A: Metric<R>>(metric: &A, _enter: <A as Enter>::E, _result: &mut R) -> Advice {
     metric.on_result(_enter, _result)
 }
@@ -29,34 +88,70 @@ pub struct ExitGuard<'a, R, M: Metric<R>> {
 impl<'a, R, M: Metric<R>> ExitGuard<'a, R, M> {
     /// Enter a metric and create the guard for its exit.
     /// This calls [`aspect::Enter::enter`] on the metric internally.
+    #[inline]
     pub fn new(metric: &'a M) -> Self {
         Self {
             metric,
-            enter: Some(metric.enter()),
+            enter: Some(internal_cost!(measure, metric.enter())),
             _phantom: PhantomData,
         }
     }
 
     /// If no unexpected exit occurred, record the expression's result.
+    #[inline]
     pub fn on_result(mut self, result: &mut R) {
         if let Some(enter) = self.enter.take() {
-            self.metric.on_result(enter, result);
+            internal_cost!(measure, self.metric.on_result(enter, result));
         } else {
             // OnResult called twice - we ignore
         }
     }
 }
 
+impl<'a, R, M: Metric<R> + Gate> ExitGuard<'a, R, M> {
+    /// Returns true if the metric advised to abort the expression when it was
+    /// entered.
+    #[inline]
+    pub fn should_abort(&self) -> bool {
+        match self.enter {
+            Some(ref enter) => self.metric.should_abort(enter),
+            None => false,
+        }
+    }
+}
+
 impl<'a, R, M: Metric<R>> Drop for ExitGuard<'a, R, M> {
+    #[inline]
     fn drop(&mut self) {
         if let Some(enter) = self.enter.take() {
-            self.metric.leave_scope(enter);
+            internal_cost!(measure, self.metric.leave_scope(enter));
         } else {
             // on_result was called, so the result was already recorded
         }
     }
 }
 
+/// A trait for metrics that can reject an expression before it is evaluated,
+/// for instance to limit concurrency.
+///
+/// The decision is made when entering the metric, and is carried by the value
+/// returned by [`Enter::enter`]. When a gate advises to abort, the expression
+/// is not evaluated and the `abort` expression given to the `measure!` macro
+/// (or to the `measure` attribute) is returned instead. The metric is still
+/// notified with [`OnResultMut::leave_scope`].
+pub trait Gate: Enter {
+    /// Returns true if the expression should not be evaluated.
+    fn should_abort(&self, enter: &<Self as Enter>::E) -> bool;
+}
+
+/// A trait for metrics recording values derived from the results of measured
+/// expressions, given by the `record` option of the `measure` attribute, such
+/// as [`ValueHistogram`](crate::common::ValueHistogram).
+pub trait RecordValue {
+    /// Records a value
+    fn record_value(&self, value: u64);
+}
+
 /// A trait for Counters
 pub trait Counter: Default + Clear + Clearable + Serialize {
     /// Increment the counter
@@ -69,6 +164,38 @@ pub trait Counter: Default + Clear + Clearable + Serialize {
     /// Supplying a count larger than the underlying counter's remaining
     /// capacity will wrap like [`u8::wrapping_add`] and similar methods.
     fn incr_by(&self, count: usize);
+
+    /// Get the current value of the counter, saturating at `u64::MAX`
+    ///
+    /// The default implementation reads the value the counter serializes,
+    /// stock counters read it directly.
+    fn value(&self) -> u64 {
+        serialized_value(self)
+    }
+
+    /// Get the current value of the counter and clear it, saturating at
+    /// `u64::MAX`
+    ///
+    /// The default implementation reads the value then clears the counter,
+    /// losing increments made in between: stock counters swap it with 0 in
+    /// one step.
+    fn take(&self) -> u64 {
+        let value = self.value();
+        self.clear();
+        value
+    }
+
+    /// Get a counter with the current state of self, and clear self, e.g. to
+    /// serialize it in the same shape as self
+    ///
+    /// The default implementation builds it from [`take`](Counter::take), which
+    /// drops any state besides the value: stock counters swap their state with
+    /// a cleared one.
+    fn take_counter(&self) -> Self {
+        let taken = Self::default();
+        taken.incr_by(usize::try_from(self.take()).unwrap_or(usize::MAX));
+        taken
+    }
 }
 
 /// A trait for Gauges
@@ -94,6 +221,45 @@ pub trait Gauge: Default + Clear + Serialize {
     /// Supplying a count larger than the underlying counter's current value
     /// will wrap like [`u8::wrapping_sub`] and similar methods.
     fn decr_by(&self, count: usize);
+
+    /// Get the current value of the gauge, saturating at `u64::MAX`
+    ///
+    /// The default implementation reads the value the gauge serializes, stock
+    /// gauges read it directly.
+    fn value(&self) -> u64 {
+        serialized_value(self)
+    }
+}
+
+/// A trait for Gauges holding a fractional value, such as a ratio or a
+/// temperature
+pub trait FloatValue: Gauge {
+    /// Get the current value of the gauge
+    fn get(&self) -> f64;
+
+    /// Set the gauge to a value
+    fn set(&self, value: f64);
+
+    /// Add a value to the gauge, which may be negative
+    fn add(&self, value: f64);
+
+    /// Subtract a value from the gauge
+    fn sub(&self, value: f64) {
+        self.add(-value)
+    }
+}
+
+/// Reads the single number a value serializes, or 0 if it does not serialize
+/// exactly one number
+fn serialized_value<T: Serialize + ?Sized>(value: &T) -> u64 {
+    use crate::flatten::{raw_values, Value};
+
+    match raw_values(value).as_deref() {
+        Ok([(_, Value::Unsigned(v))]) => *v,
+        Ok([(_, Value::Signed(v))]) => u64::try_from(*v).unwrap_or(0),
+        Ok([(_, Value::Float(v))]) => *v as u64,
+        _ => 0,
+    }
 }
 
 /// A trait for Histograms
@@ -101,9 +267,102 @@ pub trait Histogram: Clear + Serialize {
     /// Build a new histogram with the given max bounds
     fn with_bound(max_value: u64) -> Self;
 
+    /// Build a new histogram with the given max bounds, keeping `sig_figs`
+    /// significant figures of recorded values
+    ///
+    /// The default implementation ignores the precision, stock HdrHistograms
+    /// support it.
+    fn with_precision(max_value: u64, sig_figs: u8) -> Self
+    where
+        Self: Sized,
+    {
+        let _ = sig_figs;
+        Self::with_bound(max_value)
+    }
+
     /// Record a value to the histogram.
     ///
     /// It will saturate if the value is higher than the histogram's
     /// `max_value`.
     fn record(&self, value: u64);
 }
+
+/// A trait for histograms whose recorded values can be copied out, e.g. to
+/// build custom reports.
+///
+/// Stock histograms implement it with the type of their `histogram()` method,
+/// and `ResponseTime` and `Throughput` expose it as `snapshot_histogram()`.
+pub trait SnapshotHistogram {
+    /// The copy of the recorded values
+    type Snapshot;
+
+    /// Get a copy of the recorded values
+    fn snapshot_histogram(&self) -> Self::Snapshot;
+}
+
+/// A plain copy of the statistics of a histogram, for programmatic
+/// consumption in tests or admission-control logic.
+///
+/// Stock histograms convert into it with `From`, without serializing. Its
+/// values are 0 if the histogram is empty.
+///
+#[cfg_attr(not(feature = "histograms"), doc = "```ignore")]
+#[cfg_attr(feature = "histograms", doc = "```rust")]
+/// use metered::{hdr_histogram::AtomicHdrHistogram, metric::HistogramSnapshot, Histogram};
+///
+/// let histogram = AtomicHdrHistogram::with_bound(1000);
+/// histogram.record(10);
+/// histogram.record(20);
+///
+/// let snapshot = HistogramSnapshot::from(&histogram);
+/// assert_eq!(snapshot.count, 2);
+/// assert_eq!(snapshot.min, 10);
+/// assert_eq!(snapshot.mean, 15.0);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HistogramSnapshot {
+    /// The number of recorded values
+    pub count: u64,
+    /// The lowest recorded value
+    pub min: u64,
+    /// The highest recorded value
+    pub max: u64,
+    /// The mean of recorded values
+    pub mean: f64,
+    /// The value at the 50% quantile
+    pub p50: u64,
+    /// The value at the 90% quantile
+    pub p90: u64,
+    /// The value at the 95% quantile
+    pub p95: u64,
+    /// The value at the 99% quantile
+    pub p99: u64,
+    /// The value at the 99.9% quantile
+    pub p999: u64,
+}
+
+impl HistogramSnapshot {
+    /// Builds a snapshot from the statistics of a histogram
+    pub(crate) fn new<F: Fn(f64) -> u64>(
+        count: u64,
+        min: u64,
+        max: u64,
+        mean: f64,
+        quantile: F,
+    ) -> Self {
+        if count == 0 {
+            return HistogramSnapshot::default();
+        }
+        HistogramSnapshot {
+            count,
+            min,
+            max,
+            mean,
+            p50: quantile(0.5),
+            p90: quantile(0.9),
+            p95: quantile(0.95),
+            p99: quantile(0.99),
+            p999: quantile(0.999),
+        }
+    }
+}
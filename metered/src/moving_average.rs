@@ -0,0 +1,268 @@
+//! A module providing thread-safe and unsynchronized implementations of simple
+//! and exponential moving averages.
+
+use crate::{
+    clear::{self, Clear, Clearable},
+    common::ResponseTime,
+    metric::{Histogram, SnapshotHistogram},
+    sync::Mutex,
+    time_source::StdInstant,
+};
+use serde::{Serialize, Serializer};
+use std::cell::RefCell;
+
+/// A metric tracking moving averages of the response time of an expression,
+/// over the last `N` calls (100 by default).
+///
+/// This is a lighter-weight alternative to histogram percentiles. Other values
+/// than durations can be recorded using [`Histogram::record`]:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{measure, moving_average::MovingAverage, Histogram};
+///
+/// let moving_average: MovingAverage<10> = MovingAverage::default();
+///
+/// measure!(&moving_average, {
+///     std::thread::sleep(std::time::Duration::from_millis(10));
+/// });
+/// assert!(moving_average.histogram().simple() >= 10.0);
+///
+/// moving_average.record(0);
+/// assert_eq!(moving_average.histogram().len(), 2);
+/// ```
+pub type MovingAverage<const N: usize = 100, T = StdInstant> =
+    ResponseTime<AtomicMovingAverages<N>, T>;
+
+/// A thread-safe implementation of moving averages
+pub struct AtomicMovingAverages<const N: usize> {
+    inner: Mutex<MovingAverages<N>>,
+}
+
+impl<const N: usize> AtomicMovingAverages<N> {
+    /// Returns a cloned snapshot of the inner averages.
+    pub fn histogram(&self) -> MovingAverages<N> {
+        self.inner.lock().clone()
+    }
+}
+
+impl<const N: usize> SnapshotHistogram for AtomicMovingAverages<N> {
+    type Snapshot = MovingAverages<N>;
+
+    fn snapshot_histogram(&self) -> MovingAverages<N> {
+        self.histogram()
+    }
+}
+
+impl<const N: usize> Histogram for AtomicMovingAverages<N> {
+    fn with_bound(max_value: u64) -> Self {
+        AtomicMovingAverages {
+            inner: Mutex::new(MovingAverages::with_bound(max_value)),
+        }
+    }
+
+    fn record(&self, value: u64) {
+        lock_histogram!(self.inner).record(value);
+    }
+}
+
+impl<const N: usize> Clear for AtomicMovingAverages<N> {
+    fn clear(&self) {
+        self.inner.lock().clear();
+    }
+}
+
+impl<const N: usize> Clearable for AtomicMovingAverages<N> {
+    fn is_cleared(&self) -> bool {
+        self.inner.lock().is_empty()
+    }
+}
+
+impl<const N: usize> Serialize for AtomicMovingAverages<N> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        internal_cost!(serialization, {
+            let mut inner = self.inner.lock();
+            let result = Serialize::serialize(&*inner, serializer);
+            // Under the same lock, so that values are serialized exactly once
+            if clear::is_clearing_serialization() {
+                inner.clear();
+            }
+            result
+        })
+    }
+}
+
+use std::{fmt, fmt::Debug};
+impl<const N: usize> Debug for AtomicMovingAverages<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock();
+        write!(f, "AtomicMovingAverages {{ {:?} }}", &*inner)
+    }
+}
+
+/// Simple and exponential moving averages over the last `N` recorded values
+///
+/// The simple moving average is the mean of the last `N` values, kept in a
+/// ring buffer. The exponential moving average weighs all values with a
+/// smoothing factor of `2 / (N + 1)`, the usual choice for an `N`-period
+/// average.
+#[derive(Clone)]
+pub struct MovingAverages<const N: usize> {
+    max_value: u64,
+    window: [u64; N],
+    sum: u64,
+    count: u64,
+    exponential: f64,
+}
+
+impl<const N: usize> MovingAverages<N> {
+    /// Instantiates new moving averages, saturating values above `max_value`.
+    pub fn with_bound(max_value: u64) -> Self {
+        MovingAverages {
+            max_value,
+            window: [0; N],
+            sum: 0,
+            count: 0,
+            exponential: 0.0,
+        }
+    }
+
+    /// Records a value
+    ///
+    /// This is a saturating record: if the value is higher than `max_value`,
+    /// `max_value` will be recorded instead.
+    pub fn record(&mut self, value: u64) {
+        let value = value.min(self.max_value);
+        if N > 0 {
+            let slot = &mut self.window[(self.count % N as u64) as usize];
+            self.sum = self.sum.wrapping_sub(*slot).wrapping_add(value);
+            *slot = value;
+        }
+
+        if self.count == 0 {
+            self.exponential = value as f64;
+        } else {
+            let alpha = 2.0 / (N as f64 + 1.0);
+            self.exponential += alpha * (value as f64 - self.exponential);
+        }
+        self.count += 1;
+    }
+
+    /// Clears the recorded values
+    pub fn clear(&mut self) {
+        self.window = [0; N];
+        self.sum = 0;
+        self.count = 0;
+        self.exponential = 0.0;
+    }
+
+    /// Get the number of recorded values.
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    /// Check if no value was recorded
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Get the mean of the last `N` values, or 0 if no value was recorded.
+    pub fn simple(&self) -> f64 {
+        let len = self.count.min(N as u64);
+        if len == 0 {
+            0.0
+        } else {
+            self.sum as f64 / len as f64
+        }
+    }
+
+    /// Get the exponential moving average, or 0 if no value was recorded.
+    pub fn exponential(&self) -> f64 {
+        self.exponential
+    }
+}
+
+impl<const N: usize> Serialize for MovingAverages<N> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("samples", &self.len())?;
+        map.serialize_entry("sma", &self.simple())?;
+        map.serialize_entry("ewma", &self.exponential())?;
+        map.end()
+    }
+}
+
+impl<const N: usize> Debug for MovingAverages<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "MovingAverages {{ samples: {}, sma: {}, ewma: {} }}",
+            self.len(),
+            self.simple(),
+            self.exponential()
+        )
+    }
+}
+
+impl<const N: usize> Histogram for RefCell<MovingAverages<N>> {
+    fn with_bound(max_value: u64) -> Self {
+        RefCell::new(MovingAverages::with_bound(max_value))
+    }
+
+    fn record(&self, value: u64) {
+        self.borrow_mut().record(value);
+    }
+}
+
+impl<const N: usize> Clear for RefCell<MovingAverages<N>> {
+    fn clear(&self) {
+        self.borrow_mut().clear();
+    }
+}
+
+impl<const N: usize> Clearable for RefCell<MovingAverages<N>> {
+    fn is_cleared(&self) -> bool {
+        self.borrow().is_empty()
+    }
+}
+
+impl<const N: usize> SnapshotHistogram for RefCell<MovingAverages<N>> {
+    type Snapshot = MovingAverages<N>;
+
+    fn snapshot_histogram(&self) -> MovingAverages<N> {
+        self.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_averages() {
+        let mut averages = MovingAverages::<3>::with_bound(u64::MAX);
+        assert_eq!(averages.simple(), 0.0);
+
+        averages.record(10);
+        assert_eq!(averages.simple(), 10.0);
+        assert_eq!(averages.exponential(), 10.0);
+
+        averages.record(20);
+        averages.record(30);
+        assert_eq!(averages.simple(), 20.0);
+        // alpha = 0.5: 10 -> 15 -> 22.5
+        assert_eq!(averages.exponential(), 22.5);
+
+        averages.record(40);
+        assert_eq!(averages.simple(), 30.0);
+        assert_eq!(averages.len(), 4);
+    }
+}
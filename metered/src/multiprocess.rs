@@ -0,0 +1,693 @@
+//! A module aggregating the registries of several processes, such as pre-forked
+//! workers or one process per core, to be scraped as a single registry.
+//!
+//! Each process publishes the values of its registries to its own
+//! memory-mapped file in a shared directory, where every value lives in a slot
+//! keyed by its name and labels. Any process can then aggregate the files of
+//! the directory: counters are summed, gauges are combined according to a
+//! [`GaugeMode`], and other values, such as quantiles, are kept per process
+//! with a `pid` label.
+//!
+#![cfg_attr(feature = "disabled", doc = "```ignore")]
+#![cfg_attr(not(feature = "disabled"), doc = "```rust")]
+//! use metered::{metered, multiprocess::MultiProcess, HitCount};
+//! use std::{sync::Arc, time::Duration};
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Service {
+//!     metrics: ServiceMetrics,
+//! }
+//!
+//! #[metered(registry = ServiceMetrics)]
+//! impl Service {
+//!     #[measure(HitCount)]
+//!     pub fn call(&self) {}
+//! }
+//!
+//! # let dir = std::env::temp_dir().join(format!("metered-doc-{}", std::process::id()));
+//! # std::fs::create_dir_all(&dir).unwrap();
+//! let shared = MultiProcess::new(&dir);
+//! // In the parent process, before forking
+//! shared.reset().unwrap();
+//!
+//! // In each worker process
+//! let service = Arc::new(Service::default());
+//! let file = Arc::new(shared.open().unwrap());
+//! let publishing = file.spawn(Arc::clone(&service), |s| &s.metrics, Duration::from_secs(1));
+//! service.call();
+//! publishing.stop();
+//!
+//! // In the process serving metrics
+//! let samples = shared.aggregate().unwrap();
+//! assert_eq!(samples[0].name, "call_hit_count");
+//! assert_eq!(samples[0].value, 1.0);
+//! # std::fs::remove_dir_all(&dir).unwrap();
+//! ```
+//!
+//! Files are kept when processes exit, so that counters keep the counts of
+//! exited processes: the directory should be reset when the deployment starts.
+//!
+//! This module is only available when the `multiprocess` feature is enabled.
+
+use crate::{
+    flatten::{self, Sample},
+    metadata::{DescribeMetrics, MetricType},
+    sync::Mutex,
+};
+use memmap2::{Mmap, MmapMut};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex as StdMutex, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// The first bytes of process files, with the version of their layout
+const MAGIC: &[u8; 8] = b"metered\x01";
+/// The magic bytes followed by the number of bytes in use
+const HEADER_LEN: usize = 16;
+const INITIAL_LEN: usize = 64 * 1024;
+const PREFIX: &str = "metered-";
+const EXTENSION: &str = "db";
+
+/// How the values of gauges are aggregated across processes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum GaugeMode {
+    /// Keeps the value of each process, with a `pid` label
+    #[default]
+    PerProcess,
+    /// Sums the values of the processes, e.g. for requests in flight
+    Sum,
+    /// Keeps the lowest value
+    Min,
+    /// Keeps the highest value
+    Max,
+}
+
+/// How a value is aggregated, as recorded in process files
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Counter = 0,
+    Gauge = 1,
+    Other = 2,
+}
+
+impl Kind {
+    fn of(metric_type: MetricType) -> Self {
+        match metric_type {
+            MetricType::Counter => Kind::Counter,
+            MetricType::Gauge => Kind::Gauge,
+            _ => Kind::Other,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            0 => Kind::Counter,
+            1 => Kind::Gauge,
+            _ => Kind::Other,
+        }
+    }
+}
+
+/// A directory shared by processes publishing their registries, see the
+/// [module documentation](crate::multiprocess).
+#[derive(Clone, Debug)]
+pub struct MultiProcess {
+    dir: PathBuf,
+    gauges: GaugeMode,
+}
+
+impl MultiProcess {
+    /// Shares registries through a directory, which must exist
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        MultiProcess {
+            dir: dir.as_ref().to_path_buf(),
+            gauges: GaugeMode::default(),
+        }
+    }
+
+    /// Aggregates gauges according to a mode, rather than per process
+    pub fn with_gauges(mut self, mode: GaugeMode) -> Self {
+        self.gauges = mode;
+        self
+    }
+
+    /// Get the shared directory
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Removes the files of all processes, e.g. when the deployment starts
+    pub fn reset(&self) -> io::Result<()> {
+        for (path, _) in self.files()? {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Creates the file of the current process, replacing any file left by a
+    /// previous process with the same id
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`io::ErrorKind::AlreadyExists`] if the file of the current
+    /// process is already open in this directory.
+    pub fn open(&self) -> io::Result<ProcessFile> {
+        let pid = process::id();
+        let path = fs::canonicalize(&self.dir)?.join(format!("{}{}.{}", PREFIX, pid, EXTENSION));
+        let registration = Registration::new(path.clone())?;
+
+        // Readers may still map the file left by a previous process with the
+        // same id: it is replaced rather than truncated under them
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        file.set_len(INITIAL_LEN as u64)?;
+        // SAFETY: process files are only written through this mapping, as the
+        // registration prevents opening them twice, and are never truncated:
+        // they only grow while mapped
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        map[..MAGIC.len()].copy_from_slice(MAGIC);
+        atomic_at(&map, MAGIC.len()).store(HEADER_LEN as u64, Ordering::Release);
+
+        Ok(ProcessFile {
+            path,
+            _registration: registration,
+            mapped: Mutex::new(Mapped {
+                file,
+                map,
+                used: HEADER_LEN,
+                slots: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Aggregates the values published by all processes, sorted by name and
+    /// labels
+    pub fn aggregate(&self) -> io::Result<Vec<Sample>> {
+        let mut aggregated: BTreeMap<(String, Vec<(String, String)>), f64> = BTreeMap::new();
+
+        for (path, pid) in self.files()? {
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                // The file was removed since listed
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            // SAFETY: process files are never truncated, but replaced by new
+            // files, and are only read through atomics past their header
+            let map = unsafe { Mmap::map(&file)? };
+            if map.len() < HEADER_LEN || &map[..MAGIC.len()] != MAGIC {
+                continue;
+            }
+
+            for (kind, name, mut labels, value) in entries(&map) {
+                let mode = match kind {
+                    Kind::Counter => GaugeMode::Sum,
+                    Kind::Gauge => self.gauges,
+                    Kind::Other => GaugeMode::PerProcess,
+                };
+                if mode == GaugeMode::PerProcess {
+                    labels.push(("pid".to_string(), pid.to_string()));
+                }
+                aggregated
+                    .entry((name, labels))
+                    .and_modify(|aggregate| {
+                        *aggregate = match mode {
+                            GaugeMode::Sum | GaugeMode::PerProcess => *aggregate + value,
+                            GaugeMode::Min => aggregate.min(value),
+                            GaugeMode::Max => aggregate.max(value),
+                        }
+                    })
+                    .or_insert(value);
+            }
+        }
+
+        Ok(aggregated
+            .into_iter()
+            .map(|((name, labels), value)| Sample {
+                name,
+                labels,
+                value,
+            })
+            .collect())
+    }
+
+    /// Lists the process files of the directory, with the id of their process
+    fn files(&self) -> io::Result<Vec<(PathBuf, u32)>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let pid = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(PREFIX))
+                .and_then(|name| name.strip_suffix(EXTENSION))
+                .and_then(|name| name.strip_suffix('.'))
+                .and_then(|pid| pid.parse().ok());
+            if let Some(pid) = pid {
+                files.push((path, pid));
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// The file of the current process in a [`MultiProcess`] directory.
+pub struct ProcessFile {
+    path: PathBuf,
+    mapped: Mutex<Mapped>,
+    _registration: Registration,
+}
+
+/// The paths of the process files open in this process
+static OPEN_FILES: StdMutex<Vec<PathBuf>> = StdMutex::new(Vec::new());
+
+/// The registration of an open process file, released when dropped
+struct Registration(PathBuf);
+
+impl Registration {
+    fn new(path: PathBuf) -> io::Result<Self> {
+        let mut open_files = OPEN_FILES.lock().unwrap_or_else(PoisonError::into_inner);
+        if open_files.contains(&path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} is already open", path.display()),
+            ));
+        }
+        open_files.push(path.clone());
+        Ok(Registration(path))
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut open_files = OPEN_FILES.lock().unwrap_or_else(PoisonError::into_inner);
+        open_files.retain(|path| *path != self.0);
+    }
+}
+
+struct Mapped {
+    file: File,
+    map: MmapMut,
+    used: usize,
+    /// The offsets of the values of the entries, by key
+    slots: HashMap<Vec<u8>, usize>,
+}
+
+impl ProcessFile {
+    /// Get the path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Publishes the current values of a registry, overwriting the values it
+    /// previously published
+    pub fn publish<R>(&self, registry: &R) -> Result<(), MultiProcessError>
+    where
+        R: Serialize + DescribeMetrics + ?Sized,
+    {
+        let samples = flatten::to_samples(registry).map_err(MultiProcessError::Flatten)?;
+        let descriptions = R::describe_metrics();
+
+        let mut mapped = self.mapped.lock();
+        for sample in samples {
+            let kind = flatten::describe_sample(&sample.name, &descriptions)
+                .map_or(Kind::Other, |description| {
+                    Kind::of(description.metadata.metric_type)
+                });
+
+            let key = encode_key(&sample.name, &sample.labels);
+            let offset = match mapped.slots.get(&key) {
+                Some(offset) => *offset,
+                None => mapped.insert(key, kind).map_err(MultiProcessError::Io)?,
+            };
+            atomic_at(&mapped.map, offset).store(sample.value.to_bits(), Ordering::Release);
+        }
+        Ok(())
+    }
+}
+
+impl ProcessFile {
+    /// Spawns a thread publishing a registry owned by a shared value at every
+    /// interval, and once more when the returned handle is stopped or dropped
+    pub fn spawn<T, R>(
+        self: &Arc<Self>,
+        owner: Arc<T>,
+        registry: fn(&T) -> &R,
+        interval: Duration,
+    ) -> PublishingHandle
+    where
+        T: Send + Sync + 'static,
+        R: Serialize + DescribeMetrics + 'static,
+    {
+        let file = Arc::clone(self);
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("metered-multiprocess".to_string())
+            .spawn(move || loop {
+                // Registries failing to publish are retried at the next
+                // interval
+                let _ = file.publish(registry(&owner));
+
+                match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => {
+                        let _ = file.publish(registry(&owner));
+                        break;
+                    }
+                }
+            })
+            .expect("could not spawn the multiprocess thread");
+
+        PublishingHandle {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Mapped {
+    /// Appends an entry with a value of 0, growing the file if needed, and
+    /// returns the offset of its value
+    fn insert(&mut self, key: Vec<u8>, kind: Kind) -> io::Result<usize> {
+        let key_len = padded(key.len());
+        let len = 8 + key_len + 8;
+        if self.used + len > self.map.len() {
+            let new_len = (self.used + len).max(self.map.len() * 2);
+            self.file.set_len(new_len as u64)?;
+            // SAFETY: see `MultiProcess::open`
+            self.map = unsafe { MmapMut::map_mut(&self.file)? };
+        }
+
+        let start = self.used;
+        self.map[start..start + 4].copy_from_slice(&(key.len() as u32).to_le_bytes());
+        self.map[start + 4] = kind as u8;
+        self.map[start + 8..start + 8 + key.len()].copy_from_slice(&key);
+        let offset = start + 8 + key_len;
+        atomic_at(&self.map, offset).store(0, Ordering::Relaxed);
+
+        // Readers only see the entry once it is complete
+        self.used += len;
+        atomic_at(&self.map, MAGIC.len()).store(self.used as u64, Ordering::Release);
+        self.slots.insert(key, offset);
+        Ok(offset)
+    }
+}
+
+impl Drop for ProcessFile {
+    fn drop(&mut self) {
+        let _ = self.mapped.lock().map.flush();
+    }
+}
+
+impl fmt::Debug for ProcessFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProcessFile")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+/// A handle on a thread publishing to a [`ProcessFile`], stopping it when
+/// dropped.
+pub struct PublishingHandle {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PublishingHandle {
+    /// Stops publishing after a last publication, waiting for the thread to
+    /// exit
+    pub fn stop(self) {
+        drop(self)
+    }
+}
+
+impl Drop for PublishingHandle {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl fmt::Debug for PublishingHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PublishingHandle").finish()
+    }
+}
+
+/// An error raised while publishing a registry to a [`ProcessFile`].
+#[derive(Debug)]
+pub enum MultiProcessError {
+    /// The registry could not be flattened into samples
+    Flatten(flatten::Error),
+    /// The file could not be grown
+    Io(io::Error),
+}
+
+impl fmt::Display for MultiProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultiProcessError::Flatten(e) => write!(f, "could not flatten registry: {}", e),
+            MultiProcessError::Io(e) => write!(f, "could not grow process file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MultiProcessError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MultiProcessError::Flatten(e) => Some(e),
+            MultiProcessError::Io(e) => Some(e),
+        }
+    }
+}
+
+/// Get the atomic at an offset of a mapping
+///
+/// # Panics
+///
+/// Panics if the offset is not aligned or out of the mapping.
+fn atomic_at(map: &[u8], offset: usize) -> &AtomicU64 {
+    assert!(offset & 7 == 0 && offset + 8 <= map.len());
+    // SAFETY: mappings are page-aligned, so the offset is aligned, and the
+    // bytes are only accessed through atomics once the entry is published
+    unsafe { &*(map.as_ptr().add(offset) as *const AtomicU64) }
+}
+
+fn padded(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+/// Encodes a name and labels as the key of an entry, separated by NUL bytes
+fn encode_key(name: &str, labels: &[(String, String)]) -> Vec<u8> {
+    let mut key = name.as_bytes().to_vec();
+    for (label, value) in labels {
+        key.push(0);
+        key.extend_from_slice(label.as_bytes());
+        key.push(0);
+        key.extend_from_slice(value.as_bytes());
+    }
+    key
+}
+
+type Entry = (Kind, String, Vec<(String, String)>, f64);
+
+/// Reads the complete entries of a mapped process file
+fn entries(map: &[u8]) -> Vec<Entry> {
+    let used = (atomic_at(map, MAGIC.len()).load(Ordering::Acquire) as usize).min(map.len());
+    let mut entries = Vec::new();
+    let mut start = HEADER_LEN;
+
+    while start + 8 <= used {
+        let mut len = [0; 4];
+        len.copy_from_slice(&map[start..start + 4]);
+        let key_len = u32::from_le_bytes(len) as usize;
+        let offset = start + 8 + padded(key_len);
+        if offset + 8 > used {
+            break;
+        }
+
+        let key = String::from_utf8_lossy(&map[start + 8..start + 8 + key_len]);
+        let mut parts = key.split('\0');
+        let name = parts.next().unwrap_or_default().to_string();
+        let mut labels = Vec::new();
+        while let (Some(label), Some(value)) = (parts.next(), parts.next()) {
+            labels.push((label.to_string(), value.to_string()));
+        }
+        let value = f64::from_bits(atomic_at(map, offset).load(Ordering::Acquire));
+
+        entries.push((Kind::from_byte(map[start + 4]), name, labels, value));
+        start = offset + 8;
+    }
+    entries
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{
+        common::FloatGauge,
+        measure,
+        metadata::{MetricDescription, MetricMetadata, Unit},
+        HitCount,
+    };
+
+    #[derive(Default, Serialize)]
+    struct Registry {
+        hit_count: HitCount,
+        in_flight: FloatGauge,
+    }
+
+    impl DescribeMetrics for Registry {
+        fn describe_metrics() -> Vec<MetricDescription> {
+            vec![
+                MetricDescription {
+                    path: vec!["hit_count"],
+                    metadata: MetricMetadata::new(MetricType::Counter, Unit::None),
+                },
+                MetricDescription {
+                    path: vec!["in_flight"],
+                    metadata: MetricMetadata::new(MetricType::Gauge, Unit::None),
+                },
+            ]
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("metered-{}-{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes the file of another process, as `open` would
+    fn write_other(dir: &Path, pid: u32, registry: &Registry) {
+        let other = MultiProcess::new(dir).open().unwrap();
+        other.publish(registry).unwrap();
+        let path = dir.join(format!("{}{}.{}", PREFIX, pid, EXTENSION));
+        fs::copy(other.path(), path).unwrap();
+    }
+
+    #[test]
+    fn aggregates_processes() {
+        let dir = temp_dir("aggregate");
+        let other = Registry::default();
+        measure!(&other.hit_count, {});
+        other.in_flight.set(3.0);
+        write_other(&dir, 1, &other);
+
+        let shared = MultiProcess::new(&dir);
+        let registry = Registry::default();
+        let file = shared.open().unwrap();
+        measure!(&registry.hit_count, {});
+        measure!(&registry.hit_count, {});
+        registry.in_flight.set(1.0);
+        file.publish(&registry).unwrap();
+
+        let samples = shared.aggregate().unwrap();
+        let pid = |pid: u32| vec![("pid".to_string(), pid.to_string())];
+        assert_eq!(
+            samples,
+            [
+                Sample {
+                    name: "hit_count".to_string(),
+                    labels: Vec::new(),
+                    value: 3.0
+                },
+                Sample {
+                    name: "in_flight".to_string(),
+                    labels: pid(1),
+                    value: 3.0
+                },
+                Sample {
+                    name: "in_flight".to_string(),
+                    labels: pid(process::id()),
+                    value: 1.0
+                },
+            ]
+        );
+
+        let max = shared
+            .clone()
+            .with_gauges(GaugeMode::Max)
+            .aggregate()
+            .unwrap();
+        assert_eq!(max[1].name, "in_flight");
+        assert_eq!(max[1].value, 3.0);
+
+        shared.reset().unwrap();
+        assert!(shared.aggregate().unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_open_files_twice() {
+        let dir = temp_dir("twice");
+        let shared = MultiProcess::new(&dir);
+        let file = shared.open().unwrap();
+        let registry = Registry::default();
+        measure!(&registry.hit_count, {});
+        file.publish(&registry).unwrap();
+
+        let error = shared.open().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        let error = MultiProcess::new(dir.join(".")).open().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        // The open file is left untouched
+        measure!(&registry.hit_count, {});
+        file.publish(&registry).unwrap();
+        assert_eq!(shared.aggregate().unwrap()[0].value, 2.0);
+
+        drop(file);
+        let file = shared.open().unwrap();
+        file.publish(&Registry::default()).unwrap();
+        assert_eq!(shared.aggregate().unwrap()[0].value, 0.0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn grows_file() {
+        let dir = temp_dir("grow");
+        let shared = MultiProcess::new(&dir);
+        let file = shared.open().unwrap();
+
+        let labels: Vec<_> = (0..2000)
+            .map(|i| (format!("label_{}", i), "x".repeat(i % 50)))
+            .collect();
+        {
+            let mut mapped = file.mapped.lock();
+            for (i, label) in labels.iter().enumerate() {
+                let key = encode_key("value", std::slice::from_ref(label));
+                let offset = mapped.insert(key, Kind::Counter).unwrap();
+                atomic_at(&mapped.map, offset).store((i as f64).to_bits(), Ordering::Release);
+            }
+            assert!(mapped.map.len() > INITIAL_LEN);
+        }
+
+        let samples = shared.aggregate().unwrap();
+        assert_eq!(samples.len(), 2000);
+        let sum: f64 = samples.iter().map(|sample| sample.value).sum();
+        assert_eq!(sum, (0..2000).sum::<u32>() as f64);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+//! A module namespacing metrics at export time, so that the same binary can
+//! report under different names and labels in each environment.
+//!
+//! Labels given with the `labels` option of `#[metered]` are part of the
+//! registry, for every exporter. A [`Namespace`] is instead configured once on
+//! an exporter, usually from the environment when the service starts, and
+//! prepends a prefix and adds deployment labels to the samples it exports:
+//!
+#![cfg_attr(feature = "disabled", doc = "```ignore")]
+#![cfg_attr(not(feature = "disabled"), doc = "```rust")]
+//! use metered::{metered, namespace::Namespace, HitCount};
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Service {
+//!     metrics: ServiceMetrics,
+//! }
+//!
+//! #[metered(registry = ServiceMetrics)]
+//! impl Service {
+//!     #[measure(HitCount)]
+//!     pub fn call(&self) {}
+//! }
+//!
+//! let service = Service::default();
+//! service.call();
+//!
+//! let namespace = Namespace::new()
+//!     .with_prefix("checkout")
+//!     .with_label("region", "eu-west-1")
+//!     .with_label("version", env!("CARGO_PKG_VERSION"));
+//!
+//! let samples = namespace.to_samples(&service.metrics).unwrap();
+//! assert_eq!(samples[0].name, "checkout_call_hit_count");
+//! assert_eq!(samples[0].labels[0], ("region".to_string(), "eu-west-1".to_string()));
+//! ```
+//!
+//! Exporters such as [`Sink`](crate::sink::Sink), [`Emf`](crate::emf::Emf) or
+//! `RemoteWrite` take a namespace with `with_namespace`.
+
+use crate::flatten::{self, Sample};
+use serde::Serialize;
+
+/// A prefix and labels applied to samples when exporting them, see the
+/// [module documentation](crate::namespace).
+///
+/// Labels of the samples take precedence over the labels of the namespace
+/// with the same key.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Namespace {
+    prefix: Option<String>,
+    labels: Vec<(String, String)>,
+}
+
+impl Namespace {
+    /// Creates a namespace without prefix or labels, leaving samples as they
+    /// are
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prepends a prefix, followed by a separator, to the name of every sample
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Adds a label to every sample
+    pub fn with_label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.push((key.into(), value.into()));
+        self
+    }
+
+    /// Adds a label to every sample, read from an environment variable, if it
+    /// is set and not empty
+    pub fn with_env_label(self, key: impl Into<String>, var: &str) -> Self {
+        match std::env::var(var) {
+            Ok(value) if !value.is_empty() => self.with_label(key, value),
+            _ => self,
+        }
+    }
+
+    /// Adds the usual deployment labels: `host` from the `HOSTNAME`
+    /// environment variable, `pod` from `POD_NAME` (set with the Kubernetes
+    /// downward API), when set, and `version`, usually
+    /// `env!("CARGO_PKG_VERSION")`
+    pub fn with_deployment_labels(self, version: impl Into<String>) -> Self {
+        self.with_env_label("host", "HOSTNAME")
+            .with_env_label("pod", "POD_NAME")
+            .with_label("version", version)
+    }
+
+    /// Get the prefix, if any
+    pub fn prefix(&self) -> Option<&str> {
+        self.prefix.as_deref()
+    }
+
+    /// Get the labels, in the order they were added
+    pub fn labels(&self) -> &[(String, String)] {
+        &self.labels
+    }
+
+    /// Get the name of a sample, prefixed with a separator, such as `_` for
+    /// Prometheus or `.` for StatsD
+    pub fn name(&self, name: &str, separator: &str) -> String {
+        match self.prefix {
+            Some(ref prefix) => format!("{}{}{}", prefix, separator, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// Applies the prefix, followed by an underscore, and the labels to
+    /// samples
+    pub fn apply(&self, samples: &mut [Sample]) {
+        for sample in samples.iter_mut() {
+            if self.prefix.is_some() {
+                sample.name = self.name(&sample.name, "_");
+            }
+            for (key, value) in self.labels.iter() {
+                if !sample.labels.iter().any(|(k, _)| k == key) {
+                    sample.labels.push((key.clone(), value.clone()));
+                }
+            }
+        }
+    }
+
+    /// Flattens a registry with [`flatten::to_samples`], then applies the
+    /// namespace to the samples
+    pub fn to_samples<T: Serialize + ?Sized>(
+        &self,
+        registry: &T,
+    ) -> Result<Vec<Sample>, flatten::Error> {
+        let mut samples = flatten::to_samples(registry)?;
+        self.apply(&mut samples);
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(name: &str, labels: &[(&str, &str)]) -> Sample {
+        Sample {
+            name: name.to_string(),
+            labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            value: 1.0,
+        }
+    }
+
+    #[test]
+    fn applies_prefix_and_labels() {
+        let namespace = Namespace::new()
+            .with_prefix("app")
+            .with_label("env", "prod")
+            .with_label("region", "eu");
+        let mut samples = vec![sample("calls", &[("region", "us")]), sample("errors", &[])];
+        namespace.apply(&mut samples);
+
+        assert_eq!(
+            samples,
+            [
+                sample("app_calls", &[("region", "us"), ("env", "prod")]),
+                sample("app_errors", &[("env", "prod"), ("region", "eu")]),
+            ]
+        );
+        assert_eq!(namespace.name("calls", "."), "app.calls");
+    }
+
+    #[test]
+    fn skips_missing_env_labels() {
+        let namespace = Namespace::new().with_env_label("host", "METERED_NAMESPACE_TEST_UNSET");
+        assert!(namespace.labels().is_empty());
+
+        let namespace = namespace.with_deployment_labels("1.0.0");
+        assert_eq!(
+            namespace.labels().last().unwrap(),
+            &("version".to_string(), "1.0.0".to_string())
+        );
+        assert!(namespace.prefix().is_none());
+
+        let mut samples = vec![sample("calls", &[])];
+        Namespace::new().apply(&mut samples);
+        assert_eq!(samples, [sample("calls", &[])]);
+    }
+}
@@ -0,0 +1,134 @@
+//! A module tracking nested calls of measured methods, so that methods calling
+//! each other are not counted twice.
+//!
+//! Registries generated with the `skip_nested = true` option of `#[metered]`
+//! only measure the outermost call of their methods on each thread: methods
+//! called by other measured methods of the same registry are not measured.
+//!
+#![cfg_attr(feature = "disabled", doc = "```ignore")]
+#![cfg_attr(not(feature = "disabled"), doc = "```rust")]
+//! use metered::{metered, HitCount};
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Counter {
+//!     metrics: CounterMetrics,
+//! }
+//!
+//! #[metered(registry = CounterMetrics, skip_nested = true)]
+//! impl Counter {
+//!     #[measure(HitCount)]
+//!     pub fn increment_once(&self) {}
+//!
+//!     #[measure(HitCount)]
+//!     pub fn increment_twice(&self) {
+//!         self.increment_once();
+//!         self.increment_once();
+//!     }
+//! }
+//!
+//! let counter = Counter::default();
+//! counter.increment_twice();
+//! counter.increment_once();
+//! assert_eq!(counter.metrics.increment_twice.hit_count.get(), 1);
+//! assert_eq!(counter.metrics.increment_once.hit_count.get(), 1);
+//! ```
+//!
+//! To keep measuring nested calls while still telling apart the time methods
+//! spend on their own, see
+//! [`ExclusiveTime`](crate::common::ExclusiveTime).
+//!
+//! Calls are tracked per thread, so `skip_nested` only applies to synchronous
+//! methods.
+
+use std::cell::{Cell, RefCell};
+
+thread_local! {
+    static REGISTRIES: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    static FRAMES: RefCell<Vec<(u64, u64)>> = const { RefCell::new(Vec::new()) };
+    static NEXT_FRAME: Cell<u64> = const { Cell::new(0) };
+}
+
+/// A call of a method of a registry, tracked until dropped.
+#[derive(Debug)]
+pub struct NestedCall {
+    registry: usize,
+    nested: bool,
+}
+
+impl NestedCall {
+    /// Starts tracking a call of a method of `registry`, nested if a call of
+    /// the same registry is already tracked on the thread
+    pub fn enter<R>(registry: &R) -> Self {
+        let registry = registry as *const R as usize;
+        let nested = REGISTRIES.with(|registries| {
+            let mut registries = registries.borrow_mut();
+            let nested = registries.contains(&registry);
+            registries.push(registry);
+            nested
+        });
+        NestedCall { registry, nested }
+    }
+
+    /// Returns true if the call is nested in another call of the registry
+    #[inline]
+    pub fn is_nested(&self) -> bool {
+        self.nested
+    }
+}
+
+impl Drop for NestedCall {
+    fn drop(&mut self) {
+        REGISTRIES.with(|registries| {
+            let mut registries = registries.borrow_mut();
+            if let Some(i) = registries.iter().rposition(|r| *r == self.registry) {
+                registries.remove(i);
+            }
+        });
+    }
+}
+
+/// A call measured by `ExclusiveTime`, accumulating the time spent in the calls
+/// it makes, in nanoseconds, until finished or dropped.
+#[derive(Debug)]
+pub struct Frame(u64);
+
+impl Frame {
+    pub(crate) fn push() -> Self {
+        let id = NEXT_FRAME.with(|next| {
+            let id = next.get();
+            next.set(id.wrapping_add(1));
+            id
+        });
+        FRAMES.with(|frames| frames.borrow_mut().push((id, 0)));
+        Frame(id)
+    }
+
+    /// Finishes the call, adding its duration to the call it is nested in, and
+    /// returns the time spent in its own nested calls
+    pub(crate) fn finish(self, elapsed_nanos: u64) -> u64 {
+        FRAMES.with(|frames| {
+            let mut frames = frames.borrow_mut();
+            let i = match frames.iter().rposition(|(id, _)| *id == self.0) {
+                Some(i) => i,
+                None => return 0,
+            };
+            let (_, nested) = frames.remove(i);
+            if let Some((_, parent)) = i.checked_sub(1).and_then(|i| frames.get_mut(i)) {
+                *parent = parent.saturating_add(elapsed_nanos);
+            }
+            nested
+        })
+    }
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+        // Calls that panicked are not attributed to their parent
+        let _ = FRAMES.try_with(|frames| {
+            let mut frames = frames.borrow_mut();
+            if let Some(i) = frames.iter().rposition(|(id, _)| *id == self.0) {
+                frames.remove(i);
+            }
+        });
+    }
+}
@@ -0,0 +1,190 @@
+//! A module providing no-op backends for Counters, Gauges and Histograms, for
+//! tests, benchmarks or builds that do not need some metrics.
+//!
+//! Null backends are zero-sized, always read as zero and ignore the values
+//! they are given, so that metrics using them only cost the measure of their
+//! expression, if any:
+//!
+#![cfg_attr(feature = "disabled", doc = "```ignore")]
+#![cfg_attr(not(feature = "disabled"), doc = "```rust")]
+//! use metered::{
+//!     metered,
+//!     null::{NullCounter, NullHistogram},
+//!     HitCount, ResponseTime,
+//! };
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Service {
+//!     metrics: ServiceMetrics,
+//! }
+//!
+//! #[metered(registry = ServiceMetrics)]
+//! impl Service {
+//!     #[measure([HitCount<NullCounter>, ResponseTime<NullHistogram>])]
+//!     pub fn call(&self) {}
+//! }
+//!
+//! let service = Service::default();
+//! service.call();
+//!
+//! assert_eq!(std::mem::size_of::<ServiceMetrics>(), 0);
+//! assert_eq!(
+//!     serde_json::to_string(&service.metrics).unwrap(),
+//!     r#"{"call":{"hit_count":0,"response_time":{"samples":0}}}"#
+//! );
+//! ```
+//!
+//! To switch a whole registry to null backends, e.g. in benchmarks, metrics can
+//! be declared with backends aliased under a `cfg`:
+//!
+#![cfg_attr(not(feature = "histograms"), doc = "```ignore")]
+#![cfg_attr(feature = "histograms", doc = "```rust")]
+//! # use metered::{hdr_histogram::AtomicHdrHistogram, null::NullHistogram};
+//! #[cfg(not(feature = "bench"))]
+//! type Histogram = AtomicHdrHistogram;
+//! #[cfg(feature = "bench")]
+//! type Histogram = NullHistogram;
+//! ```
+
+use crate::{
+    clear::{Clear, Clearable},
+    merge::Merge,
+    metric::{
+        Counter, Enter, Gate, Gauge, Histogram, HistogramSnapshot, Metric, OnResult, RecordValue,
+        SnapshotHistogram,
+    },
+    serialization::MetricAlias,
+};
+use serde::{Serialize, Serializer};
+
+/// A Counter ignoring increments, always zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NullCounter;
+
+impl Counter for NullCounter {
+    fn incr_by(&self, _count: usize) {}
+
+    fn value(&self) -> u64 {
+        0
+    }
+}
+
+/// A Gauge ignoring increments and decrements, always zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NullGauge;
+
+impl Gauge for NullGauge {
+    fn incr_by(&self, _count: usize) {}
+
+    fn decr_by(&self, _count: usize) {}
+
+    fn value(&self) -> u64 {
+        0
+    }
+}
+
+/// A Histogram ignoring recorded values, always empty.
+///
+/// It serializes as a summary of no samples, without statistics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NullHistogram;
+
+impl Histogram for NullHistogram {
+    fn with_bound(_max_value: u64) -> Self {
+        NullHistogram
+    }
+
+    fn record(&self, _value: u64) {}
+}
+
+impl SnapshotHistogram for NullHistogram {
+    type Snapshot = NullHistogram;
+
+    fn snapshot_histogram(&self) -> NullHistogram {
+        NullHistogram
+    }
+}
+
+impl From<&NullHistogram> for HistogramSnapshot {
+    fn from(_histogram: &NullHistogram) -> Self {
+        HistogramSnapshot::default()
+    }
+}
+
+impl Serialize for NullHistogram {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeMap;
+
+        // Qualified like `HdrHistogram`'s sample count
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry("samples", &MetricAlias("<|", 0u64))?;
+        map.end()
+    }
+}
+
+/// A Metric measuring nothing, and never aborting as a [`Gate`].
+///
+/// Measured methods use it in place of metrics compiled out by the `cfg`
+/// option of the `measure` attribute.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct NullMetric;
+
+impl<R> Metric<R> for NullMetric {}
+
+impl Enter for NullMetric {
+    type E = ();
+
+    #[inline]
+    fn enter(&self) {}
+}
+
+impl<R> OnResult<R> for NullMetric {}
+
+impl Gate for NullMetric {
+    #[inline]
+    fn should_abort(&self, _enter: &()) -> bool {
+        false
+    }
+}
+
+impl RecordValue for NullMetric {
+    #[inline]
+    fn record_value(&self, _value: u64) {}
+}
+
+macro_rules! impl_null {
+    ($($ty:ident),*) => {
+        $(
+            impl Clear for $ty {
+                fn clear(&self) {}
+            }
+
+            impl Clearable for $ty {
+                fn is_cleared(&self) -> bool {
+                    true
+                }
+            }
+
+            impl Merge for $ty {
+                fn merge_from(&self, _other: &Self) {}
+            }
+        )*
+    };
+}
+
+impl_null!(NullCounter, NullGauge, NullHistogram, NullMetric);
+
+impl Serialize for NullCounter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(0)
+    }
+}
+
+impl Serialize for NullGauge {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(0)
+    }
+}
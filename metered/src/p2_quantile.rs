@@ -0,0 +1,350 @@
+//! A module providing thread-safe and unsynchronized implementations of the P²
+//! (P-square) quantile estimator, usable as a constant-memory alternative to
+//! HdrHistogram.
+
+use crate::{
+    clear::{self, Clear, Clearable},
+    common::ResponseTime,
+    metric::{Histogram, SnapshotHistogram},
+    sync::Mutex,
+    time_source::StdInstant,
+};
+use serde::{Serialize, Serializer};
+use std::cell::RefCell;
+
+/// A metric estimating a single quantile of the response time of an
+/// expression, using the P² algorithm.
+///
+/// The quantile is expressed in basis points, e.g `9900` for the 99th
+/// percentile. Unlike [`ResponseTime`] backed by an HdrHistogram, it only keeps
+/// five markers in memory, which makes it suitable when thousands of methods
+/// are instrumented.
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{measure, p2_quantile::P2Quantile};
+///
+/// let p99: P2Quantile<9900> = P2Quantile::default();
+///
+/// measure!(&p99, {
+///     std::thread::sleep(std::time::Duration::from_millis(10));
+/// });
+///
+/// assert!(p99.histogram().value() >= 10);
+/// ```
+pub type P2Quantile<const BASIS_POINTS: u32, T = StdInstant> =
+    ResponseTime<AtomicP2Histogram<BASIS_POINTS>, T>;
+
+/// A thread-safe implementation of the P² quantile estimator
+pub struct AtomicP2Histogram<const BASIS_POINTS: u32> {
+    inner: Mutex<P2Histogram<BASIS_POINTS>>,
+}
+
+impl<const BASIS_POINTS: u32> AtomicP2Histogram<BASIS_POINTS> {
+    /// Returns a cloned snapshot of the inner estimator.
+    pub fn histogram(&self) -> P2Histogram<BASIS_POINTS> {
+        self.inner.lock().clone()
+    }
+}
+
+impl<const BASIS_POINTS: u32> SnapshotHistogram for AtomicP2Histogram<BASIS_POINTS> {
+    type Snapshot = P2Histogram<BASIS_POINTS>;
+
+    fn snapshot_histogram(&self) -> P2Histogram<BASIS_POINTS> {
+        self.histogram()
+    }
+}
+
+impl<const BASIS_POINTS: u32> Histogram for AtomicP2Histogram<BASIS_POINTS> {
+    fn with_bound(max_value: u64) -> Self {
+        AtomicP2Histogram {
+            inner: Mutex::new(P2Histogram::with_bound(max_value)),
+        }
+    }
+
+    fn record(&self, value: u64) {
+        lock_histogram!(self.inner).record(value);
+    }
+}
+
+impl<const BASIS_POINTS: u32> Clear for AtomicP2Histogram<BASIS_POINTS> {
+    fn clear(&self) {
+        self.inner.lock().clear();
+    }
+}
+
+impl<const BASIS_POINTS: u32> Clearable for AtomicP2Histogram<BASIS_POINTS> {
+    fn is_cleared(&self) -> bool {
+        self.inner.lock().is_empty()
+    }
+}
+
+impl<const BASIS_POINTS: u32> Serialize for AtomicP2Histogram<BASIS_POINTS> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        internal_cost!(serialization, {
+            let mut inner = self.inner.lock();
+            let result = Serialize::serialize(&*inner, serializer);
+            // Under the same lock, so that values are serialized exactly once
+            if clear::is_clearing_serialization() {
+                inner.clear();
+            }
+            result
+        })
+    }
+}
+
+use std::{fmt, fmt::Debug};
+impl<const BASIS_POINTS: u32> Debug for AtomicP2Histogram<BASIS_POINTS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock();
+        write!(f, "AtomicP2Histogram {{ {:?} }}", &*inner)
+    }
+}
+
+/// A P² (P-square) quantile estimator
+///
+/// The P² algorithm, by Jain and Chlamtac, estimates a single quantile of a
+/// stream of values without storing them: it maintains five markers whose
+/// heights are adjusted with a piecewise-parabolic interpolation as values are
+/// recorded.
+///
+/// The estimated quantile is expressed in basis points, e.g `9900` for the 99th
+/// percentile.
+#[derive(Clone)]
+pub struct P2Histogram<const BASIS_POINTS: u32> {
+    max_value: u64,
+    count: u64,
+    // Marker heights
+    heights: [f64; 5],
+    // Actual marker positions
+    positions: [f64; 5],
+    // Desired marker positions
+    desired: [f64; 5],
+}
+
+impl<const BASIS_POINTS: u32> P2Histogram<BASIS_POINTS> {
+    /// Instantiates a new estimator, saturating values above `max_value`.
+    pub fn with_bound(max_value: u64) -> Self {
+        let mut this = P2Histogram {
+            max_value,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [0.0; 5],
+            desired: [0.0; 5],
+        };
+        this.clear();
+        this
+    }
+
+    /// The estimated quantile, in `[0, 1]`.
+    pub fn quantile(&self) -> f64 {
+        f64::from(BASIS_POINTS.min(10_000)) / 10_000.0
+    }
+
+    fn increments(&self) -> [f64; 5] {
+        let p = self.quantile();
+        [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0]
+    }
+
+    /// Records a value to the estimator
+    ///
+    /// This is a saturating record: if the value is higher than `max_value`,
+    /// `max_value` will be recorded instead.
+    pub fn record(&mut self, value: u64) {
+        let x = value.min(self.max_value) as f64;
+
+        if self.count < 5 {
+            self.heights[self.count as usize] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights
+                    .sort_by(|a, b| a.partial_cmp(b).expect("values are never NaN"));
+            }
+            return;
+        }
+        self.count += 1;
+
+        // Find the cell containing x, extending the extreme markers if needed
+        let k = if x < self.heights[0] {
+            self.heights[0] = x;
+            0
+        } else if x >= self.heights[4] {
+            self.heights[4] = x;
+            3
+        } else {
+            (1..5).find(|&i| x < self.heights[i]).unwrap_or(4) - 1
+        };
+
+        for position in &mut self.positions[k + 1..] {
+            *position += 1.0;
+        }
+        let increments = self.increments();
+        for (desired, increment) in self.desired.iter_mut().zip(increments.iter()) {
+            *desired += increment;
+        }
+
+        // Adjust the heights of the middle markers if they are off position
+        for i in 1..4 {
+            let d = self.desired[i] - self.positions[i];
+            if (d >= 1.0 && self.positions[i + 1] - self.positions[i] > 1.0)
+                || (d <= -1.0 && self.positions[i - 1] - self.positions[i] < -1.0)
+            {
+                let d = d.signum();
+                let parabolic = self.parabolic(i, d);
+                self.heights[i] =
+                    if self.heights[i - 1] < parabolic && parabolic < self.heights[i + 1] {
+                        parabolic
+                    } else {
+                        self.linear(i, d)
+                    };
+                self.positions[i] += d;
+            }
+        }
+    }
+
+    fn parabolic(&self, i: usize, d: f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+        q[i] + d / (n[i + 1] - n[i - 1])
+            * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]))
+    }
+
+    fn linear(&self, i: usize, d: f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+        let j = if d > 0.0 { i + 1 } else { i - 1 };
+        q[i] + d * (q[j] - q[i]) / (n[j] - n[i])
+    }
+
+    /// Clears the values of the estimator
+    pub fn clear(&mut self) {
+        let p = self.quantile();
+        self.count = 0;
+        self.heights = [0.0; 5];
+        self.positions = [1.0, 2.0, 3.0, 4.0, 5.0];
+        self.desired = [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0];
+    }
+
+    /// Get the number of recorded values.
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    /// Check if no value was recorded
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Get the estimated value at the quantile.
+    ///
+    /// If no value was recorded, the value returned will be 0.
+    pub fn value(&self) -> u64 {
+        match self.count {
+            0 => 0,
+            n if n < 5 => {
+                // Not enough values for the markers: use the exact quantile
+                let mut values = self.heights;
+                let values = &mut values[..n as usize];
+                values.sort_by(|a, b| a.partial_cmp(b).expect("values are never NaN"));
+                let rank = (self.quantile() * n as f64).ceil() as usize;
+                values[rank.clamp(1, n as usize) - 1] as u64
+            }
+            _ => self.heights[2].round() as u64,
+        }
+    }
+}
+
+impl<const BASIS_POINTS: u32> Serialize for P2Histogram<BASIS_POINTS> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("samples", &self.len())?;
+        map.serialize_entry("quantile", &self.quantile())?;
+        map.serialize_entry("value", &self.value())?;
+        map.end()
+    }
+}
+
+impl<const BASIS_POINTS: u32> Debug for P2Histogram<BASIS_POINTS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "P2Histogram {{ samples: {}, quantile: {}, value: {} }}",
+            self.len(),
+            self.quantile(),
+            self.value()
+        )
+    }
+}
+
+impl<const BASIS_POINTS: u32> Histogram for RefCell<P2Histogram<BASIS_POINTS>> {
+    fn with_bound(max_value: u64) -> Self {
+        RefCell::new(P2Histogram::with_bound(max_value))
+    }
+
+    fn record(&self, value: u64) {
+        self.borrow_mut().record(value);
+    }
+}
+
+impl<const BASIS_POINTS: u32> Clear for RefCell<P2Histogram<BASIS_POINTS>> {
+    fn clear(&self) {
+        self.borrow_mut().clear();
+    }
+}
+
+impl<const BASIS_POINTS: u32> Clearable for RefCell<P2Histogram<BASIS_POINTS>> {
+    fn is_cleared(&self) -> bool {
+        self.borrow().is_empty()
+    }
+}
+
+impl<const BASIS_POINTS: u32> SnapshotHistogram for RefCell<P2Histogram<BASIS_POINTS>> {
+    type Snapshot = P2Histogram<BASIS_POINTS>;
+
+    fn snapshot_histogram(&self) -> P2Histogram<BASIS_POINTS> {
+        self.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::seq::SliceRandom;
+
+    #[test]
+    fn test_estimates_quantile() {
+        let mut values: Vec<u64> = (1..=10_000).collect();
+        values.shuffle(&mut rand::thread_rng());
+
+        let mut p50 = P2Histogram::<5000>::with_bound(u64::MAX);
+        let mut p99 = P2Histogram::<9900>::with_bound(u64::MAX);
+        for &v in &values {
+            p50.record(v);
+            p99.record(v);
+        }
+
+        assert_eq!(p99.len(), 10_000);
+        assert!((4_800..=5_200).contains(&p50.value()), "{:?}", p50);
+        assert!((9_800..=10_000).contains(&p99.value()), "{:?}", p99);
+    }
+
+    #[test]
+    fn test_few_values() {
+        let mut p50 = P2Histogram::<5000>::with_bound(100);
+        assert_eq!(p50.value(), 0);
+        p50.record(3);
+        p50.record(1);
+        p50.record(1_000);
+        assert_eq!(p50.value(), 3);
+
+        p50.clear();
+        assert!(p50.is_empty());
+    }
+}
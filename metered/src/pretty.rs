@@ -0,0 +1,291 @@
+//! A module printing registries as human-readable tables, for development and
+//! load-testing sessions.
+
+use crate::{
+    flatten::{self, Value},
+    metadata::{DescribeMetrics, MetricType, Unit},
+    serialization::{self, Quantile},
+};
+use serde::Serialize;
+use std::fmt;
+
+/// The quantiles printed for histograms
+const QUANTILES: &[Quantile] = &[Quantile::P50, Quantile::P90, Quantile::P99];
+
+const HEADER: [&str; 8] = [
+    "metric", "type", "unit", "count", "mean", "p50", "p90", "p99",
+];
+
+/// A wrapper displaying a registry as an aligned table, with one row per
+/// counter or gauge and one row per histogram, summarized by its sample
+/// count, mean and key percentiles.
+///
+/// Registries generated by `#[metered]` implement `Display` with it:
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{metered, HitCount, ResponseTime, Throughput};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics)]
+/// impl Service {
+///     #[measure([HitCount, ResponseTime, Throughput])]
+///     pub fn call(&self) {}
+/// }
+///
+/// let service = Service::default();
+/// service.call();
+///
+/// let table = service.metrics.to_string();
+/// let mut lines = table.lines();
+/// assert_eq!(
+///     lines.next().unwrap().split_whitespace().collect::<Vec<_>>(),
+///     ["metric", "type", "unit", "count", "mean", "p50", "p90", "p99"]
+/// );
+/// assert_eq!(
+///     lines.next().unwrap().split_whitespace().collect::<Vec<_>>(),
+///     ["call.hit_count", "counter", "1"]
+/// );
+/// assert!(lines.next().unwrap().starts_with("call.response_time  summary  ms"));
+/// assert!(lines.next().unwrap().starts_with("call.throughput     summary  req/s"));
+/// ```
+///
+/// Values are printed in the unit of the metric, e.g. milliseconds for
+/// `ResponseTime` or requests per second for `Throughput`. Metrics serializing
+/// several unrelated values are printed as one row per value.
+pub struct Pretty<'a, T: ?Sized> {
+    registry: &'a T,
+}
+
+impl<'a, T: ?Sized> Pretty<'a, T> {
+    /// Wraps a registry
+    pub fn new(registry: &'a T) -> Self {
+        Pretty { registry }
+    }
+}
+
+impl<T: Serialize + DescribeMetrics + ?Sized> fmt::Display for Pretty<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = rows(self.registry).map_err(|_| fmt::Error)?;
+        let rows = rows.iter().map(|row| {
+            let mut cells = row.leading_cells();
+            cells.push(format_value(row.count));
+            cells.extend(row.stats.iter().flatten().map(|stat| format_value(*stat)));
+            cells
+        });
+        write_table(f, &HEADER, rows)
+    }
+}
+
+/// A row of a table, for a counter or gauge value or a histogram.
+pub(crate) struct Row {
+    pub(crate) name: String,
+    pub(crate) metric_type: MetricType,
+    pub(crate) unit: Unit,
+    /// The value, or the sample count of a histogram
+    pub(crate) count: Value,
+    /// The mean, p50, p90 and p99 of a histogram
+    pub(crate) stats: Option<[Value; 4]>,
+}
+
+impl Row {
+    /// Get the name, type and unit cells
+    pub(crate) fn leading_cells(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            match self.metric_type {
+                MetricType::Untyped => String::new(),
+                metric_type => metric_type.to_string(),
+            },
+            unit_abbreviation(self.unit).to_string(),
+        ]
+    }
+}
+
+/// Flattens a registry into rows, one per histogram and one per other value.
+pub(crate) fn rows<T>(registry: &T) -> Result<Vec<Row>, flatten::Error>
+where
+    T: Serialize + DescribeMetrics + ?Sized,
+{
+    let values =
+        serialization::with_quantiles(Some(QUANTILES.to_vec()), || flatten::raw_values(registry))?;
+
+    let mut rows = Vec::new();
+    for description in T::describe_metrics() {
+        let values: Vec<(&[String], Value)> = values
+            .iter()
+            .filter(|(path, _)| starts_with(path, &description.path))
+            .map(|(path, value)| (&path[description.path.len()..], *value))
+            .collect();
+        let metric_type = description.metadata.metric_type;
+        let unit = description.metadata.unit;
+        let name = description.path.join(".");
+
+        let stat = |name: &str| {
+            values
+                .iter()
+                .find(|(key, _)| key.len() == 1 && key[0] == name)
+                .map(|(_, value)| *value)
+        };
+        if let Some(samples) = stat("samples") {
+            let stat = |name: &str| stat(name).unwrap_or(Value::Float(f64::NAN));
+            rows.push(Row {
+                name,
+                metric_type,
+                unit,
+                count: samples,
+                stats: Some([
+                    stat("mean"),
+                    stat(Quantile::P50.key()),
+                    stat(Quantile::P90.key()),
+                    stat(Quantile::P99.key()),
+                ]),
+            });
+            continue;
+        }
+
+        for (key, value) in values {
+            let mut name = name.clone();
+            for segment in key {
+                name.push('.');
+                name.push_str(segment);
+            }
+            rows.push(Row {
+                name,
+                metric_type,
+                unit,
+                count: value,
+                stats: None,
+            });
+        }
+    }
+    Ok(rows)
+}
+
+/// Writes a table aligning its columns: the first three (names and metadata)
+/// are left-aligned, the others (values) right-aligned.
+pub(crate) fn write_table<W, I>(w: &mut W, header: &[&str], rows: I) -> fmt::Result
+where
+    W: fmt::Write,
+    I: Iterator<Item = Vec<String>>,
+{
+    let mut rows: Vec<Vec<String>> = rows.collect();
+    rows.insert(0, header.iter().map(|cell| cell.to_string()).collect());
+
+    let mut widths = vec![0; header.len()];
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in rows.iter() {
+        let mut line = String::new();
+        for (i, cell) in row.iter().enumerate() {
+            if i > 0 {
+                line.push_str("  ");
+            }
+            if i < 3 {
+                line.push_str(&format!("{:<1$}", cell, widths[i]));
+            } else {
+                line.push_str(&format!("{:>1$}", cell, widths[i]));
+            }
+        }
+        writeln!(w, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+pub(crate) fn starts_with(path: &[String], prefix: &[&str]) -> bool {
+    path.len() >= prefix.len() && path.iter().zip(prefix).all(|(a, b)| a == b)
+}
+
+fn unit_abbreviation(unit: Unit) -> &'static str {
+    match unit {
+        Unit::Seconds => "s",
+        Unit::Milliseconds => "ms",
+        Unit::Microseconds => "µs",
+        Unit::Nanoseconds => "ns",
+        Unit::Bytes => "B",
+        Unit::Requests => "req",
+        Unit::RequestsPerSecond => "req/s",
+        Unit::Ratio => "ratio",
+        _ => "",
+    }
+}
+
+pub(crate) fn format_value(value: Value) -> String {
+    match value {
+        Value::Unsigned(v) => v.to_string(),
+        Value::Signed(v) => v.to_string(),
+        Value::Float(v) => format!("{:.2}", v),
+    }
+}
+
+#[cfg(all(test, feature = "histograms", not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{
+        measure,
+        metadata::{MetricDescription, MetricMetadata},
+        HitCount, ResponseTime,
+    };
+
+    #[derive(Default, Serialize)]
+    struct Registry {
+        hit_count: HitCount,
+        response_time: ResponseTime,
+        pair: (u64, f64),
+    }
+
+    impl DescribeMetrics for Registry {
+        fn describe_metrics() -> Vec<MetricDescription> {
+            vec![
+                MetricDescription {
+                    path: vec!["hit_count"],
+                    metadata: MetricMetadata::new(MetricType::Counter, Unit::None),
+                },
+                MetricDescription {
+                    path: vec!["response_time"],
+                    metadata: MetricMetadata::new(MetricType::Summary, Unit::Milliseconds),
+                },
+                MetricDescription {
+                    path: vec!["pair"],
+                    metadata: MetricMetadata::default(),
+                },
+            ]
+        }
+    }
+
+    #[test]
+    fn prints_aligned_table() {
+        let registry = Registry {
+            pair: (3, 0.5),
+            ..Registry::default()
+        };
+        for _ in 0..2 {
+            measure!(&registry.hit_count, {});
+        }
+
+        let table = Pretty::new(&registry).to_string();
+        assert_eq!(
+            table,
+            "\
+metric         type     unit  count  mean  p50  p90  p99
+hit_count      counter            2
+response_time  summary  ms        0  0.00    0    0    0
+pair.0                            3
+pair.1                         0.50
+"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! A module providing a registry of process-wide metrics.
+//!
+//! This module is only available when the `process` feature is enabled.
+
+use crate::clear::Clear;
+use serde::{Serialize, Serializer};
+
+/// A registry sampling process-wide metrics: CPU time, resident memory, open
+/// file descriptors and thread count.
+///
+/// `ProcessMetrics` holds no state: the process is sampled every time the
+/// registry is serialized, so it can be embedded as a field of an application
+/// registry and be exported alongside it.
+///
+/// ```rust
+/// use metered::process::ProcessMetrics;
+///
+/// #[derive(Default, Debug, serde::Serialize)]
+/// struct AppMetrics {
+///     process: ProcessMetrics,
+/// }
+///
+/// let metrics = AppMetrics::default();
+/// let sample = metrics.process.sample();
+/// # let _ = sample;
+/// ```
+///
+/// Sampling is currently implemented on Linux using `procfs`. On other
+/// platforms, every value is reported as 0.
+#[derive(Default, Debug, Clone)]
+pub struct ProcessMetrics {
+    _private: (),
+}
+
+/// A point-in-time sample of the process metrics.
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct ProcessSample {
+    /// Total user and system CPU time consumed by the process, in seconds.
+    pub cpu_seconds_total: f64,
+    /// Resident memory size, in bytes.
+    pub resident_memory_bytes: u64,
+    /// Number of open file descriptors.
+    pub open_fds: u64,
+    /// Number of OS threads in the process.
+    pub threads: u64,
+}
+
+impl ProcessMetrics {
+    /// Samples the current process metrics.
+    pub fn sample(&self) -> ProcessSample {
+        sys::sample()
+    }
+}
+
+impl Clear for ProcessMetrics {
+    fn clear(&self) {
+        // Do nothing: process metrics are sampled and cannot be reset
+    }
+}
+
+impl Serialize for ProcessMetrics {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Serialize::serialize(&self.sample(), serializer)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use super::ProcessSample;
+    use std::fs;
+
+    // The kernel reports times in `/proc` in USER_HZ units, which is fixed to
+    // 100 on every Linux architecture.
+    const USER_HZ: f64 = 100.0;
+
+    pub(super) fn sample() -> ProcessSample {
+        let mut sample = ProcessSample::default();
+
+        if let Ok(stat) = fs::read_to_string("/proc/self/stat") {
+            // The command name may contain spaces: skip past its closing parenthesis.
+            if let Some(pos) = stat.rfind(')') {
+                let fields: Vec<&str> = stat[pos + 1..].split_whitespace().collect();
+                // Fields are numbered from the state (3rd field of the file)
+                let field = |n: usize| fields.get(n - 3).and_then(|v| v.parse::<u64>().ok());
+                let utime = field(14).unwrap_or(0);
+                let stime = field(15).unwrap_or(0);
+                sample.cpu_seconds_total = (utime + stime) as f64 / USER_HZ;
+            }
+        }
+
+        if let Ok(status) = fs::read_to_string("/proc/self/status") {
+            for line in status.lines() {
+                let mut parts = line.split_whitespace();
+                match parts.next() {
+                    Some("VmRSS:") => {
+                        let kb = parts.next().and_then(|v| v.parse::<u64>().ok());
+                        sample.resident_memory_bytes = kb.unwrap_or(0) * 1024;
+                    }
+                    Some("Threads:") => {
+                        let threads = parts.next().and_then(|v| v.parse::<u64>().ok());
+                        sample.threads = threads.unwrap_or(0);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if let Ok(fds) = fs::read_dir("/proc/self/fd") {
+            // Reading the directory opens one descriptor which is not counted
+            sample.open_fds = (fds.count() as u64).saturating_sub(1);
+        }
+
+        sample
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use super::ProcessSample;
+
+    pub(super) fn sample() -> ProcessSample {
+        ProcessSample::default()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_current_process() {
+        let sample = ProcessMetrics::default().sample();
+        assert!(sample.threads >= 1);
+        assert!(sample.resident_memory_bytes > 0);
+        assert!(sample.open_fds > 0);
+    }
+}
@@ -0,0 +1,217 @@
+//! A module providing read-mostly snapshots of heavyweight histograms, so that
+//! scrapes never contend with recording threads.
+//!
+//! Serializing an [`AtomicHdrHistogram`](crate::hdr_histogram::AtomicHdrHistogram)
+//! holds its lock for as long as the serializer walks its quantiles, stalling
+//! every recording thread meanwhile. A [`Published`] histogram instead keeps a
+//! snapshot of its backend in an [`ArcSwap`]: recording threads publish a new
+//! snapshot at most once per interval, and serialization reads the last
+//! published one without any locking:
+//!
+#![cfg_attr(feature = "disabled", doc = "```ignore")]
+#![cfg_attr(not(feature = "disabled"), doc = "```rust")]
+//! use metered::{hdr_histogram::AtomicHdrHistogram, metered, published::Published, ResponseTime};
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Server {
+//!     metrics: ServerMetrics,
+//! }
+//!
+//! #[metered(registry = ServerMetrics)]
+//! impl Server {
+//!     #[measure(ResponseTime<Published<AtomicHdrHistogram>>)]
+//!     pub fn serve(&self) {}
+//! }
+//!
+//! let server = Server::default();
+//! server.serve();
+//!
+//! // Nothing is published until the first interval elapses, or on demand
+//! assert_eq!(server.metrics.serve.response_time.published().len(), 0);
+//! server.metrics.serve.response_time.publish();
+//! assert_eq!(server.metrics.serve.response_time.published().len(), 1);
+//! ```
+//!
+//! Scrapes thus report values up to one interval old, which
+//! [`DEFAULT_PUBLISH_INTERVAL`] keeps below usual scrape periods.
+
+use crate::{
+    clear::{Clear, Clearable},
+    merge::Merge,
+    metric::{Histogram, SnapshotHistogram},
+    sync::atomic::AtomicU64,
+    time_source::{Instant, StdInstant},
+};
+use arc_swap::ArcSwap;
+use serde::{Serialize, Serializer};
+use std::{
+    fmt,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+/// The interval between publications of histograms built with
+/// [`Histogram::with_bound`].
+pub const DEFAULT_PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A histogram serialized from its last published snapshot, see the
+/// [module documentation](crate::published).
+///
+/// The first record after the publish interval elapsed publishes a snapshot of
+/// the backend, the only time recording pays for a copy of the histogram.
+pub struct Published<H: SnapshotHistogram, T: Instant = StdInstant> {
+    histogram: H,
+    published: ArcSwap<H::Snapshot>,
+    start_time: T,
+    interval: u64,
+    last_publish: AtomicU64,
+}
+
+impl<H: SnapshotHistogram, T: Instant> Published<H, T> {
+    /// Wraps a histogram, publishing its snapshot at most once per `interval`
+    pub fn new(histogram: H, interval: Duration) -> Self {
+        let published = ArcSwap::from_pointee(histogram.snapshot_histogram());
+        Published {
+            histogram,
+            published,
+            start_time: T::now(),
+            interval: T::units(interval),
+            last_publish: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the last published snapshot, without locking the histogram
+    pub fn published(&self) -> Arc<H::Snapshot> {
+        self.published.load_full()
+    }
+
+    /// Publishes a snapshot of the histogram now
+    pub fn publish(&self) {
+        self.published
+            .store(Arc::new(self.histogram.snapshot_histogram()));
+    }
+
+    /// Publishes a snapshot if the interval elapsed since the last one, leaving
+    /// it to a single thread if several notice it
+    fn publish_if_due(&self) {
+        let now = self.start_time.elapsed_time();
+        let last_publish = self.last_publish.load(Ordering::Relaxed);
+        if now.saturating_sub(last_publish) >= self.interval
+            && self
+                .last_publish
+                .compare_exchange(last_publish, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            self.publish();
+        }
+    }
+}
+
+impl<H, T: Instant> Histogram for Published<H, T>
+where
+    H: Histogram + SnapshotHistogram,
+    H::Snapshot: Serialize,
+{
+    fn with_bound(max_value: u64) -> Self {
+        Published::new(H::with_bound(max_value), DEFAULT_PUBLISH_INTERVAL)
+    }
+
+    fn with_precision(max_value: u64, sig_figs: u8) -> Self {
+        Published::new(
+            H::with_precision(max_value, sig_figs),
+            DEFAULT_PUBLISH_INTERVAL,
+        )
+    }
+
+    fn record(&self, value: u64) {
+        self.histogram.record(value);
+        self.publish_if_due();
+    }
+}
+
+impl<H: SnapshotHistogram, T: Instant> SnapshotHistogram for Published<H, T> {
+    type Snapshot = H::Snapshot;
+
+    /// Get a fresh snapshot of the histogram, rather than the published one
+    fn snapshot_histogram(&self) -> H::Snapshot {
+        self.histogram.snapshot_histogram()
+    }
+}
+
+impl<H: SnapshotHistogram + Clear, T: Instant> Clear for Published<H, T> {
+    fn clear(&self) {
+        self.histogram.clear();
+        self.publish();
+    }
+}
+
+impl<H: SnapshotHistogram + Clearable, T: Instant> Clearable for Published<H, T> {
+    fn is_cleared(&self) -> bool {
+        self.histogram.is_cleared()
+    }
+}
+
+impl<H: SnapshotHistogram + Merge, T: Instant> Merge for Published<H, T> {
+    fn merge_from(&self, other: &Self) {
+        self.histogram.merge_from(&other.histogram);
+        self.publish();
+    }
+}
+
+impl<H, T: Instant> Serialize for Published<H, T>
+where
+    H: SnapshotHistogram,
+    H::Snapshot: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Serialize::serialize(&**self.published.load(), serializer)
+    }
+}
+
+impl<H, T: Instant> fmt::Debug for Published<H, T>
+where
+    H: SnapshotHistogram,
+    H::Snapshot: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Published {{ {:?} }}", &**self.published.load())
+    }
+}
+
+#[cfg(all(test, feature = "histograms"))]
+mod tests {
+    use super::*;
+    use crate::{
+        hdr_histogram::AtomicHdrHistogram,
+        simulation::{SimInstant, Simulation},
+    };
+
+    #[test]
+    fn publishes_once_per_interval() {
+        let simulation = Simulation::start(0);
+        let histogram: Published<AtomicHdrHistogram, SimInstant> = Published::new(
+            AtomicHdrHistogram::with_bound(1_000),
+            Duration::from_secs(1),
+        );
+
+        histogram.record(1);
+        histogram.record(2);
+        assert_eq!(histogram.published().len(), 0);
+        assert_eq!(histogram.snapshot_histogram().len(), 2);
+
+        simulation.advance(Duration::from_millis(1_500));
+        histogram.record(3);
+        assert_eq!(histogram.published().len(), 3);
+        assert_eq!(serde_json::to_value(&histogram).unwrap()["samples"], 3);
+
+        histogram.record(4);
+        assert_eq!(histogram.published().len(), 3);
+
+        histogram.clear();
+        assert!(histogram.is_cleared());
+        assert_eq!(histogram.published().len(), 0);
+    }
+}
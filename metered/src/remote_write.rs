@@ -0,0 +1,877 @@
+//! A module pushing registries to Prometheus remote write endpoints, such as
+//! Mimir, Cortex or VictoriaMetrics, without running a Prometheus agent.
+//!
+//! [`RemoteWrite`] pushes snapshots on demand, while a [`PushLoop`] pushes
+//! registries periodically from a thread, or a tokio task with the
+//! `remote-write-tokio` feature, batching samples and retrying failed
+//! requests.
+//!
+//! This module is only available when the `remote-write` feature is enabled.
+
+use crate::{
+    flatten::{self, Sample},
+    namespace::Namespace,
+};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// A client pushing registry snapshots to a Prometheus remote write endpoint.
+///
+/// Registries are flattened with [`flatten::to_samples`]: each sample becomes a
+/// time series named after it, with an optional prefix, its labels and the
+/// labels common to the client, such as `job` or `instance`, which can also be
+/// given as a [`Namespace`].
+///
+/// ```rust,no_run
+/// use metered::{metered, remote_write::RemoteWrite, HitCount};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics)]
+/// impl Service {
+///     #[measure(HitCount)]
+///     pub fn call(&self) {}
+/// }
+///
+/// let service = Service::default();
+/// let remote_write = RemoteWrite::new("http://localhost:9009/api/v1/push")
+///     .with_prefix("service")
+///     .with_label("job", "service");
+///
+/// service.call();
+/// remote_write.push(&service.metrics).unwrap();
+/// ```
+///
+/// Pushes are blocking: they should be made from a dedicated thread or task,
+/// typically on a timer.
+#[derive(Debug)]
+pub struct RemoteWrite {
+    url: String,
+    namespace: Namespace,
+    agent: ureq::Agent,
+}
+
+impl RemoteWrite {
+    /// Creates a client pushing to the URL of a remote write endpoint
+    pub fn new(url: impl Into<String>) -> Self {
+        RemoteWrite {
+            url: url.into(),
+            namespace: Namespace::new(),
+            agent: ureq::Agent::new(),
+        }
+    }
+
+    /// Prepends a prefix, followed by an underscore, to the name of every time
+    /// series
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.namespace = self.namespace.with_prefix(prefix);
+        self
+    }
+
+    /// Adds a label to every time series
+    pub fn with_label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.namespace = self.namespace.with_label(key, value);
+        self
+    }
+
+    /// Applies a namespace to every time series, replacing the prefix and
+    /// labels of the client
+    pub fn with_namespace(mut self, namespace: Namespace) -> Self {
+        self.namespace = namespace;
+        self
+    }
+
+    /// Uses a configured agent, e.g. with timeouts or a proxy
+    pub fn with_agent(mut self, agent: ureq::Agent) -> Self {
+        self.agent = agent;
+        self
+    }
+
+    /// Pushes a snapshot of a registry, timestamped with the current time
+    pub fn push<T: Serialize + ?Sized>(&self, registry: &T) -> Result<(), RemoteWriteError> {
+        let samples = self
+            .namespace
+            .to_samples(registry)
+            .map_err(RemoteWriteError::Flatten)?;
+        self.send(&samples, now_ms())
+    }
+
+    /// Sends samples as they are, without the namespace of the client
+    pub fn send(&self, samples: &[Sample], timestamp_ms: i64) -> Result<(), RemoteWriteError> {
+        let body = encode_compressed(samples, timestamp_ms)?;
+        self.agent
+            .post(&self.url)
+            .set("Content-Encoding", "snappy")
+            .set("Content-Type", "application/x-protobuf")
+            .set("X-Prometheus-Remote-Write-Version", "0.1.0")
+            .send_bytes(&body)
+            .map_err(|e| RemoteWriteError::Http(Box::new(e)))?;
+        Ok(())
+    }
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as i64)
+}
+
+type Collect = Box<dyn Fn() -> Result<Vec<Sample>, flatten::Error> + Send>;
+type Push = Box<dyn FnMut(&RemoteWrite, &[Sample], i64) -> Result<(), RemoteWriteError> + Send>;
+
+/// What a [`PushLoop`] drops when samples are collected faster than they can
+/// be pushed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Overload {
+    /// Drops the oldest pending samples, to push the most recent ones
+    #[default]
+    DropOldest,
+    /// Drops the samples just collected, to push the pending ones first
+    DropNewest,
+}
+
+/// Samples collected at the same time, pushed in a single request.
+struct Batch {
+    timestamp_ms: i64,
+    samples: Vec<Sample>,
+    attempts: u32,
+    retry_at: Option<Instant>,
+}
+
+/// A pipeline pushing watched registries to a remote write endpoint every 15
+/// seconds by default, from a dedicated thread or a tokio task, see
+/// [`PushLoop::spawn_tokio`].
+///
+/// The samples collected at each interval are split into batches, queued until
+/// pushed. Failed requests are retried with an exponential backoff, until they
+/// succeed or exhaust their retries; meanwhile, samples keep being collected
+/// up to a capacity, beyond which the [`Overload`] policy drops samples.
+///
+/// ```rust,no_run
+/// use metered::{
+///     metered,
+///     remote_write::{Overload, PushLoop, RemoteWrite},
+///     HitCount,
+/// };
+/// use std::{sync::Arc, time::Duration};
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics)]
+/// impl Service {
+///     #[measure(HitCount)]
+///     pub fn call(&self) {}
+/// }
+///
+/// let service = Arc::new(Service::default());
+/// let remote_write = RemoteWrite::new("http://localhost:9009/api/v1/push").with_label("job", "service");
+/// let pushing = PushLoop::new(remote_write)
+///     .watch(Arc::clone(&service), |service| &service.metrics)
+///     .with_interval(Duration::from_secs(10))
+///     .with_max_retries(3)
+///     .with_overload(Overload::DropNewest)
+///     .spawn();
+///
+/// service.call();
+/// pushing.stop();
+/// ```
+///
+/// Stopping the loop collects the registries a last time, and tries to push
+/// the pending samples once, without retrying.
+pub struct PushLoop {
+    remote_write: RemoteWrite,
+    push: Push,
+    registries: Vec<Collect>,
+    interval: Duration,
+    max_batch: usize,
+    capacity: usize,
+    overload: Overload,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    max_retries: u32,
+    pending: VecDeque<Batch>,
+    pending_samples: usize,
+    next_collect: Instant,
+    stats: Arc<PushStats>,
+}
+
+impl PushLoop {
+    /// Creates a loop pushing with a client, watching no registries
+    pub fn new(remote_write: RemoteWrite) -> Self {
+        PushLoop {
+            remote_write,
+            push: Box::new(|remote_write, samples, timestamp_ms| {
+                remote_write.send(samples, timestamp_ms)
+            }),
+            registries: Vec::new(),
+            interval: Duration::from_secs(15),
+            max_batch: 1_000,
+            capacity: 100_000,
+            overload: Overload::default(),
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            max_retries: 5,
+            pending: VecDeque::new(),
+            pending_samples: 0,
+            next_collect: Instant::now(),
+            stats: Arc::default(),
+        }
+    }
+
+    /// Watches a registry owned by a shared value, usually the measured
+    /// service
+    pub fn watch<T, R>(mut self, owner: Arc<T>, registry: fn(&T) -> &R) -> Self
+    where
+        T: Send + Sync + 'static,
+        R: Serialize + 'static,
+    {
+        let collect = move || flatten::to_samples(registry(&owner));
+        self.registries.push(Box::new(collect));
+        self
+    }
+
+    /// Collects registries at another interval
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Pushes at most a number of samples per request, at least one
+    pub fn with_max_batch(mut self, max_batch: usize) -> Self {
+        self.max_batch = max_batch.max(1);
+        self
+    }
+
+    /// Keeps up to a number of samples pending, before applying the overload
+    /// policy
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Applies another policy when the capacity is exceeded
+    pub fn with_overload(mut self, overload: Overload) -> Self {
+        self.overload = overload;
+        self
+    }
+
+    /// Waits `initial` before the first retry of a request, doubling the wait
+    /// at each retry up to `max`
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Retries failed requests a number of times before dropping their samples
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Collects the watched registries, queuing their samples
+    fn collect(&mut self) {
+        let timestamp_ms = now_ms();
+        let mut samples = Vec::new();
+        for collect in self.registries.iter() {
+            // Registries failing to serialize are collected at the next
+            // interval
+            if let Ok(registry_samples) = collect() {
+                samples.extend(registry_samples);
+            }
+        }
+        self.remote_write.namespace.apply(&mut samples);
+
+        while !samples.is_empty() {
+            let rest = samples.split_off(self.max_batch.min(samples.len()));
+            self.enqueue(Batch {
+                timestamp_ms,
+                samples,
+                attempts: 0,
+                retry_at: None,
+            });
+            samples = rest;
+        }
+    }
+
+    fn enqueue(&mut self, batch: Batch) {
+        while self.pending_samples + batch.samples.len() > self.capacity {
+            let dropped = match self.overload {
+                Overload::DropNewest => None,
+                Overload::DropOldest => self.pending.pop_front(),
+            };
+            match dropped {
+                Some(dropped) => self.drop_batch(dropped),
+                None => {
+                    self.stats.add(&self.stats.dropped, batch.samples.len());
+                    return;
+                }
+            }
+        }
+        self.pending_samples += batch.samples.len();
+        self.pending.push_back(batch);
+    }
+
+    fn drop_batch(&mut self, batch: Batch) {
+        self.pending_samples -= batch.samples.len();
+        self.stats.add(&self.stats.dropped, batch.samples.len());
+    }
+
+    /// Pushes pending batches in order, until one fails, and returns when it
+    /// should be retried
+    fn push_pending(&mut self) -> Option<Instant> {
+        while let Some(batch) = self.pending.front_mut() {
+            let now = Instant::now();
+            if let Some(retry_at) = batch.retry_at.filter(|retry_at| *retry_at > now) {
+                return Some(retry_at);
+            }
+
+            if (self.push)(&self.remote_write, &batch.samples, batch.timestamp_ms).is_ok() {
+                let batch = self.pending.pop_front().expect("batch is pending");
+                self.pending_samples -= batch.samples.len();
+                self.stats.add(&self.stats.pushed, batch.samples.len());
+                continue;
+            }
+
+            self.stats.add(&self.stats.failures, 1);
+            batch.attempts += 1;
+            if batch.attempts > self.max_retries {
+                let batch = self.pending.pop_front().expect("batch is pending");
+                self.drop_batch(batch);
+                continue;
+            }
+            let max_backoff = self.max_backoff;
+            let backoff = self
+                .initial_backoff
+                .checked_mul(1 << (batch.attempts - 1).min(31))
+                .map_or(max_backoff, |backoff| backoff.min(max_backoff));
+            batch.retry_at = Some(now + backoff);
+            return batch.retry_at;
+        }
+        None
+    }
+
+    /// Tries to push every pending batch once
+    fn flush(&mut self) {
+        while let Some(batch) = self.pending.pop_front() {
+            self.pending_samples -= batch.samples.len();
+            match (self.push)(&self.remote_write, &batch.samples, batch.timestamp_ms) {
+                Ok(()) => self.stats.add(&self.stats.pushed, batch.samples.len()),
+                Err(_) => {
+                    self.stats.add(&self.stats.failures, 1);
+                    self.stats.add(&self.stats.dropped, batch.samples.len());
+                }
+            }
+        }
+    }
+
+    /// Collects the registries if due and pushes pending batches, and returns
+    /// when to run again
+    fn run_once(&mut self) -> Instant {
+        if Instant::now() >= self.next_collect {
+            self.collect();
+            self.next_collect = Instant::now() + self.interval;
+        }
+        match self.push_pending() {
+            Some(retry_at) => retry_at.min(self.next_collect),
+            None => self.next_collect,
+        }
+    }
+
+    /// Spawns a thread collecting and pushing the watched registries, until
+    /// the returned handle is stopped or dropped
+    pub fn spawn(mut self) -> PushLoopHandle {
+        let stats = Arc::clone(&self.stats);
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("metered-remote-write".to_string())
+            .spawn(move || {
+                loop {
+                    let wake_at = self.run_once();
+                    let timeout = wake_at.saturating_duration_since(Instant::now());
+                    match stopped.recv_timeout(timeout) {
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        _ => break,
+                    }
+                }
+                self.collect();
+                self.flush();
+            })
+            .expect("could not spawn the remote write thread");
+
+        PushLoopHandle {
+            stats,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Spawns a tokio task collecting and pushing the watched registries,
+    /// until the returned handle is stopped or dropped
+    ///
+    /// Requests are blocking, so collections and pushes run on the blocking
+    /// threads of the runtime, while the task waits on its timer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime, and the task panics if the
+    /// runtime has no time driver.
+    ///
+    /// This method is only available when the `remote-write-tokio` feature is
+    /// enabled.
+    #[cfg(feature = "remote-write-tokio")]
+    pub fn spawn_tokio(self) -> PushTaskHandle {
+        let stats = Arc::clone(&self.stats);
+        let (stop, mut stopped) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            let mut push_loop = self;
+            loop {
+                let run = tokio::task::spawn_blocking(move || {
+                    let wake_at = push_loop.run_once();
+                    (push_loop, wake_at)
+                });
+                let wake_at = match run.await {
+                    Ok((returned, wake_at)) => {
+                        push_loop = returned;
+                        wake_at
+                    }
+                    // The loop panicked, as a thread would
+                    Err(_) => return,
+                };
+
+                let wake_at = tokio::time::Instant::from_std(wake_at);
+                if tokio::time::timeout_at(wake_at, &mut stopped).await.is_ok() {
+                    break;
+                }
+            }
+            let _ = tokio::task::spawn_blocking(move || {
+                push_loop.collect();
+                push_loop.flush();
+            })
+            .await;
+        });
+
+        PushTaskHandle {
+            stats,
+            stop: Some(stop),
+            task: Some(task),
+        }
+    }
+}
+
+impl fmt::Debug for PushLoop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PushLoop")
+            .field("remote_write", &self.remote_write)
+            .field("interval", &self.interval)
+            .field("pending_samples", &self.pending_samples)
+            .finish()
+    }
+}
+
+/// Counters of the samples pushed by a [`PushLoop`].
+#[derive(Debug, Default)]
+pub struct PushStats {
+    pushed: AtomicU64,
+    failures: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl PushStats {
+    /// Get the number of samples pushed
+    pub fn pushed(&self) -> u64 {
+        self.pushed.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of failed requests, including retries
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of samples dropped, because of overloads or exhausted
+    /// retries
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, counter: &AtomicU64, count: usize) {
+        counter.fetch_add(count as u64, Ordering::Relaxed);
+    }
+}
+
+/// A handle on a running [`PushLoop`], stopping it when dropped.
+pub struct PushLoopHandle {
+    stats: Arc<PushStats>,
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PushLoopHandle {
+    /// Get the counters of the samples pushed so far
+    pub fn stats(&self) -> &PushStats {
+        &self.stats
+    }
+
+    /// Stops the loop after a last push, waiting for its thread to exit
+    pub fn stop(self) {
+        drop(self)
+    }
+}
+
+impl Drop for PushLoopHandle {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl fmt::Debug for PushLoopHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PushLoopHandle")
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+/// A handle on a [`PushLoop`] running as a tokio task, stopping it when
+/// dropped, in which case its last push completes in the background.
+///
+/// This type is only available when the `remote-write-tokio` feature is
+/// enabled.
+#[cfg(feature = "remote-write-tokio")]
+pub struct PushTaskHandle {
+    stats: Arc<PushStats>,
+    stop: Option<tokio::sync::oneshot::Sender<()>>,
+    task: Option<tokio::task::JoinHandle<()>>,
+}
+
+#[cfg(feature = "remote-write-tokio")]
+impl PushTaskHandle {
+    /// Get the counters of the samples pushed so far
+    pub fn stats(&self) -> &PushStats {
+        &self.stats
+    }
+
+    /// Stops the loop after a last push, waiting for its task to complete
+    pub async fn stop(mut self) {
+        drop(self.stop.take());
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+}
+
+#[cfg(feature = "remote-write-tokio")]
+impl Drop for PushTaskHandle {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+    }
+}
+
+#[cfg(feature = "remote-write-tokio")]
+impl fmt::Debug for PushTaskHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PushTaskHandle")
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+/// Encodes samples as a remote write `WriteRequest` protobuf message, with
+/// one time series per sample.
+///
+/// The name of each sample is written as the `__name__` label, and labels are
+/// sorted by name as required by the protocol.
+pub fn encode(samples: &[Sample], timestamp_ms: i64) -> Vec<u8> {
+    let mut request = Vec::new();
+    let mut series = Vec::new();
+    let mut message = Vec::new();
+
+    for sample in samples {
+        let mut labels: Vec<(&str, &str)> = sample
+            .labels
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        labels.push(("__name__", &sample.name));
+        labels.sort_by(|a, b| a.0.cmp(b.0));
+
+        series.clear();
+        for (name, value) in labels {
+            // Label { string name = 1; string value = 2; }
+            message.clear();
+            write_bytes(&mut message, 1, name.as_bytes());
+            write_bytes(&mut message, 2, value.as_bytes());
+            // TimeSeries { repeated Label labels = 1; ... }
+            write_bytes(&mut series, 1, &message);
+        }
+
+        // Sample { double value = 1; int64 timestamp = 2; }
+        message.clear();
+        message.push(1 << 3 | 1);
+        message.extend_from_slice(&sample.value.to_le_bytes());
+        message.push(2 << 3);
+        write_varint(&mut message, timestamp_ms as u64);
+        // TimeSeries { ...; repeated Sample samples = 2; }
+        write_bytes(&mut series, 2, &message);
+
+        // WriteRequest { repeated TimeSeries timeseries = 1; }
+        write_bytes(&mut request, 1, &series);
+    }
+
+    request
+}
+
+/// Encodes samples with [`encode`], and compresses them with the snappy block
+/// format, ready to be sent to a remote write endpoint.
+pub fn encode_compressed(
+    samples: &[Sample],
+    timestamp_ms: i64,
+) -> Result<Vec<u8>, RemoteWriteError> {
+    snap::raw::Encoder::new()
+        .compress_vec(&encode(samples, timestamp_ms))
+        .map_err(RemoteWriteError::Compress)
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Writes a length-delimited field
+fn write_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(buf, field << 3 | 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// An error raised while pushing to a remote write endpoint.
+#[derive(Debug)]
+pub enum RemoteWriteError {
+    /// The registry could not be flattened into samples
+    Flatten(flatten::Error),
+    /// The samples could not be compressed
+    Compress(snap::Error),
+    /// The request failed, or the endpoint returned an error status
+    Http(Box<ureq::Error>),
+}
+
+impl fmt::Display for RemoteWriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemoteWriteError::Flatten(e) => write!(f, "could not flatten registry: {}", e),
+            RemoteWriteError::Compress(e) => write!(f, "could not compress samples: {}", e),
+            RemoteWriteError::Http(e) => write!(f, "remote write request failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RemoteWriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RemoteWriteError::Flatten(e) => Some(e),
+            RemoteWriteError::Compress(e) => Some(e),
+            RemoteWriteError::Http(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{measure, sync::Mutex, HitCount};
+    use serde::ser::Error as _;
+
+    #[derive(Default, Serialize)]
+    struct Registry {
+        first: HitCount,
+        second: HitCount,
+        third: HitCount,
+    }
+
+    fn failure() -> RemoteWriteError {
+        RemoteWriteError::Flatten(flatten::Error::custom("endpoint is down"))
+    }
+
+    fn push_loop(registry: Arc<Registry>) -> PushLoop {
+        PushLoop::new(RemoteWrite::new("http://localhost").with_prefix("app"))
+            .watch(registry, |r| r)
+            .with_backoff(Duration::ZERO, Duration::ZERO)
+    }
+
+    #[test]
+    fn batches_and_drops_on_overload() {
+        let pushed = Arc::new(Mutex::new(Vec::new()));
+        let mut push_loop = push_loop(Arc::default()).with_max_batch(2).with_capacity(4);
+        let batches = Arc::clone(&pushed);
+        push_loop.push = Box::new(move |_, samples, _| {
+            batches.lock().push(samples.to_vec());
+            Ok(())
+        });
+
+        push_loop.collect();
+        push_loop.collect();
+        assert_eq!(push_loop.pending_samples, 4);
+        assert_eq!(push_loop.stats.dropped(), 2);
+
+        assert_eq!(push_loop.push_pending(), None);
+        let pushed = pushed.lock();
+        assert_eq!(pushed.len(), 3);
+        assert_eq!(pushed[0][0].name, "app_third");
+        assert_eq!(pushed[1].len(), 2);
+        assert_eq!(pushed[1][0].name, "app_first");
+        assert_eq!(push_loop.stats.pushed(), 4);
+
+        let mut push_loop = self::push_loop(Arc::default())
+            .with_max_batch(2)
+            .with_capacity(4)
+            .with_overload(Overload::DropNewest);
+        push_loop.collect();
+        push_loop.collect();
+        assert_eq!(push_loop.pending.len(), 3);
+        assert_eq!(push_loop.pending[2].samples[0].name, "app_third");
+        assert_eq!(push_loop.stats.dropped(), 2);
+    }
+
+    #[test]
+    fn retries_failed_pushes() {
+        let registry = Arc::new(Registry::default());
+        measure!(&registry.first, {});
+        let mut push_loop = push_loop(Arc::clone(&registry)).with_max_retries(2);
+        let mut attempts = 0;
+        push_loop.push = Box::new(move |_, _, _| {
+            attempts += 1;
+            if attempts < 3 {
+                Err(failure())
+            } else {
+                Ok(())
+            }
+        });
+
+        push_loop.collect();
+        assert!(push_loop.push_pending().is_some());
+        assert!(push_loop.push_pending().is_some());
+        assert_eq!(push_loop.push_pending(), None);
+        assert_eq!(push_loop.stats.failures(), 2);
+        assert_eq!(push_loop.stats.pushed(), 3);
+
+        push_loop.push = Box::new(|_, _, _| Err(failure()));
+        push_loop.collect();
+        push_loop.push_pending();
+        push_loop.push_pending();
+        assert_eq!(push_loop.push_pending(), None);
+        assert_eq!(push_loop.stats.failures(), 5);
+        assert_eq!(push_loop.stats.dropped(), 3);
+        assert!(push_loop.pending.is_empty());
+    }
+
+    #[test]
+    fn pushes_when_stopped() {
+        let pushed = Arc::new(AtomicU64::new(0));
+        let mut push_loop = push_loop(Arc::default()).with_interval(Duration::from_secs(3600));
+        let count = Arc::clone(&pushed);
+        push_loop.push = Box::new(move |_, samples, _| {
+            count.fetch_add(samples.len() as u64, Ordering::Relaxed);
+            Ok(())
+        });
+
+        let handle = push_loop.spawn();
+        handle.stop();
+        assert_eq!(pushed.load(Ordering::Relaxed), 6);
+    }
+
+    #[cfg(feature = "remote-write-tokio")]
+    #[test]
+    fn pushes_from_tokio_tasks() {
+        let registry = Arc::new(Registry::default());
+        measure!(&registry.first, {});
+        let pushed = Arc::new(AtomicU64::new(0));
+        let mut push_loop =
+            push_loop(Arc::clone(&registry)).with_interval(Duration::from_secs(3600));
+        let count = Arc::clone(&pushed);
+        push_loop.push = Box::new(move |_, samples, _| {
+            count.fetch_add(samples.len() as u64, Ordering::Relaxed);
+            Ok(())
+        });
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let handle = push_loop.spawn_tokio();
+            while handle.stats().pushed() < 3 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            handle.stop().await;
+        });
+        assert_eq!(pushed.load(Ordering::Relaxed), 6);
+    }
+
+    #[test]
+    fn encodes_write_request() {
+        let samples = vec![Sample {
+            name: "hits".to_string(),
+            labels: vec![("job".to_string(), "app".to_string())],
+            value: 2.0,
+        }];
+
+        let label_name = [0x0a, 8, b'_', b'_', b'n', b'a', b'm', b'e', b'_', b'_'];
+        let label_value = [0x12, 4, b'h', b'i', b't', b's'];
+        let job_name = [0x0a, 3, b'j', b'o', b'b'];
+        let job_value = [0x12, 3, b'a', b'p', b'p'];
+        let mut sample = vec![0x09];
+        sample.extend_from_slice(&2.0f64.to_le_bytes());
+        sample.extend_from_slice(&[0x10, 0xac, 0x02]);
+
+        let mut series = vec![0x0a, 16];
+        series.extend_from_slice(&label_name);
+        series.extend_from_slice(&label_value);
+        series.extend_from_slice(&[0x0a, 10]);
+        series.extend_from_slice(&job_name);
+        series.extend_from_slice(&job_value);
+        series.extend_from_slice(&[0x12, sample.len() as u8]);
+        series.extend_from_slice(&sample);
+
+        let mut expected = vec![0x0a, series.len() as u8];
+        expected.extend_from_slice(&series);
+
+        assert_eq!(encode(&samples, 300), expected);
+    }
+
+    #[test]
+    fn compresses_with_snappy() {
+        let samples = vec![Sample {
+            name: "hits".to_string(),
+            labels: Vec::new(),
+            value: 1.0,
+        }];
+
+        let compressed = encode_compressed(&samples, 0).unwrap();
+        let decompressed = snap::raw::Decoder::new()
+            .decompress_vec(&compressed)
+            .unwrap();
+        assert_eq!(decompressed, encode(&samples, 0));
+    }
+}
@@ -0,0 +1,425 @@
+//! A module providing thread-safe and unsynchronized implementations of a
+//! uniform reservoir sampling histogram.
+
+use crate::{
+    clear::{self, Clear, Clearable},
+    metric::{Histogram, HistogramSnapshot, SnapshotHistogram},
+    serialization::{self, MetricAlias},
+    sync::Mutex,
+};
+use serde::{Serialize, Serializer};
+use std::{
+    cell::RefCell,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+/// The number of values kept by reservoirs built with
+/// [`Histogram::with_bound`], a 99.9% confidence level with a 5% margin of
+/// error assuming a normal distribution.
+pub const DEFAULT_RESERVOIR_SIZE: usize = 1028;
+
+/// A thread-safe implementation of a uniform reservoir
+///
+/// It can be used as a `ResponseTime` backend:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{measure, reservoir::ReservoirHistogram, ResponseTime};
+///
+/// let response_time: ResponseTime<ReservoirHistogram> = ResponseTime::default();
+///
+/// measure!(&response_time, {
+///     std::thread::sleep(std::time::Duration::from_millis(10));
+/// });
+///
+/// assert!(response_time.histogram().quantile(0.5) >= 10);
+/// ```
+pub struct ReservoirHistogram {
+    inner: Mutex<Reservoir>,
+}
+
+impl ReservoirHistogram {
+    /// Returns a cloned snapshot of the inner reservoir.
+    pub fn histogram(&self) -> Reservoir {
+        self.inner.lock().clone()
+    }
+}
+
+impl SnapshotHistogram for ReservoirHistogram {
+    type Snapshot = Reservoir;
+
+    fn snapshot_histogram(&self) -> Reservoir {
+        self.histogram()
+    }
+}
+
+impl Histogram for ReservoirHistogram {
+    fn with_bound(max_value: u64) -> Self {
+        ReservoirHistogram {
+            inner: Mutex::new(Reservoir::with_bound(max_value)),
+        }
+    }
+
+    fn record(&self, value: u64) {
+        lock_histogram!(self.inner).record(value);
+    }
+}
+
+impl Clear for ReservoirHistogram {
+    fn clear(&self) {
+        self.inner.lock().clear();
+    }
+}
+
+impl Clearable for ReservoirHistogram {
+    fn is_cleared(&self) -> bool {
+        self.inner.lock().is_empty()
+    }
+}
+
+impl From<&ReservoirHistogram> for HistogramSnapshot {
+    fn from(histogram: &ReservoirHistogram) -> Self {
+        HistogramSnapshot::from(&*histogram.inner.lock())
+    }
+}
+
+impl Serialize for ReservoirHistogram {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        internal_cost!(serialization, {
+            let mut inner = self.inner.lock();
+            let result = Serialize::serialize(&*inner, serializer);
+            // Under the same lock, so that values are serialized exactly once
+            if clear::is_clearing_serialization() {
+                inner.clear();
+            }
+            result
+        })
+    }
+}
+
+use std::{fmt, fmt::Debug};
+impl Debug for ReservoirHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock();
+        write!(f, "ReservoirHistogram {{ {:?} }}", &*inner)
+    }
+}
+
+/// A uniform reservoir
+///
+/// The reservoir keeps a fixed-size sample of the recorded values, where each
+/// recorded value has the same probability of being kept (Vitter's Algorithm
+/// R). Statistics are computed on the sample, so they are approximate, but
+/// recording a value is very cheap and never allocates.
+#[derive(Clone)]
+pub struct Reservoir {
+    max_value: u64,
+    size: usize,
+    values: Vec<u64>,
+    count: u64,
+    rng: u64,
+}
+
+impl Reservoir {
+    /// Instantiates a new reservoir with a `max_value` and the default size.
+    pub fn with_bound(max_value: u64) -> Self {
+        Self::with_size(max_value, DEFAULT_RESERVOIR_SIZE)
+    }
+
+    /// Instantiates a new reservoir with a `max_value`, keeping up to `size`
+    /// values.
+    pub fn with_size(max_value: u64, size: usize) -> Self {
+        // Seed from std's randomly-keyed hasher to avoid depending on `rand`,
+        // unless simulating
+        let seed = crate::simulation::next_seed()
+            .unwrap_or_else(|| RandomState::new().build_hasher().finish());
+        let size = size.max(1);
+        Reservoir {
+            max_value,
+            size,
+            values: Vec::with_capacity(size),
+            count: 0,
+            rng: seed | 1,
+        }
+    }
+
+    /// A xorshift64* pseudo-random number generator
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Get the reservoir bound
+    pub fn bound(&self) -> u64 {
+        self.max_value
+    }
+
+    /// Get the maximum number of values kept by the reservoir
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Records a value to the reservoir
+    ///
+    /// This is a saturating record: if the value is higher than `max_value`,
+    /// max_value will be recorded instead.
+    pub fn record(&mut self, value: u64) {
+        let value = value.min(self.max_value);
+        self.count += 1;
+        if self.values.len() < self.size {
+            self.values.push(value);
+        } else {
+            let index = self.next_random() % self.count;
+            if let Some(slot) = self.values.get_mut(index as usize) {
+                *slot = value;
+            }
+        }
+    }
+
+    /// Clears the values of the reservoir
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.count = 0;
+    }
+
+    /// Get the number of values recorded to the reservoir, including the ones
+    /// that were not sampled.
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    /// Check if the reservoir's recorded values are empty
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Get the sampled values, in no particular order.
+    pub fn values(&self) -> &[u64] {
+        &self.values
+    }
+
+    /// Get a view of the sample, sorted to compute statistics.
+    pub fn snapshot(&self) -> SortedSample {
+        SortedSample::new(self.values.clone())
+    }
+
+    /// Get the approximate value at a quantile in `[0, 1]`.
+    /// If the reservoir has no recorded values, the value returned will be 0.
+    ///
+    /// This sorts the sample: prefer [`Reservoir::snapshot`] to compute
+    /// several quantiles.
+    pub fn quantile(&self, quantile: f64) -> u64 {
+        self.snapshot().quantile(quantile)
+    }
+}
+
+/// A sorted sample of values, used to compute statistics on reservoirs.
+#[derive(Clone, Debug, Default)]
+pub struct SortedSample(Vec<u64>);
+
+impl SortedSample {
+    /// Builds a sample from values, sorting them.
+    pub fn new(mut values: Vec<u64>) -> Self {
+        values.sort_unstable();
+        SortedSample(values)
+    }
+
+    /// Get the number of values in the sample.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check if the sample is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Get the lowest value in the sample, or 0 if it is empty.
+    pub fn min(&self) -> u64 {
+        self.0.first().copied().unwrap_or(0)
+    }
+
+    /// Get the highest value in the sample, or 0 if it is empty.
+    pub fn max(&self) -> u64 {
+        self.0.last().copied().unwrap_or(0)
+    }
+
+    /// Get the mean of the sample, or 0 if it is empty.
+    pub fn mean(&self) -> f64 {
+        if self.0.is_empty() {
+            0.0
+        } else {
+            self.0.iter().map(|&v| v as f64).sum::<f64>() / self.0.len() as f64
+        }
+    }
+
+    /// Get the value at a quantile in `[0, 1]` using the nearest-rank method,
+    /// or 0 if the sample is empty.
+    pub fn quantile(&self, quantile: f64) -> u64 {
+        if self.0.is_empty() {
+            return 0;
+        }
+        let rank = (quantile.clamp(0.0, 1.0) * self.0.len() as f64).ceil() as usize;
+        self.0[rank.clamp(1, self.0.len()) - 1]
+    }
+
+    /// Serializes the sample statistics, reporting `samples` as the number of
+    /// recorded values.
+    pub(crate) fn serialize_with_samples<S>(
+        &self,
+        samples: u64,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        /// A 'qualified' metric name, see `HdrHistogram`'s serialization.
+        macro_rules! qual {
+            ($e:expr) => {
+                &MetricAlias("<|", $e)
+            };
+        }
+
+        use serde::ser::SerializeMap;
+
+        let mut tup = serializer.serialize_map(Some(4 + serialization::quantile_count()))?;
+        tup.serialize_entry("samples", qual!(samples))?;
+        tup.serialize_entry("min", qual!(self.min()))?;
+        tup.serialize_entry("max", qual!(self.max()))?;
+        tup.serialize_entry("mean", qual!(self.mean()))?;
+        serialization::serialize_quantiles(&mut tup, |q| self.quantile(q))?;
+        tup.end()
+    }
+
+    pub(crate) fn fmt_with_samples(
+        &self,
+        name: &str,
+        samples: u64,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(
+            f,
+            "{} {{
+            samples: {}, min: {}, max: {}, mean: {},
+            90%ile = {}, 95%ile = {}, 99%ile = {}, 99.9%ile = {}, 99.99%ile = {} }}",
+            name,
+            samples,
+            self.min(),
+            self.max(),
+            self.mean(),
+            self.quantile(0.9),
+            self.quantile(0.95),
+            self.quantile(0.99),
+            self.quantile(0.999),
+            self.quantile(0.9999)
+        )
+    }
+}
+
+impl From<&Reservoir> for HistogramSnapshot {
+    fn from(reservoir: &Reservoir) -> Self {
+        let sample = reservoir.snapshot();
+        HistogramSnapshot::new(
+            reservoir.len(),
+            sample.min(),
+            sample.max(),
+            sample.mean(),
+            |q| sample.quantile(q),
+        )
+    }
+}
+
+impl Serialize for Reservoir {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.snapshot()
+            .serialize_with_samples(self.len(), serializer)
+    }
+}
+
+impl Debug for Reservoir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.snapshot().fmt_with_samples("Reservoir", self.len(), f)
+    }
+}
+
+impl Histogram for RefCell<Reservoir> {
+    fn with_bound(max_value: u64) -> Self {
+        RefCell::new(Reservoir::with_bound(max_value))
+    }
+
+    fn record(&self, value: u64) {
+        self.borrow_mut().record(value);
+    }
+}
+
+impl Clear for RefCell<Reservoir> {
+    fn clear(&self) {
+        self.borrow_mut().clear();
+    }
+}
+
+impl Clearable for RefCell<Reservoir> {
+    fn is_cleared(&self) -> bool {
+        self.borrow().is_empty()
+    }
+}
+
+impl SnapshotHistogram for RefCell<Reservoir> {
+    type Snapshot = Reservoir;
+
+    fn snapshot_histogram(&self) -> Reservoir {
+        self.borrow().clone()
+    }
+}
+
+impl From<&RefCell<Reservoir>> for HistogramSnapshot {
+    fn from(reservoir: &RefCell<Reservoir>) -> Self {
+        HistogramSnapshot::from(&*reservoir.borrow())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uniform_sample() {
+        let mut reservoir = Reservoir::with_size(u64::MAX, 1_000);
+        for v in 1..=100_000 {
+            reservoir.record(v);
+        }
+
+        assert_eq!(reservoir.len(), 100_000);
+        assert_eq!(reservoir.values().len(), 1_000);
+
+        let snapshot = reservoir.snapshot();
+        assert!((40_000..=60_000).contains(&snapshot.quantile(0.5)));
+        assert!(snapshot.quantile(0.99) >= 90_000);
+    }
+
+    #[test]
+    fn test_small_sample_is_exact() {
+        let mut reservoir = Reservoir::with_size(100, 10);
+        for v in &[5, 1, 500, 3] {
+            reservoir.record(*v);
+        }
+
+        let snapshot = reservoir.snapshot();
+        assert_eq!(snapshot.min(), 1);
+        assert_eq!(snapshot.max(), 100);
+        assert_eq!(snapshot.quantile(0.5), 3);
+
+        reservoir.clear();
+        assert!(reservoir.is_empty());
+        assert_eq!(reservoir.quantile(0.5), 0);
+    }
+}
@@ -0,0 +1,681 @@
+//! A module providing runtime configuration of the serialization of
+//! registries, for exporters needing different outputs from the same
+//! registries.
+
+use crate::{
+    flatten::{self, Flat},
+    labels::LabelSet,
+};
+use serde::{ser, Serialize, Serializer};
+use std::cell::RefCell;
+
+/// This is a mocked 'newtype' (eg. `A(u64)`) that instead allows us to
+/// define our own type name that doesn't have to abide by Rust's constraints
+/// on type names. This allows us to do some manipulation of our metrics,
+/// allowing us to add dimensionality to our metrics via key=value pairs, or
+/// key manipulation on serializers that support it.
+pub(crate) struct MetricAlias<T: Serialize>(pub(crate) &'static str, pub(crate) T);
+impl<T: Serialize> Serialize for MetricAlias<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(self.0, &self.1)
+    }
+}
+
+/// A quantile reported by histograms.
+///
+/// Quantiles are serialized under keys such as `99%ile`, wrapped in a newtype
+/// struct adding a `quantile=0.99` label for serializers supporting it, like
+/// `serde_prometheus`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quantile {
+    value: f64,
+    key: &'static str,
+    alias: &'static str,
+}
+
+macro_rules! quantile {
+    ($name:ident, $value:expr, $key:expr, $doc:expr) => {
+        #[doc = $doc]
+        pub const $name: Quantile = Quantile {
+            value: $value,
+            key: $key,
+            alias: concat!("!|quantile=", $value),
+        };
+    };
+}
+
+impl Quantile {
+    quantile!(P50, 0.5, "50%ile", "The median");
+    quantile!(P75, 0.75, "75%ile", "The 75th percentile");
+    quantile!(P90, 0.9, "90%ile", "The 90th percentile");
+    quantile!(P95, 0.95, "95%ile", "The 95th percentile");
+    quantile!(P99, 0.99, "99%ile", "The 99th percentile");
+    quantile!(P999, 0.999, "99.9%ile", "The 99.9th percentile");
+    quantile!(P9999, 0.9999, "99.99%ile", "The 99.99th percentile");
+
+    /// The quantiles reported by default
+    pub const DEFAULT: &'static [Quantile] = &[
+        Quantile::P90,
+        Quantile::P95,
+        Quantile::P99,
+        Quantile::P999,
+        Quantile::P9999,
+    ];
+
+    /// Get the quantile, between 0 and 1
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    /// Get the key the quantile is serialized under
+    pub fn key(&self) -> &'static str {
+        self.key
+    }
+}
+
+thread_local! {
+    static QUANTILES: RefCell<Option<Vec<Quantile>>> = const { RefCell::new(None) };
+}
+
+/// Get the number of quantiles histograms serialize on this thread
+pub(crate) fn quantile_count() -> usize {
+    QUANTILES.with(|quantiles| {
+        quantiles
+            .borrow()
+            .as_ref()
+            .map_or(Quantile::DEFAULT.len(), Vec::len)
+    })
+}
+
+/// Runs a closure with histograms serializing some quantiles on this thread,
+/// or the defaults if `None`
+pub(crate) fn with_quantiles<F, R>(quantiles: Option<Vec<Quantile>>, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    /// Restores the previous quantiles, even if serialization panics
+    struct Restore(Option<Vec<Quantile>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            QUANTILES.with(|current| current.replace(previous));
+        }
+    }
+
+    let _restore = Restore(QUANTILES.with(|current| current.replace(quantiles)));
+    f()
+}
+
+/// Serializes the quantiles of a histogram as map entries: the configured
+/// quantiles within [`serialize_with_config`], or [`Quantile::DEFAULT`].
+///
+/// For supporting serializers, the key (such as `90%ile`) is ignored and a
+/// dimension (such as `quantile=0.9`) is added to the metrics instead.
+pub(crate) fn serialize_quantiles<M, F>(map: &mut M, quantile: F) -> Result<(), M::Error>
+where
+    M: ser::SerializeMap,
+    F: Fn(f64) -> u64,
+{
+    QUANTILES.with(|quantiles| {
+        let quantiles = quantiles.borrow();
+        let quantiles = quantiles.as_deref().unwrap_or(Quantile::DEFAULT);
+        for q in quantiles {
+            map.serialize_entry(q.key, &MetricAlias(q.alias, quantile(q.value)))?;
+        }
+        Ok(())
+    })
+}
+
+/// The configuration of [`serialize_with_config`].
+#[derive(Clone, Debug, Default)]
+pub struct SerializationConfig {
+    quantiles: Option<Vec<Quantile>>,
+    flatten: Option<String>,
+    skip_cleared: bool,
+    labels: Vec<(&'static str, String)>,
+}
+
+impl SerializationConfig {
+    /// Creates a configuration serializing registries as usual
+    pub fn new() -> Self {
+        SerializationConfig::default()
+    }
+
+    /// Sets the quantiles reported by `HdrHistogram`, `DdSketch`, `TDigest`
+    /// and `Reservoir` histograms, instead of [`Quantile::DEFAULT`]
+    pub fn with_quantiles(mut self, quantiles: &[Quantile]) -> Self {
+        self.quantiles = Some(quantiles.to_vec());
+        self
+    }
+
+    /// Flattens registries into a single-level map, joining keys with a
+    /// separator, like [`Flat`]. Labels are ignored when flattening.
+    pub fn flatten(mut self, separator: impl Into<String>) -> Self {
+        self.flatten = Some(separator.into());
+        self
+    }
+
+    /// Skips struct fields, e.g. registries and metrics, whose values are all
+    /// zero, as is the case for cleared or never recorded stock metrics
+    pub fn skip_cleared(mut self, skip_cleared: bool) -> Self {
+        self.skip_cleared = skip_cleared;
+        self
+    }
+
+    /// Adds a label to every metric, for serializers supporting them like
+    /// `serde_prometheus`.
+    ///
+    /// See [`LabelSet`]: the registry is wrapped in a newtype struct named
+    /// after its labels. Names are leaked once per distinct set of labels, for
+    /// up to 256 sets, beyond which labels are only attached by the flattener
+    /// of this crate, e.g. with [`to_samples`](crate::flatten::to_samples).
+    pub fn with_label(mut self, key: &'static str, value: impl Into<String>) -> Self {
+        self.labels.push((key, value.into()));
+        self
+    }
+}
+
+/// Serializes a registry with a [`SerializationConfig`].
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{
+///     metered,
+///     serialization::{serialize_with_config, Quantile, SerializationConfig},
+///     HitCount, ResponseTime,
+/// };
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics)]
+/// impl Service {
+///     #[measure([HitCount, ResponseTime])]
+///     pub fn call(&self) {}
+///
+///     #[measure(HitCount)]
+///     pub fn rare(&self) {}
+/// }
+///
+/// let service = Service::default();
+/// service.call();
+///
+/// let config = SerializationConfig::new()
+///     .with_quantiles(&[Quantile::P50])
+///     .flatten(".")
+///     .skip_cleared(true);
+///
+/// let mut json = Vec::new();
+/// let mut serializer = serde_json::Serializer::new(&mut json);
+/// serialize_with_config(&service.metrics, &config, &mut serializer).unwrap();
+///
+/// let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+/// assert_eq!(json["call.hit_count"], 1);
+/// assert!(json.get("call.response_time.50%ile").is_some());
+/// assert!(json.get("call.response_time.99%ile").is_none());
+/// assert!(json.get("rare.hit_count").is_none());
+/// ```
+pub fn serialize_with_config<T, S>(
+    registry: &T,
+    config: &SerializationConfig,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    T: Serialize + ?Sized,
+    S: Serializer,
+{
+    let configured = Configured {
+        value: registry,
+        config,
+    };
+    with_quantiles(config.quantiles.clone(), || match config.flatten {
+        Some(ref separator) => Flat::new(&configured)
+            .with_separator(separator)
+            .serialize(serializer),
+        None if !config.labels.is_empty() => {
+            let labels = LabelSet::new(config.labels.clone());
+            labels.serialize_labeled(&configured, serializer)
+        }
+        None => configured.serialize(serializer),
+    })
+}
+
+/// A value serialized with a configuration, at any depth.
+struct Configured<'a, T: ?Sized> {
+    value: &'a T,
+    config: &'a SerializationConfig,
+}
+
+impl<'a, T: ?Sized> Configured<'a, T> {
+    fn new(value: &'a T, config: &'a SerializationConfig) -> Self {
+        Configured { value, config }
+    }
+}
+
+impl<T: Serialize + ?Sized> Serialize for Configured<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(ConfiguredSerializer {
+            inner: serializer,
+            config: self.config,
+        })
+    }
+}
+
+struct ConfiguredSerializer<'a, S> {
+    inner: S,
+    config: &'a SerializationConfig,
+}
+
+macro_rules! forward {
+    ($($method:ident: $ty:ty),*) => {
+        $(
+            fn $method(self, v: $ty) -> Result<S::Ok, S::Error> {
+                self.inner.$method(v)
+            }
+        )*
+    };
+}
+
+impl<'a, S: Serializer> Serializer for ConfiguredSerializer<'a, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    type SerializeSeq = Compound<'a, S::SerializeSeq>;
+    type SerializeTuple = Compound<'a, S::SerializeTuple>;
+    type SerializeTupleStruct = Compound<'a, S::SerializeTupleStruct>;
+    type SerializeTupleVariant = Compound<'a, S::SerializeTupleVariant>;
+    type SerializeMap = Compound<'a, S::SerializeMap>;
+    type SerializeStruct = Compound<'a, S::SerializeStruct>;
+    type SerializeStructVariant = Compound<'a, S::SerializeStructVariant>;
+
+    forward! {
+        serialize_bool: bool, serialize_i8: i8, serialize_i16: i16, serialize_i32: i32,
+        serialize_i64: i64, serialize_i128: i128, serialize_u8: u8, serialize_u16: u16,
+        serialize_u32: u32, serialize_u64: u64, serialize_u128: u128, serialize_f32: f32,
+        serialize_f64: f64, serialize_char: char, serialize_str: &str,
+        serialize_bytes: &[u8], serialize_unit_struct: &'static str
+    }
+
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_none()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<S::Ok, S::Error> {
+        self.inner
+            .serialize_some(&Configured::new(value, self.config))
+    }
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit_variant(name, index, variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner
+            .serialize_newtype_struct(name, &Configured::new(value, self.config))
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_newtype_variant(
+            name,
+            index,
+            variant,
+            &Configured::new(value, self.config),
+        )
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, S::Error> {
+        Compound::wrap(self.inner.serialize_seq(len), self.config)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, S::Error> {
+        Compound::wrap(self.inner.serialize_tuple(len), self.config)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, S::Error> {
+        Compound::wrap(self.inner.serialize_tuple_struct(name, len), self.config)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, S::Error> {
+        Compound::wrap(
+            self.inner
+                .serialize_tuple_variant(name, index, variant, len),
+            self.config,
+        )
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+        Compound::wrap(self.inner.serialize_map(len), self.config)
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, S::Error> {
+        Compound::wrap(self.inner.serialize_struct(name, len), self.config)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, S::Error> {
+        Compound::wrap(
+            self.inner
+                .serialize_struct_variant(name, index, variant, len),
+            self.config,
+        )
+    }
+}
+
+struct Compound<'a, C> {
+    inner: C,
+    config: &'a SerializationConfig,
+}
+
+impl<'a, C> Compound<'a, C> {
+    fn wrap<E>(inner: Result<C, E>, config: &'a SerializationConfig) -> Result<Self, E> {
+        inner.map(|inner| Compound { inner, config })
+    }
+
+    fn skip<T: Serialize + ?Sized>(&self, value: &T) -> bool {
+        self.config.skip_cleared && flatten::is_zero(value)
+    }
+}
+
+impl<C: ser::SerializeSeq> ser::SerializeSeq for Compound<'_, C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        self.inner
+            .serialize_element(&Configured::new(value, self.config))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: ser::SerializeTuple> ser::SerializeTuple for Compound<'_, C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        self.inner
+            .serialize_element(&Configured::new(value, self.config))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: ser::SerializeTupleStruct> ser::SerializeTupleStruct for Compound<'_, C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        self.inner
+            .serialize_field(&Configured::new(value, self.config))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: ser::SerializeTupleVariant> ser::SerializeTupleVariant for Compound<'_, C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        self.inner
+            .serialize_field(&Configured::new(value, self.config))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: ser::SerializeMap> ser::SerializeMap for Compound<'_, C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), C::Error> {
+        self.inner.serialize_key(key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        self.inner
+            .serialize_value(&Configured::new(value, self.config))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: ser::SerializeStruct> ser::SerializeStruct for Compound<'_, C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), C::Error> {
+        if self.skip(value) {
+            self.inner.skip_field(key)
+        } else {
+            self.inner
+                .serialize_field(key, &Configured::new(value, self.config))
+        }
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: ser::SerializeStructVariant> ser::SerializeStructVariant for Compound<'_, C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), C::Error> {
+        if self.skip(value) {
+            self.inner.skip_field(key)
+        } else {
+            self.inner
+                .serialize_field(key, &Configured::new(value, self.config))
+        }
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+#[cfg(all(test, feature = "histograms", not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{measure, HitCount, ResponseTime};
+
+    #[derive(Default, Serialize)]
+    struct Registry {
+        hot: Metrics,
+        rare: Metrics,
+    }
+
+    #[derive(Default, Serialize)]
+    struct Metrics {
+        hit_count: HitCount,
+        response_time: ResponseTime,
+    }
+
+    /// A registry serialized with a configuration
+    struct WithConfig<'a>(&'a Registry, &'a SerializationConfig);
+
+    impl Serialize for WithConfig<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serialize_with_config(self.0, self.1, serializer)
+        }
+    }
+
+    fn to_json(registry: &Registry, config: &SerializationConfig) -> serde_json::Value {
+        let mut json = Vec::new();
+        serialize_with_config(
+            registry,
+            config,
+            &mut serde_json::Serializer::new(&mut json),
+        )
+        .unwrap();
+        serde_json::from_slice(&json).unwrap()
+    }
+
+    #[test]
+    fn skips_cleared_fields() {
+        let registry = Registry::default();
+        measure!(&registry.hot.hit_count, {});
+
+        let config = SerializationConfig::new().skip_cleared(true);
+        assert_eq!(
+            to_json(&registry, &config),
+            serde_json::json!({ "hot": { "hit_count": 1 } })
+        );
+    }
+
+    #[test]
+    fn skips_cleared_fields_while_clearing() {
+        let registry = Registry::default();
+        for _ in 0..5 {
+            measure!(&registry.hot.hit_count, {});
+        }
+
+        let config = SerializationConfig::new().skip_cleared(true);
+
+        let mut json = Vec::new();
+        crate::clear::serialize_and_clear(
+            &WithConfig(&registry, &config),
+            &mut serde_json::Serializer::new(&mut json),
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&json).unwrap(),
+            serde_json::json!({ "hot": { "hit_count": 5 } })
+        );
+        assert_eq!(registry.hot.hit_count.get(), 0);
+        assert!(!crate::clear::is_clearing_serialization());
+    }
+
+    #[test]
+    fn configures_quantiles_while_serializing() {
+        let registry = Registry::default();
+
+        let config = SerializationConfig::new().with_quantiles(&[Quantile::P50, Quantile::P75]);
+        let json = to_json(&registry, &config);
+        let response_time = json["hot"]["response_time"].as_object().unwrap();
+        assert!(response_time.contains_key("50%ile"));
+        assert!(response_time.contains_key("75%ile"));
+        assert!(!response_time.contains_key("90%ile"));
+
+        // Back to the defaults
+        let json = serde_json::to_value(&registry).unwrap();
+        assert!(json["hot"]["response_time"].get("90%ile").is_some());
+        assert_eq!(quantile_count(), Quantile::DEFAULT.len());
+    }
+
+    #[test]
+    fn restores_quantiles_after_panics() {
+        let panicked = std::panic::catch_unwind(|| {
+            with_quantiles(Some(vec![Quantile::P50]), || panic!("serialization failed"))
+        });
+        assert!(panicked.is_err());
+        assert_eq!(quantile_count(), Quantile::DEFAULT.len());
+    }
+
+    #[test]
+    fn injects_labels() {
+        let registry = Registry::default();
+        measure!(&registry.hot.hit_count, {});
+
+        let config = SerializationConfig::new()
+            .skip_cleared(true)
+            .with_label("region", "eu");
+
+        let samples = crate::flatten::to_samples(&WithConfig(&registry, &config)).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].name, "hot_hit_count");
+        assert_eq!(
+            samples[0].labels,
+            [("region".to_string(), "eu".to_string())]
+        );
+    }
+
+    #[test]
+    fn injects_labels_beyond_leaked_names() {
+        let registry = Registry::default();
+        measure!(&registry.hot.hit_count, {});
+
+        for tenant in 0..300 {
+            let config = SerializationConfig::new()
+                .skip_cleared(true)
+                .with_label("tenant", format!("leak-{}", tenant));
+
+            let samples = crate::flatten::to_samples(&WithConfig(&registry, &config)).unwrap();
+            assert_eq!(
+                samples[0].labels,
+                [("tenant".to_string(), format!("leak-{}", tenant))]
+            );
+        }
+        assert!(crate::labels::take_dynamic_alias().is_none());
+    }
+}
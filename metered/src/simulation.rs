@@ -0,0 +1,233 @@
+//! A module providing a deterministic simulation mode, for integration tests
+//! of time-dependent metrics such as `ResponseTime` or `Throughput`.
+//!
+//! A [`Simulation`] drives a manual clock, read by metrics using the
+//! [`SimInstant`] time source, and seeds the random number generators of
+//! metrics created while it runs, such as [`Reservoir`]s. Combined with
+//! [`Snapshot::exact`], which orders values by name, tests produce identical
+//! snapshots across runs and platforms:
+//!
+#![cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#![cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+//! use metered::{
+//!     hdr_histogram::AtomicHdrHistogram,
+//!     metered,
+//!     simulation::{SimInstant, Simulation},
+//!     ResponseTime, Throughput,
+//! };
+//! use std::time::Duration;
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Service {
+//!     metrics: ServiceMetrics,
+//! }
+//!
+//! #[metered(registry = ServiceMetrics)]
+//! impl Service {
+//!     #[measure([ResponseTime<AtomicHdrHistogram, SimInstant>, Throughput<SimInstant>])]
+//!     pub fn call(&self, simulation: &Simulation, latency: Duration) {
+//!         simulation.advance(latency);
+//!     }
+//! }
+//!
+//! let simulation = Simulation::start(42);
+//! let service = Service::default();
+//! for millis in [10, 20, 30, 1_500, 40] {
+//!     service.call(&simulation, Duration::from_millis(millis));
+//! }
+//!
+//! let snapshot = simulation.snapshot(&service.metrics).unwrap();
+//! assert_eq!(snapshot.get("call.response_time.samples"), Some(5.0));
+//! assert_eq!(snapshot.get("call.response_time.min"), Some(10.0));
+//! assert_eq!(snapshot.get("call.throughput.samples"), Some(1.0));
+//! assert_eq!(snapshot.get("call.throughput.max"), Some(3.0));
+//! ```
+//!
+//! The clock and the seed are local to the thread running the simulation:
+//! tests running in parallel do not interfere, but measured code must run on
+//! that thread, e.g. on a current-thread async runtime.
+//!
+//! [`Reservoir`]: crate::reservoir::Reservoir
+//! [`Snapshot::exact`]: crate::snapshot::Snapshot::exact
+
+use crate::{flatten, metadata::DescribeMetrics, snapshot::Snapshot, time_source::Instant};
+use serde::Serialize;
+use std::{cell::Cell, convert::TryFrom, marker::PhantomData, time::Duration};
+
+thread_local! {
+    /// The simulated time, in milliseconds
+    static CLOCK: Cell<u64> = const { Cell::new(0) };
+
+    /// The state of the seed generator, if simulating
+    static SEED: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Get a new seed for a random number generator if a simulation runs on this
+/// thread, each call returning another seed.
+pub(crate) fn next_seed() -> Option<u64> {
+    SEED.with(|seed| {
+        let state = seed.get()?.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        seed.set(Some(state));
+
+        // SplitMix64, to derive independent seeds from successive states
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Some(z ^ (z >> 31))
+    })
+}
+
+/// A deterministic simulation, running on the current thread until dropped.
+///
+/// See the [module documentation](crate::simulation).
+#[derive(Debug)]
+pub struct Simulation {
+    // The clock and seed are thread-local
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Simulation {
+    /// Starts a simulation on the current thread, with the clock at zero and
+    /// random number generators seeded from `seed`.
+    ///
+    /// A simulation started while another runs on the same thread restarts
+    /// it.
+    pub fn start(seed: u64) -> Self {
+        CLOCK.with(|clock| clock.set(0));
+        SEED.with(|state| state.set(Some(seed)));
+        Simulation {
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Advances the clock
+    pub fn advance(&self, duration: Duration) {
+        let millis = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+        CLOCK.with(|clock| clock.set(clock.get().saturating_add(millis)));
+    }
+
+    /// Get the time elapsed since the simulation started
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_millis(CLOCK.with(Cell::get))
+    }
+
+    /// Takes an exact snapshot of a registry, see [`Snapshot::exact`]
+    pub fn snapshot<T>(&self, registry: &T) -> Result<Snapshot, flatten::Error>
+    where
+        T: Serialize + DescribeMetrics + ?Sized,
+    {
+        Snapshot::exact(registry)
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        CLOCK.with(|clock| clock.set(0));
+        SEED.with(|state| state.set(None));
+    }
+}
+
+/// A time source reading the clock of the [`Simulation`] running on the
+/// current thread, in milliseconds.
+///
+/// Without a running simulation, the clock stays at zero.
+#[derive(Debug, Clone)]
+pub struct SimInstant(u64);
+
+impl Instant for SimInstant {
+    const ONE_SEC: u64 = 1_000;
+
+    fn now() -> Self {
+        SimInstant(CLOCK.with(Cell::get))
+    }
+
+    fn elapsed_time(&self) -> u64 {
+        CLOCK.with(Cell::get).saturating_sub(self.0)
+    }
+
+    fn units(duration: Duration) -> u64 {
+        u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+    }
+}
+
+#[cfg(all(test, feature = "histograms", not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{
+        measure,
+        metadata::{MetricDescription, MetricMetadata, MetricType, Unit},
+        reservoir::{Reservoir, ReservoirHistogram},
+        ResponseTime, Throughput,
+    };
+
+    #[derive(Default, Serialize)]
+    struct Registry {
+        response_time: ResponseTime<ReservoirHistogram, SimInstant>,
+        throughput: Throughput<SimInstant>,
+    }
+
+    impl DescribeMetrics for Registry {
+        fn describe_metrics() -> Vec<MetricDescription> {
+            vec![
+                MetricDescription {
+                    path: vec!["response_time"],
+                    metadata: MetricMetadata::new(MetricType::Summary, Unit::Milliseconds),
+                },
+                MetricDescription {
+                    path: vec!["throughput"],
+                    metadata: MetricMetadata::new(MetricType::Summary, Unit::RequestsPerSecond),
+                },
+            ]
+        }
+    }
+
+    fn run(seed: u64) -> String {
+        let simulation = Simulation::start(seed);
+        let registry = Registry::default();
+        for i in 0..2_000 {
+            let latency = Duration::from_millis(i % 7);
+            measure!(&registry.response_time, simulation.advance(latency));
+            measure!(&registry.throughput, {});
+        }
+        simulation.snapshot(&registry).unwrap().to_string()
+    }
+
+    #[test]
+    fn snapshots_are_identical_across_runs() {
+        assert_eq!(run(7), run(7));
+        assert!(run(7).contains("throughput.samples = 5\n"));
+    }
+
+    #[test]
+    fn seeds_reservoirs() {
+        let sample = |seed| {
+            let _simulation = Simulation::start(seed);
+            let mut reservoir = Reservoir::with_size(1_000, 8);
+            for value in 0..1_000 {
+                reservoir.record(value);
+            }
+            reservoir.values().to_vec()
+        };
+        assert_eq!(sample(1), sample(1));
+        assert_ne!(sample(1), sample(2));
+        assert_eq!(next_seed(), None);
+    }
+
+    #[test]
+    fn advances_clock() {
+        let simulation = Simulation::start(0);
+        let start = SimInstant::now();
+        simulation.advance(Duration::from_secs(2));
+        assert_eq!(start.elapsed_time(), 2 * SimInstant::ONE_SEC);
+        assert_eq!(simulation.elapsed(), Duration::from_secs(2));
+
+        drop(simulation);
+        assert_eq!(SimInstant::now().0, 0);
+    }
+}
@@ -0,0 +1,456 @@
+//! A module emitting the values of registries as events to a message bus, such
+//! as Kafka or NATS, for organizations centralizing telemetry through event
+//! streams.
+//!
+//! A [`Sink`] periodically flattens watched registries into [`Event`]s, holding
+//! either all their values or the changes since the previous event, and hands
+//! them to a [`Publisher`] wrapping the client of the message bus. Events
+//! implement `Serialize`, to be encoded in the format expected by consumers.
+//!
+//! A publisher to NATS only needs a TCP connection, speaking its text
+//! protocol:
+//!
+//! ```rust,no_run
+//! use metered::{
+//!     metered,
+//!     sink::{Event, Publisher, Sink},
+//!     HitCount,
+//! };
+//! use std::{
+//!     io::{self, Write},
+//!     net::TcpStream,
+//!     sync::Arc,
+//! };
+//!
+//! struct Nats {
+//!     stream: TcpStream,
+//!     subject: String,
+//! }
+//!
+//! impl Publisher for Nats {
+//!     type Error = io::Error;
+//!
+//!     fn publish(&mut self, event: &Event) -> io::Result<()> {
+//!         let payload = serde_json::to_vec(event)?;
+//!         write!(self.stream, "PUB {} {}\r\n", self.subject, payload.len())?;
+//!         self.stream.write_all(&payload)?;
+//!         self.stream.write_all(b"\r\n")
+//!     }
+//! }
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Service {
+//!     metrics: ServiceMetrics,
+//! }
+//!
+//! #[metered(registry = ServiceMetrics)]
+//! impl Service {
+//!     #[measure(HitCount)]
+//!     pub fn call(&self) {}
+//! }
+//!
+//! let mut stream = TcpStream::connect("127.0.0.1:4222").unwrap();
+//! stream.write_all(b"CONNECT {\"verbose\":false}\r\n").unwrap();
+//! let nats = Nats {
+//!     stream,
+//!     subject: "telemetry.metrics".to_string(),
+//! };
+//!
+//! let service = Arc::new(Service::default());
+//! let sink = Sink::new(nats)
+//!     .watch("service", Arc::clone(&service), |service| &service.metrics)
+//!     .deltas()
+//!     .spawn();
+//!
+//! service.call();
+//! sink.stop();
+//! ```
+//!
+//! Publishing to Kafka is similar, e.g. with the `rdkafka` crate:
+//!
+//! ```rust,ignore
+//! use rdkafka::producer::{BaseProducer, BaseRecord};
+//!
+//! struct Kafka {
+//!     producer: BaseProducer,
+//!     topic: String,
+//! }
+//!
+//! impl Publisher for Kafka {
+//!     type Error = rdkafka::error::KafkaError;
+//!
+//!     fn publish(&mut self, event: &Event) -> Result<(), Self::Error> {
+//!         let payload = serde_json::to_vec(event).expect("events serialize to JSON");
+//!         let record = BaseRecord::to(&self.topic)
+//!             .key(&event.registry)
+//!             .payload(&payload);
+//!         self.producer.send(record).map_err(|(e, _)| e)?;
+//!         self.producer.poll(std::time::Duration::ZERO);
+//!         Ok(())
+//!     }
+//! }
+//! ```
+
+use crate::{
+    flatten::{self, Sample},
+    namespace::Namespace,
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+type Collect = Box<dyn Fn() -> Result<Vec<Sample>, flatten::Error> + Send>;
+/// A sample of a registry: the index of the registry, and the name and labels
+/// of the sample
+type SampleKey = (usize, String, Vec<(String, String)>);
+
+/// The values of a registry at a point in time, emitted by a [`Sink`].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Event {
+    /// The name the registry is watched under
+    pub registry: String,
+    /// When the values were collected, in milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    /// Whether the event holds all the values, or their changes
+    pub kind: EventKind,
+    /// The values of the registry, or by how much they changed since the
+    /// previous event for deltas
+    pub samples: Vec<Sample>,
+}
+
+/// The kind of the events emitted by a [`Sink`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// Holds all the values of a registry
+    Snapshot,
+    /// Holds the values that changed since the previous event, and by how much
+    Delta,
+}
+
+/// A trait for the clients of message buses a [`Sink`] publishes events to.
+///
+/// It is implemented by closures taking events.
+pub trait Publisher {
+    /// The error returned when an event could not be published
+    type Error;
+
+    /// Publishes an event
+    fn publish(&mut self, event: &Event) -> Result<(), Self::Error>;
+}
+
+impl<F, E> Publisher for F
+where
+    F: FnMut(&Event) -> Result<(), E>,
+{
+    type Error = E;
+
+    fn publish(&mut self, event: &Event) -> Result<(), E> {
+        self(event)
+    }
+}
+
+/// A sink emitting events for watched registries every 10 seconds by default,
+/// see the [module documentation](crate::sink).
+pub struct Sink<P> {
+    publisher: P,
+    registries: Vec<(String, Collect)>,
+    kind: EventKind,
+    interval: Duration,
+    namespace: Namespace,
+    /// The values last published, by registry and sample
+    published: HashMap<SampleKey, f64>,
+}
+
+impl<P: Publisher> Sink<P> {
+    /// Creates a sink emitting snapshot events to a publisher, watching no
+    /// registries
+    pub fn new(publisher: P) -> Self {
+        Sink {
+            publisher,
+            registries: Vec::new(),
+            kind: EventKind::Snapshot,
+            interval: Duration::from_secs(10),
+            namespace: Namespace::new(),
+            published: HashMap::new(),
+        }
+    }
+
+    /// Watches a registry owned by a shared value, usually the measured
+    /// service, under a name
+    pub fn watch<T, R>(
+        mut self,
+        name: impl Into<String>,
+        owner: Arc<T>,
+        registry: fn(&T) -> &R,
+    ) -> Self
+    where
+        T: Send + Sync + 'static,
+        R: Serialize + 'static,
+    {
+        let collect = move || flatten::to_samples(registry(&owner));
+        self.registries.push((name.into(), Box::new(collect)));
+        self
+    }
+
+    /// Emits delta events, holding the values that changed since the previous
+    /// event of their registry, rather than snapshots
+    pub fn deltas(mut self) -> Self {
+        self.kind = EventKind::Delta;
+        self
+    }
+
+    /// Emits events at another interval
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Applies a namespace to the samples of every event
+    pub fn with_namespace(mut self, namespace: Namespace) -> Self {
+        self.namespace = namespace;
+        self
+    }
+
+    /// Emits an event for each watched registry, skipping delta events without
+    /// changes.
+    ///
+    /// Changes are computed against the values of the last event published
+    /// for the registry, so that changes not published because of an error
+    /// are part of the next delta.
+    pub fn emit(&mut self) -> Result<(), SinkError<P::Error>> {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+
+        for (i, (name, collect)) in self.registries.iter().enumerate() {
+            let mut samples = collect().map_err(SinkError::Flatten)?;
+            self.namespace.apply(&mut samples);
+            let event_samples = match self.kind {
+                EventKind::Snapshot => samples.clone(),
+                EventKind::Delta => samples
+                    .iter()
+                    .filter_map(|sample| {
+                        let key = (i, sample.name.clone(), sample.labels.clone());
+                        let previous = self.published.get(&key).copied().unwrap_or(0.0);
+                        let delta = sample.value - previous;
+                        (delta != 0.0).then(|| Sample {
+                            value: delta,
+                            ..sample.clone()
+                        })
+                    })
+                    .collect(),
+            };
+            if self.kind == EventKind::Delta && event_samples.is_empty() {
+                continue;
+            }
+
+            let event = Event {
+                registry: name.clone(),
+                timestamp_ms,
+                kind: self.kind,
+                samples: event_samples,
+            };
+            self.publisher.publish(&event).map_err(SinkError::Publish)?;
+
+            if self.kind == EventKind::Delta {
+                for sample in samples {
+                    self.published
+                        .insert((i, sample.name, sample.labels), sample.value);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<P: Publisher + Send + 'static> Sink<P> {
+    /// Spawns a thread emitting events at every interval, and once more when
+    /// the returned handle is stopped or dropped
+    pub fn spawn(mut self) -> SinkHandle {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("metered-sink".to_string())
+            .spawn(move || loop {
+                // Events failing to publish are part of the next deltas, or
+                // superseded by the next snapshots
+                let _ = self.emit();
+
+                match stopped.recv_timeout(self.interval) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => {
+                        let _ = self.emit();
+                        break;
+                    }
+                }
+            })
+            .expect("could not spawn the sink thread");
+
+        SinkHandle {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl<P> fmt::Debug for Sink<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let registries: Vec<&str> = self
+            .registries
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        f.debug_struct("Sink")
+            .field("registries", &registries)
+            .field("kind", &self.kind)
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+/// A handle on a running [`Sink`], stopping it when dropped.
+pub struct SinkHandle {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SinkHandle {
+    /// Stops the sink after a last emission, waiting for its thread to exit
+    pub fn stop(self) {
+        drop(self)
+    }
+}
+
+impl Drop for SinkHandle {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl fmt::Debug for SinkHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SinkHandle").finish()
+    }
+}
+
+/// An error raised while emitting events.
+#[derive(Debug)]
+pub enum SinkError<E> {
+    /// A registry could not be flattened into samples
+    Flatten(flatten::Error),
+    /// The publisher failed to publish an event
+    Publish(E),
+}
+
+impl<E: fmt::Display> fmt::Display for SinkError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkError::Flatten(e) => write!(f, "could not flatten registry: {}", e),
+            SinkError::Publish(e) => write!(f, "could not publish event: {}", e),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for SinkError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SinkError::Flatten(e) => Some(e),
+            SinkError::Publish(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{measure, sync::Mutex, HitCount};
+
+    #[derive(Default, Serialize)]
+    struct Registry {
+        first: HitCount,
+        second: HitCount,
+    }
+
+    #[test]
+    fn emits_deltas() {
+        let registry = Arc::new(Registry::default());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let published = Arc::clone(&events);
+        let mut failing = false;
+        let mut sink = Sink::new(move |event: &Event| {
+            if failing {
+                return Err("bus is down");
+            }
+            published.lock().push(event.clone());
+            failing = event.samples.len() == 1;
+            Ok(())
+        })
+        .watch("service", Arc::clone(&registry), |r| r)
+        .deltas();
+
+        sink.emit().unwrap();
+        assert!(events.lock().is_empty());
+
+        measure!(&registry.first, {});
+        measure!(&registry.first, {});
+        sink.emit().unwrap();
+        measure!(&registry.second, {});
+        assert!(matches!(
+            sink.emit(),
+            Err(SinkError::Publish("bus is down"))
+        ));
+
+        let events = events.lock();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].registry, "service");
+        assert_eq!(events[0].kind, EventKind::Delta);
+        assert_eq!(events[0].samples.len(), 1);
+        assert_eq!(events[0].samples[0].name, "first");
+        assert_eq!(events[0].samples[0].value, 2.0);
+
+        // Changes not published are part of the next delta
+        let second = (0, "second".to_string(), Vec::new());
+        assert_eq!(sink.published.get(&second), Some(&0.0));
+    }
+
+    #[test]
+    fn emits_snapshots_until_stopped() {
+        let registry = Arc::new(Registry::default());
+        measure!(&registry.second, {});
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let published = Arc::clone(&events);
+        let sink = Sink::new(move |event: &Event| {
+            published.lock().push(event.clone());
+            Ok::<_, ()>(())
+        })
+        .watch("service", registry, |r| r)
+        .with_interval(Duration::from_secs(3600))
+        .with_namespace(Namespace::new().with_prefix("app"))
+        .spawn();
+        sink.stop();
+
+        let events = events.lock();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].kind, EventKind::Snapshot);
+        assert_eq!(
+            events[1]
+                .samples
+                .iter()
+                .map(|sample| sample.value)
+                .collect::<Vec<_>>(),
+            [0.0, 1.0]
+        );
+        assert_eq!(events[1].samples[1].name, "app_second");
+    }
+}
@@ -0,0 +1,269 @@
+//! A module providing thread-safe and unsynchronized implementations of a
+//! sliding time window reservoir.
+
+use crate::{
+    clear::{self, Clear, Clearable},
+    metric::{Histogram, SnapshotHistogram},
+    reservoir::SortedSample,
+    sync::Mutex,
+    time_source::{Instant, StdInstant},
+};
+use serde::{Serialize, Serializer};
+use std::{cell::RefCell, collections::VecDeque};
+
+/// A thread-safe implementation of a sliding time window reservoir
+///
+/// It only reports statistics on the values recorded during the last
+/// `WINDOW_SECS` seconds (60 by default), and can be used as a `ResponseTime`
+/// backend:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{measure, sliding_window::SlidingWindowHistogram, ResponseTime};
+///
+/// let response_time: ResponseTime<SlidingWindowHistogram<10>> = ResponseTime::default();
+///
+/// measure!(&response_time, {
+///     std::thread::sleep(std::time::Duration::from_millis(10));
+/// });
+///
+/// assert_eq!(response_time.histogram().len(), 1);
+/// ```
+pub struct SlidingWindowHistogram<const WINDOW_SECS: u64 = 60, T: Instant = StdInstant> {
+    inner: Mutex<SlidingWindow<WINDOW_SECS, T>>,
+}
+
+impl<const WINDOW_SECS: u64, T: Instant> SlidingWindowHistogram<WINDOW_SECS, T> {
+    /// Returns a sorted sample of the values in the current window.
+    pub fn histogram(&self) -> SortedSample {
+        self.inner.lock().snapshot()
+    }
+}
+
+impl<const WINDOW_SECS: u64, T: Instant> SnapshotHistogram
+    for SlidingWindowHistogram<WINDOW_SECS, T>
+{
+    type Snapshot = SortedSample;
+
+    fn snapshot_histogram(&self) -> SortedSample {
+        self.histogram()
+    }
+}
+
+impl<const WINDOW_SECS: u64, T: Instant> Histogram for SlidingWindowHistogram<WINDOW_SECS, T> {
+    fn with_bound(max_value: u64) -> Self {
+        SlidingWindowHistogram {
+            inner: Mutex::new(SlidingWindow::with_bound(max_value)),
+        }
+    }
+
+    fn record(&self, value: u64) {
+        lock_histogram!(self.inner).record(value);
+    }
+}
+
+impl<const WINDOW_SECS: u64, T: Instant> Clear for SlidingWindowHistogram<WINDOW_SECS, T> {
+    fn clear(&self) {
+        self.inner.lock().clear();
+    }
+}
+
+impl<const WINDOW_SECS: u64, T: Instant> Clearable for SlidingWindowHistogram<WINDOW_SECS, T> {
+    fn is_cleared(&self) -> bool {
+        self.inner.lock().snapshot().is_empty()
+    }
+}
+
+impl<const WINDOW_SECS: u64, T: Instant> Serialize for SlidingWindowHistogram<WINDOW_SECS, T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        internal_cost!(serialization, {
+            let mut inner = self.inner.lock();
+            let result = Serialize::serialize(&*inner, serializer);
+            // Under the same lock, so that values are serialized exactly once
+            if clear::is_clearing_serialization() {
+                inner.clear();
+            }
+            result
+        })
+    }
+}
+
+use std::{fmt, fmt::Debug};
+impl<const WINDOW_SECS: u64, T: Instant> Debug for SlidingWindowHistogram<WINDOW_SECS, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock();
+        write!(f, "SlidingWindowHistogram {{ {:?} }}", &*inner)
+    }
+}
+
+/// A sliding time window reservoir
+///
+/// The reservoir keeps every value recorded during the last `WINDOW_SECS`
+/// seconds, like Dropwizard's `SlidingTimeWindowReservoir`. Statistics are
+/// exact over the window, but memory usage grows with the call rate: prefer a
+/// [`Reservoir`](crate::reservoir::Reservoir) for very hot code paths.
+pub struct SlidingWindow<const WINDOW_SECS: u64 = 60, T: Instant = StdInstant> {
+    max_value: u64,
+    start: T,
+    // Pairs of recording time since `start`, in `T` units, and value
+    values: VecDeque<(u64, u64)>,
+}
+
+impl<const WINDOW_SECS: u64, T: Instant> SlidingWindow<WINDOW_SECS, T> {
+    /// Instantiates a new window with a `max_value`.
+    pub fn with_bound(max_value: u64) -> Self {
+        SlidingWindow {
+            max_value,
+            start: T::now(),
+            values: VecDeque::new(),
+        }
+    }
+
+    /// Get the window bound
+    pub fn bound(&self) -> u64 {
+        self.max_value
+    }
+
+    /// Get the window duration, in seconds
+    pub fn window_secs(&self) -> u64 {
+        WINDOW_SECS
+    }
+
+    /// The time before which values are out of the window
+    fn window_start(&self, now: u64) -> u64 {
+        now.saturating_sub(WINDOW_SECS.saturating_mul(T::ONE_SEC))
+    }
+
+    /// Records a value to the window
+    ///
+    /// This is a saturating record: if the value is higher than `max_value`,
+    /// max_value will be recorded instead.
+    pub fn record(&mut self, value: u64) {
+        let now = self.start.elapsed_time();
+        self.values.push_back((now, value.min(self.max_value)));
+
+        let window_start = self.window_start(now);
+        while let Some(&(time, _)) = self.values.front() {
+            if time >= window_start {
+                break;
+            }
+            self.values.pop_front();
+        }
+    }
+
+    /// Clears the values of the window
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    /// Get a sorted sample of the values in the current window.
+    pub fn snapshot(&self) -> SortedSample {
+        let window_start = self.window_start(self.start.elapsed_time());
+        SortedSample::new(
+            self.values
+                .iter()
+                .filter(|(time, _)| *time >= window_start)
+                .map(|(_, value)| *value)
+                .collect(),
+        )
+    }
+}
+
+impl<const WINDOW_SECS: u64, T: Instant> Serialize for SlidingWindow<WINDOW_SECS, T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let snapshot = self.snapshot();
+        snapshot.serialize_with_samples(snapshot.len() as u64, serializer)
+    }
+}
+
+impl<const WINDOW_SECS: u64, T: Instant> Debug for SlidingWindow<WINDOW_SECS, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let snapshot = self.snapshot();
+        snapshot.fmt_with_samples("SlidingWindow", snapshot.len() as u64, f)
+    }
+}
+
+impl<const WINDOW_SECS: u64, T: Instant> Histogram for RefCell<SlidingWindow<WINDOW_SECS, T>> {
+    fn with_bound(max_value: u64) -> Self {
+        RefCell::new(SlidingWindow::with_bound(max_value))
+    }
+
+    fn record(&self, value: u64) {
+        self.borrow_mut().record(value);
+    }
+}
+
+impl<const WINDOW_SECS: u64, T: Instant> Clear for RefCell<SlidingWindow<WINDOW_SECS, T>> {
+    fn clear(&self) {
+        self.borrow_mut().clear();
+    }
+}
+
+impl<const WINDOW_SECS: u64, T: Instant> Clearable for RefCell<SlidingWindow<WINDOW_SECS, T>> {
+    fn is_cleared(&self) -> bool {
+        self.borrow().snapshot().is_empty()
+    }
+}
+
+impl<const WINDOW_SECS: u64, T: Instant> SnapshotHistogram
+    for RefCell<SlidingWindow<WINDOW_SECS, T>>
+{
+    type Snapshot = SortedSample;
+
+    fn snapshot_histogram(&self) -> SortedSample {
+        self.borrow().snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    thread_local! {
+        static CLOCK: Cell<u64> = const { Cell::new(0) };
+    }
+
+    /// A time source in seconds, manually advanced by the test
+    struct TestInstant(u64);
+    impl Instant for TestInstant {
+        const ONE_SEC: u64 = 1;
+
+        fn now() -> Self {
+            TestInstant(CLOCK.with(Cell::get))
+        }
+
+        fn elapsed_time(&self) -> u64 {
+            CLOCK.with(Cell::get) - self.0
+        }
+
+        fn units(duration: std::time::Duration) -> u64 {
+            duration.as_secs()
+        }
+    }
+
+    #[test]
+    fn test_values_expire() {
+        let mut window = SlidingWindow::<10, TestInstant>::with_bound(1_000);
+        window.record(1);
+        window.record(2);
+        CLOCK.with(|c| c.set(5));
+        window.record(3);
+        assert_eq!(window.snapshot().len(), 3);
+
+        CLOCK.with(|c| c.set(12));
+        let snapshot = window.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot.min(), 3);
+
+        window.record(5_000);
+        assert_eq!(window.values.len(), 2);
+        assert_eq!(window.snapshot().max(), 1_000);
+    }
+}
@@ -0,0 +1,557 @@
+//! A module providing golden snapshots of registries, to lock down the names
+//! and shapes of metrics in tests and catch accidental renames.
+//!
+//! A [`Snapshot`] lists the values a registry serializes, one per line and
+//! sorted by name. Values depending on the clock, such as response times,
+//! throughputs or timestamps, are normalized to `*`, so that snapshots are
+//! identical across runs:
+//!
+#![cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#![cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+//! use metered::{metered, snapshot::Snapshot, HitCount, ResponseTime};
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Service {
+//!     metrics: ServiceMetrics,
+//! }
+//!
+//! #[metered(registry = ServiceMetrics)]
+//! impl Service {
+//!     #[measure([HitCount, ResponseTime])]
+//!     pub fn call(&self) {}
+//! }
+//!
+//! let service = Service::default();
+//! service.call();
+//!
+//! let snapshot = Snapshot::of(&service.metrics).unwrap();
+//! assert_eq!(
+//!     snapshot.to_string(),
+//!     "\
+//! call.hit_count = 1
+//! call.response_time.90%ile = *
+//! call.response_time.95%ile = *
+//! call.response_time.99%ile = *
+//! call.response_time.99.9%ile = *
+//! call.response_time.99.99%ile = *
+//! call.response_time.max = *
+//! call.response_time.mean = *
+//! call.response_time.min = *
+//! call.response_time.samples = 1
+//! call.response_time.stdev = *
+//! "
+//! );
+//! ```
+//!
+//! [`assert_snapshot!`](crate::assert_snapshot) compares a registry against a
+//! snapshot file stored with the tests, and records it when run with the
+//! `METERED_UPDATE_SNAPSHOTS` environment variable set:
+//!
+//! ```rust,no_run
+//! # use metered::{assert_snapshot, metered, HitCount};
+//! # #[derive(Default, Debug)]
+//! # pub struct Service {
+//! #     metrics: ServiceMetrics,
+//! # }
+//! # #[metered(registry = ServiceMetrics)]
+//! # impl Service {
+//! #     #[measure(HitCount)]
+//! #     pub fn call(&self) {}
+//! # }
+//! let service = Service::default();
+//! service.call();
+//!
+//! // Relative to the crate's manifest directory
+//! assert_snapshot!(service.metrics, "tests/snapshots/service.snap");
+//! ```
+//!
+//! [`RegistryDiff`] compares two snapshots, to assert which metrics some code
+//! updates.
+
+use crate::{
+    flatten::{self, Value},
+    metadata::{DescribeMetrics, Unit},
+    pretty,
+    serialization::{self, Quantile},
+};
+use serde::Serialize;
+use std::{fmt, fs, path::Path};
+
+/// The environment variable recording snapshot files instead of comparing
+/// them, when set.
+pub const UPDATE_VAR: &str = "METERED_UPDATE_SNAPSHOTS";
+
+/// Asserts a registry matches a snapshot file, given relative to the manifest
+/// directory of the crate under test.
+///
+/// See the [module documentation](crate::snapshot).
+#[macro_export]
+macro_rules! assert_snapshot {
+    ($registry:expr, $path:expr) => {
+        $crate::snapshot::assert_snapshot(
+            &$registry,
+            ::std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join($path),
+        )
+    };
+}
+
+/// A single value of a snapshot.
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    /// The serialization keys leading to the value, joined with dots
+    name: String,
+    /// The name of the metric serializing the value, or the value's name if
+    /// it is not described by the registry
+    metric: String,
+    value: Value,
+    /// Whether the value depends on the clock
+    normalized: bool,
+}
+
+/// The values serialized by a registry, sorted by name.
+///
+/// See the [module documentation](crate::snapshot).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    entries: Vec<Entry>,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of a registry.
+    ///
+    /// Histograms report their default quantiles, whatever the quantiles
+    /// configured for serialization on this thread.
+    pub fn of<T>(registry: &T) -> Result<Self, flatten::Error>
+    where
+        T: Serialize + DescribeMetrics + ?Sized,
+    {
+        Self::take(registry, true)
+    }
+
+    /// Takes a snapshot of a registry without normalizing values depending on
+    /// the clock, for registries measured with a manual clock such as in a
+    /// [`Simulation`](crate::simulation::Simulation).
+    pub fn exact<T>(registry: &T) -> Result<Self, flatten::Error>
+    where
+        T: Serialize + DescribeMetrics + ?Sized,
+    {
+        Self::take(registry, false)
+    }
+
+    fn take<T>(registry: &T, normalize: bool) -> Result<Self, flatten::Error>
+    where
+        T: Serialize + DescribeMetrics + ?Sized,
+    {
+        let values = serialization::with_quantiles(Some(Quantile::DEFAULT.to_vec()), || {
+            flatten::raw_values(registry)
+        })?;
+        let descriptions = T::describe_metrics();
+
+        let mut entries: Vec<Entry> = values
+            .into_iter()
+            .map(|(path, value)| {
+                let description = descriptions
+                    .iter()
+                    .filter(|description| pretty::starts_with(&path, &description.path))
+                    .max_by_key(|description| description.path.len());
+                let unit = description
+                    .map(|description| description.metadata.unit)
+                    .unwrap_or_default();
+                let key = path.last().map(String::as_str);
+                let name = path.join(".");
+                Entry {
+                    metric: description
+                        .map(|description| description.path.join("."))
+                        .unwrap_or_else(|| name.clone()),
+                    name,
+                    value,
+                    normalized: normalize && depends_on_clock(unit, key),
+                }
+            })
+            .collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Snapshot { entries })
+    }
+
+    /// Get a value by name, such as `call.hit_count`, even if it is
+    /// normalized when displayed
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.value.as_f64())
+    }
+
+    /// Get the names of the values, in order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|entry| entry.name.as_str())
+    }
+}
+
+/// Checks if a value depends on the clock: values of metrics measured in units
+/// of time or rates, except the sample counts of durations.
+fn depends_on_clock(unit: Unit, key: Option<&str>) -> bool {
+    match unit {
+        Unit::Seconds | Unit::Milliseconds | Unit::Microseconds | Unit::Nanoseconds => {
+            key != Some("samples")
+        }
+        Unit::RequestsPerSecond => true,
+        _ => false,
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in self.entries.iter() {
+            if entry.normalized {
+                writeln!(f, "{} = *", entry.name)?;
+            } else {
+                writeln!(f, "{} = {}", entry.name, format_value(entry.value))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn format_value(value: Value) -> String {
+    match value {
+        Value::Unsigned(v) => v.to_string(),
+        Value::Signed(v) => v.to_string(),
+        Value::Float(v) => v.to_string(),
+    }
+}
+
+/// The changes of the values of a registry between two snapshots, to assert
+/// which metrics some code updates.
+///
+#[cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#[cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+/// use metered::{
+///     metered,
+///     snapshot::{RegistryDiff, Snapshot},
+///     HitCount, ResponseTime,
+/// };
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics)]
+/// impl Service {
+///     #[measure([HitCount, ResponseTime])]
+///     pub fn call(&self) {}
+///
+///     #[measure(HitCount)]
+///     pub fn ping(&self) {}
+/// }
+///
+/// let service = Service::default();
+/// let before = Snapshot::of(&service.metrics).unwrap();
+/// service.call();
+/// service.call();
+/// let after = Snapshot::of(&service.metrics).unwrap();
+///
+/// let diff = RegistryDiff::between(&before, &after);
+/// assert_eq!(diff.changed_metrics(), ["call.hit_count", "call.response_time"]);
+/// assert_eq!(diff.delta("call.hit_count"), Some(2.0));
+/// assert_eq!(diff.delta("ping.hit_count"), None);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegistryDiff {
+    changes: Vec<Change>,
+}
+
+/// The change of a single value between two snapshots.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// The name of the value, such as `call.response_time.samples`
+    pub name: String,
+    /// The name of the metric serializing the value, such as
+    /// `call.response_time`
+    pub metric: String,
+    /// The value before, or `None` if it was not serialized
+    pub before: Option<f64>,
+    /// The value after, or `None` if it is not serialized anymore
+    pub after: Option<f64>,
+}
+
+impl Change {
+    /// Get by how much the value changed, missing values counting as zero
+    pub fn delta(&self) -> f64 {
+        self.after.unwrap_or(0.0) - self.before.unwrap_or(0.0)
+    }
+}
+
+impl RegistryDiff {
+    /// Lists the values that differ between two snapshots of a registry
+    pub fn between(before: &Snapshot, after: &Snapshot) -> Self {
+        let mut changes = Vec::new();
+        let mut before = before.entries.iter().peekable();
+        let mut after = after.entries.iter().peekable();
+        loop {
+            // Both are sorted by name
+            let (old, new) = match (before.peek(), after.peek()) {
+                (None, None) => break,
+                (Some(old), Some(new)) if old.name == new.name => (before.next(), after.next()),
+                (Some(old), Some(new)) if old.name < new.name => (before.next(), None),
+                (Some(_), None) => (before.next(), None),
+                _ => (None, after.next()),
+            };
+            let old_value = old.map(|entry| entry.value.as_f64());
+            let new_value = new.map(|entry| entry.value.as_f64());
+            if !same_value(old_value, new_value) {
+                let entry = new.or(old).expect("at least one entry");
+                changes.push(Change {
+                    name: entry.name.clone(),
+                    metric: entry.metric.clone(),
+                    before: old_value,
+                    after: new_value,
+                });
+            }
+        }
+        RegistryDiff { changes }
+    }
+
+    /// Get the changed values, sorted by name
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// Returns true if no value changed
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Get the names of the metrics with changed values, sorted
+    pub fn changed_metrics(&self) -> Vec<&str> {
+        let mut metrics: Vec<&str> = self
+            .changes
+            .iter()
+            .map(|change| change.metric.as_str())
+            .collect();
+        metrics.sort_unstable();
+        metrics.dedup();
+        metrics
+    }
+
+    /// Get by how much a value changed, or `None` if it did not
+    pub fn delta(&self, name: &str) -> Option<f64> {
+        self.changes
+            .iter()
+            .find(|change| change.name == name)
+            .map(Change::delta)
+    }
+}
+
+fn same_value(before: Option<f64>, after: Option<f64>) -> bool {
+    match (before, after) {
+        (Some(a), Some(b)) => a == b || (a.is_nan() && b.is_nan()),
+        (a, b) => a.is_none() && b.is_none(),
+    }
+}
+
+impl fmt::Display for RegistryDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = |value: Option<f64>| match value {
+            Some(value) => value.to_string(),
+            None => "-".to_string(),
+        };
+        for change in self.changes.iter() {
+            writeln!(
+                f,
+                "{}: {} -> {} ({:+})",
+                change.name,
+                value(change.before),
+                value(change.after),
+                change.delta()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Asserts a registry matches a snapshot file, as [`assert_snapshot!`] does.
+///
+/// The file is written instead when the [`UPDATE_VAR`] environment variable is
+/// set.
+///
+/// # Panics
+///
+/// Panics if the snapshot differs from the file, listing the differing lines,
+/// or if the file does not exist.
+#[track_caller]
+pub fn assert_snapshot<T, P>(registry: &T, path: P)
+where
+    T: Serialize + DescribeMetrics + ?Sized,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let actual = match Snapshot::of(registry) {
+        Ok(snapshot) => snapshot.to_string(),
+        Err(e) => panic!("registry could not be serialized: {}", e),
+    };
+
+    if std::env::var_os(UPDATE_VAR).is_some() {
+        if let Some(parent) = path.parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                panic!("could not create {}: {}", parent.display(), e);
+            }
+        }
+        if let Err(e) = fs::write(path, &actual) {
+            panic!("could not write snapshot {}: {}", path.display(), e);
+        }
+        return;
+    }
+
+    let expected = match fs::read_to_string(path) {
+        Ok(expected) => expected,
+        Err(e) => panic!(
+            "could not read snapshot {}: {}\nrun with {}=1 to record it",
+            path.display(),
+            e,
+            UPDATE_VAR
+        ),
+    };
+    if let Some(diff) = diff_lines(&expected, &actual) {
+        panic!(
+            "registry does not match snapshot {}:\n{}run with {}=1 to update it",
+            path.display(),
+            diff,
+            UPDATE_VAR
+        );
+    }
+}
+
+/// Lists the lines only in the expected (`-`) or actual (`+`) snapshot, if
+/// any.
+fn diff_lines(expected: &str, actual: &str) -> Option<String> {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut diff = String::new();
+    for line in expected.iter().filter(|line| !actual.contains(line)) {
+        diff.push_str(&format!("- {}\n", line));
+    }
+    for line in actual.iter().filter(|line| !expected.contains(line)) {
+        diff.push_str(&format!("+ {}\n", line));
+    }
+    if diff.is_empty() {
+        None
+    } else {
+        Some(diff)
+    }
+}
+
+#[cfg(all(test, feature = "histograms", not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{
+        measure,
+        metadata::{MetricDescription, MetricMetadata, MetricType},
+        HitCount, ResponseTime, Throughput,
+    };
+
+    #[derive(Default, Serialize)]
+    struct Registry {
+        response_time: ResponseTime,
+        throughput: Throughput,
+        hit_count: HitCount,
+    }
+
+    impl DescribeMetrics for Registry {
+        fn describe_metrics() -> Vec<MetricDescription> {
+            vec![
+                MetricDescription {
+                    path: vec!["response_time"],
+                    metadata: MetricMetadata::new(MetricType::Summary, Unit::Milliseconds),
+                },
+                MetricDescription {
+                    path: vec!["throughput"],
+                    metadata: MetricMetadata::new(MetricType::Summary, Unit::RequestsPerSecond),
+                },
+                MetricDescription {
+                    path: vec!["hit_count"],
+                    metadata: MetricMetadata::new(MetricType::Counter, Unit::None),
+                },
+            ]
+        }
+    }
+
+    #[test]
+    fn sorts_and_normalizes_values() {
+        let registry = Registry::default();
+        measure!(&registry.hit_count, {});
+        measure!(&registry.response_time, {});
+
+        let snapshot = Snapshot::of(&registry).unwrap();
+        let names: Vec<&str> = snapshot.names().collect();
+        let mut sorted = names.clone();
+        sorted.sort_unstable();
+        assert_eq!(names, sorted);
+        assert_eq!(names[0], "hit_count");
+
+        let text = snapshot.to_string();
+        assert!(text.contains("hit_count = 1\n"));
+        assert!(text.contains("response_time.samples = 1\n"));
+        assert!(text.contains("response_time.max = *\n"));
+        assert!(text.contains("throughput.samples = *\n"));
+        assert_eq!(snapshot.get("response_time.samples"), Some(1.0));
+    }
+
+    #[test]
+    fn diffs_snapshots() {
+        let registry = Registry::default();
+        let before = Snapshot::of(&registry).unwrap();
+        measure!(&registry.hit_count, {});
+        let after = Snapshot::of(&registry).unwrap();
+
+        let diff = RegistryDiff::between(&before, &after);
+        assert_eq!(diff.changed_metrics(), ["hit_count"]);
+        assert_eq!(diff.to_string(), "hit_count: 0 -> 1 (+1)\n");
+        assert!(RegistryDiff::between(&after, &after).is_empty());
+
+        let empty = Snapshot { entries: vec![] };
+        let diff = RegistryDiff::between(&after, &empty);
+        assert_eq!(diff.changes().len(), after.names().count());
+        assert_eq!(diff.delta("hit_count"), Some(-1.0));
+        assert_eq!(diff.changes()[0].after, None);
+    }
+
+    #[test]
+    fn diffs_lines() {
+        assert_eq!(diff_lines("a = 1\nb = 2\n", "a = 1\nb = 2\n"), None);
+        assert_eq!(
+            diff_lines("a = 1\nb = 2\n", "a = 1\nc = 2\n").unwrap(),
+            "- b = 2\n+ c = 2\n"
+        );
+    }
+
+    #[test]
+    fn asserts_snapshot_files() {
+        let registry = Registry::default();
+        measure!(&registry.hit_count, {});
+
+        let path = std::env::temp_dir().join(format!("metered-{}.snap", std::process::id()));
+        fs::write(&path, Snapshot::of(&registry).unwrap().to_string()).unwrap();
+        assert_snapshot(&registry, &path);
+
+        measure!(&registry.hit_count, {});
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            assert_snapshot(&registry, &path)
+        }));
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
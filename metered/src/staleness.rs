@@ -0,0 +1,53 @@
+//! A module providing the `LastUpdated` trait, used to detect stale metrics.
+
+use std::time::{Duration, SystemTime};
+
+/// The `LastUpdated` trait reports when metrics were last updated, so that
+/// exporters can mark or drop stale series and operators can spot dead code
+/// paths.
+///
+/// Registries generated with `#[metered(registry = ..., last_updated = true)]`
+/// implement it: each method sub-registry tracks the wall-clock time of the
+/// last call to its method, and the registry reports the most recent of them.
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{metered, staleness::LastUpdated, HitCount};
+/// use std::time::Duration;
+///
+/// #[derive(Default, Debug)]
+/// pub struct Service {
+///     metrics: ServiceMetrics,
+/// }
+///
+/// #[metered(registry = ServiceMetrics, last_updated = true)]
+/// impl Service {
+///     #[measure(HitCount)]
+///     pub fn hot(&self) {}
+///
+///     #[measure(HitCount)]
+///     pub fn dead(&self) {}
+/// }
+///
+/// let service = Service::default();
+/// assert!(service.metrics.is_stale(Duration::from_secs(60)));
+///
+/// service.hot();
+/// assert!(service.metrics.last_updated().is_some());
+/// assert!(!service.metrics.hot.is_stale(Duration::from_secs(60)));
+/// assert!(service.metrics.dead.is_stale(Duration::from_secs(60)));
+/// ```
+pub trait LastUpdated {
+    /// Returns the wall-clock time of the last update, or `None` if there was
+    /// no update.
+    fn last_updated(&self) -> Option<SystemTime>;
+
+    /// Returns true if there was no update for longer than `max_age`, or no
+    /// update at all.
+    fn is_stale(&self, max_age: Duration) -> bool {
+        match self.last_updated() {
+            Some(time) => time.elapsed().is_ok_and(|age| age > max_age),
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Locks used by thread-safe metrics, backed by `parking_lot` by default, or by
+//! `std::sync` without the `parking_lot` feature or with the `std-sync`
+//! feature.
+//!
+//! Both backends expose `parking_lot`'s API: locking never fails, as a panic
+//! while holding the lock of a metric leaves it in a usable state.
+//!
+//! Built with `--cfg loom`, locks and the atomics of [`atomic`] come from
+//! `loom`, which model-checks their interleavings in the `loom_tests` suite.
+
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "parking_lot", not(feature = "std-sync"), not(loom)))] {
+        pub(crate) use parking_lot::{Mutex, MutexGuard, RwLock};
+
+        /// A condition variable waiting on a [`Mutex`]
+        #[derive(Debug, Default)]
+        pub(crate) struct Condvar(parking_lot::Condvar);
+
+        impl Condvar {
+            pub(crate) const fn new() -> Self {
+                Condvar(parking_lot::Condvar::new())
+            }
+
+            /// Blocks until notified, releasing the lock meanwhile
+            pub(crate) fn wait<'a, T>(&self, mut guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+                self.0.wait(&mut guard);
+                guard
+            }
+
+            pub(crate) fn notify_one(&self) {
+                self.0.notify_one();
+            }
+        }
+    } else {
+        #[cfg(loom)]
+        use loom::sync as imp;
+        #[cfg(not(loom))]
+        use std::sync as imp;
+        use imp::{RwLockReadGuard, RwLockWriteGuard};
+        use std::sync::PoisonError;
+
+        pub(crate) use imp::MutexGuard;
+
+        /// A mutual exclusion lock ignoring poisoning
+        #[derive(Debug, Default)]
+        pub(crate) struct Mutex<T: ?Sized>(imp::Mutex<T>);
+
+        impl<T> Mutex<T> {
+            pub(crate) fn new(value: T) -> Self {
+                Mutex(imp::Mutex::new(value))
+            }
+        }
+
+        impl<T: ?Sized> Mutex<T> {
+            pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+                self.0.lock().unwrap_or_else(PoisonError::into_inner)
+            }
+
+            #[cfg(all(feature = "histograms", feature = "internals"))]
+            pub(crate) fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+                match self.0.try_lock() {
+                    Ok(guard) => Some(guard),
+                    Err(std::sync::TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+                    Err(std::sync::TryLockError::WouldBlock) => None,
+                }
+            }
+        }
+
+        /// A reader-writer lock ignoring poisoning
+        #[derive(Debug, Default)]
+        pub(crate) struct RwLock<T>(imp::RwLock<T>);
+
+        impl<T> RwLock<T> {
+            pub(crate) fn new(value: T) -> Self {
+                RwLock(imp::RwLock::new(value))
+            }
+
+            pub(crate) fn read(&self) -> RwLockReadGuard<'_, T> {
+                self.0.read().unwrap_or_else(PoisonError::into_inner)
+            }
+
+            pub(crate) fn write(&self) -> RwLockWriteGuard<'_, T> {
+                self.0.write().unwrap_or_else(PoisonError::into_inner)
+            }
+        }
+
+        /// A condition variable waiting on a [`Mutex`]
+        #[derive(Debug, Default)]
+        pub(crate) struct Condvar(imp::Condvar);
+
+        impl Condvar {
+            pub(crate) fn new() -> Self {
+                Condvar(imp::Condvar::new())
+            }
+
+            /// Blocks until notified, releasing the lock meanwhile
+            pub(crate) fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+                self.0.wait(guard).unwrap_or_else(PoisonError::into_inner)
+            }
+
+            pub(crate) fn notify_one(&self) {
+                self.0.notify_one();
+            }
+        }
+    }
+}
+
+/// Atomics modeled by loom with `--cfg loom`
+pub(crate) mod atomic {
+    #[cfg(loom)]
+    pub(crate) use loom::sync::atomic::AtomicU64;
+    #[cfg(not(loom))]
+    pub(crate) use std::sync::atomic::AtomicU64;
+
+    #[cfg(loom)]
+    pub use self::model::Atomic;
+
+    /// A model of `atomic::Atomic`, which loom cannot see through
+    #[cfg(loom)]
+    mod model {
+        use loom::sync::atomic::{AtomicU64, Ordering};
+        use std::{
+            marker::PhantomData,
+            mem::{self, MaybeUninit},
+            ptr,
+        };
+
+        /// An atomic value of up to 64 bits, modeled by an [`AtomicU64`]
+        #[derive(Debug)]
+        pub struct Atomic<T> {
+            bits: AtomicU64,
+            value: PhantomData<T>,
+        }
+
+        /// Copies a value to the low-order bytes of a `u64`
+        fn into_bits<T: Copy>(v: T) -> u64 {
+            let size = mem::size_of::<T>();
+            assert!(size <= 8, "loom models atomics of up to 64 bits");
+            let offset = if cfg!(target_endian = "little") {
+                0
+            } else {
+                8 - size
+            };
+            let mut bytes = [0u8; 8];
+            // SAFETY: `T` fits in the bytes
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    &v as *const T as *const u8,
+                    bytes[offset..].as_mut_ptr(),
+                    size,
+                );
+            }
+            u64::from_ne_bytes(bytes)
+        }
+
+        /// Copies a value back from the low-order bytes of a `u64`
+        fn from_bits<T: Copy>(bits: u64) -> T {
+            let size = mem::size_of::<T>();
+            let offset = if cfg!(target_endian = "little") {
+                0
+            } else {
+                8 - size
+            };
+            let bytes = bits.to_ne_bytes();
+            let mut v = MaybeUninit::<T>::uninit();
+            // SAFETY: the bytes were copied from a `T` by `into_bits`, and
+            // arithmetic only changes them to other integers
+            unsafe {
+                ptr::copy_nonoverlapping(bytes[offset..].as_ptr(), v.as_mut_ptr() as *mut u8, size);
+                v.assume_init()
+            }
+        }
+
+        impl<T: Copy> Atomic<T> {
+            /// Creates a new atomic value
+            pub fn new(v: T) -> Self {
+                Atomic {
+                    bits: AtomicU64::new(into_bits(v)),
+                    value: PhantomData,
+                }
+            }
+
+            /// Loads the value
+            pub fn load(&self, order: Ordering) -> T {
+                from_bits(self.bits.load(order))
+            }
+
+            /// Stores a value
+            pub fn store(&self, v: T, order: Ordering) {
+                self.bits.store(into_bits(v), order);
+            }
+
+            /// Stores a value, returning the previous one
+            pub fn swap(&self, v: T, order: Ordering) -> T {
+                from_bits(self.bits.swap(into_bits(v), order))
+            }
+
+            /// Updates the value with a function, in a compare-and-swap loop
+            pub fn fetch_update<F>(
+                &self,
+                set_order: Ordering,
+                fetch_order: Ordering,
+                mut f: F,
+            ) -> Result<T, T>
+            where
+                F: FnMut(T) -> Option<T>,
+            {
+                self.bits
+                    .fetch_update(set_order, fetch_order, |bits| {
+                        f(from_bits(bits)).map(into_bits)
+                    })
+                    .map(from_bits)
+                    .map_err(from_bits)
+            }
+
+            /// Applies an infallible update, returning the previous value
+            fn update(&self, order: Ordering, f: impl Fn(T) -> T) -> T {
+                let fetch_order = match order {
+                    Ordering::Release => Ordering::Relaxed,
+                    Ordering::AcqRel => Ordering::Acquire,
+                    order => order,
+                };
+                let previous = self.fetch_update(order, fetch_order, |v| Some(f(v)));
+                previous.unwrap_or_else(|v| v)
+            }
+        }
+
+        // Additions use loom's own, which models their synchronization more
+        // closely than compare-and-swap loops: adding the bits of
+        // two's-complement integers wraps their low-order bytes alike.
+        macro_rules! impl_arithmetic_for {
+            ($($int:ty: $uint:ty),*) => {$(
+                impl Atomic<$int> {
+                    /// Adds to the value, wrapping on overflow
+                    pub fn fetch_add(&self, v: $int, order: Ordering) -> $int {
+                        from_bits(self.bits.fetch_add(v as $uint as u64, order))
+                    }
+
+                    /// Subtracts from the value, wrapping on overflow
+                    pub fn fetch_sub(&self, v: $int, order: Ordering) -> $int {
+                        from_bits(self.bits.fetch_sub(v as $uint as u64, order))
+                    }
+
+                    /// Stores the maximum of the value and `v`
+                    pub fn fetch_max(&self, v: $int, order: Ordering) -> $int {
+                        self.update(order, |x| x.max(v))
+                    }
+                }
+            )*};
+        }
+
+        // `u128` compiles, but panics when modeled
+        impl_arithmetic_for!(
+            u8: u8, u16: u16, u32: u32, u64: u64, u128: u128,
+            i8: u8, i16: u16, i32: u32, i64: u64
+        );
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locks_after_a_panic() {
+        let mutex = Mutex::new(0);
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = mutex.lock();
+            panic!("poisoning");
+        }));
+        *mutex.lock() += 1;
+        assert_eq!(*mutex.lock(), 1);
+
+        let lock = RwLock::new(0);
+        *lock.write() += 1;
+        assert_eq!(*lock.read(), 1);
+    }
+}
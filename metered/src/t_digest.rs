@@ -0,0 +1,536 @@
+//! A module providing thread-safe and unsynchronized implementations of
+//! t-digest, a compact and mergeable quantile sketch.
+
+use crate::{
+    clear::{self, Clear, Clearable},
+    merge::Merge,
+    metric::{Histogram, HistogramSnapshot, SnapshotHistogram},
+    serialization::{self, MetricAlias},
+    sync::Mutex,
+};
+use serde::{Serialize, Serializer};
+use std::{borrow::Cow, cell::RefCell};
+
+/// The compression of t-digests built with [`Histogram::with_bound`].
+pub const DEFAULT_COMPRESSION: usize = 100;
+
+/// A thread-safe implementation of t-digest
+///
+/// It can be used as a `ResponseTime` backend:
+///
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+#[cfg_attr(not(feature = "disabled"), doc = "```rust")]
+/// use metered::{measure, t_digest::TDigestHistogram, ResponseTime};
+///
+/// let response_time: ResponseTime<TDigestHistogram> = ResponseTime::default();
+///
+/// measure!(&response_time, {
+///     std::thread::sleep(std::time::Duration::from_millis(10));
+/// });
+///
+/// assert!(response_time.histogram().quantile(0.5) >= 10);
+/// ```
+pub struct TDigestHistogram {
+    inner: Mutex<TDigest>,
+}
+
+impl TDigestHistogram {
+    /// Returns a cloned snapshot of the inner digest.
+    pub fn histogram(&self) -> TDigest {
+        let mut inner = self.inner.lock();
+        inner.compress();
+        inner.clone()
+    }
+
+    /// Merges another digest into this one.
+    pub fn merge(&self, other: &TDigest) {
+        self.inner.lock().merge(other);
+    }
+}
+
+impl SnapshotHistogram for TDigestHistogram {
+    type Snapshot = TDigest;
+
+    fn snapshot_histogram(&self) -> TDigest {
+        self.histogram()
+    }
+}
+
+impl Histogram for TDigestHistogram {
+    fn with_bound(max_value: u64) -> Self {
+        TDigestHistogram {
+            inner: Mutex::new(TDigest::with_bound(max_value)),
+        }
+    }
+
+    fn record(&self, value: u64) {
+        lock_histogram!(self.inner).record(value);
+    }
+}
+
+impl Clear for TDigestHistogram {
+    fn clear(&self) {
+        self.inner.lock().clear();
+    }
+}
+
+impl Clearable for TDigestHistogram {
+    fn is_cleared(&self) -> bool {
+        self.inner.lock().is_empty()
+    }
+}
+
+impl Merge for TDigestHistogram {
+    fn merge_from(&self, other: &Self) {
+        // Clone first, in case other is self
+        self.merge(&other.histogram());
+    }
+}
+
+impl From<&TDigestHistogram> for HistogramSnapshot {
+    fn from(histogram: &TDigestHistogram) -> Self {
+        HistogramSnapshot::from(&histogram.histogram())
+    }
+}
+
+impl Serialize for TDigestHistogram {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        internal_cost!(serialization, {
+            let mut inner = self.inner.lock();
+            inner.compress();
+            let result = Serialize::serialize(&*inner, serializer);
+            // Under the same lock, so that values are serialized exactly once
+            if clear::is_clearing_serialization() {
+                inner.clear();
+            }
+            result
+        })
+    }
+}
+
+use std::{fmt, fmt::Debug};
+impl Debug for TDigestHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock();
+        write!(f, "TDigestHistogram {{ {:?} }}", &*inner)
+    }
+}
+
+/// A cluster of values in a [`TDigest`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Centroid {
+    /// The mean of the values in the cluster
+    pub mean: f64,
+    /// The number of values in the cluster
+    pub weight: f64,
+}
+
+/// A t-digest quantile sketch
+///
+/// A t-digest summarizes recorded values as a list of centroids, which are
+/// kept small near the extreme quantiles and larger near the median. This
+/// gives accurate tail quantiles with a representation much smaller than a
+/// histogram, and digests can be merged across processes.
+///
+/// Values are first appended to a buffer, which is merged into the centroids
+/// when full. Both are allocated up front for a given compression, so
+/// recording a value does not allocate.
+#[derive(Clone)]
+pub struct TDigest {
+    compression: usize,
+    max_value: u64,
+    centroids: Vec<Centroid>,
+    buffer: Vec<Centroid>,
+    scratch: Vec<Centroid>,
+    count: u64,
+    sum: f64,
+    min: u64,
+    max: u64,
+}
+
+impl TDigest {
+    /// Instantiates a new digest with a `max_value` and the default
+    /// compression.
+    pub fn with_bound(max_value: u64) -> Self {
+        Self::with_compression(max_value, DEFAULT_COMPRESSION)
+    }
+
+    /// Instantiates a new digest with a `max_value` and a compression: higher
+    /// compressions keep more centroids, for more accuracy.
+    pub fn with_compression(max_value: u64, compression: usize) -> Self {
+        let compression = compression.max(10);
+        TDigest {
+            compression,
+            max_value,
+            centroids: Vec::with_capacity(2 * compression),
+            buffer: Vec::with_capacity(5 * compression),
+            scratch: Vec::with_capacity(7 * compression),
+            count: 0,
+            sum: 0.0,
+            min: 0,
+            max: 0,
+        }
+    }
+
+    /// Get the digest bound
+    pub fn bound(&self) -> u64 {
+        self.max_value
+    }
+
+    /// Get the digest compression
+    pub fn compression(&self) -> usize {
+        self.compression
+    }
+
+    /// Records a value to the digest
+    ///
+    /// This is a saturating record: if the value is higher than `max_value`,
+    /// max_value will be recorded instead.
+    pub fn record(&mut self, value: u64) {
+        let value = value.min(self.max_value);
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        if value > self.max {
+            self.max = value;
+        }
+        self.count += 1;
+        self.sum += value as f64;
+
+        self.buffer.push(Centroid {
+            mean: value as f64,
+            weight: 1.0,
+        });
+        if self.buffer.len() == self.buffer.capacity() {
+            self.compress();
+        }
+    }
+
+    /// Merges another digest into this one.
+    pub fn merge(&mut self, other: &TDigest) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 || other.min < self.min {
+            self.min = other.min;
+        }
+        self.max = self.max.max(other.max);
+        self.max_value = self.max_value.max(other.max_value);
+        self.count += other.count;
+        self.sum += other.sum;
+
+        for centroid in other.centroids.iter().chain(other.buffer.iter()) {
+            self.buffer.push(*centroid);
+            if self.buffer.len() == self.buffer.capacity() {
+                self.compress();
+            }
+        }
+        self.compress();
+    }
+
+    /// Merges buffered values into the centroids.
+    pub fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        self.scratch.clear();
+        self.scratch.extend_from_slice(&self.centroids);
+        self.scratch.append(&mut self.buffer);
+        self.centroids.clear();
+        compress_into(
+            &mut self.scratch,
+            &mut self.centroids,
+            self.count as f64,
+            self.compression,
+        );
+    }
+
+    fn merged_centroids(&self) -> Cow<'_, [Centroid]> {
+        if self.buffer.is_empty() {
+            Cow::Borrowed(&self.centroids)
+        } else {
+            let mut all = self.centroids.clone();
+            all.extend_from_slice(&self.buffer);
+            let mut merged = Vec::with_capacity(all.len());
+            compress_into(&mut all, &mut merged, self.count as f64, self.compression);
+            Cow::Owned(merged)
+        }
+    }
+
+    /// Returns the centroids of the digest, sorted by mean.
+    pub fn centroids(&self) -> Vec<Centroid> {
+        self.merged_centroids().into_owned()
+    }
+
+    /// Clears the values of the digest
+    pub fn clear(&mut self) {
+        self.centroids.clear();
+        self.buffer.clear();
+        self.count = 0;
+        self.sum = 0.0;
+        self.min = 0;
+        self.max = 0;
+    }
+
+    /// Get the number of recorded values in the digest.
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    /// Check if the digest's recorded values are empty
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Get the lowest recorded value in the digest.
+    /// If the digest has no recorded values, the value returned will be 0.
+    pub fn min(&self) -> u64 {
+        self.min
+    }
+
+    /// Get the highest recorded value in the digest.
+    /// If the digest has no recorded values, the value returned will be 0.
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Get the exact mean value of all recorded values in the digest.
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum / self.count as f64
+        }
+    }
+
+    /// Get the estimated value at a quantile in `[0, 1]`.
+    /// If the digest has no recorded values, the value returned will be 0.
+    pub fn quantile(&self, quantile: f64) -> u64 {
+        let centroids = self.merged_centroids();
+        let (first, last) = match (centroids.first(), centroids.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0,
+        };
+        let (min, max) = (self.min as f64, self.max as f64);
+        let total = self.count as f64;
+        let target = quantile.clamp(0.0, 1.0) * total;
+
+        let value = if centroids.len() == 1 {
+            first.mean
+        } else if target < first.weight / 2.0 {
+            min + (first.mean - min) * target / (first.weight / 2.0)
+        } else if target > total - last.weight / 2.0 {
+            let left = total - last.weight / 2.0;
+            last.mean + (max - last.mean) * (target - left) / (last.weight / 2.0)
+        } else {
+            // Interpolate between the centers of the two surrounding centroids
+            let mut seen = 0.0;
+            let mut value = last.mean;
+            for pair in centroids.windows(2) {
+                let left = seen + pair[0].weight / 2.0;
+                let right = seen + pair[0].weight + pair[1].weight / 2.0;
+                if target <= right {
+                    let t = (target - left) / (right - left);
+                    value = pair[0].mean + t * (pair[1].mean - pair[0].mean);
+                    break;
+                }
+                seen += pair[0].weight;
+            }
+            value
+        };
+
+        (value.round() as u64).clamp(self.min, self.max)
+    }
+}
+
+/// Sorts `centroids` and merges neighbours into `out` as long as the merged
+/// centroid spans less than one unit of the `k1` scale function
+/// `k(q) = δ / 2π · asin(2q - 1)`, which keeps centroids small near the tails.
+fn compress_into(
+    centroids: &mut [Centroid],
+    out: &mut Vec<Centroid>,
+    total: f64,
+    compression: usize,
+) {
+    centroids.sort_by(|a, b| a.mean.partial_cmp(&b.mean).expect("means are never NaN"));
+
+    let scale = |q: f64| {
+        compression as f64 / (2.0 * std::f64::consts::PI) * (2.0 * q.min(1.0) - 1.0).asin()
+    };
+
+    let mut iter = centroids.iter();
+    let mut current = match iter.next() {
+        Some(c) => *c,
+        None => return,
+    };
+    let mut seen = 0.0;
+    let mut k_left = scale(0.0);
+
+    for next in iter {
+        let weight = current.weight + next.weight;
+        if scale((seen + weight) / total) - k_left <= 1.0 {
+            current.mean += (next.mean - current.mean) * next.weight / weight;
+            current.weight = weight;
+        } else {
+            seen += current.weight;
+            k_left = scale(seen / total);
+            out.push(current);
+            current = *next;
+        }
+    }
+    out.push(current);
+}
+
+impl From<&TDigest> for HistogramSnapshot {
+    fn from(histogram: &TDigest) -> Self {
+        HistogramSnapshot::new(
+            histogram.len(),
+            histogram.min(),
+            histogram.max(),
+            histogram.mean(),
+            |q| histogram.quantile(q),
+        )
+    }
+}
+
+impl Serialize for TDigest {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        /// A 'qualified' metric name, see `HdrHistogram`'s serialization.
+        macro_rules! qual {
+            ($e:expr) => {
+                &MetricAlias("<|", $e)
+            };
+        }
+
+        use serde::ser::SerializeMap;
+
+        let mut tup = serializer.serialize_map(Some(4 + serialization::quantile_count()))?;
+        tup.serialize_entry("samples", qual!(self.len()))?;
+        tup.serialize_entry("min", qual!(self.min()))?;
+        tup.serialize_entry("max", qual!(self.max()))?;
+        tup.serialize_entry("mean", qual!(self.mean()))?;
+        serialization::serialize_quantiles(&mut tup, |q| self.quantile(q))?;
+        tup.end()
+    }
+}
+
+impl Debug for TDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TDigest {{
+            samples: {}, min: {}, max: {}, mean: {},
+            90%ile = {}, 95%ile = {}, 99%ile = {}, 99.9%ile = {}, 99.99%ile = {} }}",
+            self.len(),
+            self.min(),
+            self.max(),
+            self.mean(),
+            self.quantile(0.9),
+            self.quantile(0.95),
+            self.quantile(0.99),
+            self.quantile(0.999),
+            self.quantile(0.9999)
+        )
+    }
+}
+
+impl Histogram for RefCell<TDigest> {
+    fn with_bound(max_value: u64) -> Self {
+        RefCell::new(TDigest::with_bound(max_value))
+    }
+
+    fn record(&self, value: u64) {
+        self.borrow_mut().record(value);
+    }
+}
+
+impl Clear for RefCell<TDigest> {
+    fn clear(&self) {
+        self.borrow_mut().clear();
+    }
+}
+
+impl Clearable for RefCell<TDigest> {
+    fn is_cleared(&self) -> bool {
+        self.borrow().is_empty()
+    }
+}
+
+impl SnapshotHistogram for RefCell<TDigest> {
+    type Snapshot = TDigest;
+
+    fn snapshot_histogram(&self) -> TDigest {
+        self.borrow().clone()
+    }
+}
+
+impl From<&RefCell<TDigest>> for HistogramSnapshot {
+    fn from(histogram: &RefCell<TDigest>) -> Self {
+        HistogramSnapshot::from(&*histogram.borrow())
+    }
+}
+
+impl Merge for RefCell<TDigest> {
+    fn merge_from(&self, other: &Self) {
+        let other = other.borrow().clone();
+        self.borrow_mut().merge(&other);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::seq::SliceRandom;
+
+    fn assert_close(actual: u64, expected: u64, tolerance: u64) {
+        assert!(
+            (actual as i64 - expected as i64).unsigned_abs() <= tolerance,
+            "expected {} ± {}, got {}",
+            expected,
+            tolerance,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_quantiles() {
+        let mut values: Vec<u64> = (1..=100_000).collect();
+        values.shuffle(&mut rand::thread_rng());
+
+        let mut digest = TDigest::with_bound(1_000_000);
+        for &v in &values {
+            digest.record(v);
+        }
+
+        assert_eq!(digest.len(), 100_000);
+        assert_close(digest.quantile(0.5), 50_000, 1_000);
+        assert_close(digest.quantile(0.99), 99_000, 200);
+        assert_close(digest.quantile(0.999), 99_900, 50);
+        assert_eq!(digest.quantile(0.0), 1);
+        assert_eq!(digest.quantile(1.0), 100_000);
+        assert!(digest.centroids().len() <= DEFAULT_COMPRESSION);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a = TDigest::with_bound(1_000_000);
+        let mut b = TDigest::with_bound(1_000_000);
+        for v in 1..=50_000 {
+            a.record(v);
+            b.record(v + 50_000);
+        }
+
+        a.merge(&b);
+        assert_eq!(a.len(), 100_000);
+        assert_eq!(a.min(), 1);
+        assert_eq!(a.max(), 100_000);
+        assert_close(a.quantile(0.5), 50_000, 1_000);
+        assert_close(a.quantile(0.99), 99_000, 200);
+    }
+}
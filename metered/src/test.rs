@@ -0,0 +1,285 @@
+//! A module providing assertions on metrics, for tests of measured code.
+//!
+//! Assertions read metrics through their serialization, and work the same
+//! regardless of their backends:
+//!
+#![cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#![cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+//! use metered::{assert_hit_count, assert_histogram, metered, HitCount, ResponseTime};
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Service {
+//!     metrics: ServiceMetrics,
+//! }
+//!
+//! #[metered(registry = ServiceMetrics)]
+//! impl Service {
+//!     #[measure([HitCount, ResponseTime])]
+//!     pub fn call(&self) {}
+//! }
+//!
+//! let service = Service::default();
+//! for _ in 0..3 {
+//!     service.call();
+//! }
+//!
+//! assert_hit_count!(service.metrics.call.hit_count, 3);
+//! assert_histogram!(service.metrics.call.response_time)
+//!     .samples(3)
+//!     .max_below(1_000);
+//! ```
+//!
+//! On failure, they panic with the asserted expression and the actual values:
+//!
+//! ```text
+//! `service.metrics.call.hit_count` is 3, expected 2
+//! ```
+
+use crate::{
+    flatten::{self, Value},
+    serialization::{self, Quantile},
+};
+use serde::Serialize;
+
+/// The quantiles available to histogram assertions
+const QUANTILES: &[Quantile] = &[
+    Quantile::P50,
+    Quantile::P75,
+    Quantile::P90,
+    Quantile::P95,
+    Quantile::P99,
+    Quantile::P999,
+    Quantile::P9999,
+];
+
+/// Asserts the value of a counter or gauge, such as `HitCount`, `ErrorCount`
+/// or `InFlight`.
+///
+/// See the [module documentation](crate::test).
+#[macro_export]
+macro_rules! assert_hit_count {
+    ($metric:expr, $expected:expr) => {
+        $crate::test::assert_value(&$metric, $expected, stringify!($metric))
+    };
+}
+
+/// Starts assertions on a histogram-backed metric, such as `ResponseTime` or
+/// `Throughput`, returning a [`HistogramAssert`](crate::test::HistogramAssert).
+///
+/// See the [module documentation](crate::test).
+#[macro_export]
+macro_rules! assert_histogram {
+    ($metric:expr) => {
+        $crate::test::assert_histogram(&$metric, stringify!($metric))
+    };
+}
+
+/// Get the value of a metric serializing a single number, such as a counter or
+/// gauge.
+///
+/// # Panics
+///
+/// Panics if the metric does not serialize exactly one number.
+#[track_caller]
+pub fn value_of<M: Serialize + ?Sized>(metric: &M) -> u64 {
+    checked_value_of(metric, "metric")
+}
+
+/// Asserts the value of a metric serializing a single number, as
+/// [`assert_hit_count!`] does.
+#[track_caller]
+pub fn assert_value<M: Serialize + ?Sized>(metric: &M, expected: u64, name: &str) {
+    let value = checked_value_of(metric, name);
+    assert!(
+        value == expected,
+        "`{}` is {}, expected {}",
+        name,
+        value,
+        expected
+    );
+}
+
+#[track_caller]
+fn checked_value_of<M: Serialize + ?Sized>(metric: &M, name: &str) -> u64 {
+    let values = match flatten::raw_values(metric) {
+        Ok(values) => values,
+        Err(e) => panic!("`{}` could not be serialized: {}", name, e),
+    };
+    match values[..] {
+        [(_, Value::Unsigned(v))] => v,
+        [(_, Value::Signed(v))] if v >= 0 => v as u64,
+        [(_, value)] => panic!("`{}` is {:?}, expected an unsigned integer", name, value),
+        _ => panic!(
+            "`{}` serializes {} values, expected a single counter or gauge value",
+            name,
+            values.len()
+        ),
+    }
+}
+
+/// Starts assertions on a histogram-backed metric, as [`assert_histogram!`]
+/// does.
+///
+/// # Panics
+///
+/// Panics if the metric does not serialize a histogram summary, with a
+/// `samples` count.
+#[track_caller]
+pub fn assert_histogram<'a, M: Serialize + ?Sized>(
+    metric: &M,
+    name: &'a str,
+) -> HistogramAssert<'a> {
+    let values = match serialization::with_quantiles(Some(QUANTILES.to_vec()), || {
+        flatten::raw_values(metric)
+    }) {
+        Ok(values) => values,
+        Err(e) => panic!("`{}` could not be serialized: {}", name, e),
+    };
+    let values: Vec<(String, f64)> = values
+        .into_iter()
+        .filter(|(path, _)| path.len() == 1)
+        .map(|(mut path, value)| (path.remove(0), value.as_f64()))
+        .collect();
+    assert!(
+        values.iter().any(|(key, _)| key == "samples"),
+        "`{}` is not a histogram",
+        name
+    );
+    HistogramAssert { name, values }
+}
+
+/// Assertions on a histogram summary, chained and panicking on failure.
+#[derive(Debug)]
+pub struct HistogramAssert<'a> {
+    name: &'a str,
+    values: Vec<(String, f64)>,
+}
+
+impl HistogramAssert<'_> {
+    /// Get a statistic of the summary, such as `samples`, `max` or `99%ile`
+    pub fn get(&self, key: &str) -> Option<f64> {
+        self.values
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| *value)
+    }
+
+    #[track_caller]
+    fn stat(&self, key: &str) -> f64 {
+        match self.get(key) {
+            Some(value) => value,
+            None => panic!("`{}` does not report its {}", self.name, key),
+        }
+    }
+
+    /// Asserts the number of recorded samples
+    #[track_caller]
+    pub fn samples(self, expected: u64) -> Self {
+        let samples = self.stat("samples");
+        assert!(
+            samples == expected as f64,
+            "`{}` has {} samples, expected {}",
+            self.name,
+            samples,
+            expected
+        );
+        self
+    }
+
+    /// Asserts the largest recorded sample is strictly below a bound
+    #[track_caller]
+    pub fn max_below(self, bound: u64) -> Self {
+        let max = self.stat("max");
+        assert!(
+            max < bound as f64,
+            "`{}` has a max of {}, expected below {}",
+            self.name,
+            max,
+            bound
+        );
+        self
+    }
+
+    /// Asserts a quantile of the samples is strictly below a bound
+    #[track_caller]
+    pub fn quantile_below(self, quantile: Quantile, bound: u64) -> Self {
+        let value = self.stat(quantile.key());
+        assert!(
+            value < bound as f64,
+            "`{}` has a {} of {}, expected below {}",
+            self.name,
+            quantile.key(),
+            value,
+            bound
+        );
+        self
+    }
+}
+
+#[cfg(all(test, feature = "histograms", not(feature = "disabled")))]
+mod tests {
+    use super::*;
+    use crate::{
+        atomic::AtomicInt, common::ErrorCount, hdr_histogram::AtomicHdrHistogram, measure,
+        metric::Histogram, HitCount,
+    };
+
+    #[test]
+    fn asserts_counters() {
+        let hit_count: HitCount = HitCount::default();
+        let error_count: ErrorCount = ErrorCount::default();
+        for _ in 0..2 {
+            measure!(&hit_count, {});
+            let _ = measure!(&error_count, Err::<(), ()>(()));
+        }
+        assert_hit_count!(hit_count, 2);
+        assert_hit_count!(error_count, 2);
+        assert_eq!(value_of(&hit_count), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "`hit_count` is 0, expected 1")]
+    fn reports_counter_mismatches() {
+        let hit_count: HitCount = HitCount::default();
+        assert_hit_count!(hit_count, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "serializes 2 values")]
+    fn rejects_composite_metrics() {
+        assert_hit_count!((1u64, 2u64), 1);
+    }
+
+    #[test]
+    fn asserts_histograms() {
+        let histogram = AtomicHdrHistogram::with_bound(60_000);
+        for value in 1..=100 {
+            histogram.record(value);
+        }
+        let stats = assert_histogram!(histogram)
+            .samples(100)
+            .max_below(101)
+            .quantile_below(Quantile::P50, 51);
+        assert_eq!(stats.get("min"), Some(1.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "`histogram` has a max of 100, expected below 100")]
+    fn reports_histogram_mismatches() {
+        let histogram = AtomicHdrHistogram::with_bound(60_000);
+        histogram.record(100);
+        assert_histogram!(histogram).max_below(100);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not a histogram")]
+    fn rejects_non_histograms() {
+        assert_histogram!(HitCount::<AtomicInt<u64>>::default());
+    }
+}
@@ -0,0 +1,119 @@
+//! A module providing runtime switches for the metrics of registries, to turn
+//! heavy metrics off and on without redeploying.
+//!
+//! Registries generated with the `toggle = true` option of `#[metered]` have a
+//! [`Toggle`] for the whole registry and one for each method sub-registry.
+//! When either is disabled, calls to the method are not measured: metrics are
+//! not entered at all, and keep their values.
+//!
+#![cfg_attr(
+    any(feature = "disabled", not(feature = "histograms")),
+    doc = "```ignore"
+)]
+#![cfg_attr(
+    all(not(feature = "disabled"), feature = "histograms"),
+    doc = "```rust"
+)]
+//! use metered::{metered, toggle::Toggles, HitCount, ResponseTime};
+//!
+//! #[derive(Default, Debug)]
+//! pub struct Service {
+//!     metrics: ServiceMetrics,
+//! }
+//!
+//! #[metered(registry = ServiceMetrics, toggle = true)]
+//! impl Service {
+//!     #[measure([HitCount, ResponseTime])]
+//!     pub fn call(&self) {}
+//!
+//!     #[measure(HitCount)]
+//!     pub fn ping(&self) {}
+//! }
+//!
+//! let service = Service::default();
+//!
+//! // e.g. from an admin endpoint
+//! service.metrics.method_toggle("call").unwrap().disable();
+//! service.call();
+//! service.ping();
+//! assert_eq!(service.metrics.call.hit_count.get(), 0);
+//! assert_eq!(service.metrics.ping.hit_count.get(), 1);
+//!
+//! service.metrics.call.toggle.enable();
+//! service.metrics.toggle().disable();
+//! service.call();
+//! assert_eq!(service.metrics.call.hit_count.get(), 0);
+//!
+//! service.metrics.toggle().enable();
+//! service.call();
+//! assert_eq!(service.metrics.call.hit_count.get(), 1);
+//! ```
+//!
+//! Checking toggles costs two relaxed atomic loads per call.
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// A switch enabling or disabling metrics at runtime, enabled by default.
+pub struct Toggle(AtomicBool);
+
+impl Toggle {
+    /// Creates a toggle, enabled or not
+    pub const fn new(enabled: bool) -> Self {
+        Toggle(AtomicBool::new(enabled))
+    }
+
+    /// Returns true if metrics are enabled
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Enables or disables metrics
+    pub fn set(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Enables metrics
+    pub fn enable(&self) {
+        self.set(true);
+    }
+
+    /// Disables metrics
+    pub fn disable(&self) {
+        self.set(false);
+    }
+}
+
+impl Default for Toggle {
+    fn default() -> Self {
+        Toggle::new(true)
+    }
+}
+
+impl fmt::Debug for Toggle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            if self.is_enabled() {
+                "enabled"
+            } else {
+                "disabled"
+            }
+        )
+    }
+}
+
+/// A trait for registries whose metrics can be toggled at runtime,
+/// implemented by the registries generated with the `toggle = true` option of
+/// `#[metered]`.
+pub trait Toggles {
+    /// Get the toggle of the whole registry
+    fn toggle(&self) -> &Toggle;
+
+    /// Get the toggle of a method sub-registry, by its serialized name
+    fn method_toggle(&self, method: &str) -> Option<&Toggle>;
+}